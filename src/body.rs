@@ -1,9 +1,16 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{Stream, TryStreamExt};
+use futures::stream::BoxStream;
 use http::HeaderValue;
-use hyper::body::HttpBody;
+use hyper::body::{Bytes, HttpBody};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
 
 pub use memory::*;
 
-use crate::error::ProtocolResult;
+use crate::error::{ProtocolError, ProtocolResult};
 
 mod memory;
 
@@ -19,46 +26,207 @@ impl Body {
         Body::InMemory(InMemoryBody::new_empty())
     }
 
+    /// A body which is sent as it's produced by `stream`, rather than buffered in memory.
+    /// Unless the request has a Content-Length header, it's sent with chunked transfer encoding.
+    pub fn from_stream<S, O, E>(stream: S) -> Self
+        where
+            S: Stream<Item=Result<O, E>> + Send + 'static,
+            O: Into<Bytes> + 'static,
+            E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+    {
+        Body::Hyper(hyper::Body::wrap_stream(stream))
+    }
+
+    /// A body which is sent as it's read from `reader`, e.g. a `tokio::fs::File`.
+    pub fn from_reader(reader: impl AsyncRead + Send + 'static) -> Self {
+        Self::from_stream(ReaderStream::new(reader))
+    }
+
     pub fn is_empty(&self) -> bool {
         match self {
-            Body::Hyper(b) => b.size_hint().upper() == Some(0),
+            Body::Hyper(b) => HttpBody::size_hint(b).upper() == Some(0),
             Body::InMemory(m) => m.is_empty(),
         }
     }
 
+    /// The body as a stream of chunks, as they arrive from the network.
+    pub fn into_stream(self) -> BoxStream<'static, ProtocolResult<Bytes>> {
+        Box::pin(TryStreamExt::map_err(hyper::Body::from(self), ProtocolError::from))
+    }
+
+    /// The body as a stream of lines, without their `\n` or `\r\n` endings, as they arrive from the network.
+    pub(crate) fn into_lines(self) -> BoxStream<'static, ProtocolResult<Bytes>> {
+        let state = (self.into_stream(), Vec::new(), false);
+        Box::pin(futures::stream::try_unfold(state, |(mut stream, mut buf, mut done): (_, Vec<u8>, _)| async move {
+            loop {
+                if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+                    let mut line: Vec<u8> = buf.drain(..=i).collect();
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    return Ok(Some((Bytes::from(line), (stream, buf, done))));
+                }
+                if done {
+                    // The last line needn't end with a newline.
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+                    let line = std::mem::take(&mut buf);
+                    return Ok(Some((Bytes::from(line), (stream, buf, done))));
+                }
+                match stream.try_next().await? {
+                    Some(chunk) => buf.extend_from_slice(&chunk),
+                    None => done = true,
+                }
+            }
+        }))
+    }
+
     pub async fn into_memory(self) -> ProtocolResult<InMemoryBody> {
         match self {
             Body::InMemory(m) => Ok(m),
             Body::Hyper(hyper_body) => {
                 let bytes = hyper::body::to_bytes(hyper_body).await?;
-                Ok(InMemoryBody::Bytes(bytes.to_vec()))
+                Ok(InMemoryBody::Bytes(bytes))
+            }
+        }
+    }
+
+    /// Like `into_memory`, but fails with `ProtocolError::BodyTooLarge` rather than buffer more than `max_size`
+    /// bytes. An in-memory body is returned as is.
+    pub async fn into_memory_limited(self, max_size: u64) -> ProtocolResult<InMemoryBody> {
+        match self {
+            Body::InMemory(m) => Ok(m),
+            Body::Hyper(hyper_body) => {
+                let bytes = hyper::body::to_bytes(limit_size(hyper_body, max_size)).await?;
+                Ok(InMemoryBody::Bytes(bytes))
             }
         }
     }
 
+    /// Read the whole body, keeping it in memory if it's at most `threshold` bytes, and spooling it to an anonymous
+    /// temporary file otherwise. A spooled body reads back from the file, which is deleted once it's dropped.
+    pub async fn into_spooled(self, threshold: u64) -> ProtocolResult<Body> {
+        let Body::Hyper(mut body) = self else {
+            return Ok(self);
+        };
+        let mut buf = Vec::new();
+        while let Some(chunk) = HttpBody::data(&mut body).await {
+            let chunk = chunk?;
+            if (buf.len() + chunk.len()) as u64 <= threshold {
+                buf.extend_from_slice(&chunk);
+                continue;
+            }
+            // Creating the file is blocking I/O, so it's kept off the runtime's threads, like `tokio::fs` does.
+            let file = tokio::task::spawn_blocking(tempfile::tempfile).await.map_err(std::io::Error::other)??;
+            let mut file = tokio::fs::File::from_std(file);
+            file.write_all(&buf).await?;
+            file.write_all(&chunk).await?;
+            while let Some(chunk) = HttpBody::data(&mut body).await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            file.rewind().await?;
+            return Ok(Body::from_reader(file));
+        }
+        Ok(Body::InMemory(InMemoryBody::Bytes(buf.into())))
+    }
+
     pub async fn into_content_type(self, content_type: Option<&HeaderValue>) -> ProtocolResult<InMemoryBody> {
         match self {
             Body::InMemory(m) => Ok(m),
             Body::Hyper(hyper_body) => {
                 let bytes = hyper::body::to_bytes(hyper_body).await?;
-                let content_type = content_type.map(|ct| ct.to_str().unwrap().split(';').next().unwrap());
-                match content_type {
-                    Some("application/json") => {
-                        let value = serde_json::from_slice(&bytes)?;
-                        Ok(InMemoryBody::Json(value))
-                    }
-                    Some("application/octet-stream") => Ok(InMemoryBody::Bytes(bytes.to_vec())),
-                    _ if bytes.is_empty() => Ok(InMemoryBody::Empty),
-                    _ => match String::from_utf8(bytes.to_vec()) {
-                        Ok(text) => Ok(InMemoryBody::Text(text)),
-                        Err(e) => {
-                            let bytes = e.into_bytes();
-                            Ok(InMemoryBody::Bytes(bytes))
-                        }
-                    }
+                InMemoryBody::from_content_type(bytes, content_type)
+            }
+        }
+    }
+}
+
+/// A body was larger than the limit, in bytes. Reported as `ProtocolError::BodyTooLarge`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyTooLarge(pub u64);
+
+impl std::fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Body exceeded {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// Wrap `body` so reading more than `max_size` bytes fails. If the Content-Length is already too large, it fails
+/// before anything is read.
+pub(crate) fn limit_size(body: hyper::Body, max_size: u64) -> hyper::Body {
+    if HttpBody::size_hint(&body).lower() > max_size {
+        return hyper::Body::wrap_stream(futures::stream::once(async move { Err::<Bytes, _>(BodyTooLarge(max_size)) }));
+    }
+    let mut read = 0;
+    let stream = TryStreamExt::map_err(body, |e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })
+        .and_then(move |chunk| {
+            read += chunk.len() as u64;
+            let checked = if read > max_size { Err(BodyTooLarge(max_size).into()) } else { Ok(chunk) };
+            futures::future::ready(checked)
+        });
+    hyper::Body::wrap_stream(stream)
+}
+
+/// Reading a body stopped because the request was cancelled. Reported as `ProtocolError::Cancelled`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Wrap `body` so reading it fails once `token` is cancelled. The body is dropped, which closes the connection.
+pub(crate) fn cancel_on(body: hyper::Body, token: CancellationToken) -> hyper::Body {
+    let stream = futures::stream::unfold(Some(body), move |body| {
+        let token = token.clone();
+        async move {
+            let mut body = body?;
+            tokio::select! {
+                biased;
+                _ = token.cancelled() => Some((Err(Cancelled.into()), None)),
+                chunk = body.data() => {
+                    let chunk: Result<Bytes, Box<dyn std::error::Error + Send + Sync>> = chunk?.map_err(Into::into);
+                    Some((chunk, Some(body)))
                 }
             }
         }
+    });
+    hyper::Body::wrap_stream(stream)
+}
+
+impl InMemoryBody {
+    /// Interpret raw bytes according to the content type: JSON is parsed, forms are decoded, and other bodies
+    /// become text if they're valid UTF-8. Bodies in another charset are kept as bytes, unless they're plain ASCII,
+    /// and decoded by the response's `text`.
+    pub fn from_content_type(bytes: Bytes, content_type: Option<&HeaderValue>) -> ProtocolResult<InMemoryBody> {
+        let encoding = memory::charset(content_type).unwrap_or(encoding_rs::UTF_8);
+        let content_type = content_type.map(|ct| ct.to_str().unwrap().split(';').next().unwrap());
+        match content_type {
+            Some("application/json") => {
+                let value = serde_json::from_slice(&bytes)?;
+                Ok(InMemoryBody::Json(value))
+            }
+            Some("application/octet-stream" | "application/msgpack" | "application/x-msgpack" | "application/cbor" | "application/x-protobuf") => Ok(InMemoryBody::Bytes(bytes)),
+            Some(FORM_CONTENT_TYPE) => match serde_urlencoded::from_bytes(&bytes) {
+                Ok(pairs) => Ok(InMemoryBody::Form(pairs)),
+                Err(_) => Ok(InMemoryBody::Bytes(bytes)),
+            },
+            _ if bytes.is_empty() => Ok(InMemoryBody::Empty),
+            _ if encoding != encoding_rs::UTF_8 && !(encoding.is_ascii_compatible() && bytes.is_ascii()) => Ok(InMemoryBody::Bytes(bytes)),
+            _ => match std::str::from_utf8(&bytes) {
+                Ok(text) => Ok(InMemoryBody::Text(text.to_string())),
+                Err(_) => Ok(InMemoryBody::Bytes(bytes)),
+            }
+        }
     }
 }
 
@@ -93,6 +261,7 @@ impl From<InMemoryBody> for hyper::Body {
                 let b = serde_json::to_vec(&value).unwrap();
                 hyper::Body::from(b)
             }
+            InMemoryBody::Form(pairs) => hyper::Body::from(encode_form(&pairs)),
         }
     }
 }
@@ -103,6 +272,23 @@ impl From<hyper::Body> for Body {
     }
 }
 
+/// A streaming request body. Middleware only sees an `InMemoryRequest`, so the stream rides along in the request
+/// extensions and is swapped in when the request is sent. It can only be sent once.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamingBody(Arc<Mutex<Option<hyper::Body>>>);
+
+impl StreamingBody {
+    pub fn new(body: hyper::Body) -> Self {
+        StreamingBody(Arc::new(Mutex::new(Some(body))))
+    }
+
+    pub fn take(&self) -> ProtocolResult<hyper::Body> {
+        self.0.lock().unwrap().take().ok_or_else(|| ProtocolError::IoError(std::io::Error::other(
+            "Streaming request body was already sent, and can't be sent again.",
+        )))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,4 +300,135 @@ mod tests {
         }));
         assert_eq!(serde_json::to_string(&body).unwrap(), r#"{"foo":"bar"}"#);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_clone_bytes() {
+        let body = InMemoryBody::new_bytes(vec![0xff; 1024]);
+        let (InMemoryBody::Bytes(a), InMemoryBody::Bytes(b)) = (&body, &body.clone()) else {
+            unreachable!()
+        };
+        assert_eq!(a.as_ptr(), b.as_ptr());
+        assert_eq!(serde_json::to_value(&body).unwrap().as_array().unwrap().len(), 1024);
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        // /chunked has no Content-Length, so the limit is only hit while reading.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let body = match req.uri().path() {
+                    "/chunked" => hyper::Body::wrap_stream(futures::stream::iter((0..4).map(|_| Ok::<_, std::io::Error>(vec![b'a'; 1024])))),
+                    _ => hyper::Body::from(vec![b'a'; 4096]),
+                };
+                Ok::<_, hyper::Error>(hyper::Response::new(body))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = crate::Client::builder().max_body_size(1024).build();
+        for path in ["/", "/chunked"] {
+            let err = client.get(&format!("http://{addr}{path}")).await.unwrap_err();
+            assert!(matches!(err, crate::Error::Protocol(ProtocolError::BodyTooLarge(1024))), "{path}: {err:?}");
+        }
+
+        let client = crate::Client::new();
+        let res = client.get(&format!("http://{addr}/chunked")).send().await.unwrap();
+        assert!(matches!(res.into_body().into_memory_limited(2048).await, Err(ProtocolError::BodyTooLarge(2048))));
+        let res = client.get(&format!("http://{addr}/chunked")).send().await.unwrap();
+        assert_eq!(res.into_body().into_memory_limited(4096).await.unwrap().bytes().unwrap().len(), 4096);
+    }
+
+    #[tokio::test]
+    async fn test_into_spooled() {
+        let chunks = || futures::stream::iter((0..4).map(|i| Ok::<_, std::io::Error>(vec![b'a' + i; 1024])));
+        let body = Body::from_stream(chunks()).into_spooled(1024).await.unwrap();
+        assert!(matches!(body, Body::Hyper(_)));
+        let bytes = body.into_memory().await.unwrap().bytes().unwrap();
+        assert_eq!(bytes.len(), 4096);
+        assert_eq!(&bytes[1023..1025], b"ab");
+
+        let body = Body::from_stream(chunks()).into_spooled(4096).await.unwrap();
+        assert!(matches!(body, Body::InMemory(InMemoryBody::Bytes(b)) if b.len() == 4096));
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_xml() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Animal {
+            name: String,
+            legs: u32,
+        }
+        let animal = Animal { name: "pony".to_string(), legs: 4 };
+        let request = crate::Request::build_post("https://example.com/").xml(&animal).build();
+        assert_eq!(request.headers()["content-type"], "application/xml");
+        assert_eq!(request.body().clone().text().unwrap(), "<Animal><name>pony</name><legs>4</legs></Animal>");
+        assert_eq!(request.body().clone().xml::<Animal>().unwrap(), animal);
+        assert!(matches!(
+            InMemoryBody::Text("<Animal>".to_string()).xml::<Animal>(),
+            Err(crate::Error::Protocol(ProtocolError::DecodeError(_)))
+        ));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Animal {
+            name: String,
+            legs: u32,
+        }
+        let animal = Animal { name: "pony".to_string(), legs: 4 };
+        let request = crate::Request::build_post("https://example.com/").msgpack(&animal).build();
+        assert_eq!(request.headers()["content-type"], "application/msgpack");
+        let bytes = request.body().clone().bytes().unwrap();
+        assert_eq!(&bytes[..7], b"\x82\xa4name\xa4");
+        let body = InMemoryBody::from_content_type(bytes, request.headers().get("content-type")).unwrap();
+        assert!(matches!(body, InMemoryBody::Bytes(_)));
+        assert_eq!(body.msgpack::<Animal>().unwrap(), animal);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor() {
+        #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct Reading {
+            sensor: String,
+            value: f32,
+        }
+        let reading = Reading { sensor: "t1".to_string(), value: 21.5 };
+        let request = crate::Request::build_post("https://example.com/").cbor(&reading).build();
+        assert_eq!(request.headers()["content-type"], "application/cbor");
+        let bytes = request.body().clone().bytes().unwrap();
+        assert_eq!(&bytes[..8], b"\xa2\x66sensor");
+        let body = InMemoryBody::from_content_type(bytes, request.headers().get("content-type")).unwrap();
+        assert!(matches!(body, InMemoryBody::Bytes(_)));
+        assert_eq!(body.cbor::<Reading>().unwrap(), reading);
+        assert!(InMemoryBody::new_bytes(vec![0xa2]).cbor::<Reading>().is_err());
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_protobuf() {
+        #[derive(Clone, PartialEq, prost::Message)]
+        struct Animal {
+            #[prost(string, tag = "1")]
+            name: String,
+            #[prost(uint32, tag = "2")]
+            legs: u32,
+        }
+        let animal = Animal { name: "pony".to_string(), legs: 4 };
+        let request = crate::Request::build_post("https://example.com/").protobuf(&animal).build();
+        assert_eq!(request.headers()["content-type"], "application/x-protobuf");
+        assert_eq!(request.headers()["accept"], "application/x-protobuf");
+        let bytes = request.body().clone().bytes().unwrap();
+        assert_eq!(bytes.as_ref(), b"\x0a\x04pony\x10\x04");
+        let body = InMemoryBody::from_content_type(bytes, request.headers().get("content-type")).unwrap();
+        assert!(matches!(body, InMemoryBody::Bytes(_)));
+        assert_eq!(body.protobuf::<Animal>().unwrap(), animal);
+    }
+}
@@ -1,3 +1,7 @@
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use encoding_rs::{Encoding, UTF_8};
+use http::HeaderValue;
 use hyper::body::Bytes;
 use std::hash::Hasher;
 use serde::{Deserialize, Serialize};
@@ -6,19 +10,35 @@ use serde::de::{DeserializeOwned, Error};
 use crate::InMemoryResult;
 use crate::sanitize::sanitize_value;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(untagged)]
 pub enum InMemoryBody {
+    #[default]
     Empty,
-    Bytes(Vec<u8>),
+    /// Cheap to clone, so requests can be retried or redirected without copying the body.
+    Bytes(Bytes),
     Text(String),
     Json(Value),
+    /// An `application/x-www-form-urlencoded` body, as decoded pairs. Serialized as the encoded string.
+    #[serde(serialize_with = "serialize_form", skip_deserializing)]
+    Form(Vec<(String, String)>),
 }
 
-impl Default for InMemoryBody {
-    fn default() -> Self {
-        InMemoryBody::Empty
-    }
+fn serialize_form<S: serde::Serializer>(pairs: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode_form(pairs))
+}
+
+/// Flat values, including sequences of pairs, are encoded with serde_urlencoded. Those it can't encode, like nested
+/// structs and sequences, fall back to serde_qs, which writes them with brackets, e.g. `user[name]=x&ids[0]=1`.
+pub(crate) fn form_pairs(value: impl Serialize) -> Vec<(String, String)> {
+    let encoded = serde_urlencoded::to_string(&value)
+        .or_else(|_| serde_qs::to_string(&value))
+        .expect("Failed to serialize form: it must be a struct, a map, or a sequence of pairs");
+    serde_urlencoded::from_str(&encoded).expect("Encoded forms are valid")
+}
+
+pub(crate) fn encode_form(pairs: &[(String, String)]) -> String {
+    serde_urlencoded::to_string(pairs).expect("Failed to encode form")
 }
 
 impl TryInto<String> for InMemoryBody {
@@ -28,12 +48,13 @@ impl TryInto<String> for InMemoryBody {
         match self {
             InMemoryBody::Empty => Ok("".to_string()),
             InMemoryBody::Bytes(b) => {
-                String::from_utf8(b)
+                String::from_utf8(Vec::from(b))
                     .map_err(|e| e.into())
             }
             InMemoryBody::Text(s) => Ok(s),
             InMemoryBody::Json(val) => serde_json::to_string(&val)
-                .map_err(|e| e.into())
+                .map_err(|e| e.into()),
+            InMemoryBody::Form(pairs) => Ok(encode_form(&pairs)),
         }
     }
 }
@@ -44,16 +65,17 @@ impl TryInto<Bytes> for InMemoryBody {
     fn try_into(self) -> InMemoryResult<Bytes> {
         match self {
             InMemoryBody::Empty => Ok(Bytes::new()),
-            InMemoryBody::Bytes(b) => Ok(Bytes::from(b)),
+            InMemoryBody::Bytes(b) => Ok(b),
             InMemoryBody::Text(s) => Ok(Bytes::from(s)),
             InMemoryBody::Json(val) => Ok(Bytes::from(serde_json::to_string(&val)?)),
+            InMemoryBody::Form(pairs) => Ok(Bytes::from(encode_form(&pairs))),
         }
     }
 }
 
 
 impl InMemoryBody {
-    pub fn new_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+    pub fn new_bytes(bytes: impl Into<Bytes>) -> Self {
         InMemoryBody::Bytes(bytes.into())
     }
 
@@ -65,6 +87,14 @@ impl InMemoryBody {
         InMemoryBody::Json(serde_json::to_value(value).unwrap())
     }
 
+    /// A form body from a struct, a map, or a sequence of pairs. Nested values are written with brackets, e.g.
+    /// `user[name]=x`.
+    /// # Panics
+    /// If `value` is a bare string, number, or other value without field names.
+    pub fn new_form(value: impl Serialize) -> Self {
+        InMemoryBody::Form(form_pairs(value))
+    }
+
     pub fn new_empty() -> Self {
         InMemoryBody::Empty
     }
@@ -76,13 +106,50 @@ impl InMemoryBody {
             Bytes(b) => b.is_empty(),
             Text(s) => s.is_empty(),
             Json(_) => false,
+            Form(pairs) => pairs.is_empty(),
         }
     }
 
+    #[allow(clippy::result_large_err)]
     pub fn text(self) -> InMemoryResult<String> {
         self.try_into()
     }
 
+    /// Decode the body as text in the encoding named by `charset`, e.g. `ISO-8859-1` or `Shift_JIS`. Unknown
+    /// charsets are treated as UTF-8.
+    #[allow(clippy::result_large_err)]
+    pub fn text_with_charset(self, charset: &str) -> InMemoryResult<String> {
+        self.decode(Encoding::for_label(charset.as_bytes()).unwrap_or(UTF_8))
+    }
+
+    /// Like `text`, but invalid UTF-8 is replaced with U+FFFD rather than failing.
+    pub fn text_lossy(self) -> String {
+        self.decode_lossy(UTF_8)
+    }
+
+    /// Text and JSON bodies are already decoded, so only bytes depend on the encoding.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn decode(self, encoding: &'static Encoding) -> InMemoryResult<String> {
+        match self {
+            InMemoryBody::Bytes(b) if encoding != UTF_8 => {
+                let (text, _, had_errors) = encoding.decode(&b);
+                if had_errors {
+                    let msg = format!("Body is not valid {}", encoding.name());
+                    return Err(crate::Error::Protocol(crate::ProtocolError::DecodeError(msg.into())));
+                }
+                Ok(text.into_owned())
+            }
+            body => body.text(),
+        }
+    }
+
+    pub(crate) fn decode_lossy(self, encoding: &'static Encoding) -> String {
+        match self {
+            InMemoryBody::Bytes(b) => encoding.decode(&b).0.into_owned(),
+            body => body.text().unwrap_or_default(),
+        }
+    }
+
     pub fn json<T: DeserializeOwned>(self) -> serde_json::Result<T> {
         match self {
             InMemoryBody::Empty => Err(serde_json::Error::custom("Empty body")),
@@ -95,13 +162,54 @@ impl InMemoryBody {
             InMemoryBody::Json(v) => {
                 serde_json::from_value(v)
             }
+            InMemoryBody::Form(_) => Err(serde_json::Error::custom("Form body is not JSON")),
         }
     }
 
+    #[allow(clippy::result_large_err)]
     pub fn bytes(self) -> InMemoryResult<Bytes> {
         self.try_into()
     }
 
+    #[cfg(feature = "xml")]
+    #[allow(clippy::result_large_err)]
+    pub fn xml<T: DeserializeOwned>(self) -> InMemoryResult<T> {
+        let text = self.text()?;
+        quick_xml::de::from_str(&text).map_err(decode_error)
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[allow(clippy::result_large_err)]
+    pub fn msgpack<T: DeserializeOwned>(self) -> InMemoryResult<T> {
+        let bytes = self.bytes()?;
+        rmp_serde::from_slice(&bytes).map_err(decode_error)
+    }
+
+    #[cfg(feature = "cbor")]
+    #[allow(clippy::result_large_err)]
+    pub fn cbor<T: DeserializeOwned>(self) -> InMemoryResult<T> {
+        let bytes = self.bytes()?;
+        ciborium::from_reader(bytes.as_ref()).map_err(decode_error)
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[allow(clippy::result_large_err)]
+    pub fn protobuf<T: prost::Message + Default>(self) -> InMemoryResult<T> {
+        let bytes = self.bytes()?;
+        T::decode(bytes).map_err(decode_error)
+    }
+
+    /// Deserialize a form body, e.g. into a struct or `HashMap<String, String>`.
+    #[allow(clippy::result_large_err)]
+    pub fn form<T: DeserializeOwned>(self) -> InMemoryResult<T> {
+        let encoded = match self {
+            InMemoryBody::Form(pairs) => encode_form(&pairs),
+            body => body.text()?,
+        };
+        serde_urlencoded::from_str(&encoded)
+            .map_err(|e| crate::Error::Protocol(crate::ProtocolError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, e))))
+    }
+
     pub fn sanitize(&mut self) {
         if let InMemoryBody::Json(value) = self {
             sanitize_value(value)
@@ -109,6 +217,70 @@ impl InMemoryBody {
     }
 }
 
+#[cfg(any(feature = "xml", feature = "msgpack", feature = "cbor", feature = "protobuf"))]
+fn decode_error(e: impl std::error::Error + Send + Sync + 'static) -> crate::InMemoryError {
+    crate::Error::Protocol(crate::ProtocolError::DecodeError(Box::new(e)))
+}
+
+/// The encoding named by the `charset` parameter of a Content-Type, if it's one encoding_rs knows.
+pub(crate) fn charset(content_type: Option<&HeaderValue>) -> Option<&'static Encoding> {
+    let content_type: mime::Mime = content_type?.to_str().ok()?.parse().ok()?;
+    Encoding::for_label(content_type.get_param(mime::CHARSET)?.as_str().as_bytes())
+}
+
+pub(crate) const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// The marker key for binary bodies in recordings, e.g. `{"$base64": "iVBORw0KGgo="}`.
+pub(crate) const BASE64_MARKER: &str = "$base64";
+
+/// Serializes a body for a recording. Unlike the derived `Serialize`, binary bodies are written as base64 with a
+/// marker, so they can't be confused with JSON arrays.
+pub(crate) struct RecordedBody<'a>(pub &'a InMemoryBody);
+
+impl Serialize for RecordedBody<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            InMemoryBody::Bytes(b) => {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(BASE64_MARKER, &BASE64_STANDARD.encode(b))?;
+                map.end()
+            }
+            body => body.serialize(serializer),
+        }
+    }
+}
+
+impl InMemoryBody {
+    /// Restore a body from a recording, using the content type to resolve ambiguities. Also reads recordings from
+    /// before binary bodies were base64 encoded, when they were written as arrays of numbers.
+    pub(crate) fn from_recorded(value: Value, content_type: Option<&HeaderValue>) -> InMemoryBody {
+        let mime = content_type
+            .and_then(|ct| ct.to_str().ok())
+            .map(|ct| ct.split(';').next().unwrap().trim());
+        let is_json = mime == Some("application/json");
+        match value {
+            Value::String(s) if mime == Some(FORM_CONTENT_TYPE) => match serde_urlencoded::from_str(&s) {
+                Ok(pairs) => InMemoryBody::Form(pairs),
+                Err(_) => InMemoryBody::Text(s),
+            },
+            Value::Null => InMemoryBody::Empty,
+            Value::Object(map) if map.len() == 1 && map.get(BASE64_MARKER).is_some_and(Value::is_string) => {
+                let encoded = map[BASE64_MARKER].as_str().unwrap();
+                match BASE64_STANDARD.decode(encoded) {
+                    Ok(bytes) => InMemoryBody::Bytes(bytes.into()),
+                    Err(_) => InMemoryBody::Json(Value::Object(map)),
+                }
+            }
+            Value::String(s) if !is_json => InMemoryBody::Text(s),
+            Value::Array(vec) if !is_json && !vec.is_empty() && vec.iter().all(|v| v.as_u64().is_some_and(|n| n <= 255)) => {
+                InMemoryBody::Bytes(vec.iter().map(|v| v.as_u64().unwrap() as u8).collect())
+            }
+            value => InMemoryBody::Json(value),
+        }
+    }
+}
+
 impl std::hash::Hash for InMemoryBody {
     fn hash<H: Hasher>(&self, state: &mut H) {
         use InMemoryBody::*;
@@ -116,7 +288,7 @@ impl std::hash::Hash for InMemoryBody {
             Empty => state.write_u8(0),
             Bytes(b) => {
                 state.write_u8(1);
-                state.write(b.as_slice());
+                state.write(b);
             }
             Text(s) => {
                 state.write_u8(2);
@@ -126,6 +298,10 @@ impl std::hash::Hash for InMemoryBody {
                 state.write_u8(3);
                 state.write(v.to_string().as_bytes());
             }
+            Form(pairs) => {
+                state.write_u8(4);
+                state.write(encode_form(pairs).as_bytes());
+            }
         }
     }
 }
\ No newline at end of file
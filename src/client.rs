@@ -1,28 +1,44 @@
 use std::fmt::Formatter;
+use std::io::ErrorKind;
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use http::Method;
-use hyper::client::HttpConnector;
+use http::{header, Method, StatusCode};
+use http::uri::Scheme;
 use hyper::Uri;
-use hyper_rustls::HttpsConnector;
 
 use crate::middleware::{Middleware, MiddlewareStack};
-use crate::RequestBuilder;
+use crate::{DecompressionLimits, ProtocolError, ProtocolResult, RequestBuilder, Response, ResponseExt};
+use crate::response::write_body;
 
-static HTTPS_CONNECTOR: OnceLock<HttpsConnector<HttpConnector>> = OnceLock::new();
+pub use builder::ClientBuilder;
+pub use dns::{CachingResolver, IpPreference, Resolver, StaticResolver, SystemResolver};
+pub use hooks::{ConnectionInfo, RedirectEvent, RetryEvent};
+pub use pool::{HostStats, PoolStats};
+pub use profile::HostProfile;
+pub use proxy::{NoProxy, Proxies, Proxy};
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tls::{Certificate, Identity};
 
-fn https_connector() -> &'static HttpsConnector<HttpConnector> {
-    HTTPS_CONNECTOR.get_or_init(|| {
-        hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build()
-    })
-}
+use connector::Connector;
+use hooks::Hooks;
+pub(crate) use pool::{hold_until_read, Pool};
+
+mod builder;
+mod connector;
+mod dns;
+mod hooks;
+mod pool;
+mod profile;
+mod proxy;
+mod tcp;
+mod tls;
+
+type HyperClient = hyper::Client<Connector, hyper::Body>;
 
-static APP_USER_AGENT: &str = concat!(
+pub(crate) static APP_USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
     "/",
     env!("CARGO_PKG_VERSION"),
@@ -30,10 +46,26 @@ static APP_USER_AGENT: &str = concat!(
 
 #[derive(Clone)]
 pub struct Client {
-    base_url: Option<String>,
+    base_url: Option<Uri>,
     default_headers: Vec<(String, String)>,
+    default_query: Vec<(String, String)>,
+    host_profiles: Vec<(String, HostProfile)>,
     pub(crate) middlewares: MiddlewareStack,
-    pub(crate) inner: hyper::Client<HttpsConnector<HttpConnector>, hyper::Body>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) decompress: bool,
+    pub(crate) decompression_limits: DecompressionLimits,
+    /// The minimum body size to send `Expect: 100-continue` for, and how long to wait for a rejection.
+    pub(crate) expect_continue: Option<(u64, Duration)>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) hooks: Arc<Hooks>,
+    pub(crate) pool: Arc<Pool>,
+    connector: Connector,
+    hyper_builder: hyper::client::Builder,
+    /// Replaced by `close_idle`, which drops the old pool. Shared by clones, like the pool is.
+    inner: Arc<RwLock<HyperClient>>,
+    /// Pools for requests sent through a proxy other than the client's, since hyper pools connections by origin.
+    proxied: Arc<Mutex<Vec<(Proxy, HyperClient)>>>,
 }
 
 /**
@@ -55,19 +87,25 @@ impl std::fmt::Debug for Client {
 
 impl Client {
     pub fn new() -> Self {
-        let https = https_connector().clone();
-        Client {
-            base_url: None,
-            default_headers: vec![("User-Agent".to_string(), APP_USER_AGENT.to_string())],
-            middlewares: Vec::new(),
-            inner: hyper::Client::builder().build(https),
-        }
+        ClientBuilder::new().build()
     }
 
-    /// Set a `base_url` so you can pass relative paths instead of full URLs.
-    pub fn base_url(mut self, base_url: &str) -> Self {
-        self.base_url = Some(base_url.to_string());
-        self
+    /// Use the builder to configure timeouts and other transport-level settings.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Set a `base_url` so you can pass relative paths instead of full URLs. Paths are resolved against it as links
+    /// are (RFC 3986), so end the base with a `/` and use paths without one, e.g. a base of
+    /// `https://api.example.com/v2/` and a path of `users/1`. A path starting with `/` replaces the base's path.
+    ///
+    /// Fails with `ProtocolError::InvalidRequest` if `base_url` isn't an absolute URL.
+    pub fn base_url(mut self, base_url: &str) -> ProtocolResult<Self> {
+        let uri = Uri::from_str(base_url).ok()
+            .filter(|uri| uri.scheme().is_some() && uri.host().is_some())
+            .ok_or_else(|| ProtocolError::InvalidRequest(format!("The base URL must be absolute: {base_url}")))?;
+        self.base_url = Some(uri);
+        Ok(self)
     }
 
     pub fn with_middleware<T: Middleware + 'static>(mut self, middleware: T) -> Self {
@@ -85,63 +123,236 @@ impl Client {
         self
     }
 
+    /// Send a header with every request, unless the request sets it itself. Replaces any earlier default of the same
+    /// name, including the `User-Agent`.
     pub fn default_header<S: AsRef<str>>(mut self, key: S, value: S) -> Self {
-        self.default_headers.push((key.as_ref().to_string(), value.as_ref().to_string()));
+        let key = key.as_ref();
+        self.default_headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.default_headers.push((key.to_string(), value.as_ref().to_string()));
         self
     }
 
-    fn build_uri(&self, uri_or_path: &str) -> Uri {
+    /// Add a query parameter to every request which doesn't set it itself. Replaces any earlier default of the same
+    /// name.
+    pub fn default_query<S: AsRef<str>>(mut self, key: S, value: S) -> Self {
+        let key = key.as_ref();
+        self.default_query.retain(|(k, _)| k != key);
+        self.default_query.push((key.to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    fn build_uri(&self, uri_or_path: &str) -> ProtocolResult<Uri> {
         if let Ok(uri) = Uri::from_str(uri_or_path) {
             if uri.scheme().is_some() && uri.host().is_some() {
-                return uri;
+                return Ok(uri);
             }
         }
-        let uri = self.base_url.as_ref().map(|s| s.clone() + uri_or_path).unwrap_or_else(|| uri_or_path.to_string());
-        Uri::from_str(&uri).unwrap()
+        let uri = match &self.base_url {
+            Some(base) => crate::uri::resolve(base, uri_or_path),
+            None => Uri::from_str(uri_or_path),
+        };
+        uri.map_err(|e| ProtocolError::InvalidRequest(format!("Invalid URL {uri_or_path}: {e}")))
     }
 
-    pub fn get(&self, url_or_path: &str) -> RequestBuilder<Client> {
-        let uri = self.build_uri(url_or_path);
-        RequestBuilder::new(self, Method::GET, uri)
-            .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+    pub fn get(&self, url_or_path: &str) -> RequestBuilder<'_, Client> {
+        self.request(Method::GET, url_or_path)
     }
 
-    pub fn post(&self, uri_or_path: &str) -> RequestBuilder<Client> {
-        let uri = self.build_uri(uri_or_path);
-        RequestBuilder::new(self, Method::POST, uri)
-            .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+    pub fn post(&self, uri_or_path: &str) -> RequestBuilder<'_, Client> {
+        self.request(Method::POST, uri_or_path)
     }
 
-    pub fn delete(&self, uri_or_path: &str) -> RequestBuilder {
-        let uri = self.build_uri(uri_or_path);
-        RequestBuilder::new(self, Method::DELETE, uri)
-            .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+    pub fn delete(&self, uri_or_path: &str) -> RequestBuilder<'_> {
+        self.request(Method::DELETE, uri_or_path)
     }
 
-    pub fn put(&self, uri_or_path: &str) -> RequestBuilder {
-        let uri = self.build_uri(uri_or_path);
-        RequestBuilder::new(self, Method::PUT, uri)
-            .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+    pub fn put(&self, uri_or_path: &str) -> RequestBuilder<'_> {
+        self.request(Method::PUT, uri_or_path)
     }
 
-    pub fn patch(&self, uri_or_path: &str) -> RequestBuilder {
-        let uri = self.build_uri(uri_or_path);
-        RequestBuilder::new(self, Method::PATCH, uri)
-            .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+    pub fn patch(&self, uri_or_path: &str) -> RequestBuilder<'_> {
+        self.request(Method::PATCH, uri_or_path)
     }
 
-    pub fn request(&self, method: Method, uri_or_path: &str) -> RequestBuilder {
-        let uri = self.build_uri(uri_or_path);
-        RequestBuilder::new(self, method, uri)
+    /// If `uri_or_path` isn't a valid URL, sending the request fails with `ProtocolError::InvalidRequest`.
+    pub fn request(&self, method: Method, uri_or_path: &str) -> RequestBuilder<'_> {
+        let (uri, error) = match self.build_uri(uri_or_path) {
+            Ok(uri) => (uri, None),
+            Err(e) => (Uri::default(), Some(e)),
+        };
+        let profile = uri.host().and_then(|host| self.host_profile(host));
+        let mut builder = RequestBuilder::new(self, method, uri)
             .headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())))
-            .set_middlewares(self.middlewares.clone())
+            .set_default_query(self.default_query.clone())
+            .set_middlewares(self.middlewares.clone());
+        if let Some(profile) = profile {
+            for (key, value) in &profile.headers {
+                builder = builder.header(key, value);
+            }
+            builder.timeout = profile.timeout;
+            if let Some(proxy) = &profile.proxy {
+                builder.extensions.insert(proxy.clone());
+            }
+            builder.middlewares.extend(profile.middlewares.iter().cloned());
+        }
+        match error {
+            Some(e) => builder.invalid(e),
+            None => builder,
+        }
+    }
+
+    /// Open a connection to the origin of `url_or_path`, resolving its DNS and completing the TLS handshake, so the
+    /// next request there can skip them. The connection is kept for up to 90 seconds, and counts as idle in
+    /// `pool_stats` until it's used. Call it more than once to have several connections ready.
+    pub async fn warm_up(&self, url_or_path: &str) -> ProtocolResult<()> {
+        let uri = self.build_uri(url_or_path)?;
+        self.connector.warm_up(uri).await.map_err(ProtocolError::from)
+    }
+
+    /// Open a raw TCP stream to `host` and `port`, for a protocol other than HTTP. It's tunneled through the proxy
+    /// the client would use for `https://host:port`, with `CONNECT` for an HTTP proxy, so it can get through a
+    /// corporate proxy; otherwise, e.g. if the host is in `NO_PROXY`, it connects directly. Anything sent is
+    /// passed on as is, so to speak TLS, wrap it yourself.
+    pub async fn connect_tunnel(&self, host: &str, port: u16) -> ProtocolResult<tokio::net::TcpStream> {
+        self.connector.tunnel(host, port).await.map_err(ProtocolError::from)
+    }
+
+    /// Warm up a connection to the base URL in the background, so it's ready by the first request. If it fails, the
+    /// first request connects as usual. Does nothing without a base URL, or outside a Tokio runtime.
+    pub fn prewarm(self) -> Self {
+        if let (Some(base_url), Ok(runtime)) = (&self.base_url, tokio::runtime::Handle::try_current()) {
+            let connector = self.connector.clone();
+            let uri = base_url.clone();
+            runtime.spawn(async move { connector.warm_up(uri).await });
+        }
+        self
+    }
+
+    /// Close the connections idle in the pool, including warmed-up ones, e.g. after a burst of traffic or when the
+    /// network changes. Connections serving a request are closed when it finishes, rather than returned to the pool.
+    pub fn close_idle(&self) {
+        self.connector.close_warm();
+        *self.inner.write().unwrap() = self.hyper_builder.build(self.connector.clone());
+        self.proxied.lock().unwrap().clear();
+    }
+
+    /// Present `identity` on new connections, e.g. when a short-lived certificate is renewed. Connections already
+    /// open keep the old one until they close; `close_idle` closes the idle ones. Clones of the client share it.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn set_identity(&self, identity: Identity) {
+        self.connector.update_tls(|tls| tls.identity = Some(identity));
     }
 
+    /// Present `identity` to `host` on new connections, replacing the identity registered for it, if any. See
+    /// `ClientBuilder::host_identity`.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn set_host_identity(&self, host: &str, identity: Identity) {
+        self.connector.update_tls(|tls| match tls.host_identities.iter_mut().find(|(h, _)| h == host) {
+            Some((_, existing)) => *existing = identity,
+            None => tls.host_identities.push((host.to_string(), identity)),
+        });
+    }
+
+    /// Trust `certs` as root certificates on new connections, instead of those added so far, e.g. when a CA bundle
+    /// is rotated. The platform's roots are still trusted unless `ClientBuilder::tls_native_roots` turned them off.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn set_root_certificates(&self, certs: Vec<Certificate>) {
+        self.connector.update_tls(|tls| tls.roots = certs);
+    }
+
+    /// Shut the client down gracefully, e.g. to drain a service before a deploy: new requests fail with
+    /// `ProtocolError::ClientClosed`, and those in flight get up to `timeout` to finish, including reading their
+    /// response bodies, before idle connections are closed. Returns whether they all finished in time.
+    ///
+    /// Clones of the client share its pool, and are shut down too.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        let drained = tokio::time::timeout(timeout, self.pool.drain()).await.is_ok();
+        self.close_idle();
+        drained
+    }
+
+    /// Send `request` through `proxy` if given, or else the client's proxy, if it has one.
+    pub(crate) fn send(
+        &self,
+        mut request: hyper::Request<hyper::Body>,
+        proxy: Option<&Proxy>,
+    ) -> hyper::client::ResponseFuture {
+        let client_proxy = self.connector.proxy_for(request.uri());
+        let proxy = proxy.filter(|proxy| client_proxy != Some(proxy));
+        // Plain HTTP requests are forwarded by the proxy, so its credentials go with them. HTTPS requests send them
+        // when opening the tunnel.
+        if request.uri().scheme() == Some(&Scheme::HTTP) {
+            let forwarding = proxy.or(client_proxy).filter(|proxy| proxy.forwards());
+            if let Some(authorization) = forwarding.and_then(Proxy::authorization) {
+                request.headers_mut().entry(header::PROXY_AUTHORIZATION).or_insert(authorization);
+            }
+        }
+        let Some(proxy) = proxy else {
+            return self.inner.read().unwrap().request(request);
+        };
+        let mut proxied = self.proxied.lock().unwrap();
+        let client = match proxied.iter().find(|(p, _)| p == proxy) {
+            Some((_, client)) => client,
+            None => {
+                let client = self.hyper_builder.build(self.connector.with_proxy(proxy.clone()));
+                proxied.push((proxy.clone(), client));
+                &proxied.last().unwrap().1
+            }
+        };
+        client.request(request)
+    }
+
+    /// Open, active, and idle connections and bytes transferred, per host. Clones of a client share its pool, and so
+    /// its stats.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// The first profile registered for `host`.
+    fn host_profile(&self, host: &str) -> Option<&HostProfile> {
+        self.host_profiles.iter()
+            .find(|(pattern, _)| profile::host_matches(pattern, host))
+            .map(|(_, profile)| profile)
+    }
+
+    /// Download `url_or_path` to `path`, streaming it to disk. If the file already exists, only the remainder is
+    /// requested with a Range header, so an interrupted download picks up where it left off.
+    /// Returns the size of the file.
+    pub async fn download(&self, url_or_path: &str, path: impl AsRef<Path>) -> crate::Result<u64> {
+        let path = path.as_ref();
+        let offset = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(ProtocolError::from(e).into()),
+        };
+        let mut request = self.get(url_or_path);
+        if offset > 0 {
+            request = request.header(header::RANGE.as_str(), &format!("bytes={offset}-"));
+        }
+        let res = request.send().await?;
+        let range = content_range(&res);
+        match res.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                if range.map(|(start, _)| start) != Some(Some(offset)) {
+                    return Err(ProtocolError::IoError(std::io::Error::new(ErrorKind::InvalidData, "Server responded with a different range than requested.")).into());
+                }
+                let file = tokio::fs::OpenOptions::new().append(true).open(path).await.map_err(ProtocolError::from)?;
+                Ok(offset + write_body(res.into_body(), file).await?)
+            }
+            // The file is already complete.
+            StatusCode::RANGE_NOT_SATISFIABLE if range.map(|(_, total)| total) == Some(Some(offset)) => Ok(offset),
+            // The server ignored the Range header, so start over.
+            _ => Ok(res.error_for_status()?.save_to_path(path).await?),
+        }
+    }
+}
+
+/// Parse `Content-Range: bytes start-end/total` (or `bytes */total`) into the start and total, where known.
+fn content_range(res: &Response) -> Option<(Option<u64>, Option<u64>)> {
+    let value = res.headers().get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split_once('-').and_then(|(start, _)| start.parse().ok());
+    Some((start, total.parse().ok()))
 }
 
 impl Default for Client {
@@ -154,19 +365,21 @@ impl Default for Client {
 mod tests {
     use std::collections::HashMap;
 
-    use crate::middleware::{Recorder, RecorderMode};
-    use crate::ResponseExt;
+    use async_trait::async_trait;
+
+    use crate::middleware::{Next, Recorder, RecorderMode};
+    use crate::{InMemoryRequest, ProtocolError, ProtocolResult, Response, ResponseExt};
 
     use super::*;
 
     #[tokio::test]
     async fn test_make_request() {
         let client = Client::new()
-            .base_url("https://www.jsonip.com")
+            .base_url("https://www.jsonip.com").unwrap()
             .no_default_headers()
             .default_headers(vec![("User-Agent", "test-client")].into_iter())
             .with_middleware(Recorder::new()
-                .mode(RecorderMode::ForceNoRequests)
+                .mode(RecorderMode::ReplayOnly)
             );
 
         let res = client.get("/")
@@ -179,4 +392,356 @@ mod tests {
         let res = serde_json::to_value(res).unwrap();
         assert_eq!(res, serde_json::json!({"ip":"70.107.97.117","geo-ip":"https://getjsonip.com/#plus","API Help":"https://getjsonip.com/#docs"}));
     }
+
+    #[test]
+    fn test_host_profile() {
+        let client = Client::builder()
+            .default_header("Accept", "application/json")
+            .host_profile("*.github.com", HostProfile::new()
+                .header("Accept", "application/vnd.github+json")
+                .timeout(Duration::from_secs(5))
+                .retry(crate::middleware::RetryPolicy::new().max_attempts(5)))
+            .build();
+        let request = client.get("https://api.github.com/repos");
+        assert_eq!(request.headers["accept"], "application/vnd.github+json");
+        assert_eq!(request.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(request.middlewares.len(), 1);
+        for url in ["https://github.com/", "https://example.com/", "https://notgithub.com/"] {
+            let request = client.get(url);
+            assert_eq!(request.headers["accept"], "application/json");
+            assert_eq!(request.timeout, None);
+            assert!(request.middlewares.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_default_header() {
+        let client = Client::new()
+            .default_header("user-agent", "test-client")
+            .default_header("X-Api-Key", "old")
+            .default_header("x-api-key", "new");
+        let request = client.get("https://example.com/");
+        assert_eq!(request.headers.get_all("user-agent").iter().collect::<Vec<_>>(), ["test-client"]);
+        assert_eq!(request.headers.get_all("x-api-key").iter().collect::<Vec<_>>(), ["new"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_url() {
+        let e = Client::new().base_url("api.example.com/v2/").unwrap_err();
+        assert_eq!(e.to_string(), "InvalidRequest: The base URL must be absolute: api.example.com/v2/");
+
+        let client = Client::new().base_url("https://api.example.com/v2/").unwrap();
+        assert_eq!(client.get("users/1").uri, "https://api.example.com/v2/users/1");
+        let e = client.get("users/a b").send().await.unwrap_err();
+        assert!(matches!(e, ProtocolError::InvalidRequest(ref message) if message.contains("users/a b")), "{e:?}");
+        assert!(client.warm_up("users/a b").await.is_err());
+    }
+
+    #[derive(Debug)]
+    struct Slow;
+
+    #[async_trait]
+    impl Middleware for Slow {
+        async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            next.run(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(10))
+            .build()
+            .with_middleware(Slow);
+        let res = client.get("http://localhost/").send().await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        // Accepts connections but never responds.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let client = Client::builder()
+            .read_timeout(Duration::from_millis(50))
+            .build();
+        let res = client.get(&format!("http://{addr}/")).send().await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        use hyper::service::{make_service_fn, service_fn};
+
+        use crate::middleware::{Backoff, Follow, Retry};
+
+        // Redirects `/old` to `/new`, which fails once before succeeding.
+        let calls = Arc::new(AtomicUsize::new(0));
+        let make_svc = make_service_fn(move |_| {
+            let calls = calls.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<hyper::Body>| {
+                    let status = match req.uri().path() {
+                        "/old" => StatusCode::FOUND,
+                        _ if calls.fetch_add(1, Ordering::SeqCst) == 0 => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::OK,
+                    };
+                    let res = hyper::Response::builder().status(status).header(header::LOCATION, "/new");
+                    async move { Ok::<_, hyper::Error>(res.body(hyper::Body::empty()).unwrap()) }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = |events: &Arc<Mutex<Vec<String>>>, event: String| {
+            let events = events.clone();
+            async move { events.lock().unwrap().push(event) }
+        };
+        let (e1, e2, e3) = (events.clone(), events.clone(), events.clone());
+        let (e4, e5, e6) = (events.clone(), events.clone(), events.clone());
+        let client = Client::builder()
+            .on_request_start(move |req| log(&e1, format!("start {}", req.url().path())))
+            .on_response(move |res| log(&e2, format!("response {}", res.status().as_u16())))
+            .on_retry(move |retry| log(&e3, format!("retry {} {:?}", retry.attempt, retry.status.map(|s| s.as_u16()))))
+            .on_redirect(move |redirect| log(&e4, format!("redirect {} {}", redirect.from.path(), redirect.to.path())))
+            .on_connection_open(move |conn| log(&e5, format!("open {}", conn.remote_addr.unwrap() == addr)))
+            .on_connection_close(move |conn| log(&e6, format!("close {}", conn.remote_addr.unwrap() == addr)))
+            .build()
+            .with_middleware(Follow::new())
+            .with_middleware(Retry::new().backoff(Backoff::none()));
+        let res = client.get(&format!("http://{addr}/old")).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        drop(res);
+        drop(client);
+        for _ in 0..100 {
+            if events.lock().unwrap().len() == 6 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*events.lock().unwrap(), [
+            "start /old",
+            "open true",
+            "redirect /old /new",
+            "retry 1 Some(503)",
+            "response 200",
+            "close true",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_pool_stats() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|_| async {
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from("hello")))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new();
+        assert_eq!(client.pool_stats(), PoolStats::default());
+        let res = client.get(&format!("http://{addr}/")).send().await.unwrap();
+        let stats = client.pool_stats().hosts[&addr.to_string()];
+        assert_eq!((stats.open, stats.active, stats.idle), (1, 1, 0));
+        assert_eq!(res.text().await.unwrap(), "hello");
+        let stats = client.pool_stats().hosts[&addr.to_string()];
+        assert_eq!((stats.open, stats.active, stats.idle), (1, 0, 1));
+        assert!(stats.bytes_read > 5 && stats.bytes_written > 0);
+        assert_eq!(client.pool_stats().total(), stats);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use hyper::service::{make_service_fn, service_fn};
+
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let make_svc = make_service_fn(move |_| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            async {
+                Ok::<_, hyper::Error>(service_fn(|_| async {
+                    Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty()))
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new().base_url(&format!("http://{addr}/")).unwrap();
+        client.warm_up("/").await.unwrap();
+        let stats = client.pool_stats().total();
+        assert_eq!((stats.open, stats.idle), (1, 1));
+        client.get("a").send().await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(client.pool_stats().total().open, 1);
+
+        let client = Client::new().base_url(&format!("http://{addr}/")).unwrap().prewarm();
+        for _ in 0..100 {
+            if client.pool_stats().total().open == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        client.get("a").send().await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+        let closed = Client::new().warm_up("http://127.0.0.1:1/").await;
+        assert!(matches!(closed, Err(ProtocolError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        // Answers after a short delay.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|_| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from("hello")))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let url = format!("http://{addr}/");
+
+        let client = Client::new();
+        client.get(&url).send().await.unwrap().text().await.unwrap();
+        assert_eq!(client.pool_stats().total().open, 1);
+        client.close_idle();
+        for _ in 0..100 {
+            if client.pool_stats().total().open == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(client.pool_stats().total().open, 0);
+
+        let in_flight = tokio::spawn({
+            let client = client.clone();
+            let url = url.clone();
+            async move { client.get(&url).send().await.unwrap().text().await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(client.shutdown(Duration::from_secs(5)).await);
+        assert_eq!(in_flight.await.unwrap(), "hello");
+        assert!(matches!(client.get(&url).send().await, Err(ProtocolError::ClientClosed)));
+
+        // An unread body keeps the request in flight.
+        let client = Client::new();
+        let res = client.get(&url).send().await.unwrap();
+        assert!(!client.shutdown(Duration::from_millis(10)).await);
+        drop(res);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_options() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        // Free a port, and only start listening on it after a while.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let make_svc = make_service_fn(|_| async {
+                Ok::<_, hyper::Error>(service_fn(|_| async {
+                    Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty()))
+                }))
+            });
+            hyper::Server::bind(&addr).serve(make_svc).await
+        });
+        let client = Client::builder()
+            .connect_retries(50, Duration::from_millis(10))
+            .tcp_nodelay(true)
+            .tcp_keepalive(Duration::from_secs(30))
+            .tcp_keepalive_interval(Duration::from_secs(5))
+            .tcp_keepalive_retries(3)
+            .send_buffer_size(64 * 1024)
+            .recv_buffer_size(64 * 1024)
+            .build();
+        let res = client.get(&format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let client = Client::builder().connect_retries(1, Duration::from_millis(1)).build();
+        assert!(client.get("http://127.0.0.1:1/").send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_resume() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        const CONTENT: &str = "hello world";
+        // Serves CONTENT, honoring `Range: bytes=N-`.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let start = req.headers().get(header::RANGE)
+                    .and_then(|v| v.to_str().ok()?.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok());
+                let res = match start {
+                    None => hyper::Response::new(hyper::Body::from(CONTENT)),
+                    Some(start) if start >= CONTENT.len() => hyper::Response::builder()
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .header(header::CONTENT_RANGE, format!("bytes */{}", CONTENT.len()))
+                        .body(hyper::Body::empty())
+                        .unwrap(),
+                    Some(start) => hyper::Response::builder()
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .header(header::CONTENT_RANGE, format!("bytes {start}-{}/{}", CONTENT.len() - 1, CONTENT.len()))
+                        .body(hyper::Body::from(&CONTENT[start..]))
+                        .unwrap(),
+                };
+                Ok::<_, hyper::Error>(res)
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let path = std::env::temp_dir().join(format!("httpclient-download-{}", std::process::id()));
+        let url = format!("http://{addr}/file");
+        let client = Client::new();
+        std::fs::write(&path, "hello ").unwrap();
+        assert_eq!(client.download(&url, &path).await.unwrap(), 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), CONTENT);
+        assert_eq!(client.download(&url, &path).await.unwrap(), 11);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), CONTENT);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http2_prior_knowledge() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|_| async {
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty()))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).http2_only(true).serve(make_svc);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let client = Client::builder().http2_prior_knowledge(true).build();
+        let res = client.get(&url).send().await.unwrap();
+        assert_eq!(res.version(), http::Version::HTTP_2);
+        assert!(Client::new().get(&url).send().await.is_err());
+    }
 }
@@ -0,0 +1,440 @@
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::client::{Connector, Hooks, HostProfile, IpPreference, Pool, Proxies, Proxy, APP_USER_AGENT};
+use crate::client::{ConnectionInfo, RedirectEvent, Resolver, RetryEvent, StaticResolver, SystemResolver};
+use crate::client::dns::DnsResolver;
+use crate::client::proxy::ProxyConnector;
+use crate::client::tcp::TcpOptions;
+use crate::client::tls::TlsOptions;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use crate::client::{tls::Backend, Certificate, Identity};
+use crate::{Client, DecompressionLimits, InMemoryRequest, Response};
+
+/// Configure the transport-level settings of a [`Client`].
+/// Use `Client::builder()` to get one, and `.build()` to finish.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    decompress: bool,
+    decompression_limits: DecompressionLimits,
+    expect_continue: Option<(u64, Duration)>,
+    max_body_size: Option<u64>,
+    default_headers: Vec<(String, String)>,
+    default_query: Vec<(String, String)>,
+    host_profiles: Vec<(String, HostProfile)>,
+    hooks: Hooks,
+    tcp: TcpOptions,
+    resolver: Option<Arc<dyn Resolver>>,
+    overrides: Option<StaticResolver>,
+    proxies: Proxies,
+    tls: TlsOptions,
+    http2_prior_knowledge: bool,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            connect_timeout: None,
+            read_timeout: None,
+            decompress: true,
+            decompression_limits: DecompressionLimits::default(),
+            expect_continue: None,
+            max_body_size: None,
+            default_headers: vec![("User-Agent".to_string(), APP_USER_AGENT.to_string())],
+            default_query: Vec::new(),
+            host_profiles: Vec::new(),
+            hooks: Hooks::default(),
+            tcp: TcpOptions::default(),
+            resolver: None,
+            overrides: None,
+            proxies: Proxies::default(),
+            tls: TlsOptions::default(),
+            http2_prior_knowledge: false,
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total time allowed for a request, from the start of the middleware chain until the response
+    /// headers are received. This includes any retries and redirects performed by middleware.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Time allowed to establish the TCP connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Retry a failed connection attempt up to `retries` times, waiting `delay` before each, e.g. to ride out a
+    /// server restarting. Only connecting is retried, so unlike `Retry` it's safe for any request.
+    pub fn connect_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.tcp.connect_retries = Some((retries, delay));
+        self
+    }
+
+    /// Which IP family to connect over when a host has both IPv4 and IPv6 addresses. See [`IpPreference`].
+    pub fn ip_preference(mut self, preference: IpPreference) -> Self {
+        self.tcp.ip_preference = preference;
+        self
+    }
+
+    /// How long to wait for a connection over the preferred IP family before racing one over the other, as
+    /// RFC 8305 describes. 250ms by default; `None` tries each address in turn instead.
+    pub fn happy_eyeballs_delay(mut self, delay: Option<Duration>) -> Self {
+        self.tcp.happy_eyeballs_delay = delay;
+        self
+    }
+
+    /// Resolve hostnames with `resolver` rather than the system resolver. See [`Resolver`].
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Connect to `addr` for `host`, whatever it resolves to, like curl's `--resolve`. Call it again to give a host
+    /// more addresses. The URL, `Host` header, and TLS server name are unchanged.
+    pub fn resolve(mut self, host: &str, addr: IpAddr) -> Self {
+        self.overrides = Some(self.overrides.take().unwrap_or_default().host(host, [addr]));
+        self
+    }
+
+    /// Send requests through `proxy`. See [`Proxy`].
+    pub fn proxy(self, proxy: Proxy) -> Self {
+        self.proxies(proxy.into())
+    }
+
+    /// Choose a proxy for each request by its URL, e.g. with `Proxies::from_env()`. See [`Proxies`].
+    pub fn proxies(mut self, proxies: Proxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Negotiate HTTP/2 with servers which support it, over TLS with ALPN, sending concurrent requests to a host over
+    /// one connection. On by default; `Response::version` tells which version a request used.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.tls.disable_http2 = !enabled;
+        self
+    }
+
+    /// Speak HTTP/2 from the start, without negotiating it, including over plain HTTP (h2c). Only for servers known to
+    /// support it: the rest will fail.
+    pub fn http2_prior_knowledge(mut self, enabled: bool) -> Self {
+        self.http2_prior_knowledge = enabled;
+        #[cfg(any(feature = "rustls", feature = "native-tls"))]
+        {
+            self.tls.http2_only = enabled;
+        }
+        self
+    }
+
+    /// Make HTTPS connections with `config` rather than rustls' safe defaults, e.g. to pin certificates or restrict
+    /// cipher suites. Its ALPN protocols are replaced by the HTTP versions the client speaks. Root certificates set
+    /// on the builder don't apply to it.
+    #[cfg(feature = "rustls")]
+    pub fn tls_config(mut self, config: rustls::ClientConfig) -> Self {
+        self.tls.backend = Backend::Rustls(Some(Arc::new(config)));
+        self
+    }
+
+    /// Make HTTPS connections with the platform's TLS library (OpenSSL, Secure Transport, or SChannel) rather than
+    /// rustls, with its defaults.
+    #[cfg(feature = "native-tls")]
+    pub fn use_native_tls(mut self) -> Self {
+        self.tls.backend = Backend::NativeTls(None);
+        self
+    }
+
+    /// Make HTTPS connections with the platform's TLS library, configured by `connector`. Root certificates,
+    /// identities, and HTTP versions set on the builder don't apply to it; for HTTP/2, have it request the `h2` ALPN
+    /// protocol.
+    #[cfg(feature = "native-tls")]
+    pub fn native_tls_config(mut self, connector: native_tls::TlsConnector) -> Self {
+        self.tls.backend = Backend::NativeTls(Some(connector.into()));
+        self
+    }
+
+    /// Trust `cert` as a root certificate, as well as the platform's, e.g. for servers with certificates from an
+    /// internal CA. See [`Certificate`].
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn add_root_certificate(mut self, cert: Certificate) -> Self {
+        self.tls.roots.push(cert);
+        self
+    }
+
+    /// Trust the platform's root certificates, from its trust store. On by default; turn it off to trust only the
+    /// certificates added with `add_root_certificate`.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn tls_native_roots(mut self, enabled: bool) -> Self {
+        self.tls.disable_native_roots = !enabled;
+        self
+    }
+
+    /// Accept any certificate the server presents, even if it's expired, self-signed, or for another host. This makes
+    /// TLS open to man-in-the-middle attacks: only use it to test against development servers. Off by default.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Accept certificates for other hosts, as long as they're otherwise valid. Like `danger_accept_invalid_certs`,
+    /// only use it in development. Off by default.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn danger_accept_invalid_hostnames(mut self, accept: bool) -> Self {
+        self.tls.accept_invalid_hostnames = accept;
+        self
+    }
+
+    /// Connect to `host`, as it appears in URLs, as if it were `name`: send `name` for SNI, and verify the server's
+    /// certificate for it, e.g. to reach one server behind a load balancer by its IP address. Only TLS is affected;
+    /// set the `Host` header too if the server needs it. Identities are chosen by `name`.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn tls_server_name(mut self, host: &str, name: &str) -> Self {
+        self.tls.server_names.push((host.to_string(), name.to_string()));
+        self
+    }
+
+    /// Append TLS session keys to the file named by the `SSLKEYLOGFILE` environment variable, if it's set, so captured
+    /// traffic can be decrypted with Wireshark. Anyone with the file can read the traffic, so only use it to debug.
+    /// Off by default, and only supported by rustls.
+    #[cfg(feature = "rustls")]
+    pub fn tls_keylog(mut self, enabled: bool) -> Self {
+        self.tls.keylog = enabled;
+        self
+    }
+
+    /// Present `identity` to servers which ask for a client certificate (mutual TLS). See [`Identity`].
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.tls.identity = Some(identity);
+        self
+    }
+
+    /// Present `identity` to `host` instead of the client's identity, e.g. when one API needs its own certificate.
+    /// `host` can be a wildcard like `*.example.com`; if several match a host, the first registered is used.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn host_identity(mut self, host: &str, identity: Identity) -> Self {
+        self.tls.host_identities.push((host.to_string(), identity));
+        self
+    }
+
+    /// Set `TCP_NODELAY`, sending small writes immediately rather than batching them (Nagle's algorithm). Off by
+    /// default.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp.nodelay = nodelay;
+        self
+    }
+
+    /// Enable TCP keepalive (`SO_KEEPALIVE`), probing connections which have been idle for `time`, so dead peers are
+    /// noticed and middleboxes don't drop long-lived connections.
+    pub fn tcp_keepalive(mut self, time: Duration) -> Self {
+        self.tcp.keepalive = Some(time);
+        self
+    }
+
+    /// Time between keepalive probes, once `tcp_keepalive` is on. Ignored on platforms other than Linux, Android,
+    /// macOS, iOS, FreeBSD, and NetBSD.
+    pub fn tcp_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.tcp.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Number of unanswered keepalive probes before the connection is dropped, once `tcp_keepalive` is on. Ignored
+    /// on the same platforms as `tcp_keepalive_interval`.
+    pub fn tcp_keepalive_retries(mut self, retries: u32) -> Self {
+        self.tcp.keepalive_retries = Some(retries);
+        self
+    }
+
+    /// Set the socket's send buffer size (`SO_SNDBUF`), in bytes.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.tcp.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the socket's receive buffer size (`SO_RCVBUF`), in bytes.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.tcp.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Time allowed for a single attempt to receive response headers once it's been sent to the connection pool.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Send `Accept-Encoding` and transparently decompress responses, removing the Content-Encoding and
+    /// Content-Length headers. On by default; supports the codings in [`crate::ContentCoding::ALL`].
+    /// If the request sets its own Accept-Encoding, that's sent instead.
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.decompress = decompress;
+        self
+    }
+
+    /// Limit how large decompressed responses can get. See [`DecompressionLimits`] for the defaults.
+    pub fn decompression_limits(mut self, limits: DecompressionLimits) -> Self {
+        self.decompression_limits = limits;
+        self
+    }
+
+    /// Fail with `ProtocolError::BodyTooLarge` when a response body, after decompression, is larger than
+    /// `max_size` bytes, rather than buffer it into memory. This also applies to streamed bodies.
+    pub fn max_body_size(mut self, max_size: u64) -> Self {
+        self.max_body_size = Some(max_size);
+        self
+    }
+
+    /// Send `Expect: 100-continue` with bodies of at least `min_size` bytes, and hold the body back for up to
+    /// `timeout`, so a server which rejects the request (e.g. for failed auth) can answer before it's uploaded.
+    /// hyper doesn't report the interim 100 Continue, so accepted uploads wait out the whole timeout; keep it short.
+    pub fn expect_continue(mut self, min_size: u64, timeout: Duration) -> Self {
+        self.expect_continue = Some((min_size, timeout));
+        self
+    }
+
+    /// Send a header with every request, unless the request sets it itself. Replaces any earlier default of the same
+    /// name, including the `User-Agent`.
+    pub fn default_header<S: AsRef<str>>(mut self, key: S, value: S) -> Self {
+        let key = key.as_ref();
+        self.default_headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.default_headers.push((key.to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    /// Add a query parameter to every request which doesn't set it itself, e.g. an API key. Replaces any earlier
+    /// default of the same name.
+    pub fn default_query<S: AsRef<str>>(mut self, key: S, value: S) -> Self {
+        let key = key.as_ref();
+        self.default_query.retain(|(k, _)| k != key);
+        self.default_query.push((key.to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    /// Apply `profile` to requests to `host`, which can be a wildcard like `*.example.com`. If several profiles
+    /// match a host, the first registered is used.
+    pub fn host_profile(mut self, host: &str, profile: HostProfile) -> Self {
+        self.host_profiles.push((host.to_string(), profile));
+        self
+    }
+
+    /// Run `hook` before each request enters the middleware chain. Hooks are lighter than a [`crate::Middleware`] when
+    /// you only need to watch: they can't change the request, and each one is awaited in turn, in the order they were
+    /// added. The returned future must be `'static`, so copy what it needs out of the request first.
+    /// ```
+    /// use httpclient::Client;
+    /// let client = Client::builder()
+    ///     .on_request_start(|request| {
+    ///         let url = request.url().clone();
+    ///         async move { println!("starting {url}") }
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_request_start<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&InMemoryRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_request_start(hook);
+        self
+    }
+
+    /// Run `hook` when a request gets its response head back from the middleware chain.
+    pub fn on_response<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_response(hook);
+        self
+    }
+
+    /// Run `hook` when `Retry` is about to retry a request, before it waits.
+    pub fn on_retry<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_retry(hook);
+        self
+    }
+
+    /// Run `hook` when `Follow` is about to follow a redirect.
+    pub fn on_redirect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&RedirectEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_redirect(hook);
+        self
+    }
+
+    /// Run `hook` when a new connection is opened, after any TLS handshake and before it's used.
+    pub fn on_connection_open<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_connection_open(hook);
+        self
+    }
+
+    /// Run `hook` when the pool discards a connection, e.g. because it went idle for too long or the server closed
+    /// it. The hook runs in a spawned task, so it's skipped if the connection is dropped outside a Tokio runtime.
+    pub fn on_connection_close<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(&ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.hooks.on_connection_close(hook);
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let mut resolver = self.resolver.unwrap_or_else(|| Arc::new(SystemResolver));
+        if let Some(overrides) = self.overrides {
+            resolver = Arc::new(overrides.fallback_arc(resolver));
+        }
+        let resolver = DnsResolver::new(resolver, self.tcp.ip_preference);
+        let http = self.tcp.http_connector(resolver.clone(), self.connect_timeout);
+        let hooks = Arc::new(self.hooks);
+        let pool = Arc::new(Pool::default());
+        let proxy = ProxyConnector::new(http, resolver, self.proxies);
+        let connector = Connector::new(proxy, self.tls.build(), hooks.clone(), pool.clone(), self.tcp);
+        let mut hyper_builder = hyper::Client::builder();
+        hyper_builder.http2_only(self.http2_prior_knowledge);
+        Client {
+            base_url: None,
+            default_headers: self.default_headers,
+            default_query: self.default_query,
+            host_profiles: self.host_profiles,
+            middlewares: Vec::new(),
+            timeout: self.timeout,
+            read_timeout: self.read_timeout,
+            decompress: self.decompress,
+            decompression_limits: self.decompression_limits,
+            expect_continue: self.expect_continue,
+            max_body_size: self.max_body_size,
+            inner: Arc::new(RwLock::new(hyper_builder.build(connector.clone()))),
+            hyper_builder,
+            connector,
+            proxied: Arc::default(),
+            hooks,
+            pool,
+        }
+    }
+}
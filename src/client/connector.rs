@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use http::uri::Scheme;
+use hyper::client::connect::{Connected, Connection};
+use hyper::service::Service;
+use hyper::Uri;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::client::hooks::{ConnectionInfo, Hooks};
+use crate::client::pool::{HostCounters, Pool};
+use crate::client::proxy::{Proxy, ProxyConnector};
+use crate::client::tcp::TcpOptions;
+use crate::client::tls::{MaybeTlsStream, Tls};
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+use crate::client::tls::TlsOptions;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type WarmConns = HashMap<String, Vec<(Instant, Conn)>>;
+
+/// How long a warmed-up connection waits to be used before it's closed, like hyper's idle pool timeout.
+const WARM_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Opens connections for the pool, through a proxy if need be and with TLS for HTTPS, running the connection hooks as
+/// they open and close, and counting them and their traffic for `Client::pool_stats`.
+///
+/// hyper can't be handed a connection for its pool, so connections opened by `warm_up` wait here, and are given to
+/// hyper the next time it connects to their origin.
+#[derive(Clone)]
+pub(crate) struct Connector {
+    proxy: ProxyConnector,
+    /// Shared with the connectors for other proxies, so `update_tls` reaches them all.
+    tls: Arc<RwLock<Tls>>,
+    hooks: Arc<Hooks>,
+    pool: Arc<Pool>,
+    tcp: TcpOptions,
+    /// Warmed-up connections and when they were opened, by origin.
+    warm: Arc<Mutex<WarmConns>>,
+}
+
+impl Connector {
+    pub fn new(proxy: ProxyConnector, tls: Tls, hooks: Arc<Hooks>, pool: Arc<Pool>, tcp: TcpOptions) -> Self {
+        let tls = Arc::new(RwLock::new(tls));
+        Connector { proxy, tls, hooks, pool, tcp, warm: Arc::default() }
+    }
+
+    /// A connector like this one, but connecting through `proxy`, for a separate pool.
+    pub fn with_proxy(&self, proxy: Proxy) -> Self {
+        let proxy = self.proxy.with_proxies(proxy.into());
+        Connector { proxy, warm: Arc::default(), ..self.clone() }
+    }
+
+    /// Change the TLS settings for new connections.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn update_tls(&self, update: impl FnOnce(&mut TlsOptions)) {
+        let mut tls = self.tls.write().unwrap();
+        *tls = tls.rebuild(update);
+    }
+
+    /// The proxy the connector uses for `uri`, if any.
+    pub fn proxy_for(&self, uri: &Uri) -> Option<&Proxy> {
+        self.proxy.proxies().for_uri(uri)
+    }
+
+    /// Open a connection to the origin of `uri`, and keep it for the next request there.
+    pub async fn warm_up(&self, uri: Uri) -> io::Result<()> {
+        let conn = self.clone().connect(uri.clone()).await.map_err(into_io)?;
+        self.warm.lock().unwrap().entry(origin(&uri)).or_default().push((Instant::now(), conn));
+        Ok(())
+    }
+
+    /// Open a tunnel to `host` and `port` through the proxy the client uses for HTTPS there, or connect directly
+    /// if there isn't one.
+    pub async fn tunnel(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let host = if host.contains(':') && !host.starts_with('[') { format!("[{host}]") } else { host.to_string() };
+        let uri = Uri::try_from(format!("https://{host}:{port}")).map_err(io::Error::other)?;
+        let tcp = self.proxy.clone().call(uri).await.map_err(into_io)?;
+        self.tcp.configure(&tcp)?;
+        Ok(tcp)
+    }
+
+    pub fn close_warm(&self) {
+        self.warm.lock().unwrap().clear();
+    }
+
+    /// A warmed-up connection to the origin of `uri` that's still open, if there is one.
+    fn take_warm(&self, uri: &Uri) -> Option<Conn> {
+        let mut warm = self.warm.lock().unwrap();
+        let conns = warm.get_mut(&origin(uri))?;
+        while let Some((opened, mut conn)) = conns.pop() {
+            if opened.elapsed() < WARM_TIMEOUT && conn.is_open() {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    fn connect(&mut self, uri: Uri) -> Pin<Box<dyn Future<Output=Result<Conn, BoxError>> + Send>> {
+        let mut proxy = self.proxy.clone();
+        let tls = self.tls.read().unwrap().clone();
+        let hooks = self.hooks.clone();
+        let counters = self.pool.host(uri.authority().map_or("", |authority| authority.as_str()));
+        let tcp = self.tcp.clone();
+        let https = match uri.scheme() {
+            Some(scheme) if *scheme == Scheme::HTTPS => true,
+            Some(scheme) if *scheme == Scheme::HTTP => false,
+            _ => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported scheme in {uri}"));
+                return Box::pin(futures::future::ready(Err(e.into())));
+            }
+        };
+        // hyper needs to know to send the whole URL to a proxy forwarding plain HTTP.
+        let forwarded = !https && self.proxy_for(&uri).is_some_and(Proxy::forwards);
+        Box::pin(async move {
+            let mut retries = tcp.connect_retries;
+            let stream = loop {
+                match proxy.call(uri.clone()).await {
+                    Ok(stream) => break stream,
+                    Err(e) => match &mut retries {
+                        Some((remaining, delay)) if *remaining > 0 => {
+                            *remaining -= 1;
+                            tokio::time::sleep(*delay).await;
+                        }
+                        _ => return Err(e),
+                    },
+                }
+            };
+            tcp.configure(&stream)?;
+            let info = ConnectionInfo { uri, remote_addr: stream.peer_addr().ok() };
+            // The URI keeps the brackets around IPv6 addresses, but they aren't part of the server name.
+            let host = info.uri.host().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+            let stream = match https {
+                true => tls.connect(host, stream).await?,
+                false => MaybeTlsStream::Plain(stream),
+            };
+            hooks.connection_open(&info).await;
+            counters.opened();
+            Ok(Conn { stream, forwarded, hooks, info, counters })
+        })
+    }
+}
+
+fn into_io(e: BoxError) -> io::Error {
+    match e.downcast::<io::Error>() {
+        Ok(e) => *e,
+        Err(e) => io::Error::other(e),
+    }
+}
+
+fn origin(uri: &Uri) -> String {
+    format!("{}://{}", uri.scheme_str().unwrap_or("http"), uri.authority().map_or("", |authority| authority.as_str()))
+}
+
+impl Service<Uri> for Connector {
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output=Result<Conn, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.proxy.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self.take_warm(&uri) {
+            Some(conn) => Box::pin(futures::future::ready(Ok(conn))),
+            None => self.connect(uri),
+        }
+    }
+}
+
+/// A pooled connection. Dropping it, when the pool discards it, runs the connection close hooks.
+pub(crate) struct Conn {
+    stream: MaybeTlsStream,
+    /// Whether requests are forwarded by a proxy.
+    forwarded: bool,
+    hooks: Arc<Hooks>,
+    info: ConnectionInfo,
+    counters: Arc<HostCounters>,
+}
+
+impl Conn {
+    /// Whether an idle connection can still be used: the server hasn't closed it, or sent anything unprompted.
+    fn is_open(&mut self) -> bool {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut byte = [0];
+        let mut buf = ReadBuf::new(&mut byte);
+        Pin::new(&mut self.stream).poll_read(&mut cx, &mut buf).is_pending()
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        self.counters.closed();
+        if !self.hooks.observes_connection_close() {
+            return;
+        }
+        // The pool may drop connections outside of a runtime, e.g. when it's dropped at exit.
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let hooks = self.hooks.clone();
+            let info = self.info.clone();
+            runtime.spawn(async move { hooks.connection_close(&info).await });
+        }
+    }
+}
+
+impl Connection for Conn {
+    fn connected(&self) -> Connected {
+        self.stream.connected().proxy(self.forwarded)
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.stream).poll_read(cx, buf);
+        self.counters.read(buf.filled().len() - filled);
+        poll
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.counters.written(written);
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.stream).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.counters.written(written);
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::client::connect::dns::Name;
+use hyper::service::Service;
+
+pub use cache::CachingResolver;
+
+mod cache;
+
+/// Looks up the addresses of a host for the client's connections. Implement it to resolve with another library,
+/// like hickory-dns, or through a service-discovery system, and set it with `ClientBuilder::resolver`.
+///
+/// It isn't called for URLs with an IP address for a host.
+/// ```
+/// use std::net::IpAddr;
+/// use httpclient::{Client, Resolver};
+/// #[derive(Debug)]
+/// struct Consul;
+/// #[async_trait::async_trait]
+/// impl Resolver for Consul {
+///     async fn resolve(&self, host: &str) -> std::io::Result<Vec<IpAddr>> {
+///         // Look up `host` in the service catalog.
+///         Ok(vec![[10, 0, 0, 1].into()])
+///     }
+/// }
+/// let client = Client::builder().resolver(Consul).build();
+/// ```
+#[async_trait]
+pub trait Resolver: Send + Sync + Debug {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+
+    /// Resolve `host`, along with how long the answer can be cached, if the resolver knows, e.g. from the DNS
+    /// record's TTL. Used by [`CachingResolver`]; by default, the TTL isn't known.
+    async fn resolve_with_ttl(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+        Ok((self.resolve(host).await?, None))
+    }
+}
+
+#[async_trait]
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        (**self).resolve(host).await
+    }
+
+    async fn resolve_with_ttl(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+        (**self).resolve_with_ttl(host).await
+    }
+}
+
+/// The operating system's resolver (`getaddrinfo`), used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(tokio::net::lookup_host((host, 0)).await?.map(|addr| addr.ip()).collect())
+    }
+}
+
+/// Answers for some hosts with fixed addresses, like curl's `--resolve`, and passes the rest on to another
+/// resolver, by default the [`SystemResolver`]. `ClientBuilder::resolve` adds hosts to one for you.
+#[derive(Debug, Clone)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Arc<dyn Resolver>,
+}
+
+impl Default for StaticResolver {
+    fn default() -> Self {
+        StaticResolver { hosts: HashMap::new(), fallback: Arc::new(SystemResolver) }
+    }
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve `host` to `addrs`. Hosts are matched ignoring case.
+    pub fn host(mut self, host: &str, addrs: impl IntoIterator<Item=IpAddr>) -> Self {
+        self.hosts.entry(host.to_ascii_lowercase()).or_default().extend(addrs);
+        self
+    }
+
+    /// Resolve hosts without fixed addresses with `resolver`.
+    pub fn fallback(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.fallback = Arc::new(resolver);
+        self
+    }
+
+    pub(crate) fn fallback_arc(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.fallback = resolver;
+        self
+    }
+}
+
+#[async_trait]
+impl Resolver for StaticResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        match self.hosts.get(&host.to_ascii_lowercase()) {
+            Some(addrs) => Ok(addrs.clone()),
+            None => self.fallback.resolve(host).await,
+        }
+    }
+
+    async fn resolve_with_ttl(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+        match self.hosts.get(&host.to_ascii_lowercase()) {
+            Some(addrs) => Ok((addrs.clone(), None)),
+            None => self.fallback.resolve_with_ttl(host).await,
+        }
+    }
+}
+
+/// Which IP family to connect over when a host has both IPv4 and IPv6 addresses.
+///
+/// Connections are made as RFC 8305 ("Happy Eyeballs") describes: the preferred family is tried first, and if it
+/// hasn't connected within `ClientBuilder::happy_eyeballs_delay`, the other is raced against it, so a broken IPv6
+/// path costs a fraction of a second rather than a full connect timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Prefer whichever family the system resolver lists first, usually IPv6 where it's available (RFC 6724).
+    #[default]
+    System,
+    /// Try IPv6 first, falling back to IPv4.
+    Ipv6,
+    /// Try IPv4 first, falling back to IPv6.
+    Ipv4,
+    /// Only connect over IPv6.
+    Ipv6Only,
+    /// Only connect over IPv4.
+    Ipv4Only,
+}
+
+impl IpPreference {
+    /// Order or filter resolved addresses. The order within each family is kept.
+    pub(crate) fn apply(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpPreference::System => {}
+            IpPreference::Ipv6 => addrs.sort_by_key(|addr| addr.is_ipv4()),
+            IpPreference::Ipv4 => addrs.sort_by_key(|addr| addr.is_ipv6()),
+            IpPreference::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+            IpPreference::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+        }
+        addrs
+    }
+}
+
+/// Adapts a [`Resolver`] for hyper, ordering the addresses by the client's `IpPreference`.
+#[derive(Debug, Clone)]
+pub(crate) struct DnsResolver {
+    resolver: Arc<dyn Resolver>,
+    preference: IpPreference,
+}
+
+impl DnsResolver {
+    pub fn new(resolver: Arc<dyn Resolver>, preference: IpPreference) -> Self {
+        DnsResolver { resolver, preference }
+    }
+}
+
+impl Service<Name> for DnsResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.resolver.clone();
+        let preference = self.preference;
+        async move {
+            let addrs = resolver.resolve(name.as_str()).await?;
+            // hyper fills in the port.
+            let addrs = preference.apply(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect());
+            if addrs.is_empty() {
+                let message = format!("{} has no addresses allowed by {:?}", name, preference);
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+            Ok(addrs.into_iter())
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
+
+    use crate::{Client, ResponseExt};
+
+    use super::*;
+
+    #[test]
+    fn test_ip_preference() {
+        let addrs: Vec<SocketAddr> = ["[::1]:0", "127.0.0.1:0", "[::2]:0", "127.0.0.2:0"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let ordered = |preference: IpPreference| {
+            preference.apply(addrs.clone()).iter().map(|addr| addr.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(ordered(IpPreference::System), ["[::1]:0", "127.0.0.1:0", "[::2]:0", "127.0.0.2:0"]);
+        assert_eq!(ordered(IpPreference::Ipv6), ["[::1]:0", "[::2]:0", "127.0.0.1:0", "127.0.0.2:0"]);
+        assert_eq!(ordered(IpPreference::Ipv4), ["127.0.0.1:0", "127.0.0.2:0", "[::1]:0", "[::2]:0"]);
+        assert_eq!(ordered(IpPreference::Ipv6Only), ["[::1]:0", "[::2]:0"]);
+        assert_eq!(ordered(IpPreference::Ipv4Only), ["127.0.0.1:0", "127.0.0.2:0"]);
+    }
+
+    #[tokio::test]
+    async fn test_ipv4_only() {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|_| async {
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty()))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://localhost:{}/", server.local_addr().port());
+        tokio::spawn(server);
+
+        let client = Client::builder().ip_preference(IpPreference::Ipv4Only).build();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+        let client = Client::builder().ip_preference(IpPreference::Ipv6Only).build();
+        assert!(client.get(&url).send().await.is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct Counting(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl Resolver for Counting {
+        async fn resolve(&self, _host: &str) -> io::Result<Vec<IpAddr>> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(vec![[127, 0, 0, 1].into()])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolver() {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let host = req.headers()[hyper::header::HOST].to_str().unwrap().to_string();
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(host)))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let port = server.local_addr().port();
+        tokio::spawn(server);
+
+        let client = Client::builder().resolve("API.example.test", [127, 0, 0, 1].into()).build();
+        let res = client.get(&format!("http://api.example.test:{port}/")).send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), format!("api.example.test:{port}"));
+
+        let resolver = Arc::new(Counting::default());
+        let client = Client::builder().resolver(resolver.clone()).build();
+        client.get(&format!("http://service.internal:{port}/")).send().await.unwrap();
+        assert_eq!(resolver.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::client::dns::{Resolver, SystemResolver};
+
+/// A cached lookup: the addresses, or the kind and message of the error, since `io::Error` can't be cloned.
+#[derive(Debug, Clone)]
+struct Entry {
+    result: Result<Vec<IpAddr>, (io::ErrorKind, String)>,
+    expires: Instant,
+}
+
+/// Caches another resolver's answers, so busy clients don't resolve a host for every new connection.
+///
+/// Answers are kept for their TTL when the resolver reports one (see `Resolver::resolve_with_ttl`), and for
+/// `default_ttl` otherwise, clamped between `min_ttl` and `max_ttl`. Failed lookups are kept for `negative_ttl`, so a
+/// missing host isn't looked up again for every request.
+///
+/// Clones share the cache, so keep one to `invalidate` hosts after giving it to the client.
+/// ```
+/// use std::time::Duration;
+/// use httpclient::{CachingResolver, Client};
+/// let cache = CachingResolver::system().min_ttl(Duration::from_secs(5));
+/// let client = Client::builder().resolver(cache.clone()).build();
+/// // After a failover:
+/// cache.invalidate("api.example.com");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CachingResolver {
+    resolver: Arc<dyn Resolver>,
+    default_ttl: Duration,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl CachingResolver {
+    pub fn new(resolver: impl Resolver + 'static) -> Self {
+        CachingResolver {
+            resolver: Arc::new(resolver),
+            default_ttl: Duration::from_secs(60),
+            min_ttl: Duration::ZERO,
+            max_ttl: Duration::from_secs(60 * 60),
+            negative_ttl: Duration::from_secs(5),
+            entries: Arc::default(),
+        }
+    }
+
+    /// Cache the system resolver, which doesn't report TTLs.
+    pub fn system() -> Self {
+        Self::new(SystemResolver)
+    }
+
+    /// How long to keep answers without a TTL. 60s by default.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Keep answers for at least `ttl`, even if their TTL is shorter. Zero by default.
+    pub fn min_ttl(mut self, ttl: Duration) -> Self {
+        self.min_ttl = ttl;
+        self
+    }
+
+    /// Keep answers for at most `ttl`, even if their TTL is longer. An hour by default.
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    /// How long to keep failed lookups. 5s by default; zero doesn't cache them.
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// Forget the cached answer for `host`, so it's resolved again on the next connection.
+    pub fn invalidate(&self, host: &str) {
+        self.entries.lock().unwrap().remove(&host.to_ascii_lowercase());
+    }
+
+    /// Forget every cached answer.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn cached(&self, host: &str) -> Option<Entry> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(host) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.clone()),
+            Some(_) => {
+                entries.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn lookup(&self, host: &str) -> Entry {
+        let (result, ttl) = match self.resolver.resolve_with_ttl(host).await {
+            Ok((addrs, ttl)) => {
+                let ttl = ttl.unwrap_or(self.default_ttl).clamp(self.min_ttl, self.max_ttl.max(self.min_ttl));
+                (Ok(addrs), ttl)
+            }
+            Err(e) => (Err((e.kind(), e.to_string())), self.negative_ttl),
+        };
+        Entry { result, expires: Instant::now() + ttl }
+    }
+}
+
+#[async_trait]
+impl Resolver for CachingResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(self.resolve_with_ttl(host).await?.0)
+    }
+
+    async fn resolve_with_ttl(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+        let host = host.to_ascii_lowercase();
+        let entry = match self.cached(&host) {
+            Some(entry) => entry,
+            None => {
+                let entry = self.lookup(&host).await;
+                if entry.expires > Instant::now() {
+                    self.entries.lock().unwrap().insert(host, entry.clone());
+                }
+                entry
+            }
+        };
+        let ttl = entry.expires.saturating_duration_since(Instant::now());
+        match entry.result {
+            Ok(addrs) => Ok((addrs, Some(ttl))),
+            Err((kind, message)) => Err(io::Error::new(kind, message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Answers `up.test` with a 20ms TTL, and fails anything else, counting lookups.
+    #[derive(Debug, Default)]
+    struct Counting(AtomicUsize);
+
+    #[async_trait]
+    impl Resolver for Counting {
+        async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+            Ok(self.resolve_with_ttl(host).await?.0)
+        }
+
+        async fn resolve_with_ttl(&self, host: &str) -> io::Result<(Vec<IpAddr>, Option<Duration>)> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match host {
+                "up.test" => Ok((vec![[127, 0, 0, 1].into()], Some(Duration::from_millis(20)))),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_caching_resolver() {
+        let counting = Arc::new(Counting::default());
+        let lookups = || counting.0.load(Ordering::SeqCst);
+        let cache = CachingResolver::new(counting.clone()).negative_ttl(Duration::from_secs(60));
+
+        assert_eq!(cache.resolve("up.test").await.unwrap(), [IpAddr::from([127, 0, 0, 1])]);
+        cache.resolve("UP.test").await.unwrap();
+        assert_eq!(lookups(), 1);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        cache.resolve("up.test").await.unwrap();
+        assert_eq!(lookups(), 2);
+        cache.invalidate("up.test");
+        cache.resolve("up.test").await.unwrap();
+        assert_eq!(lookups(), 3);
+
+        for _ in 0..2 {
+            let e = cache.resolve("down.test").await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        }
+        assert_eq!(lookups(), 4);
+
+        // The floor keeps the short TTL from expiring.
+        let cache = CachingResolver::new(counting.clone()).min_ttl(Duration::from_secs(60));
+        cache.resolve("up.test").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let (_, ttl) = cache.resolve_with_ttl("up.test").await.unwrap();
+        assert_eq!(lookups(), 5);
+        assert!(ttl.unwrap() > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::{Method, StatusCode, Uri};
+
+use crate::{InMemoryRequest, Response};
+
+type Hook<T> = Arc<dyn Fn(&T) -> BoxFuture<'static, ()> + Send + Sync>;
+
+fn hook<T, F, Fut>(hook: F) -> Hook<T>
+where
+    F: Fn(&T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output=()> + Send + 'static,
+{
+    Arc::new(move |event| hook(event).boxed())
+}
+
+/// A retry `Retry` is about to make, after waiting `delay`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryEvent {
+    pub method: Method,
+    pub url: Uri,
+    /// The attempt which failed, starting from 1.
+    pub attempt: u32,
+    pub delay: Duration,
+    /// The status of the failed attempt, if it got a response.
+    pub status: Option<StatusCode>,
+    /// The error of the failed attempt, if it didn't.
+    pub error: Option<String>,
+}
+
+/// A redirect `Follow` is about to follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectEvent {
+    pub from: Uri,
+    pub to: Uri,
+    pub status: StatusCode,
+}
+
+/// A connection to a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The scheme and authority the connection was made for.
+    pub uri: Uri,
+    pub remote_addr: Option<SocketAddr>,
+}
+
+/// Callbacks registered with `ClientBuilder`, run in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    request_start: Vec<Hook<InMemoryRequest>>,
+    response: Vec<Hook<Response>>,
+    retry: Vec<Hook<RetryEvent>>,
+    redirect: Vec<Hook<RedirectEvent>>,
+    connection_open: Vec<Hook<ConnectionInfo>>,
+    connection_close: Vec<Hook<ConnectionInfo>>,
+}
+
+impl Hooks {
+    pub fn on_request_start<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&InMemoryRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.request_start.push(hook(f));
+    }
+
+    pub fn on_response<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&Response) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.response.push(hook(f));
+    }
+
+    pub fn on_retry<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&RetryEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.retry.push(hook(f));
+    }
+
+    pub fn on_redirect<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&RedirectEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.redirect.push(hook(f));
+    }
+
+    pub fn on_connection_open<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.connection_open.push(hook(f));
+    }
+
+    pub fn on_connection_close<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(&ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output=()> + Send + 'static,
+    {
+        self.connection_close.push(hook(f));
+    }
+
+    pub async fn request_start(&self, request: &InMemoryRequest) {
+        run(&self.request_start, request).await
+    }
+
+    pub async fn response(&self, response: &Response) {
+        run(&self.response, response).await
+    }
+
+    pub async fn retry(&self, event: &RetryEvent) {
+        run(&self.retry, event).await
+    }
+
+    pub async fn redirect(&self, event: &RedirectEvent) {
+        run(&self.redirect, event).await
+    }
+
+    pub async fn connection_open(&self, info: &ConnectionInfo) {
+        run(&self.connection_open, info).await
+    }
+
+    pub fn observes_connection_close(&self) -> bool {
+        !self.connection_close.is_empty()
+    }
+
+    pub async fn connection_close(&self, info: &ConnectionInfo) {
+        run(&self.connection_close, info).await
+    }
+}
+
+async fn run<T>(hooks: &[Hook<T>], event: &T) {
+    for hook in hooks {
+        hook(event).await;
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("request_start", &self.request_start.len())
+            .field("response", &self.response.len())
+            .field("retry", &self.retry.len())
+            .field("redirect", &self.redirect.len())
+            .field("connection_open", &self.connection_open.len())
+            .field("connection_close", &self.connection_close.len())
+            .finish()
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper::body::HttpBody;
+use tokio::sync::Notify;
+
+/// Connections and traffic for one host, as reported by `Client::pool_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostStats {
+    /// Open connections, whether in use or idle in the pool.
+    pub open: usize,
+    /// Requests waiting for a response, or whose response body is still being read.
+    pub active: usize,
+    /// Open connections not serving a request. An HTTP/2 connection can serve several at once, so with HTTP/2 this is
+    /// an estimate.
+    pub idle: usize,
+    /// Bytes read from the network, including headers and TLS overhead, over the life of the client.
+    pub bytes_read: u64,
+    /// Bytes written to the network, likewise.
+    pub bytes_written: u64,
+}
+
+impl std::ops::Add for HostStats {
+    type Output = HostStats;
+
+    fn add(self, other: HostStats) -> HostStats {
+        HostStats {
+            open: self.open + other.open,
+            active: self.active + other.active,
+            idle: self.idle + other.idle,
+            bytes_read: self.bytes_read + other.bytes_read,
+            bytes_written: self.bytes_written + other.bytes_written,
+        }
+    }
+}
+
+/// A snapshot of a client's connection pool, keyed by host and port (the URL's authority).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub hosts: HashMap<String, HostStats>,
+}
+
+impl PoolStats {
+    /// The stats summed over every host.
+    pub fn total(&self) -> HostStats {
+        self.hosts.values().fold(HostStats::default(), |total, host| total + *host)
+    }
+}
+
+/// Live counters for one host, shared by its connections and requests.
+#[derive(Debug, Default)]
+pub(crate) struct HostCounters {
+    open: AtomicUsize,
+    active: AtomicUsize,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl HostCounters {
+    pub fn opened(&self) {
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn closed(&self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn written(&self, bytes: usize) {
+        self.bytes_written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HostStats {
+        let open = self.open.load(Ordering::Relaxed);
+        let active = self.active.load(Ordering::Relaxed);
+        HostStats {
+            open,
+            active,
+            idle: open.saturating_sub(active),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a request as active until it's dropped.
+#[derive(Debug)]
+pub(crate) struct Active {
+    host: Arc<HostCounters>,
+    _in_flight: InFlight,
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.host.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a request as in flight until it's dropped, so `Client::shutdown` can wait for it.
+#[derive(Debug)]
+pub(crate) struct InFlight(Arc<Pool>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Pool {
+    hosts: Mutex<HashMap<String, Arc<HostCounters>>>,
+    closed: AtomicBool,
+    in_flight: AtomicUsize,
+    drained: Notify,
+}
+
+impl Pool {
+    pub fn host(&self, authority: &str) -> Arc<HostCounters> {
+        self.hosts.lock().unwrap().entry(authority.to_string()).or_default().clone()
+    }
+
+    fn in_flight(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.clone())
+    }
+
+    /// Start a request through the middleware, unless the client has been shut down.
+    pub fn begin(self: &Arc<Self>) -> Option<InFlight> {
+        let in_flight = self.in_flight();
+        // Checked after counting the request, so `shutdown` either sees it or it sees the client closed.
+        (!self.closed.load(Ordering::SeqCst)).then_some(in_flight)
+    }
+
+    /// Start sending a request to the network. This isn't refused after shutdown, since it's part of a request that
+    /// was already accepted, e.g. a retry.
+    pub fn start_request(self: &Arc<Self>, authority: &str) -> Active {
+        let host = self.host(authority);
+        host.active.fetch_add(1, Ordering::Relaxed);
+        Active { host, _in_flight: self.in_flight() }
+    }
+
+    /// Refuse new requests, and wait for those in flight to finish, including reading their bodies.
+    pub async fn drain(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        loop {
+            let drained = self.drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let hosts = self.hosts.lock().unwrap();
+        PoolStats {
+            hosts: hosts.iter().map(|(authority, host)| (authority.clone(), host.stats())).collect(),
+        }
+    }
+}
+
+/// Keep the request active until `body` has been read to the end, or dropped. Empty bodies are left as they are.
+pub(crate) fn hold_until_read(body: hyper::Body, active: Active) -> hyper::Body {
+    if HttpBody::size_hint(&body).exact() == Some(0) {
+        return body;
+    }
+    let stream = futures::stream::unfold(Some((body, active)), |state| async move {
+        let (mut body, active) = state?;
+        let chunk: Result<_, Box<dyn std::error::Error + Send + Sync>> = body.data().await?.map_err(Into::into);
+        Some((chunk, Some((body, active))))
+    });
+    hyper::Body::wrap_stream(stream)
+}
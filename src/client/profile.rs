@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::middleware::{Middleware, MiddlewareStack, RetryPolicy};
+use crate::{Proxy, Retry};
+
+/// Defaults for requests to one host, so a single client can talk to APIs with different requirements. Register
+/// them with `ClientBuilder::host_profile`.
+///
+/// The headers replace the client's defaults of the same name, and the timeout and proxy replace the client's.
+/// Middleware runs after the client's own, closer to the network.
+#[derive(Debug, Clone, Default)]
+pub struct HostProfile {
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) proxy: Option<Proxy>,
+    pub(crate) middlewares: MiddlewareStack,
+}
+
+impl HostProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn header<S: AsRef<str>>(mut self, key: S, value: S) -> Self {
+        self.headers.push((key.as_ref().to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send requests to this host through `proxy`, unless the request sets its own with `RequestBuilder::proxy`.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Retry requests to this host. Don't also give the client a `Retry`, or the two will multiply.
+    pub fn retry(self, policy: RetryPolicy) -> Self {
+        self.with_middleware(Retry::new().policy(policy))
+    }
+
+    pub fn with_middleware<T: Middleware + 'static>(mut self, middleware: T) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+}
+
+/// Whether `pattern`, either a host or a wildcard like `*.example.com`, matches `host`.
+pub(crate) fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.len() > domain.len()
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.',
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
@@ -0,0 +1,113 @@
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    /// A domain and its subdomains.
+    Domain(String),
+    /// An address block; a single address has the full prefix length.
+    Network(IpAddr, u8),
+}
+
+impl Rule {
+    fn parse(entry: &str) -> Option<Rule> {
+        let entry = entry.trim().trim_start_matches('.').trim_end_matches('.').to_ascii_lowercase();
+        if entry.is_empty() {
+            return None;
+        }
+        let (addr, prefix) = match entry.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (entry.as_str(), None),
+        };
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        let Ok(ip) = addr.parse::<IpAddr>() else {
+            return Some(Rule::Domain(entry));
+        };
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        match prefix.map(str::parse::<u8>) {
+            None => Some(Rule::Network(ip, max)),
+            Some(Ok(prefix)) if prefix <= max => Some(Rule::Network(ip, prefix)),
+            Some(_) => None,
+        }
+    }
+
+    fn matches(&self, host: &str, ip: Option<IpAddr>) -> bool {
+        match (self, ip) {
+            (Rule::Domain(domain), None) => {
+                host == domain || host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.'))
+            }
+            (Rule::Network(network, prefix), Some(ip)) => in_network(ip, *network, *prefix),
+            _ => false,
+        }
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => (u32::from(ip) as u128, u32::from(network) as u128, 32),
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = bits - prefix as u32;
+    shift == bits || ip >> shift == network >> shift
+}
+
+/// Hosts to connect to directly rather than through a proxy, as a `NO_PROXY` list, with curl's rules:
+///
+/// - Entries are separated by commas, and matched ignoring case.
+/// - A domain matches itself and its subdomains, so `example.com` and `.example.com` both match `example.com` and
+///   `api.example.com`.
+/// - An IP address matches itself, and a CIDR block like `10.0.0.0/8` or `fd00::/8` the addresses in it. They only
+///   match URLs with an IP address for a host; hostnames aren't resolved to check.
+/// - `*` on its own matches every host.
+///
+/// Ports are ignored.
+/// ```
+/// use httpclient::NoProxy;
+/// let no_proxy = NoProxy::parse("localhost, .internal, 10.0.0.0/8");
+/// assert!(no_proxy.matches("api.internal"));
+/// assert!(!no_proxy.matches("example.com"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoProxy {
+    all: bool,
+    rules: Vec<Rule>,
+}
+
+impl NoProxy {
+    pub fn parse(list: &str) -> Self {
+        let all = list.trim() == "*";
+        let rules = if all { Vec::new() } else { list.split(',').filter_map(Rule::parse).collect() };
+        NoProxy { all, rules }
+    }
+
+    /// Whether requests to `host` should skip the proxy.
+    pub fn matches(&self, host: &str) -> bool {
+        if self.all {
+            return true;
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_ascii_lowercase();
+        let ip = host.parse::<IpAddr>().ok();
+        self.rules.iter().any(|rule| rule.matches(&host, ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_proxy() {
+        let no_proxy = NoProxy::parse("localhost,.Example.com, internal., 10.0.0.0/8,192.168.1.1,[::1],fd00::/8,,x/9");
+        for host in ["localhost", "example.com", "api.EXAMPLE.com", "db.internal.", "10.1.2.3", "192.168.1.1"] {
+            assert!(no_proxy.matches(host), "{host}");
+        }
+        for host in ["[::1]", "[fd12::1]"] {
+            assert!(no_proxy.matches(host), "{host}");
+        }
+        for host in ["notexample.com", "example.com.evil", "11.0.0.1", "192.168.1.2", "[::2]", "localhost2"] {
+            assert!(!no_proxy.matches(host), "{host}");
+        }
+        assert!(NoProxy::parse("*").matches("anything.test"));
+        assert!(!NoProxy::default().matches("localhost"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use tokio::net::TcpStream;
+
+use crate::client::dns::{DnsResolver, IpPreference};
+
+/// Socket settings for the client's connections, set with `ClientBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TcpOptions {
+    pub ip_preference: IpPreference,
+    pub happy_eyeballs_delay: Option<Duration>,
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_retries: Option<u32>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// How many times to retry a failed connection attempt, and how long to wait first.
+    pub connect_retries: Option<(u32, Duration)>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            ip_preference: IpPreference::default(),
+            // The delay RFC 8305 recommends.
+            happy_eyeballs_delay: Some(Duration::from_millis(250)),
+            nodelay: false,
+            keepalive: None,
+            keepalive_interval: None,
+            keepalive_retries: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            connect_retries: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    pub fn http_connector(&self, resolver: DnsResolver, timeout: Option<Duration>) -> HttpConnector<DnsResolver> {
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+        http.set_happy_eyeballs_timeout(self.happy_eyeballs_delay);
+        http.set_connect_timeout(timeout);
+        http.set_nodelay(self.nodelay);
+        http.set_keepalive(self.keepalive);
+        http.set_send_buffer_size(self.send_buffer_size);
+        http.set_recv_buffer_size(self.recv_buffer_size);
+        http
+    }
+
+    /// Set the keepalive options hyper can't, once connected.
+    pub fn configure(&self, tcp: &TcpStream) -> std::io::Result<()> {
+        match self.keepalive {
+            Some(time) if self.keepalive_interval.is_some() || self.keepalive_retries.is_some() => {
+                socket2::SockRef::from(tcp).set_tcp_keepalive(&self.tcp_keepalive(time))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+    ))]
+    fn tcp_keepalive(&self, time: Duration) -> socket2::TcpKeepalive {
+        let mut keepalive = socket2::TcpKeepalive::new().with_time(time);
+        if let Some(interval) = self.keepalive_interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(retries) = self.keepalive_retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        keepalive
+    }
+
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+    )))]
+    fn tcp_keepalive(&self, time: Duration) -> socket2::TcpKeepalive {
+        socket2::TcpKeepalive::new().with_time(time)
+    }
+}
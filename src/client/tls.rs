@@ -0,0 +1,610 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::pin::Pin;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+#[cfg(feature = "rustls")]
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+
+use hyper::client::connect::{Connected, Connection};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::client::profile::host_matches;
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use certificate::Certificate;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use identity::Identity;
+
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+mod certificate;
+#[cfg(feature = "rustls")]
+mod danger;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+mod identity;
+
+#[cfg(feature = "rustls")]
+static NATIVE_ROOTS: OnceLock<rustls::RootCertStore> = OnceLock::new();
+
+/// The platform's root certificates, for rustls. Shared by clients, so they're only loaded once.
+#[cfg(feature = "rustls")]
+fn native_roots() -> &'static rustls::RootCertStore {
+    NATIVE_ROOTS.get_or_init(|| {
+        let mut roots = rustls::RootCertStore::empty();
+        match rustls_native_certs::load_native_certs() {
+            Ok(certs) => {
+                let certs: Vec<_> = certs.into_iter().map(|cert| cert.0).collect();
+                roots.add_parsable_certificates(&certs);
+            }
+            Err(e) => tracing::warn!(target: "httpclient", "Failed to load the platform's root certificates: {}", e),
+        }
+        roots
+    })
+}
+
+/// The TLS library the client makes HTTPS connections with. rustls is used by default; with the `native-tls`
+/// feature, the platform's library can be used instead.
+#[derive(Clone)]
+pub(crate) enum Backend {
+    /// rustls, with its safe defaults unless the user gave a config.
+    #[cfg(feature = "rustls")]
+    Rustls(Option<Arc<rustls::ClientConfig>>),
+    /// The platform's library, with its defaults unless the user gave a connector.
+    #[cfg(feature = "native-tls")]
+    NativeTls(Option<tokio_native_tls::TlsConnector>),
+    /// Built without a TLS library, so HTTPS can't be used.
+    #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+    Disabled,
+}
+
+impl Default for Backend {
+    #[cfg(feature = "rustls")]
+    fn default() -> Self {
+        Backend::Rustls(None)
+    }
+
+    #[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+    fn default() -> Self {
+        Backend::NativeTls(None)
+    }
+
+    #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+    fn default() -> Self {
+        Backend::Disabled
+    }
+}
+
+impl Debug for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(_) => write!(f, "Rustls"),
+            #[cfg(feature = "native-tls")]
+            Backend::NativeTls(_) => write!(f, "NativeTls"),
+            #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+            Backend::Disabled => write!(f, "Disabled"),
+        }
+    }
+}
+
+/// The TLS settings on `ClientBuilder`, which `build` turns into a [`Tls`]. Only the identities and, for rustls, the
+/// ALPN protocols apply to configs and connectors the user gave.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsOptions {
+    pub backend: Backend,
+    /// Don't trust the platform's root certificates.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub disable_native_roots: bool,
+    /// Root certificates to trust as well.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub roots: Vec<Certificate>,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub accept_invalid_certs: bool,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub accept_invalid_hostnames: bool,
+    /// Only offer HTTP/1.1 with ALPN.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub disable_http2: bool,
+    /// Only offer HTTP/2 with ALPN, since hyper will speak it regardless.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub http2_only: bool,
+    /// Names to connect to hosts as, overriding the host in the URL.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub server_names: Vec<(String, String)>,
+    /// Log session keys to the file `SSLKEYLOGFILE` names.
+    #[cfg(feature = "rustls")]
+    pub keylog: bool,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub identity: Option<Identity>,
+    /// Identities for particular hosts, or wildcards like `*.example.com`, overriding `identity`.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub host_identities: Vec<(String, Identity)>,
+}
+
+impl TlsOptions {
+    pub fn build(&self) -> Tls {
+        #[cfg(any(feature = "rustls", feature = "native-tls"))]
+        {
+            if self.accept_invalid_certs || self.accept_invalid_hostnames {
+                let message = "TLS certificate verification is disabled; only use this in development";
+                tracing::warn!(target: "httpclient", "{}", message);
+            }
+            let default = self.connector(self.identity.as_ref());
+            let hosts = self.host_identities
+                .iter()
+                .map(|(host, identity)| (host.clone(), self.connector(Some(identity))))
+                .collect();
+            Tls { options: self.clone(), default, hosts, server_names: self.server_names.clone() }
+        }
+        #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+        {
+            let Backend::Disabled = self.backend;
+            Tls { default: TlsConnector::Disabled, hosts: Vec::new(), server_names: Vec::new() }
+        }
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn connector(&self, identity: Option<&Identity>) -> TlsConnector {
+        match &self.backend {
+            #[cfg(feature = "rustls")]
+            Backend::Rustls(config) => {
+                let mut config = config.clone().unwrap_or_else(|| self.rustls_config());
+                let config_mut = Arc::make_mut(&mut config);
+                config_mut.alpn_protocols = self.alpn().iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+                if let Some(identity) = identity {
+                    config_mut.client_auth_cert_resolver = identity.resolver();
+                }
+                TlsConnector::Rustls(config)
+            }
+            #[cfg(feature = "native-tls")]
+            Backend::NativeTls(Some(connector)) => TlsConnector::NativeTls(connector.clone()),
+            #[cfg(feature = "native-tls")]
+            Backend::NativeTls(None) => {
+                let mut builder = native_tls::TlsConnector::builder();
+                builder.disable_built_in_roots(self.disable_native_roots);
+                builder.danger_accept_invalid_certs(self.accept_invalid_certs);
+                builder.danger_accept_invalid_hostnames(self.accept_invalid_hostnames);
+                builder.request_alpns(self.alpn());
+                for cert in &self.roots {
+                    builder.add_root_certificate(cert.native_tls.clone());
+                }
+                if let Some(identity) = identity {
+                    builder.identity(identity.native_tls.clone());
+                }
+                let connector = builder.build().expect("Failed to initialize the platform's TLS library");
+                TlsConnector::NativeTls(connector.into())
+            }
+        }
+    }
+
+    /// The protocols to offer with ALPN, most preferred first.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn alpn(&self) -> &'static [&'static str] {
+        match (self.http2_only, self.disable_http2) {
+            (true, _) => &["h2"],
+            (false, false) => &["h2", "http/1.1"],
+            (false, true) => &["http/1.1"],
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    fn rustls_config(&self) -> Arc<rustls::ClientConfig> {
+        let mut roots = match self.disable_native_roots {
+            true => rustls::RootCertStore::empty(),
+            false => native_roots().clone(),
+        };
+        for cert in &self.roots {
+            if let Err(e) = roots.add(&cert.rustls) {
+                tracing::warn!(target: "httpclient", "Failed to add a root certificate: {}", e);
+            }
+        }
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+        if self.accept_invalid_certs {
+            config.dangerous().set_certificate_verifier(Arc::new(danger::AcceptInvalidCerts));
+        } else if self.accept_invalid_hostnames {
+            config.dangerous().set_certificate_verifier(Arc::new(danger::AcceptInvalidHostnames::new(roots)));
+        }
+        if self.keylog {
+            config.key_log = Arc::new(rustls::KeyLogFile::new());
+        }
+        Arc::new(config)
+    }
+}
+
+/// The TLS connectors a client makes HTTPS connections with: one for most hosts, and others for hosts with their own
+/// client certificate.
+#[derive(Debug, Clone)]
+pub(crate) struct Tls {
+    /// The options the connectors were built with, to `rebuild` them.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    options: TlsOptions,
+    default: TlsConnector,
+    hosts: Vec<(String, TlsConnector)>,
+    /// The names to send for SNI and verify certificates for, by the host in the URL.
+    server_names: Vec<(String, String)>,
+}
+
+impl Tls {
+    /// Build the connectors again with `update` applied to the options they were built with.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn rebuild(&self, update: impl FnOnce(&mut TlsOptions)) -> Tls {
+        let mut options = self.options.clone();
+        update(&mut options);
+        options.build()
+    }
+
+    /// Make a TLS connection to `host` over `tcp`. IPv6 addresses are given without brackets, e.g. `::1`, so they're
+    /// verified as IP addresses rather than DNS names.
+    pub async fn connect(&self, host: &str, tcp: TcpStream) -> io::Result<MaybeTlsStream> {
+        let name = self.server_names
+            .iter()
+            .find(|(pattern, _)| pattern.trim_start_matches('[').trim_end_matches(']').eq_ignore_ascii_case(host))
+            .map_or(host, |(_, name)| name.as_str());
+        let connector = self.hosts
+            .iter()
+            .find(|(pattern, _)| host_matches(pattern, name))
+            .map_or(&self.default, |(_, connector)| connector);
+        connector.connect(name, tcp).await
+    }
+}
+
+#[derive(Clone)]
+enum TlsConnector {
+    #[cfg(feature = "rustls")]
+    Rustls(Arc<rustls::ClientConfig>),
+    #[cfg(feature = "native-tls")]
+    NativeTls(tokio_native_tls::TlsConnector),
+    #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+    Disabled,
+}
+
+impl Debug for TlsConnector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "rustls")]
+            TlsConnector::Rustls(_) => write!(f, "Rustls"),
+            #[cfg(feature = "native-tls")]
+            TlsConnector::NativeTls(_) => write!(f, "NativeTls"),
+            #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+            TlsConnector::Disabled => write!(f, "Disabled"),
+        }
+    }
+}
+
+impl TlsConnector {
+    async fn connect(&self, host: &str, tcp: TcpStream) -> io::Result<MaybeTlsStream> {
+        match self {
+            #[cfg(feature = "rustls")]
+            TlsConnector::Rustls(config) => {
+                let name = rustls::ServerName::try_from(host)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid server name: {host}")))?;
+                let tls = tokio_rustls::TlsConnector::from(config.clone()).connect(name, tcp).await?;
+                Ok(MaybeTlsStream::Rustls(Box::new(tls)))
+            }
+            #[cfg(feature = "native-tls")]
+            TlsConnector::NativeTls(connector) => {
+                let tls = connector.connect(host, tcp).await.map_err(io::Error::other)?;
+                Ok(MaybeTlsStream::NativeTls(Box::new(tls)))
+            }
+            #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+            TlsConnector::Disabled => {
+                let _ = (host, tcp);
+                let message = "HTTPS needs the `rustls` or `native-tls` feature";
+                Err(io::Error::new(io::ErrorKind::Unsupported, message))
+            }
+        }
+    }
+}
+
+/// A connection, with TLS for HTTPS.
+pub(crate) enum MaybeTlsStream {
+    Plain(TcpStream),
+    #[cfg(feature = "rustls")]
+    Rustls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+    #[cfg(feature = "native-tls")]
+    NativeTls(Box<tokio_native_tls::TlsStream<TcpStream>>),
+}
+
+impl MaybeTlsStream {
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            MaybeTlsStream::Plain(tcp) => tcp,
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Rustls(tls) => tls.get_ref().0,
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(tls) => tls.get_ref().get_ref().get_ref(),
+        }
+    }
+}
+
+impl MaybeTlsStream {
+    /// Whether HTTP/2 was negotiated with ALPN.
+    fn is_h2(&self) -> bool {
+        match self {
+            MaybeTlsStream::Plain(_) => false,
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Rustls(tls) => tls.get_ref().1.alpn_protocol() == Some(b"h2"),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(tls) => tls.get_ref().negotiated_alpn().ok().flatten().as_deref() == Some(b"h2"),
+        }
+    }
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        let connected = self.tcp().connected();
+        match self.is_h2() {
+            true => connected.negotiated_h2(),
+            false => connected,
+        }
+    }
+}
+
+/// Forward a method of `AsyncRead` or `AsyncWrite` to whichever stream it is.
+macro_rules! forward {
+    ($self:ident, $stream:ident => $call:expr) => {
+        match $self.get_mut() {
+            MaybeTlsStream::Plain($stream) => $call,
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Rustls($stream) => $call,
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls($stream) => $call,
+        }
+    };
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        forward!(self, stream => Pin::new(stream).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        forward!(self, stream => Pin::new(stream).poll_write(cx, buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        forward!(self, stream => Pin::new(stream).poll_write_vectored(cx, bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            MaybeTlsStream::Plain(stream) => stream.is_write_vectored(),
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Rustls(stream) => stream.is_write_vectored(),
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        forward!(self, stream => Pin::new(stream).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        forward!(self, stream => Pin::new(stream).poll_shutdown(cx))
+    }
+}
+
+#[cfg(all(test, feature = "rustls"))]
+mod tests {
+    use hyper::service::service_fn;
+
+    use crate::{Client, ResponseExt, Version};
+
+    use super::*;
+
+    /// Serve HTTPS on localhost with a self-signed certificate, requiring a client certificate signed by `client_ca`
+    /// if one is given. Returns the port and the server's certificate.
+    async fn serve(client_ca: Option<&rustls::Certificate>) -> (u16, rustls::Certificate) {
+        serve_on("127.0.0.1", "localhost", client_ca).await
+    }
+
+    /// Like `serve`, but listening on `ip`, with a certificate for `name`.
+    async fn serve_on(ip: &str, name: &str, client_ca: Option<&rustls::Certificate>) -> (u16, rustls::Certificate) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let der = rustls::Certificate(cert.serialize_der().unwrap());
+        let key = rustls::PrivateKey(cert.serialize_private_key_der());
+        let config = rustls::ServerConfig::builder().with_safe_defaults();
+        let config = match client_ca {
+            Some(ca) => {
+                let mut roots = rustls::RootCertStore::empty();
+                roots.add(ca).unwrap();
+                config.with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => config.with_no_client_auth(),
+        };
+        let mut config = config.with_single_cert(vec![der.clone()], key).unwrap();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind((ip, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(tls) = acceptor.accept(tcp).await else { return };
+                    let service = service_fn(|_| async {
+                        Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from("secure")))
+                    });
+                    let _ = hyper::server::conn::Http::new().serve_connection(tls, service).await;
+                });
+            }
+        });
+        (port, der)
+    }
+
+    /// A client config trusting only `cert`.
+    fn trusting(cert: &rustls::Certificate) -> rustls::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth()
+    }
+
+    #[tokio::test]
+    async fn test_tls_config() {
+        let (port, cert) = serve(None).await;
+        let url = format!("https://localhost:{port}/");
+
+        // The platform doesn't trust the self-signed certificate.
+        assert!(Client::new().get(&url).send().await.is_err());
+
+        let client = Client::builder().tls_config(trusting(&cert)).build();
+        let res = client.get(&url).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_root_certificate() {
+        let (port, cert) = serve(None).await;
+        let url = format!("https://localhost:{port}/");
+        let pem = format!("-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n", base64_encode(&cert.0));
+        let ca = Certificate::from_pem(pem.as_bytes()).unwrap();
+        assert!(Certificate::from_pem(b"").is_err());
+
+        let client = Client::builder().add_root_certificate(ca.clone()).build();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+        let client = Client::builder().add_root_certificate(ca).tls_native_roots(false).build();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_danger_accept_invalid() {
+        let (port, cert) = serve(None).await;
+        let ca = Certificate::from_der(&cert.0).unwrap();
+        let by_name = format!("https://localhost:{port}/");
+        // The certificate is only for `localhost`.
+        let by_ip = format!("https://127.0.0.1:{port}/");
+
+        let client = Client::builder().danger_accept_invalid_certs(true).build();
+        assert_eq!(client.get(&by_ip).send().await.unwrap().status(), 200);
+
+        let client = Client::builder().add_root_certificate(ca.clone()).build();
+        assert!(client.get(&by_ip).send().await.is_err());
+        let client = Client::builder().add_root_certificate(ca).danger_accept_invalid_hostnames(true).build();
+        assert_eq!(client.get(&by_ip).send().await.unwrap().status(), 200);
+        // The certificate still has to be trusted.
+        let client = Client::builder().danger_accept_invalid_hostnames(true).build();
+        assert!(client.get(&by_name).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keylog() {
+        let (port, _) = serve(None).await;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::env::set_var("SSLKEYLOGFILE", file.path());
+        let client = Client::builder().danger_accept_invalid_certs(true).tls_keylog(true).build();
+        std::env::remove_var("SSLKEYLOGFILE");
+        client.get(&format!("https://localhost:{port}/")).send().await.unwrap();
+        let keys = std::fs::read_to_string(file.path()).unwrap();
+        assert!(keys.contains("CLIENT_TRAFFIC_SECRET_0 "), "{keys}");
+    }
+
+    #[tokio::test]
+    async fn test_tls_server_name() {
+        let (port, cert) = serve(None).await;
+        let ca = Certificate::from_der(&cert.0).unwrap();
+        let client = Client::builder().add_root_certificate(ca).tls_server_name("127.0.0.1", "localhost").build();
+        assert_eq!(client.get(&format!("https://127.0.0.1:{port}/")).send().await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_ipv6() {
+        let (port, cert) = serve_on("::1", "::1", None).await;
+        let ca = Certificate::from_der(&cert.0).unwrap();
+        let client = Client::builder().add_root_certificate(ca).tls_native_roots(false).build();
+        assert_eq!(client.get(&format!("https://[::1]:{port}/")).send().await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let client_cert = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+        let client_der = rustls::Certificate(client_cert.serialize_der().unwrap());
+        let (port, cert) = serve(Some(&client_der)).await;
+        let url = format!("https://localhost:{port}/");
+        let pem = client_cert.serialize_pem().unwrap();
+        let identity = Identity::from_pem(pem.as_bytes(), client_cert.serialize_private_key_pem().as_bytes()).unwrap();
+
+        let client = Client::new();
+        assert!(client.get(&url).send().await.is_err());
+        client.set_root_certificates(vec![Certificate::from_der(&cert.0).unwrap()]);
+        assert!(client.get(&url).send().await.is_err());
+        client.set_identity(identity);
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_http2() {
+        let (port, _) = serve(None).await;
+        let url = format!("https://localhost:{port}/");
+
+        let client = Client::builder().danger_accept_invalid_certs(true).build();
+        assert_eq!(client.get(&url).send().await.unwrap().version(), Version::HTTP_2);
+        // Concurrent requests share the connection.
+        let requests = (0..5).map(|_| async { client.get(&url).send().await.unwrap().text().await.unwrap() });
+        futures::future::join_all(requests).await;
+        assert_eq!(client.pool_stats().total().open, 1);
+
+        let client = Client::builder().danger_accept_invalid_certs(true).http2(false).build();
+        assert_eq!(client.get(&url).send().await.unwrap().version(), Version::HTTP_11);
+        let res = client.get(&url).version(Version::HTTP_2).send().await;
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[tokio::test]
+    async fn test_native_tls() {
+        let (port, _) = serve(None).await;
+        let client = Client::builder().use_native_tls().danger_accept_invalid_certs(true).build();
+        let res = client.get(&format!("https://localhost:{port}/")).send().await.unwrap();
+        assert_eq!(res.version(), Version::HTTP_2);
+    }
+
+    fn base64_encode(der: &[u8]) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(der)
+    }
+
+    #[tokio::test]
+    async fn test_identity() {
+        let client_cert = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+        let client_der = rustls::Certificate(client_cert.serialize_der().unwrap());
+        let (port, cert) = serve(Some(&client_der)).await;
+        let url = format!("https://localhost:{port}/");
+        let pem = client_cert.serialize_pem().unwrap();
+        let identity = Identity::from_pem(pem.as_bytes(), client_cert.serialize_private_key_pem().as_bytes()).unwrap();
+
+        let client = Client::builder().tls_config(trusting(&cert)).build();
+        assert!(client.get(&url).send().await.is_err());
+        let client = Client::builder().tls_config(trusting(&cert)).identity(identity.clone()).build();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+
+        // Only hosts with their own identity present it.
+        let client = Client::builder().tls_config(trusting(&cert)).host_identity("*.test", identity).build();
+        assert!(client.get(&url).send().await.is_err());
+
+        let mut keystore = p12_keystore::KeyStore::new();
+        let chain = p12_keystore::PrivateKeyChain::new(
+            client_cert.serialize_private_key_der(),
+            [1],
+            [p12_keystore::Certificate::from_der(&client_der.0).unwrap()],
+        );
+        keystore.add_entry("client", p12_keystore::KeyStoreEntry::PrivateKeyChain(chain));
+        let p12 = keystore.writer("secret").write().unwrap();
+        assert!(Identity::from_pkcs12_der(&p12, "wrong").is_err());
+        let identity = Identity::from_pkcs12_der(&p12, "secret").unwrap();
+        let client = Client::builder().tls_config(trusting(&cert)).host_identity("localhost", identity).build();
+        assert_eq!(client.get(&url).send().await.unwrap().status(), 200);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+
+/// A root certificate for the client to trust, e.g. an internal CA's, so servers with certificates it issued can be
+/// verified without turning verification off. Add it with `ClientBuilder::add_root_certificate`.
+/// ```no_run
+/// use httpclient::{Certificate, Client};
+/// let ca = Certificate::from_pem(&std::fs::read("internal-ca.pem")?)?;
+/// let client = Client::builder().add_root_certificate(ca).build();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct Certificate {
+    #[cfg(feature = "rustls")]
+    pub(crate) rustls: rustls::Certificate,
+    #[cfg(feature = "native-tls")]
+    pub(crate) native_tls: native_tls::Certificate,
+}
+
+impl Debug for Certificate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Certificate").finish_non_exhaustive()
+    }
+}
+
+impl Certificate {
+    pub fn from_der(der: &[u8]) -> io::Result<Certificate> {
+        #[cfg(feature = "rustls")]
+        rustls::RootCertStore::empty().add(&rustls::Certificate(der.to_vec())).map_err(invalid)?;
+        Ok(Certificate {
+            #[cfg(feature = "rustls")]
+            rustls: rustls::Certificate(der.to_vec()),
+            #[cfg(feature = "native-tls")]
+            native_tls: native_tls::Certificate::from_der(der).map_err(invalid)?,
+        })
+    }
+
+    /// Read the first certificate in a PEM file.
+    pub fn from_pem(pem: &[u8]) -> io::Result<Certificate> {
+        Self::from_pem_bundle(pem)?.into_iter().next().ok_or_else(|| invalid("No certificates found in the PEM"))
+    }
+
+    /// Read every certificate in a PEM file, like a CA bundle.
+    pub fn from_pem_bundle(pem: &[u8]) -> io::Result<Vec<Certificate>> {
+        rustls_pemfile::certs(&mut &*pem)?.iter().map(|der| Self::from_der(der)).collect()
+    }
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,105 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+#[cfg(feature = "rustls")]
+use std::sync::Arc;
+
+#[cfg(feature = "rustls")]
+use rustls::sign::CertifiedKey;
+
+/// A client certificate and its private key, for servers which require mutual TLS (mTLS). Present it to every server
+/// with `ClientBuilder::identity`, or to some with `ClientBuilder::host_identity`.
+/// ```no_run
+/// use httpclient::{Client, Identity};
+/// let identity = Identity::from_pem(&std::fs::read("client.crt")?, &std::fs::read("client.key")?)?;
+/// let client = Client::builder().identity(identity).build();
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct Identity {
+    #[cfg(feature = "rustls")]
+    rustls: Arc<CertifiedKey>,
+    #[cfg(feature = "native-tls")]
+    pub(crate) native_tls: native_tls::Identity,
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity").finish_non_exhaustive()
+    }
+}
+
+impl Identity {
+    /// Read a PEM certificate chain, starting with the client's own certificate, and its PEM private key. rustls
+    /// reads PKCS#8, PKCS#1 (RSA), and SEC1 (EC) keys; with the `native-tls` feature, the key must be PKCS#8
+    /// (`BEGIN PRIVATE KEY`).
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> io::Result<Identity> {
+        Ok(Identity {
+            #[cfg(feature = "rustls")]
+            rustls: {
+                let certs = rustls_pemfile::certs(&mut &*cert_chain)?;
+                let key = rustls_pemfile::read_all(&mut &*key)?
+                    .into_iter()
+                    .find_map(|item| match item {
+                        rustls_pemfile::Item::PKCS8Key(key)
+                        | rustls_pemfile::Item::RSAKey(key)
+                        | rustls_pemfile::Item::ECKey(key) => Some(key),
+                        _ => None,
+                    })
+                    .ok_or_else(|| invalid("No private key found in the PEM"))?;
+                certified_key(certs, key)?
+            },
+            #[cfg(feature = "native-tls")]
+            native_tls: native_tls::Identity::from_pkcs8(cert_chain, key).map_err(invalid)?,
+        })
+    }
+
+    /// Read a PKCS#12 archive (a `.p12` or `.pfx` file) holding the client's certificate chain and private key.
+    pub fn from_pkcs12_der(der: &[u8], password: &str) -> io::Result<Identity> {
+        Ok(Identity {
+            #[cfg(feature = "rustls")]
+            rustls: {
+                let keystore = p12_keystore::KeyStore::from_pkcs12(der, password).map_err(invalid)?;
+                let (_, chain) = keystore
+                    .private_key_chain()
+                    .ok_or_else(|| invalid("No private key found in the PKCS#12 archive"))?;
+                let certs = chain.chain().iter().map(|cert| cert.as_der().to_vec()).collect();
+                certified_key(certs, chain.key().to_vec())?
+            },
+            #[cfg(feature = "native-tls")]
+            native_tls: native_tls::Identity::from_pkcs12(der, password).map_err(invalid)?,
+        })
+    }
+
+    /// Present this identity whenever the server asks for a client certificate.
+    #[cfg(feature = "rustls")]
+    pub(crate) fn resolver(&self) -> Arc<dyn rustls::client::ResolvesClientCert> {
+        Arc::new(ClientCert(self.rustls.clone()))
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn certified_key(certs: Vec<Vec<u8>>, key: Vec<u8>) -> io::Result<Arc<CertifiedKey>> {
+    if certs.is_empty() {
+        return Err(invalid("No certificates found"));
+    }
+    let key = rustls::sign::any_supported_type(&rustls::PrivateKey(key)).map_err(invalid)?;
+    Ok(Arc::new(CertifiedKey::new(certs.into_iter().map(rustls::Certificate).collect(), key)))
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(feature = "rustls")]
+struct ClientCert(Arc<CertifiedKey>);
+
+#[cfg(feature = "rustls")]
+impl rustls::client::ResolvesClientCert for ClientCert {
+    fn resolve(&self, _issuers: &[&[u8]], _schemes: &[rustls::SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
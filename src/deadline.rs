@@ -0,0 +1,24 @@
+use std::time::{Duration, Instant};
+
+/// The point in time by which a request must complete.
+///
+/// When a request has a timeout (set on the `Client` or with `RequestBuilder::timeout`) or a
+/// `RequestBuilder::deadline`, `send()` stores a `Deadline` in the request extensions, so middleware can check the
+/// remaining budget. `Retry` and `RateLimit` won't sleep past it, and `Next::run` fails once it has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Deadline(Instant::now() + timeout)
+    }
+
+    /// Time left before the deadline. Zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.0 <= Instant::now()
+    }
+}
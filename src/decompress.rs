@@ -0,0 +1,396 @@
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures::TryStreamExt;
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use http::{HeaderMap, HeaderValue};
+use hyper::body::HttpBody;
+
+/// A content coding the client can transparently decode. `br` and `zstd` need the `brotli` and `zstd` features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentCoding {
+    Gzip,
+    /// The zlib format, as RFC 9110 specifies for `deflate`.
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl ContentCoding {
+    /// Every coding this build supports, in order of preference.
+    pub const ALL: &'static [ContentCoding] = &[
+        ContentCoding::Gzip,
+        #[cfg(feature = "zstd")]
+        ContentCoding::Zstd,
+        #[cfg(feature = "brotli")]
+        ContentCoding::Brotli,
+        ContentCoding::Deflate,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    fn decoder(&self) -> Decoder {
+        match self {
+            ContentCoding::Gzip => Decoder::Gzip(flate2::write::GzDecoder::new(Vec::new())),
+            ContentCoding::Deflate => Decoder::Deflate(flate2::write::ZlibDecoder::new(Vec::new())),
+            #[cfg(feature = "brotli")]
+            ContentCoding::Brotli => Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(Vec::new(), 4096))),
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => Decoder::Zstd(zstd::stream::write::Decoder::new(Vec::new()).expect("Failed to create zstd decoder")),
+        }
+    }
+}
+
+impl FromStr for ContentCoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(ContentCoding::Gzip),
+            "deflate" => Ok(ContentCoding::Deflate),
+            #[cfg(feature = "brotli")]
+            "br" => Ok(ContentCoding::Brotli),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(ContentCoding::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Limits on decompressed responses, so a malicious server can't exhaust memory with a small, highly compressed body
+/// (a "decompression bomb"). Exceeding a limit fails the body with [`DecompressionLimitExceeded`].
+///
+/// By default, there's no size limit, and bodies may expand to at most 100 times their compressed size.
+/// The ratio is only checked once a body has expanded past 1 MiB, since small bodies often compress extremely well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecompressionLimits {
+    pub max_size: Option<u64>,
+    pub max_ratio: Option<u32>,
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_ratio: Some(100),
+        }
+    }
+}
+
+impl DecompressionLimits {
+    /// Decompressed bodies below this size aren't subject to `max_ratio`.
+    pub const RATIO_THRESHOLD: u64 = 1024 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No limits at all.
+    pub fn unlimited() -> Self {
+        Self {
+            max_size: None,
+            max_ratio: None,
+        }
+    }
+
+    /// The largest a decompressed body may be, in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// The most a body may expand, as a multiple of its compressed size.
+    pub fn max_ratio(mut self, max_ratio: u32) -> Self {
+        self.max_ratio = Some(max_ratio);
+        self
+    }
+
+    fn check(&self, compressed: u64, decompressed: u64) -> Result<(), DecompressionLimitExceeded> {
+        if let Some(max_size) = self.max_size {
+            if decompressed > max_size {
+                return Err(DecompressionLimitExceeded::Size(max_size));
+            }
+        }
+        if let Some(max_ratio) = self.max_ratio {
+            if decompressed > Self::RATIO_THRESHOLD && decompressed > compressed.saturating_mul(max_ratio as u64) {
+                return Err(DecompressionLimitExceeded::Ratio(max_ratio));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_size.is_none() && self.max_ratio.is_none()
+    }
+}
+
+/// A decompressed response body exceeded the client's [`DecompressionLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressionLimitExceeded {
+    /// The body was larger than this many bytes.
+    Size(u64),
+    /// The body expanded to more than this multiple of its compressed size.
+    Ratio(u32),
+}
+
+impl Display for DecompressionLimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompressionLimitExceeded::Size(size) => write!(f, "Decompressed body exceeded {} bytes", size),
+            DecompressionLimitExceeded::Ratio(ratio) => write!(f, "Decompressed body exceeded {}x its compressed size", ratio),
+        }
+    }
+}
+
+impl std::error::Error for DecompressionLimitExceeded {}
+
+/// The `Accept-Encoding` header value advertising every supported coding.
+pub(crate) fn accept_encoding() -> HeaderValue {
+    let codings: Vec<_> = ContentCoding::ALL.iter().map(|c| c.as_str()).collect();
+    HeaderValue::from_str(&codings.join(", ")).unwrap()
+}
+
+/// The codings applied to a response, in the order they were applied. `None` if any of them isn't supported,
+/// in which case the body is left as is.
+fn content_codings(headers: &HeaderMap) -> Option<Vec<ContentCoding>> {
+    let mut codings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        for coding in value.to_str().ok()?.split(',') {
+            if coding.trim().eq_ignore_ascii_case("identity") || coding.trim().is_empty() {
+                continue;
+            }
+            codings.push(coding.parse().ok()?);
+        }
+    }
+    Some(codings)
+}
+
+/// Undo the response's Content-Encoding, if every coding is supported. The body is decoded as it's read.
+/// Content-Encoding and Content-Length are removed, since they describe the encoded body.
+pub(crate) fn decode_response(headers: &mut HeaderMap, mut body: hyper::Body, limits: &DecompressionLimits) -> hyper::Body {
+    let Some(codings) = content_codings(headers) else {
+        return body;
+    };
+    // e.g. HEAD requests and 304s, whose headers describe a body that isn't sent.
+    if codings.is_empty() || HttpBody::is_end_stream(&body) {
+        return body;
+    }
+    let compressed = Arc::new(AtomicU64::new(0));
+    if !limits.is_unlimited() {
+        let compressed = compressed.clone();
+        body = hyper::Body::wrap_stream(body.inspect_ok(move |chunk| {
+            compressed.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }));
+    }
+    for coding in codings.iter().rev() {
+        body = decode(body, coding.decoder());
+    }
+    if !limits.is_unlimited() {
+        body = limit(body, compressed, *limits);
+    }
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+    body
+}
+
+/// Fail `body` once it exceeds `limits`, given the running count of `compressed` bytes read to produce it.
+fn limit(body: hyper::Body, compressed: Arc<AtomicU64>, limits: DecompressionLimits) -> hyper::Body {
+    let mut decompressed = 0;
+    let stream = TryStreamExt::map_err(body, |e| -> Box<dyn std::error::Error + Send + Sync> { Box::new(e) })
+        .and_then(move |chunk| {
+            decompressed += chunk.len() as u64;
+            let checked = limits.check(compressed.load(Ordering::Relaxed), decompressed)
+                .map(|_| chunk)
+                .map_err(Into::into);
+            futures::future::ready(checked)
+        });
+    hyper::Body::wrap_stream(stream)
+}
+
+enum Decoder {
+    Gzip(flate2::write::GzDecoder<Vec<u8>>),
+    Deflate(flate2::write::ZlibDecoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::DecompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl Decoder {
+    /// Decode `chunk`, returning whatever output it produced.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(d) => {
+                d.write_all(chunk)?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+            Decoder::Deflate(d) => {
+                d.write_all(chunk)?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(d) => {
+                d.write_all(chunk)?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(d) => {
+                d.write_all(chunk)?;
+                d.flush()?;
+                Ok(std::mem::take(d.get_mut()))
+            }
+        }
+    }
+
+    /// Flush any remaining output, checking the stream was complete.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Gzip(d) => d.finish(),
+            Decoder::Deflate(d) => d.finish(),
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(d) => d.into_inner().map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "Incomplete brotli stream")),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(mut d) => {
+                d.flush()?;
+                Ok(d.into_inner())
+            }
+        }
+    }
+}
+
+fn decode(body: hyper::Body, decoder: Decoder) -> hyper::Body {
+    let stream = futures::stream::try_unfold((body, Some(decoder)), |(mut body, mut decoder)| async move {
+        let Some(d) = decoder.as_mut() else {
+            return Ok::<_, io::Error>(None);
+        };
+        let out = match body.try_next().await.map_err(io::Error::other)? {
+            Some(chunk) => d.write(&chunk),
+            None => decoder.take().unwrap().finish(),
+        }.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((out, (body, decoder))))
+    });
+    hyper::Body::wrap_stream(stream.try_filter(|chunk| futures::future::ready(!chunk.is_empty())))
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+
+    use crate::ProtocolError;
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decode_response() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("100"));
+        // Split the encoded body across chunks, to check it's decoded incrementally.
+        let encoded = gzip(b"hello world");
+        let chunks: Vec<Result<Vec<u8>, io::Error>> = encoded.chunks(5).map(|c| Ok(c.to_vec())).collect();
+        let body = decode_response(&mut headers, hyper::Body::wrap_stream(futures::stream::iter(chunks)), &DecompressionLimits::default());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello world");
+        assert!(headers.is_empty());
+
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+        e.write_all(b"hello").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+        let body = decode_response(&mut headers, hyper::Body::from(e.finish().unwrap()), &DecompressionLimits::default());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("unknown"));
+        let body = decode_response(&mut headers, hyper::Body::from("raw"), &DecompressionLimits::default());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "raw");
+        assert!(headers.contains_key(CONTENT_ENCODING));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let body = decode_response(&mut headers, hyper::Body::empty(), &DecompressionLimits::default());
+        assert!(hyper::body::to_bytes(body).await.unwrap().is_empty());
+    }
+
+    #[cfg(feature = "brotli")]
+    #[tokio::test]
+    async fn test_brotli() {
+        let mut e = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        e.write_all(b"hello brotli").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+        let body = decode_response(&mut headers, hyper::Body::from(e.into_inner()), &DecompressionLimits::default());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello brotli");
+        assert!(accept_encoding().to_str().unwrap().contains("br"));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_zstd() {
+        let encoded = zstd::encode_all(&b"hello zstd"[..], 0).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+        let body = decode_response(&mut headers, hyper::Body::from(encoded), &DecompressionLimits::default());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello zstd");
+        assert!(accept_encoding().to_str().unwrap().contains("zstd"));
+    }
+
+    #[test]
+    fn test_accept_encoding() {
+        let expected = if cfg!(all(feature = "zstd", feature = "brotli")) {
+            "gzip, zstd, br, deflate"
+        } else if cfg!(feature = "zstd") {
+            "gzip, zstd, deflate"
+        } else if cfg!(feature = "brotli") {
+            "gzip, br, deflate"
+        } else {
+            "gzip, deflate"
+        };
+        assert_eq!(accept_encoding(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let encoded = gzip(&vec![0; 4 * 1024 * 1024]);
+        let decode = |limits: DecompressionLimits| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            crate::Body::Hyper(decode_response(&mut headers, hyper::Body::from(encoded.clone()), &limits)).into_memory()
+        };
+        let err = decode(DecompressionLimits::default()).await.unwrap_err();
+        assert!(matches!(err, ProtocolError::DecompressionLimitExceeded(DecompressionLimitExceeded::Ratio(100))), "{err}");
+        let err = decode(DecompressionLimits::unlimited().max_size(1024)).await.unwrap_err();
+        assert!(matches!(err, ProtocolError::DecompressionLimitExceeded(DecompressionLimitExceeded::Size(1024))), "{err}");
+        let body = decode(DecompressionLimits::unlimited()).await.unwrap();
+        assert!(matches!(body, crate::InMemoryBody::Bytes(b) if b.len() == 4 * 1024 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_truncated() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let encoded = gzip(b"hello world");
+        let body = decode_response(&mut headers, hyper::Body::from(encoded[..encoded.len() - 4].to_vec()), &DecompressionLimits::default());
+        assert!(hyper::body::to_bytes(body).await.is_err());
+    }
+}
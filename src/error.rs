@@ -1,7 +1,10 @@
 use std::fmt::{Debug, Display, Formatter};
 use std::string::FromUtf8Error;
-use http::StatusCode;
-use crate::{Body, InMemoryResponse, InMemoryResponseExt, Response};
+use http::{HeaderMap, StatusCode, Uri};
+use crate::{Body, DecompressionLimitExceeded, InMemoryResponse, InMemoryResponseExt, Response};
+use crate::body::{BodyTooLarge, Cancelled};
+use crate::middleware::Redirects;
+use crate::middleware::oauth2::Oauth2Error;
 
 pub type Result<T = Response, E = Error> = std::result::Result<T, E>;
 pub type InMemoryError = Error<InMemoryResponse>;
@@ -14,9 +17,34 @@ pub enum ProtocolError {
     ConnectionError(hyper::Error),
     Utf8Error(FromUtf8Error),
     JsonError(serde_json::Error),
+    /// A response body didn't deserialize into the expected type.
+    JsonDecodeError(Box<JsonDecodeError>),
     IoError(std::io::Error),
-    TooManyRedirects,
+    /// The redirects followed before giving up, ending with the one that wasn't.
+    TooManyRedirects(Redirects),
+    /// A redirect response had no Location header.
+    MissingLocation,
+    /// A redirect response's Location header wasn't a valid URL.
+    InvalidLocation(String),
     TooManyRetries,
+    Timeout,
+    /// The request's `CancellationToken` was cancelled.
+    Cancelled,
+    /// The client was shut down with `Client::shutdown`.
+    ClientClosed,
+    Oauth2Error(Oauth2Error),
+    DecompressionLimitExceeded(DecompressionLimitExceeded),
+    /// A body was larger than the limit, in bytes.
+    BodyTooLarge(u64),
+    /// A body couldn't be decoded from a format other than JSON, e.g. XML.
+    DecodeError(Box<dyn std::error::Error + Send + Sync>),
+    /// The request couldn't be built, e.g. its URL didn't resolve against the base URL, or its credentials weren't a
+    /// valid header value. The builder reports it when the request is sent.
+    InvalidRequest(String),
+    /// A JSON-RPC response didn't match the request, e.g. it answered another id, or a batch response left a call out.
+    InvalidJsonRpcResponse(String),
+    /// A proxy URL wasn't an `http://`, `socks5://`, or `socks5h://` URL with a host.
+    InvalidProxy(String),
 }
 
 impl std::error::Error for ProtocolError {}
@@ -27,13 +55,63 @@ impl Display for ProtocolError {
             ProtocolError::ConnectionError(e) => write!(f, "ConnectionError: {}", e),
             ProtocolError::Utf8Error(e) => write!(f, "Utf8Error: {}", e),
             ProtocolError::JsonError(e) => write!(f, "JsonError: {}", e),
+            ProtocolError::JsonDecodeError(e) => write!(f, "JsonDecodeError: {}", e),
             ProtocolError::IoError(e) => write!(f, "IoError: {}", e),
-            ProtocolError::TooManyRedirects => write!(f, "TooManyRedirects"),
+            ProtocolError::TooManyRedirects(redirects) => write!(f, "TooManyRedirects: {}", redirects),
+            ProtocolError::MissingLocation => write!(f, "MissingLocation"),
+            ProtocolError::InvalidLocation(location) => write!(f, "InvalidLocation: {}", location),
             ProtocolError::TooManyRetries => write!(f, "TooManyRetries"),
+            ProtocolError::Timeout => write!(f, "Timeout"),
+            ProtocolError::Cancelled => write!(f, "Cancelled"),
+            ProtocolError::ClientClosed => write!(f, "ClientClosed"),
+            ProtocolError::Oauth2Error(e) => write!(f, "Oauth2Error: {}", e),
+            ProtocolError::DecompressionLimitExceeded(e) => write!(f, "DecompressionLimitExceeded: {}", e),
+            ProtocolError::BodyTooLarge(max_size) => write!(f, "BodyTooLarge: body exceeded {} bytes", max_size),
+            ProtocolError::DecodeError(e) => write!(f, "DecodeError: {}", e),
+            ProtocolError::InvalidRequest(message) => write!(f, "InvalidRequest: {}", message),
+            ProtocolError::InvalidJsonRpcResponse(message) => write!(f, "InvalidJsonRpcResponse: {}", message),
+            ProtocolError::InvalidProxy(message) => write!(f, "InvalidProxy: {}", message),
         }
     }
 }
 
+/// The context of a failure to deserialize a JSON response, to make it easier to tell which field didn't match.
+#[derive(Debug)]
+pub struct JsonDecodeError {
+    /// Where in the document deserialization failed, e.g. `items[2].price`, or `.` for the whole document.
+    pub path: String,
+    /// The start of the body.
+    pub snippet: String,
+    /// The URL the response came from, if known.
+    pub url: Option<Uri>,
+    pub source: serde_json::Error,
+}
+
+impl Display for JsonDecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at `{}`", self.source, self.path)?;
+        if let Some(url) = &self.url {
+            write!(f, " in response from {}", url)?;
+        }
+        write!(f, ": {}", self.snippet)
+    }
+}
+
+impl std::error::Error for JsonDecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A 4xx or 5xx response whose body was deserialized into an API's error type, returned as `Error::HttpError` by
+/// `json_or_error` and `error_for_status_json`.
+#[derive(Debug)]
+pub struct ApiError<E> {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: E,
+}
+
 #[derive(Debug)]
 pub enum Error<T = Response> {
     Protocol(ProtocolError),
@@ -126,7 +204,7 @@ impl<T: Debug> std::error::Error for Error<T> {}
 
 impl serde::de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Protocol(ProtocolError::JsonError(serde_json::Error::custom(&msg.to_string())))
+        Error::Protocol(ProtocolError::JsonError(serde_json::Error::custom(msg.to_string())))
     }
 }
 
@@ -144,7 +222,7 @@ impl From<std::io::Error> for ProtocolError {
 
 impl<T> From<hyper::Error> for Error<T> {
     fn from(value: hyper::Error) -> Self {
-        Error::Protocol(ProtocolError::ConnectionError(value))
+        Error::Protocol(value.into())
     }
 }
 
@@ -162,8 +240,47 @@ impl<T> From<ProtocolError> for Error<T> {
 
 impl From<hyper::Error> for ProtocolError {
     fn from(value: hyper::Error) -> Self {
-        Self::ConnectionError(value)
+        if is_timeout(&value) {
+            Self::Timeout
+        } else if let Some(e) = find_source::<DecompressionLimitExceeded>(&value) {
+            Self::DecompressionLimitExceeded(e)
+        } else if let Some(BodyTooLarge(max_size)) = find_source(&value) {
+            Self::BodyTooLarge(max_size)
+        } else if let Some(Cancelled) = find_source(&value) {
+            Self::Cancelled
+        } else {
+            Self::ConnectionError(value)
+        }
+    }
+}
+
+/// The connector reports connect timeouts as an io error nested inside the hyper error.
+fn is_timeout(err: &hyper::Error) -> bool {
+    if err.is_timeout() {
+        return true;
+    }
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            if io.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Size limits are enforced while the body is read, so their errors arrive nested inside a hyper body error.
+fn find_source<E: std::error::Error + Copy + 'static>(err: &hyper::Error) -> Option<E> {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<E>() {
+            return Some(*e);
+        }
+        source = e.source();
     }
+    None
 }
 
 impl From<serde_json::Error> for ProtocolError {
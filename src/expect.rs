@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use tokio::sync::Notify;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Hold `body` back until `timeout` passes, giving the server a chance to reject a request sent with
+/// `Expect: 100-continue`. If `rejected` is notified first, the body fails so hyper abandons the upload.
+pub(crate) fn hold(body: hyper::Body, timeout: Duration, rejected: Arc<Notify>) -> hyper::Body {
+    let wait = async move {
+        tokio::select! {
+            _ = tokio::time::sleep(timeout) => Ok(TryStreamExt::map_err(body, BoxError::from)),
+            _ = rejected.notified() => Err(BoxError::from("The server rejected the request before the body was sent")),
+        }
+    };
+    hyper::Body::wrap_stream(futures::stream::once(wait).try_flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use hyper::service::{make_service_fn, service_fn};
+
+    use crate::{Client, InMemoryResponseExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_expect_continue() {
+        // Rejects uploads to /reject without reading them, and echoes the rest.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                assert_eq!(req.headers()["expect"], "100-continue");
+                if req.uri().path() == "/reject" {
+                    return Ok(hyper::Response::builder().status(401).body(hyper::Body::from("bad token")).unwrap());
+                }
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(body)))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::builder().expect_continue(1024, Duration::from_secs(5)).build();
+        let start = Instant::now();
+        let err = client.post(&format!("http://{addr}/reject")).bytes(vec![b'a'; 4096]).await.unwrap_err();
+        assert_eq!(err.status(), Some(http::StatusCode::UNAUTHORIZED));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let client = Client::builder().expect_continue(1024, Duration::from_millis(50)).build();
+        let res = client.post(&format!("http://{addr}/upload")).bytes(vec![b'a'; 4096]).await.unwrap();
+        assert_eq!(res.bytes().unwrap().len(), 4096);
+    }
+}
@@ -0,0 +1,135 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+trait AnyClone: Any + Send + Sync {
+    fn clone_box(&self) -> Box<dyn AnyClone>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + Send + Sync + 'static> AnyClone for T {
+    fn clone_box(&self) -> Box<dyn AnyClone> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A type map for attaching arbitrary data to a request.
+///
+/// Unlike `http::Extensions`, this is `Clone`, so values survive middleware (like `Retry`) that clones the request.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn AnyClone>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, returning the previous value of the same type, if any.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(val))
+            .and_then(|b| b.into_any().downcast().ok())
+            .map(|b| *b)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|b| (**b).as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())
+            .and_then(|b| (**b).as_any_mut().downcast_mut())
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|b| b.into_any().downcast().ok())
+            .map(|b| *b)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    /// Add all values from `other`, overwriting values of the same type.
+    pub fn extend(&mut self, other: Extensions) {
+        self.map.extend(other.map);
+    }
+
+    /// Add the values from `other` whose types aren't already present.
+    pub fn fill(&mut self, other: Extensions) {
+        for (k, v) in other.map {
+            self.map.entry(k).or_insert(v);
+        }
+    }
+}
+
+/// Look `T` up in a response's extensions, then in those of the request it answers, which the client carries over.
+pub(crate) fn response_extension<T: Send + Sync + 'static>(extensions: &http::Extensions) -> Option<&T> {
+    extensions.get::<T>().or_else(|| extensions.get::<Extensions>()?.get::<T>())
+}
+
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.iter()
+                .map(|(k, v)| (*k, (**v).clone_box()))
+                .collect(),
+        }
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions").field("len", &self.map.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Attempt(usize);
+
+    #[test]
+    fn test_insert_and_clone() {
+        let mut ext = Extensions::new();
+        assert_eq!(ext.insert(Attempt(1)), None);
+        assert_eq!(ext.insert(Attempt(2)), Some(Attempt(1)));
+        let cloned = ext.clone();
+        ext.get_mut::<Attempt>().unwrap().0 += 1;
+        assert_eq!(ext.get::<Attempt>(), Some(&Attempt(3)));
+        assert_eq!(cloned.get::<Attempt>(), Some(&Attempt(2)));
+        assert_eq!(ext.remove::<Attempt>(), Some(Attempt(3)));
+        assert!(ext.is_empty());
+    }
+}
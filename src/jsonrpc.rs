@@ -0,0 +1,276 @@
+//! A JSON-RPC 2.0 client over HTTP.
+//!
+//! ```ignore
+//! let rpc = JsonRpc::new(Client::new(), "https://rpc.example.com");
+//! let block: u64 = rpc.call("eth_blockNumber", ()).await?;
+//!
+//! let mut batch = rpc.batch();
+//! let balance = batch.call("eth_getBalance", ("0xabc", "latest"));
+//! let count = batch.call("eth_getTransactionCount", ("0xabc", "latest"));
+//! let responses = batch.send().await?;
+//! let balance: String = responses.get(balance)?;
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::Uri;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::response::decode_json;
+use crate::{Client, ProtocolError, ProtocolResult};
+
+/// A call's result, or the error object the server returned, as `Error::HttpError`.
+pub type RpcResult<T> = Result<T, crate::Error<RpcError>>;
+
+/// The error object of a failed call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Serialize)]
+struct Call<'a> {
+    jsonrpc: &'static str,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params: Option<Value>,
+    /// Notifications have no id, and get no response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+}
+
+impl<'a> Call<'a> {
+    /// Params which serialize to null, e.g. `()`, are left out. Fails if they can't be serialized to JSON.
+    fn new(method: &'a str, params: impl Serialize, id: Option<u64>) -> ProtocolResult<Self> {
+        let params = serde_json::to_value(params)?;
+        Ok(Call { jsonrpc: "2.0", method, params: Some(params).filter(|p| !p.is_null()), id })
+    }
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    #[serde(default)]
+    id: Value,
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+impl Reply {
+    fn result<T: DeserializeOwned>(&self) -> RpcResult<T> {
+        match &self.error {
+            Some(error) => Err(crate::Error::HttpError(error.clone())),
+            None => Ok(T::deserialize(&self.result).map_err(ProtocolError::from)?),
+        }
+    }
+}
+
+/// Sends calls to a JSON-RPC endpoint, numbering them with increasing ids.
+///
+/// Servers often report failed calls with a 4xx or 5xx status, so the status is ignored and the body is read as a
+/// JSON-RPC response either way.
+#[derive(Debug)]
+pub struct JsonRpc {
+    client: Client,
+    url: String,
+    next_id: AtomicU64,
+}
+
+impl JsonRpc {
+    pub fn new(client: Client, url: impl Into<String>) -> Self {
+        JsonRpc { client, url: url.into(), next_id: AtomicU64::new(1) }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Call `method`. `params` should serialize to an array or an object, or to null, e.g. `()`, for no params.
+    pub async fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> RpcResult<R> {
+        let id = self.next_id();
+        let reply: Reply = self.post(&Call::new(method, params, Some(id))?).await?;
+        if reply.id != id {
+            let message = format!("Expected a response to id {}, got {}", id, reply.id);
+            return Err(ProtocolError::InvalidJsonRpcResponse(message).into());
+        }
+        reply.result()
+    }
+
+    /// Send a notification, a call without a response.
+    pub async fn notify<P: Serialize>(&self, method: &str, params: P) -> ProtocolResult<()> {
+        let call = Call::new(method, params, None)?;
+        self.client.post(&self.url).json(call).send().await?;
+        Ok(())
+    }
+
+    /// Several calls and notifications in one request.
+    pub fn batch(&self) -> Batch<'_> {
+        Batch { rpc: self, calls: Vec::new(), error: None }
+    }
+
+    async fn post<B: Serialize, T: DeserializeOwned>(&self, body: &B) -> ProtocolResult<T> {
+        let res = self.client.post(&self.url).json(body).send().await?;
+        let (parts, body) = res.into_parts();
+        let body = body.into_memory().await?;
+        decode_json(&body, parts.extensions.get::<Uri>())
+    }
+}
+
+/// Calls to send together, created with `JsonRpc::batch`.
+pub struct Batch<'a> {
+    rpc: &'a JsonRpc,
+    calls: Vec<Value>,
+    /// The first call whose params couldn't be serialized, reported by `send`.
+    error: Option<ProtocolError>,
+}
+
+impl Batch<'_> {
+    /// Add a call, returning the id to look its result up by. If its params can't be serialized, `send` fails.
+    pub fn call<P: Serialize>(&mut self, method: &str, params: P) -> u64 {
+        let id = self.rpc.next_id();
+        self.push(Call::new(method, params, Some(id)));
+        id
+    }
+
+    pub fn notify<P: Serialize>(&mut self, method: &str, params: P) {
+        self.push(Call::new(method, params, None));
+    }
+
+    fn push(&mut self, call: ProtocolResult<Call>) {
+        match call.and_then(|call| Ok(serde_json::to_value(call)?)) {
+            Ok(call) => self.calls.push(call),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+    }
+
+    pub async fn send(self) -> ProtocolResult<BatchResponse> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.calls.is_empty() {
+            return Ok(BatchResponse { replies: HashMap::new() });
+        }
+        let only_notifications = self.calls.iter().all(|call| call.get("id").is_none());
+        if only_notifications {
+            self.rpc.client.post(&self.rpc.url).json(&self.calls).send().await?;
+            return Ok(BatchResponse { replies: HashMap::new() });
+        }
+        let replies: Vec<Reply> = self.rpc.post(&self.calls).await?;
+        let replies = replies.into_iter()
+            .filter_map(|reply| Some((reply.id.as_u64()?, reply)))
+            .collect();
+        Ok(BatchResponse { replies })
+    }
+}
+
+/// The responses to a batch, which servers may send in any order.
+pub struct BatchResponse {
+    replies: HashMap<u64, Reply>,
+}
+
+impl BatchResponse {
+    /// The result of the call with `id`. A call the server didn't respond to is a `ProtocolError`.
+    pub fn get<R: DeserializeOwned>(&self, id: u64) -> RpcResult<R> {
+        let reply = self.replies.get(&id)
+            .ok_or_else(|| ProtocolError::InvalidJsonRpcResponse(format!("No response to id {}", id)))?;
+        reply.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
+    use serde_json::json;
+
+    use super::*;
+
+    /// Adds numbers, and fails any other method.
+    fn reply(call: &Value) -> Option<Value> {
+        let id = call.get("id")?.clone();
+        Some(match call["method"].as_str() {
+            Some("add") => json!({"jsonrpc": "2.0", "id": id, "result": call["params"].as_array().unwrap().iter().map(|n| n.as_i64().unwrap()).sum::<i64>()}),
+            _ => json!({"jsonrpc": "2.0", "id": id, "error": {"code": -32601, "message": "Method not found"}}),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_jsonrpc() {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let body: Value = serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await?).unwrap();
+                let (status, reply) = match body {
+                    // Answer batches in reverse, to check they're matched by id.
+                    Value::Array(calls) => (200, Value::Array(calls.iter().rev().filter_map(reply).collect())),
+                    call => match reply(&call) {
+                        Some(reply) if reply.get("error").is_some() => (500, reply),
+                        Some(reply) => (200, reply),
+                        None => (204, Value::Null),
+                    },
+                };
+                Ok::<_, hyper::Error>(hyper::Response::builder()
+                    .status(status)
+                    .header("content-type", "application/json")
+                    .body(hyper::Body::from(if status == 204 { String::new() } else { reply.to_string() }))
+                    .unwrap())
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let rpc = JsonRpc::new(Client::new(), format!("http://{addr}/"));
+        let sum: i64 = rpc.call("add", [1, 2]).await.unwrap();
+        assert_eq!(sum, 3);
+        let err = rpc.call::<_, i64>("subtract", [1, 2]).await.unwrap_err();
+        assert!(matches!(err, crate::Error::HttpError(RpcError { code: RpcError::METHOD_NOT_FOUND, .. })));
+        rpc.notify("log", ["hello"]).await.unwrap();
+
+        let mut batch = rpc.batch();
+        let a = batch.call("add", [1, 1]);
+        let b = batch.call("add", [2, 2]);
+        let c = batch.call("nope", ());
+        batch.notify("log", ["hello"]);
+        let responses = batch.send().await.unwrap();
+        assert_eq!(responses.get::<i64>(a).unwrap(), 2);
+        assert_eq!(responses.get::<i64>(b).unwrap(), 4);
+        assert!(matches!(responses.get::<i64>(c), Err(crate::Error::HttpError(e)) if e.message == "Method not found"));
+        assert!(matches!(
+            responses.get::<i64>(42),
+            Err(crate::Error::Protocol(ProtocolError::InvalidJsonRpcResponse(_)))
+        ));
+
+        // Maps with non-string keys can't be serialized to JSON.
+        let params = HashMap::from([((1, 2), 3)]);
+        let err = rpc.call::<_, i64>("add", &params).await.unwrap_err();
+        assert!(matches!(err, crate::Error::Protocol(ProtocolError::JsonError(_))));
+        let mut batch = rpc.batch();
+        batch.call("add", [1, 1]);
+        batch.notify("log", &params);
+        assert!(matches!(batch.send().await, Err(ProtocolError::JsonError(_))));
+    }
+}
@@ -1,11 +1,28 @@
 use std::sync::OnceLock;
 pub use body::{Body, InMemoryBody};
-pub use client::{Client};
-pub use error::{Error, InMemoryError, InMemoryResult, Result, ProtocolError, ProtocolResult};
+pub use client::{Client, ClientBuilder, ConnectionInfo, HostProfile, HostStats, IpPreference, PoolStats};
+pub use client::{NoProxy, Proxies, Proxy};
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use client::{Certificate, Identity};
+pub use client::{CachingResolver, RedirectEvent, Resolver, RetryEvent, StaticResolver, SystemResolver};
+pub use deadline::Deadline;
+pub use decompress::{ContentCoding, DecompressionLimitExceeded, DecompressionLimits};
+pub use extensions::Extensions;
+pub use progress::Progress;
+pub use trailers::Trailers;
+pub use typed_headers::TypedHeaders;
+pub use error::{ApiError, Error, InMemoryError, InMemoryResult, JsonDecodeError, Result, ProtocolError, ProtocolResult};
 pub use middleware::{Middleware, Retry, Follow, Logger, Recorder, Next};
-pub use request::{InMemoryRequest, Request, RequestBuilder};
+pub use request::{ArrayFormat, InMemoryRequest, Request, RequestBuilder};
 pub use response::{InMemoryResponse, ResponseExt, InMemoryResponseExt};
-pub use http::{header, header::HeaderName, Uri, Method, StatusCode};
+pub use http::{header, header::HeaderName, Uri, Method, StatusCode, Version};
+pub use headers;
+#[cfg(feature = "native-tls")]
+pub use native_tls;
+#[cfg(feature = "rustls")]
+pub use rustls;
+pub use mime::{self, Mime};
+pub use tokio_util::sync::CancellationToken;
 
 pub type Response = http::Response<Body>;
 
@@ -16,8 +33,19 @@ mod request;
 mod response;
 pub mod middleware;
 mod body;
+mod deadline;
+mod decompress;
+mod expect;
+mod extensions;
+mod progress;
 mod sanitize;
+mod trailers;
+mod typed_headers;
+mod uri;
+pub mod jsonrpc;
 pub mod multipart;
+pub mod paginate;
+pub mod sse;
 
 static SHARED_CLIENT: OnceLock<Client> = OnceLock::new();
 
@@ -29,5 +57,5 @@ pub fn init_shared_client(client: Client) {
 
 /// Use the shared, global client
 pub fn client() -> &'static Client {
-    SHARED_CLIENT.get_or_init(|| Client::new())
+    SHARED_CLIENT.get_or_init(Client::new)
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http::{header, HeaderValue};
+
+use crate::{InMemoryRequest, Middleware, Response};
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+
+/// Credentials to send in the `Authorization` header.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+    Token(String),
+}
+
+impl Credentials {
+    pub fn basic(username: &str, password: &str) -> Self {
+        Credentials::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn bearer(token: &str) -> Self {
+        Credentials::Bearer(token.to_string())
+    }
+
+    pub fn token(token: &str) -> Self {
+        Credentials::Token(token.to_string())
+    }
+
+    /// The `Authorization` header value, marked as sensitive. Fails if a token contains characters which aren't
+    /// allowed in a header, like a line break.
+    pub fn header_value(&self) -> ProtocolResult<HeaderValue> {
+        let value = match self {
+            Credentials::Basic { username, password } => {
+                format!("Basic {}", STANDARD.encode(format!("{username}:{password}")))
+            }
+            Credentials::Bearer(token) => format!("Bearer {token}"),
+            Credentials::Token(token) => format!("Token {token}"),
+        };
+        let mut value = HeaderValue::from_str(&value).map_err(|_| {
+            ProtocolError::InvalidRequest(format!("{:?} contains characters not allowed in a header", self))
+        })?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Basic { username, .. } => write!(f, "Basic({username}, ****)"),
+            Credentials::Bearer(_) => write!(f, "Bearer(****)"),
+            Credentials::Token(_) => write!(f, "Token(****)"),
+        }
+    }
+}
+
+/// Set the `Authorization` header on every request.
+/// ```
+/// use httpclient::middleware::Authentication;
+/// let auth = Authentication::bearer("my-token").skip_if_present(true);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Authentication {
+    pub credentials: Credentials,
+    /// Leave requests that already have an `Authorization` header alone.
+    pub skip_if_present: bool,
+}
+
+impl Authentication {
+    pub fn new(credentials: Credentials) -> Self {
+        Authentication {
+            credentials,
+            skip_if_present: false,
+        }
+    }
+
+    pub fn basic(username: &str, password: &str) -> Self {
+        Self::new(Credentials::basic(username, password))
+    }
+
+    pub fn bearer(token: &str) -> Self {
+        Self::new(Credentials::bearer(token))
+    }
+
+    pub fn skip_if_present(mut self, skip: bool) -> Self {
+        self.skip_if_present = skip;
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for Authentication {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        if !(self.skip_if_present && request.headers().contains_key(header::AUTHORIZATION)) {
+            request.headers_mut().insert(header::AUTHORIZATION, self.credentials.header_value()?);
+        }
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct EchoAuth;
+
+    #[async_trait]
+    impl Middleware for EchoAuth {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let auth = request.header("authorization").unwrap_or_default().to_string();
+            Ok(http::Response::builder().header("x-auth", auth).body(Body::new_empty()).unwrap())
+        }
+    }
+
+    async fn sent_auth(builder: crate::RequestBuilder<'_>) -> String {
+        let res = builder.send().await.unwrap();
+        res.headers()["x-auth"].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let client = Client::new()
+            .with_middleware(Authentication::basic("aladdin", "opensesame"))
+            .with_middleware(EchoAuth);
+        assert_eq!(sent_auth(client.get("/")).await, "Basic YWxhZGRpbjpvcGVuc2VzYW1l");
+
+        let client = Client::new()
+            .with_middleware(Authentication::bearer("abc").skip_if_present(true))
+            .with_middleware(EchoAuth);
+        assert_eq!(sent_auth(client.get("/")).await, "Bearer abc");
+        assert_eq!(sent_auth(client.get("/").bearer_auth("override")).await, "Bearer override");
+
+        let e = client.get("/").bearer_auth("abc\r\nX-Injected: 1").send().await.unwrap_err();
+        assert!(matches!(e, ProtocolError::InvalidRequest(_)), "{e:?}");
+        let client = Client::new().with_middleware(Authentication::bearer("abc\n")).with_middleware(EchoAuth);
+        let e = client.get("/").send().await.unwrap_err();
+        assert_eq!(e.to_string(), "InvalidRequest: Bearer(****) contains characters not allowed in a header");
+    }
+}
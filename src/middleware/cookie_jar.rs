@@ -0,0 +1,351 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use http::{header, HeaderMap, HeaderValue, Uri};
+use serde::{Deserialize, Serialize};
+
+use crate::{InMemoryRequest, Middleware, Response};
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+
+/// A cookie as stored by the [`CookieJar`], with its scope resolved against the URL that set it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredCookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Set when the cookie had no `Domain` attribute, so it's only sent to the exact host that set it.
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for session cookies.
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl StoredCookie {
+    /// Parse a `Set-Cookie` header value received from `uri`. Returns `None` if the cookie is malformed,
+    /// or isn't allowed to be set by that host.
+    pub fn parse(uri: &Uri, set_cookie: &str) -> Option<Self> {
+        let cookie = cookie::Cookie::parse(set_cookie).ok()?;
+        let host = uri.host()?.to_ascii_lowercase();
+        let (domain, host_only) = match cookie.domain() {
+            Some(d) if !d.is_empty() => {
+                let d = d.trim_start_matches('.').to_ascii_lowercase();
+                if !domain_matches(&host, &d) {
+                    return None;
+                }
+                (d, false)
+            }
+            _ => (host, true),
+        };
+        let path = match cookie.path() {
+            Some(p) if p.starts_with('/') => p.to_string(),
+            _ => default_path(uri.path()),
+        };
+        let expires = if let Some(max_age) = cookie.max_age() {
+            let secs = max_age.whole_seconds();
+            if secs <= 0 {
+                Some(SystemTime::UNIX_EPOCH)
+            } else {
+                Some(SystemTime::now() + std::time::Duration::from_secs(secs as u64))
+            }
+        } else {
+            cookie.expires_datetime().map(SystemTime::from)
+        };
+        Some(StoredCookie {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            host_only,
+            path,
+            expires,
+            secure: cookie.secure().unwrap_or(false),
+            http_only: cookie.http_only().unwrap_or(false),
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.map(|e| e <= SystemTime::now()).unwrap_or(false)
+    }
+
+    /// Whether this cookie should be sent with a request to `uri`.
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let Some(host) = uri.host() else { return false };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        let secure_ok = !self.secure || uri.scheme_str() == Some("https");
+        domain_ok && secure_ok && path_matches(uri.path(), &self.path) && !self.is_expired()
+    }
+
+    fn same_identity(&self, other: &StoredCookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// RFC 6265 section 5.1.3
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+        && host.parse::<std::net::IpAddr>().is_err()
+}
+
+/// RFC 6265 section 5.1.4
+fn default_path(request_path: &str) -> String {
+    if !request_path.starts_with('/') {
+        return "/".to_string();
+    }
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => request_path[..i].to_string(),
+    }
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    let request_path = if request_path.is_empty() { "/" } else { request_path };
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || request_path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+/// Persistence for a [`CookieJar`], so sessions survive across runs.
+pub trait CookieStore: Send + Sync + Debug {
+    fn load(&self) -> std::io::Result<Vec<StoredCookie>>;
+    fn save(&self, cookies: &[StoredCookie]) -> std::io::Result<()>;
+}
+
+/// Stores cookies as a JSON array in a file.
+#[derive(Debug, Clone)]
+pub struct FileCookieStore {
+    pub path: PathBuf,
+}
+
+impl FileCookieStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileCookieStore { path: path.into() }
+    }
+}
+
+impl CookieStore for FileCookieStore {
+    /// A missing file is an empty store.
+    fn load(&self) -> std::io::Result<Vec<StoredCookie>> {
+        match std::fs::read(&self.path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, cookies: &[StoredCookie]) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write then rename, so a crash never leaves a truncated file behind.
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(cookies)?)?;
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+/// Stores cookies from `Set-Cookie` response headers, and sends them back on matching requests,
+/// following RFC 6265 domain, path, expiry, and `Secure` rules.
+///
+/// Clones share the same cookies. To have cookies applied on every hop of a redirect, add the jar
+/// after the `Follow` middleware.
+///
+/// By default cookies only live in memory. Use `CookieJar::with_store` to persist them.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<RwLock<Vec<StoredCookie>>>,
+    store: Option<Arc<dyn CookieStore>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load cookies from `store`. The middleware saves them back whenever a response sets cookies.
+    pub fn with_store(store: impl CookieStore + 'static) -> std::io::Result<Self> {
+        let mut cookies = store.load()?;
+        cookies.retain(|c| !c.is_expired());
+        Ok(CookieJar {
+            cookies: Arc::new(RwLock::new(cookies)),
+            store: Some(Arc::new(store)),
+        })
+    }
+
+    /// Write the current cookies to the store, if there is one.
+    pub fn save(&self) -> std::io::Result<()> {
+        match &self.store {
+            Some(store) => store.save(&self.cookies()),
+            None => Ok(()),
+        }
+    }
+
+    /// Store a cookie, replacing any existing cookie with the same name, domain, and path.
+    /// An expired cookie removes the existing one.
+    pub fn insert(&self, cookie: StoredCookie) {
+        let mut cookies = self.cookies.write().unwrap();
+        cookies.retain(|c| !c.same_identity(&cookie) && !c.is_expired());
+        if !cookie.is_expired() {
+            cookies.push(cookie);
+        }
+    }
+
+    /// Store every `Set-Cookie` in `headers`, as received from `uri`.
+    pub fn store_response_cookies(&self, uri: &Uri, headers: &HeaderMap) {
+        for value in headers.get_all(header::SET_COOKIE) {
+            let Ok(value) = value.to_str() else { continue };
+            if let Some(cookie) = StoredCookie::parse(uri, value) {
+                self.insert(cookie);
+            }
+        }
+    }
+
+    /// The cookies that would be sent to `uri`, longest paths first.
+    pub fn matching(&self, uri: &Uri) -> Vec<StoredCookie> {
+        let cookies = self.cookies.read().unwrap();
+        let mut matching: Vec<_> = cookies.iter()
+            .filter(|c| c.matches(uri))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        matching
+    }
+
+    /// The value of the `Cookie` header for a request to `uri`.
+    pub fn cookie_header(&self, uri: &Uri) -> Option<String> {
+        let matching = self.matching(uri);
+        if matching.is_empty() {
+            return None;
+        }
+        Some(matching.iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; "))
+    }
+
+    /// All unexpired cookies.
+    pub fn cookies(&self) -> Vec<StoredCookie> {
+        self.cookies.read().unwrap().iter()
+            .filter(|c| !c.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.cookies.write().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl Middleware for CookieJar {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let uri = request.uri().clone();
+        if let Some(cookies) = self.cookie_header(&uri) {
+            let value = match request.header(header::COOKIE.as_str()) {
+                Some(existing) => format!("{existing}; {cookies}"),
+                None => cookies,
+            };
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                request.headers_mut().insert(header::COOKIE, value);
+            }
+        }
+        let res = next.run(request).await?;
+        if res.headers().contains_key(header::SET_COOKIE) {
+            self.store_response_cookies(&uri, res.headers());
+            self.save()?;
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client};
+
+    use super::*;
+
+    fn uri(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_domain_and_path() {
+        let jar = CookieJar::new();
+        let origin = uri("https://www.example.com/account/login");
+        jar.insert(StoredCookie::parse(&origin, "a=1").unwrap());
+        jar.insert(StoredCookie::parse(&origin, "b=2; Domain=example.com; Path=/").unwrap());
+        jar.insert(StoredCookie::parse(&origin, "c=3; Secure; HttpOnly").unwrap());
+        assert!(StoredCookie::parse(&origin, "d=4; Domain=other.com").is_none());
+
+        assert_eq!(jar.cookie_header(&uri("https://www.example.com/account/settings")).unwrap(), "a=1; c=3; b=2");
+        assert_eq!(jar.cookie_header(&uri("http://www.example.com/account")).unwrap(), "a=1; b=2");
+        assert_eq!(jar.cookie_header(&uri("https://api.example.com/accounts")).unwrap(), "b=2");
+        assert_eq!(jar.cookie_header(&uri("https://example.org/")), None);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let jar = CookieJar::new();
+        let origin = uri("https://example.com/");
+        jar.insert(StoredCookie::parse(&origin, "a=1; Max-Age=3600").unwrap());
+        jar.insert(StoredCookie::parse(&origin, "b=2; Expires=Wed, 21 Oct 2015 07:28:00 GMT").unwrap());
+        assert_eq!(jar.cookie_header(&origin).unwrap(), "a=1");
+        jar.insert(StoredCookie::parse(&origin, "a=1; Max-Age=0").unwrap());
+        assert!(jar.cookies().is_empty());
+    }
+
+    #[derive(Debug)]
+    struct Login;
+
+    #[async_trait]
+    impl Middleware for Login {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let res = match request.path() {
+                "/login" => http::Response::builder().status(200).header("set-cookie", "session=abc; Path=/"),
+                _ if request.header("cookie") == Some("session=abc") => http::Response::builder().status(200),
+                _ => http::Response::builder().status(401),
+            };
+            Ok(res.body(Body::new_empty()).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let client = Client::new()
+            .with_middleware(CookieJar::new())
+            .with_middleware(Login);
+        assert_eq!(client.get("http://example.com/me").send().await.unwrap().status(), 401);
+        client.post("http://example.com/login").send().await.unwrap();
+        assert_eq!(client.get("http://example.com/me").send().await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let path = std::env::temp_dir()
+            .join(format!("httpclient-cookies-{}", std::process::id()))
+            .join("cookies.json");
+        let store = FileCookieStore::new(&path);
+        let client = Client::new()
+            .with_middleware(CookieJar::with_store(store.clone()).unwrap())
+            .with_middleware(Login);
+        client.post("http://example.com/login").send().await.unwrap();
+
+        let jar = CookieJar::with_store(store).unwrap();
+        assert_eq!(jar.cookie_header(&uri("http://example.com/me")).unwrap(), "session=abc");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
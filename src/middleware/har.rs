@@ -0,0 +1,147 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use async_trait::async_trait;
+
+use crate::{InMemoryRequest, Middleware, Response};
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::recorder::har::{self, HarEntry, HarTimings, iso8601};
+use crate::recorder::Sanitizer;
+use crate::response::{clone_inmemory_response, mem_response_into_hyper, response_into_content};
+
+/// Keep every request and response, with timings, in an in-memory [HAR](http://www.softwareishard.com/blog/har-12-spec/)
+/// log, which browsers' developer tools and many other tools can open. Clones share the log, so keep one to `save`
+/// it when needed.
+///
+/// Response bodies are buffered to record them. Entries are sanitized to hide secrets; use `.sanitizer()` to
+/// customize the redaction rules.
+/// ```
+/// use httpclient::Client;
+/// use httpclient::middleware::Har;
+/// let har = Har::new();
+/// let client = Client::new().with_middleware(har.clone());
+/// // ... make requests, then:
+/// // har.save("session.har")?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Har {
+    entries: Arc<Mutex<Vec<HarEntry>>>,
+    pub sanitizer: Sanitizer,
+}
+
+impl Har {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// The log as a HAR 1.2 document.
+    pub fn to_json(&self) -> serde_json::Value {
+        let entries = self.entries.lock().unwrap().clone();
+        serde_json::to_value(har::Har::new(entries)).expect("HAR entries are always serializable")
+    }
+
+    /// Write the log to `path`, replacing any file there.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.to_json())?;
+        std::fs::write(path, json)
+    }
+}
+
+#[async_trait]
+impl Middleware for Har {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let mut recorded = request.clone();
+        self.sanitizer.sanitize_request(&mut recorded);
+        let started = SystemTime::now();
+        let start = Instant::now();
+        let response = next.run(request).await?;
+        let wait = start.elapsed();
+        let response = response_into_content(response).await?;
+        let total = start.elapsed();
+        let mut recorded_response = clone_inmemory_response(&response);
+        self.sanitizer.sanitize_response(&mut recorded_response);
+        // Sending isn't measured separately, so it's counted in `wait`.
+        let entry = HarEntry {
+            started_date_time: iso8601(started),
+            timings: HarTimings {
+                send: 0.0,
+                wait: wait.as_secs_f64() * 1000.0,
+                receive: (total - wait).as_secs_f64() * 1000.0,
+            },
+            ..HarEntry::new(&recorded, &recorded_response).with_duration(Some(total))
+        };
+        self.entries.lock().unwrap().push(entry);
+        Ok(mem_response_into_hyper(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client, InMemoryBody};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Png;
+
+    #[async_trait]
+    impl Middleware for Png {
+        async fn handle(&self, _request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            Ok(http::Response::builder()
+                .header("content-type", "image/png")
+                .header("set-cookie", "session=abc")
+                .body(Body::InMemory(InMemoryBody::Bytes(vec![0x89, b'P', b'N', b'G'].into())))
+                .unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_har() {
+        let har = Har::new();
+        let client = Client::new().with_middleware(har.clone()).with_middleware(Png);
+        client.post("https://example.com/upload?a=1&b=two")
+            .header("authorization", "Bearer secret")
+            .text("hello".to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(har.len(), 1);
+        let json = har.to_json();
+        let entry = &json["log"]["entries"][0];
+        assert_eq!(json["log"]["version"], "1.2");
+        assert_eq!(entry["request"]["method"], "POST");
+        assert_eq!(entry["request"]["queryString"][1], serde_json::json!({"name": "b", "value": "two"}));
+        assert_eq!(entry["request"]["postData"]["text"], "hello");
+        let headers = entry["request"]["headers"].as_array().unwrap();
+        assert!(headers.contains(&serde_json::json!({"name": "authorization", "value": "**********"})));
+        assert_eq!(entry["response"]["status"], 200);
+        assert_eq!(entry["response"]["statusText"], "OK");
+        assert_eq!(entry["response"]["content"]["encoding"], "base64");
+        assert_eq!(entry["response"]["content"]["text"], "iVBORw==");
+        assert_eq!(entry["response"]["headers"][1]["value"], "**********");
+
+        let dir = tempfile::tempdir().unwrap();
+        har.save(dir.path().join("session.har")).unwrap();
+        har.clear();
+        assert!(har.is_empty());
+    }
+}
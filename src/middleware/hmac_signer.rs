@@ -0,0 +1,264 @@
+use std::fmt::{Debug, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use http::header::{HeaderName, InvalidHeaderValue};
+use http::HeaderValue;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{InMemoryBody, InMemoryRequest, Middleware, Response};
+use crate::body::encode_form;
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+impl SignatureEncoding {
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            SignatureEncoding::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            SignatureEncoding::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
+
+/// A piece of the request that goes into the string to sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignedComponent {
+    /// Uppercase method, e.g. `POST`.
+    Method,
+    /// The path, without the query.
+    Path,
+    /// The path, including the query if there is one.
+    PathAndQuery,
+    /// Unix timestamp in seconds.
+    Timestamp,
+    /// Hex-encoded SHA-256 of the body bytes.
+    BodyDigest,
+    /// The value of a request header, or an empty string if it's missing.
+    Header(HeaderName),
+}
+
+/// Sign requests with an HMAC over a canonical string built from the request, and put the signature
+/// (and the timestamp that went into it) in headers.
+///
+/// By default, the string to sign is `METHOD\npath?query\ntimestamp\nsha256(body)`, signed with
+/// HMAC-SHA256, and sent hex-encoded in `X-Signature`, with the timestamp in `X-Timestamp`.
+/// ```
+/// use httpclient::middleware::{HmacSigner, SignatureEncoding};
+/// let signer = HmacSigner::new(b"secret")
+///     .signature_header("x-hub-signature-256")
+///     .prefix("sha256=")?
+///     .encoding(SignatureEncoding::Hex);
+/// # Ok::<(), http::header::InvalidHeaderValue>(())
+/// ```
+#[derive(Clone)]
+pub struct HmacSigner {
+    key: Vec<u8>,
+    pub algorithm: HmacAlgorithm,
+    pub encoding: SignatureEncoding,
+    pub components: Vec<SignedComponent>,
+    pub separator: String,
+    pub signature_header: HeaderName,
+    /// Prepended to the encoded signature in the header value, e.g. `sha256=`.
+    pub prefix: String,
+    /// Header to send the timestamp in. `None` to not send it.
+    pub timestamp_header: Option<HeaderName>,
+}
+
+impl Debug for HmacSigner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HmacSigner")
+            .field("algorithm", &self.algorithm)
+            .field("encoding", &self.encoding)
+            .field("components", &self.components)
+            .field("signature_header", &self.signature_header)
+            .field("timestamp_header", &self.timestamp_header)
+            .finish()
+    }
+}
+
+impl HmacSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        HmacSigner {
+            key: key.into(),
+            algorithm: Default::default(),
+            encoding: Default::default(),
+            components: vec![
+                SignedComponent::Method,
+                SignedComponent::PathAndQuery,
+                SignedComponent::Timestamp,
+                SignedComponent::BodyDigest,
+            ],
+            separator: "\n".to_string(),
+            signature_header: HeaderName::from_static("x-signature"),
+            prefix: String::new(),
+            timestamp_header: Some(HeaderName::from_static("x-timestamp")),
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: HmacAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: SignatureEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn components(mut self, components: Vec<SignedComponent>) -> Self {
+        self.components = components;
+        self
+    }
+
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    pub fn signature_header(mut self, header: &str) -> Self {
+        self.signature_header = HeaderName::from_bytes(header.as_bytes()).expect("Invalid header name");
+        self
+    }
+
+    /// Fails if `prefix` contains characters which aren't allowed in a header, like a line break.
+    pub fn prefix(mut self, prefix: &str) -> Result<Self, InvalidHeaderValue> {
+        HeaderValue::from_str(prefix)?;
+        self.prefix = prefix.to_string();
+        Ok(self)
+    }
+
+    pub fn timestamp_header(mut self, header: Option<&str>) -> Self {
+        self.timestamp_header = header.map(|h| HeaderName::from_bytes(h.as_bytes()).expect("Invalid header name"));
+        self
+    }
+
+    /// The canonical string that gets signed.
+    pub fn string_to_sign(&self, request: &InMemoryRequest, timestamp: u64) -> String {
+        self.components.iter()
+            .map(|c| match c {
+                SignedComponent::Method => request.method().as_str().to_uppercase(),
+                SignedComponent::Path => request.path().to_string(),
+                SignedComponent::PathAndQuery => request.uri().path_and_query()
+                    .map(|pq| pq.as_str().to_string())
+                    .unwrap_or_else(|| "/".to_string()),
+                SignedComponent::Timestamp => timestamp.to_string(),
+                SignedComponent::BodyDigest => {
+                    SignatureEncoding::Hex.encode(&Sha256::digest(body_bytes(request.body())))
+                }
+                SignedComponent::Header(name) => request.header(name.as_str()).unwrap_or_default().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(&self.separator)
+    }
+
+    /// The header value for the signature of `request` at `timestamp`.
+    pub fn sign(&self, request: &InMemoryRequest, timestamp: u64) -> String {
+        let message = self.string_to_sign(request, timestamp);
+        let signature = match self.algorithm {
+            HmacAlgorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+                mac.update(message.as_bytes());
+                mac.finalize().into_bytes().to_vec()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+                mac.update(message.as_bytes());
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+        format!("{}{}", self.prefix, self.encoding.encode(&signature))
+    }
+}
+
+/// The bytes that will be sent on the wire for this body.
+fn body_bytes(body: &InMemoryBody) -> Vec<u8> {
+    match body {
+        InMemoryBody::Empty => Vec::new(),
+        InMemoryBody::Bytes(b) => b.to_vec(),
+        InMemoryBody::Text(s) => s.as_bytes().to_vec(),
+        InMemoryBody::Json(v) => serde_json::to_vec(v).unwrap(),
+        InMemoryBody::Form(pairs) => encode_form(pairs).into_bytes(),
+    }
+}
+
+#[async_trait]
+impl Middleware for HmacSigner {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let signature = self.sign(&request, timestamp);
+        let headers = request.headers_mut();
+        if let Some(name) = &self.timestamp_header {
+            headers.insert(name.clone(), HeaderValue::from(timestamp));
+        }
+        let mut value = HeaderValue::from_str(&signature).map_err(|_| {
+            let message = "The signature prefix contains characters not allowed in a header";
+            ProtocolError::InvalidRequest(message.to_string())
+        })?;
+        value.set_sensitive(true);
+        headers.insert(self.signature_header.clone(), value);
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        let request = Request::build_post("https://example.com/hooks?id=1")
+            .text("hello".to_string())
+            .build();
+        let signer = HmacSigner::new("secret");
+        assert_eq!(
+            signer.string_to_sign(&request, 1700000000),
+            "POST\n/hooks?id=1\n1700000000\n2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        );
+        let signer = HmacSigner::new("key")
+            .components(vec![SignedComponent::BodyDigest])
+            .prefix("sha256=")
+            .unwrap();
+        let sig = signer.sign(&Request::build_get("https://example.com/").build(), 0);
+        // HMAC-SHA256("key", sha256(""))
+        assert_eq!(sig, "sha256=75c040996f4e27689c78dd0babecd142b4bb61e78d505d6adcbafab971065dfb");
+        assert!(HmacSigner::new("key").prefix("sha256=\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_prefix() {
+        let mut signer = HmacSigner::new("key");
+        signer.prefix = "sha256=\n".to_string();
+        let client = crate::Client::new().with_middleware(signer);
+        let e = client.get("http://localhost/").send().await.unwrap_err();
+        assert!(matches!(e, ProtocolError::InvalidRequest(_)), "{e:?}");
+    }
+
+    #[test]
+    fn test_known_vector() {
+        let signer = HmacSigner::new("key")
+            .components(vec![SignedComponent::Header(HeaderName::from_static("x-message"))]);
+        let request = Request::build_get("https://example.com/")
+            .header("x-message", "The quick brown fox jumps over the lazy dog")
+            .build();
+        assert_eq!(signer.sign(&request, 0), "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+}
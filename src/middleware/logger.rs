@@ -0,0 +1,371 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use http::{HeaderMap, Uri};
+use serde::Serialize;
+use tracing::Level;
+
+use crate::{InMemoryBody, InMemoryRequest, Middleware, Response, TypedHeaders};
+use crate::body::encode_form;
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::sanitize::Sanitizer;
+
+/// How `Logger` writes requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The request line, headers, and body, each at its own level.
+    #[default]
+    Text,
+    /// A copy-pasteable `curl` command, with headers and body, at the line level. Secrets are still redacted.
+    Curl,
+    /// One JSON object per request, logged at the line level once the response head arrives, with the method, url,
+    /// status (or error), duration in milliseconds, and body sizes in bytes. The response size comes from its
+    /// Content-Length, so it's null for chunked responses. Headers and bodies aren't logged.
+    Json,
+}
+
+/// Log requests and responses through `tracing`, with the target `httpclient`, so they can be routed and filtered
+/// like any other logs.
+///
+/// By default, the request and response lines are logged at `INFO`, headers at `DEBUG`, and bodies at `TRACE`. Pass
+/// `None` to leave a part out. Response bodies are only buffered when their level is enabled.
+///
+/// Bodies longer than `max_body_size` bytes are truncated, and binary bodies, judged by their Content-Type or
+/// content, are summarized rather than printed. Binary responses aren't buffered at all.
+///
+/// Secrets are redacted with a [`Sanitizer`], by default the Authorization, Proxy-Authorization, Cookie, and
+/// Set-Cookie headers and secret-looking JSON and form fields. Use `.sanitizer()` to change the rules.
+/// ```
+/// use httpclient::Logger;
+/// use tracing::Level;
+/// let logger = Logger::new().headers_level(Some(Level::INFO)).body_level(None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Logger {
+    pub format: LogFormat,
+    pub line_level: Option<Level>,
+    pub headers_level: Option<Level>,
+    pub body_level: Option<Level>,
+    pub max_body_size: usize,
+    pub sanitizer: Sanitizer,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            format: LogFormat::Text,
+            line_level: Some(Level::INFO),
+            headers_level: Some(Level::DEBUG),
+            body_level: Some(Level::TRACE),
+            max_body_size: 4096,
+            sanitizer: Sanitizer::default(),
+        }
+    }
+}
+
+impl Logger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn line_level(mut self, level: Option<Level>) -> Self {
+        self.line_level = level;
+        self
+    }
+
+    pub fn headers_level(mut self, level: Option<Level>) -> Self {
+        self.headers_level = level;
+        self
+    }
+
+    pub fn body_level(mut self, level: Option<Level>) -> Self {
+        self.body_level = level;
+        self
+    }
+
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    fn headers_to_string(&self, headers: &HeaderMap, dir: char) -> String {
+        let mut headers = headers.clone();
+        self.sanitizer.sanitize_headers(&mut headers);
+        headers_to_string(&headers, dir)
+    }
+
+    fn body_to_string(&self, body: &InMemoryBody, headers: &HeaderMap) -> Option<String> {
+        if is_binary(headers) {
+            return Some(binary_summary(headers, Some(body_len(body))));
+        }
+        let mut body = body.clone();
+        self.sanitizer.sanitize_body(&mut body);
+        let text = match body_to_string(&body)? {
+            Some(text) => text,
+            None => return Some(binary_summary(headers, Some(body_len(&body)))),
+        };
+        Some(truncate(text, self.max_body_size))
+    }
+
+    fn log_request(&self, request: &InMemoryRequest, url: &str) {
+        log(self.line_level, &format!("> {} {} {:?}", request.method(), url, request.version()));
+        if enabled(self.headers_level) {
+            log(self.headers_level, &self.headers_to_string(request.headers(), '>'));
+        }
+        if enabled(self.body_level) {
+            if let Some(body) = self.body_to_string(request.body(), request.headers()) {
+                log(self.body_level, &body);
+            }
+        }
+    }
+
+    async fn log_json(&self, request: InMemoryRequest, url: &str, next: Next<'_>) -> ProtocolResult<Response> {
+        let method = request.method().clone();
+        let request_size = body_len(request.body());
+        let start = Instant::now();
+        let res = next.run(request).await;
+        let entry = LogEntry {
+            method: method.as_str(),
+            url,
+            status: res.as_ref().ok().map(|res| res.status().as_u16()),
+            error: res.as_ref().err().map(|e| e.to_string()),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+            request_size,
+            response_size: res.as_ref().ok().and_then(|res| res.content_length()),
+        };
+        log(self.line_level, &serde_json::to_string(&entry).unwrap());
+        res
+    }
+
+    fn url_to_string(&self, url: &Uri) -> String {
+        let mut url = url.to_string();
+        self.sanitizer.redact_text(&mut url);
+        url
+    }
+}
+
+#[derive(Serialize)]
+struct LogEntry<'a> {
+    method: &'a str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_ms: f64,
+    request_size: u64,
+    response_size: Option<u64>,
+}
+
+/// `tracing` macros need the level at compile time.
+fn log(level: Option<Level>, message: &str) {
+    match level {
+        Some(Level::ERROR) => tracing::error!(target: "httpclient", "{}", message),
+        Some(Level::WARN) => tracing::warn!(target: "httpclient", "{}", message),
+        Some(Level::INFO) => tracing::info!(target: "httpclient", "{}", message),
+        Some(Level::DEBUG) => tracing::debug!(target: "httpclient", "{}", message),
+        Some(Level::TRACE) => tracing::trace!(target: "httpclient", "{}", message),
+        None => {}
+    }
+}
+
+fn enabled(level: Option<Level>) -> bool {
+    match level {
+        Some(Level::ERROR) => tracing::enabled!(target: "httpclient", Level::ERROR),
+        Some(Level::WARN) => tracing::enabled!(target: "httpclient", Level::WARN),
+        Some(Level::INFO) => tracing::enabled!(target: "httpclient", Level::INFO),
+        Some(Level::DEBUG) => tracing::enabled!(target: "httpclient", Level::DEBUG),
+        Some(Level::TRACE) => tracing::enabled!(target: "httpclient", Level::TRACE),
+        None => false,
+    }
+}
+
+fn headers_to_string(headers: &HeaderMap, dir: char) -> String {
+    headers
+        .iter()
+        .map(|(k, v)| format!("{dir} {}: {}", k, String::from_utf8_lossy(v.as_bytes())))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The body as text, or `Some(None)` if it isn't text.
+fn body_to_string(body: &InMemoryBody) -> Option<Option<String>> {
+    if body.is_empty() {
+        return None;
+    }
+    Some(match body {
+        InMemoryBody::Text(s) => Some(s.clone()),
+        InMemoryBody::Json(o) => Some(serde_json::to_string(o).unwrap()),
+        InMemoryBody::Form(pairs) => Some(pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("\n")),
+        InMemoryBody::Bytes(bytes) => std::str::from_utf8(bytes).ok().map(str::to_string),
+        InMemoryBody::Empty => None,
+    })
+}
+
+pub(crate) fn body_len(body: &InMemoryBody) -> u64 {
+    match body {
+        InMemoryBody::Empty => 0,
+        InMemoryBody::Text(s) => s.len() as u64,
+        InMemoryBody::Bytes(b) => b.len() as u64,
+        InMemoryBody::Json(o) => serde_json::to_vec(o).map_or(0, |v| v.len() as u64),
+        InMemoryBody::Form(pairs) => encode_form(pairs).len() as u64,
+    }
+}
+
+/// Whether the Content-Type is one that isn't worth printing, like an image or `application/octet-stream`.
+fn is_binary(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.content_type() else {
+        return false;
+    };
+    let textual = content_type.type_() == mime::TEXT
+        || [Some(mime::JSON), Some(mime::XML)].contains(&content_type.suffix())
+        || [mime::JSON, mime::XML, mime::JAVASCRIPT, mime::WWW_FORM_URLENCODED].contains(&content_type.subtype())
+        || content_type.subtype().as_str().ends_with("json");
+    !textual
+}
+
+fn binary_summary(headers: &HeaderMap, len: Option<u64>) -> String {
+    let content_type = headers.content_type().map_or_else(|| "unknown type".to_string(), |ct| ct.essence_str().to_string());
+    match len {
+        Some(len) => format!("<binary body, {}, {} bytes>", content_type, len),
+        None => format!("<binary body, {}>", content_type),
+    }
+}
+
+/// Cut `text` to at most `max` bytes, on a character boundary.
+fn truncate(mut text: String, max: usize) -> String {
+    if text.len() <= max {
+        return text;
+    }
+    let len = text.len();
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    format!("{}… truncated ({} bytes)", text, len)
+}
+
+#[async_trait]
+impl Middleware for Logger {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let url = self.url_to_string(request.uri());
+        match self.format {
+            LogFormat::Text => self.log_request(&request, &url),
+            LogFormat::Curl if enabled(self.line_level) => {
+                let mut request = request.clone();
+                self.sanitizer.sanitize_request(&mut request);
+                log(self.line_level, &request.to_curl());
+            }
+            LogFormat::Curl => {}
+            LogFormat::Json => return self.log_json(request, &url, next).await,
+        }
+        let start = Instant::now();
+        let res = match next.run(request).await {
+            Ok(res) => res,
+            Err(e) => {
+                log(self.line_level, &format!("< {} failed after {:?}: {}", url, start.elapsed(), e));
+                return Err(e);
+            }
+        };
+        log(self.line_level, &format!("< {:?} {} {} ({:?})", res.version(), res.status(), url, start.elapsed()));
+        if enabled(self.headers_level) {
+            log(self.headers_level, &self.headers_to_string(res.headers(), '<'));
+        }
+        if !enabled(self.body_level) {
+            return Ok(res);
+        }
+        if is_binary(res.headers()) {
+            log(self.body_level, &binary_summary(res.headers(), res.content_length()));
+            return Ok(res);
+        }
+        let (parts, body) = res.into_parts();
+        let content_type = parts.headers.get(http::header::CONTENT_TYPE);
+        let body = body.into_content_type(content_type).await?;
+        if let Some(text) = self.body_to_string(&body, &parts.headers) {
+            log(self.body_level, &text);
+        }
+        Ok(Response::from_parts(parts, body.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/plain".parse().unwrap());
+        headers.insert("x-count", "2".parse().unwrap());
+        assert_eq!(headers_to_string(&headers, '>'), "> content-type: text/plain\n> x-count: 2");
+        assert_eq!(body_to_string(&InMemoryBody::Empty), None);
+        let form = InMemoryBody::Form(vec![("a".into(), "1".into()), ("b".into(), "2".into())]);
+        assert_eq!(body_to_string(&form).unwrap().unwrap(), "a=1\nb=2");
+        let json = InMemoryBody::Json(serde_json::json!({"a": 1}));
+        assert_eq!(body_to_string(&json).unwrap().unwrap(), r#"{"a":1}"#);
+        assert!(!enabled(None));
+    }
+
+    #[test]
+    fn test_redaction() {
+        let logger = Logger::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.insert("proxy-authorization", "Basic secret".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+        assert_eq!(logger.headers_to_string(&headers, '>'), "> authorization: **********\n> proxy-authorization: **********\n> accept: */*");
+        let json = InMemoryBody::Json(serde_json::json!({"user": "a", "password": "hunter2"}));
+        assert_eq!(logger.body_to_string(&json, &HeaderMap::new()).unwrap(), r#"{"password":"**********","user":"a"}"#);
+
+        let logger = Logger::new().sanitizer(Sanitizer::new().header("x-api-key").value_pattern("sk_[a-z0-9]+"));
+        let url = Uri::from_static("https://example.com/?key=sk_abc123");
+        assert_eq!(logger.url_to_string(&url), "https://example.com/?key=**********");
+    }
+
+    #[test]
+    fn test_body_limits() {
+        let logger = Logger::new().max_body_size(8);
+        let mut headers = HeaderMap::new();
+        let text = InMemoryBody::Text("héllo wörld".to_string());
+        assert_eq!(logger.body_to_string(&text, &headers).unwrap(), "héllo w… truncated (13 bytes)");
+        let bytes = InMemoryBody::Bytes(vec![0xff, 0xfe, 0x00].into());
+        assert_eq!(logger.body_to_string(&bytes, &headers).unwrap(), "<binary body, unknown type, 3 bytes>");
+
+        headers.insert("content-type", "image/png".parse().unwrap());
+        assert!(is_binary(&headers));
+        assert_eq!(logger.body_to_string(&text, &headers).unwrap(), "<binary body, image/png, 13 bytes>");
+        for textual in ["text/html; charset=utf-8", "application/json", "application/problem+json", "application/x-ndjson", "application/x-www-form-urlencoded"] {
+            headers.insert("content-type", textual.parse().unwrap());
+            assert!(!is_binary(&headers), "{textual}");
+        }
+    }
+
+    #[test]
+    fn test_json_entry() {
+        let entry = LogEntry {
+            method: "GET",
+            url: "https://example.com/",
+            status: Some(200),
+            error: None,
+            duration_ms: 12.5,
+            request_size: 0,
+            response_size: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"method":"GET","url":"https://example.com/","status":200,"duration_ms":12.5,"request_size":0,"response_size":null}"#
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::{Method, StatusCode};
+
+use crate::{InMemoryRequest, Middleware, Response, TypedHeaders};
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+
+use super::logger::body_len;
+
+/// What `Metrics` measured about one request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestMetrics {
+    pub host: String,
+    pub method: Method,
+    /// `None` if the request failed without a response.
+    pub status: Option<StatusCode>,
+    /// Until the response head arrived, or the request failed.
+    pub duration: Duration,
+    pub request_size: u64,
+    /// From the response's Content-Length, so `None` for chunked responses.
+    pub response_size: Option<u64>,
+}
+
+impl RequestMetrics {
+    /// Whether the request failed, or the server answered with a 5xx status.
+    pub fn is_error(&self) -> bool {
+        self.status.is_none_or(|status| status.is_server_error())
+    }
+
+    /// The `host`, `method`, and `status` labels, with `status` set to `error` if there was no response.
+    pub fn labels(&self) -> [(&'static str, String); 3] {
+        [
+            ("host", self.host.clone()),
+            ("method", self.method.to_string()),
+            ("status", self.status.map_or_else(|| "error".to_string(), |s| s.as_u16().to_string())),
+        ]
+    }
+}
+
+/// Where `Metrics` reports each request, e.g. a StatsD client or a Prometheus registry.
+pub trait MetricsSink: Send + Sync + Debug {
+    fn record(&self, metrics: &RequestMetrics);
+}
+
+/// Report request counts, errors, latencies, and payload sizes to a [`MetricsSink`], labeled by host, method, and
+/// status. Put it before `Retry` to measure whole requests, or after it to measure each attempt.
+///
+/// With the `metrics` feature, [`MetricsFacade`] reports to the `metrics` crate's global recorder.
+/// ```
+/// use httpclient::Client;
+/// use httpclient::middleware::{Metrics, MetricsSink, RequestMetrics};
+/// #[derive(Debug)]
+/// struct Print;
+/// impl MetricsSink for Print {
+///     fn record(&self, metrics: &RequestMetrics) {
+///         println!("{} {} {:?}", metrics.method, metrics.host, metrics.duration);
+///     }
+/// }
+/// let client = Client::new().with_middleware(Metrics::new(Print));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    sink: Arc<dyn MetricsSink>,
+}
+
+impl Metrics {
+    pub fn new(sink: impl MetricsSink + 'static) -> Self {
+        Metrics { sink: Arc::new(sink) }
+    }
+}
+
+#[async_trait]
+impl Middleware for Metrics {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let host = request.host().to_string();
+        let method = request.method().clone();
+        let request_size = body_len(request.body());
+        let start = Instant::now();
+        let res = next.run(request).await;
+        let metrics = RequestMetrics {
+            host,
+            method,
+            status: res.as_ref().ok().map(|res| res.status()),
+            duration: start.elapsed(),
+            request_size,
+            response_size: res.as_ref().ok().and_then(|res| res.content_length()),
+        };
+        self.sink.record(&metrics);
+        res
+    }
+}
+
+/// A [`MetricsSink`] for the `metrics` crate. Each request increments the `http_client_requests_total` counter, and
+/// `http_client_errors_total` if it failed, and records the `http_client_request_duration_seconds`,
+/// `http_client_request_size_bytes`, and `http_client_response_size_bytes` histograms.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsFacade;
+
+#[cfg(feature = "metrics")]
+impl MetricsSink for MetricsFacade {
+    fn record(&self, metrics: &RequestMetrics) {
+        let labels = metrics.labels();
+        ::metrics::counter!("http_client_requests_total", &labels).increment(1);
+        if metrics.is_error() {
+            ::metrics::counter!("http_client_errors_total", &labels).increment(1);
+        }
+        ::metrics::histogram!("http_client_request_duration_seconds", &labels).record(metrics.duration.as_secs_f64());
+        ::metrics::histogram!("http_client_request_size_bytes", &labels).record(metrics.request_size as f64);
+        if let Some(size) = metrics.response_size {
+            ::metrics::histogram!("http_client_response_size_bytes", &labels).record(size as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{Body, Client};
+    use crate::error::ProtocolError;
+
+    use super::*;
+
+    #[derive(Debug, Default, Clone)]
+    struct Collect(Arc<Mutex<Vec<RequestMetrics>>>);
+
+    impl MetricsSink for Collect {
+        fn record(&self, metrics: &RequestMetrics) {
+            self.0.lock().unwrap().push(metrics.clone());
+        }
+    }
+
+    /// Answers `/503` with a 503, fails `/fail`, and otherwise sends a small body.
+    #[derive(Debug)]
+    struct Server;
+
+    #[async_trait]
+    impl Middleware for Server {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            match request.url().path() {
+                "/503" => Ok(http::Response::builder().status(503).body(Body::new_empty()).unwrap()),
+                "/fail" => Err(ProtocolError::Timeout),
+                _ => Ok(http::Response::builder().header("content-length", "5").body(Body::new_empty()).unwrap()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let sink = Collect::default();
+        let client = Client::new().with_middleware(Metrics::new(sink.clone())).with_middleware(Server);
+        client.post("https://example.com/ok").text("hello".to_string()).send().await.unwrap();
+        client.get("https://example.com/503").send().await.unwrap();
+        client.get("https://example.com/fail").send().await.unwrap_err();
+        let recorded = sink.0.lock().unwrap();
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(recorded[0].labels(), [
+            ("host", "example.com".to_string()),
+            ("method", "POST".to_string()),
+            ("status", "200".to_string()),
+        ]);
+        assert_eq!((recorded[0].request_size, recorded[0].response_size), (5, Some(5)));
+        assert!(!recorded[0].is_error());
+        assert!(recorded[1].is_error());
+        assert_eq!(recorded[2].labels()[2].1, "error");
+        assert!(recorded[2].is_error());
+    }
+}
@@ -1,20 +1,50 @@
 use std::fmt::Debug;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
-use cookie::time;
-use cookie::time::format_description::well_known::Rfc2822;
-use http::Uri;
-use tokio::time::Duration;
-
+use http::HeaderValue;
+use hyper::body::HttpBody;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+pub use auth::*;
+pub use cookie_jar::*;
+pub use follow::*;
+pub use har::*;
+pub use hmac_signer::*;
+pub use logger::*;
+pub use metrics::*;
+pub use rate_limit::*;
 pub use recorder::*;
-
-use crate::{Body, InMemoryBody, InMemoryRequest, Response};
-use crate::client::Client;
+pub use retry::*;
+pub use revalidate::*;
+pub use slow::*;
+pub use trace::*;
+
+use crate::{Body, Deadline, Extensions, InMemoryRequest, Response, Trailers};
+use crate::body::{cancel_on, limit_size, StreamingBody};
+use crate::decompress::{accept_encoding, decode_response};
+use crate::expect;
+use crate::progress::{body_size, track, DownloadProgress, UploadProgress};
+use crate::trailers::{self, RequestTrailers};
+use crate::client::{hold_until_read, Client, Proxy};
 use crate::error::{ProtocolError, ProtocolResult};
 
+mod auth;
+pub mod oauth2;
+mod cookie_jar;
+mod follow;
+mod har;
+mod hmac_signer;
+mod logger;
+mod metrics;
+mod rate_limit;
 mod recorder;
+mod retry;
+mod revalidate;
+mod slow;
+mod trace;
 
 pub type MiddlewareStack = Vec<Arc<dyn Middleware>>;
 
@@ -25,17 +55,119 @@ pub struct Next<'a> {
 }
 
 impl Next<'_> {
-    pub async fn run(self, request: InMemoryRequest) -> ProtocolResult<Response> {
+    /// Run the rest of the chain. The response carries the extensions of the request as it was passed here, so a
+    /// middleware can read what it stored on the request with `ResponseExt::extension`, even if a later middleware
+    /// (like `Retry`) cloned the request or answered without going to the network. Values stored further down the
+    /// chain take precedence.
+    ///
+    /// Fails with `ProtocolError::Timeout` if the request's `Deadline` has passed, so a redirect or revalidation
+    /// can't start once the caller's budget is spent.
+    pub async fn run(self, mut request: InMemoryRequest) -> ProtocolResult<Response> {
+        if request.extensions().get::<Deadline>().is_some_and(Deadline::is_expired) {
+            return Err(ProtocolError::Timeout);
+        }
         if let Some((middleware, rest)) = self.middlewares.split_first() {
             let next = Next {
                 client: self.client,
                 middlewares: rest,
             };
-            middleware.handle(request, next).await
+            let extensions = request.extensions().clone();
+            let mut res = middleware.handle(request, next).await?;
+            if !extensions.is_empty() {
+                match res.extensions_mut().get_mut::<Extensions>() {
+                    Some(carried) => carried.fill(extensions),
+                    None => {
+                        res.extensions_mut().insert(extensions);
+                    }
+                }
+            }
+            Ok(res)
         } else {
-            let request = request.into_hyper();
-            let res = self.client.inner.request(request).await?;
-            let (parts, body) = res.into_parts();
+            // Whatever else middleware attached to the request is carried over to the response.
+            let mut extensions = std::mem::take(request.extensions_mut());
+            let streaming = extensions.remove::<StreamingBody>();
+            let upload_progress = extensions.remove::<UploadProgress>();
+            let download_progress = extensions.remove::<DownloadProgress>();
+            let request_trailers = extensions.remove::<RequestTrailers>();
+            let cancellation = extensions.get::<CancellationToken>().cloned();
+            let timeline = extensions.get::<Timeline>().cloned();
+            let proxy = extensions.get::<Proxy>().cloned();
+            let url = request.uri().clone();
+            let mut request = request.into_hyper();
+            if let Some(streaming) = streaming {
+                *request.body_mut() = streaming.take()?;
+            }
+            if let Some(UploadProgress(callback)) = upload_progress {
+                let total = body_size(request.headers(), request.body());
+                // Tracking hides the body's size from hyper, so make sure it's still sent.
+                if let Some(total) = total {
+                    request.headers_mut().entry(http::header::CONTENT_LENGTH).or_insert(total.into());
+                }
+                let body = std::mem::take(request.body_mut());
+                *request.body_mut() = track(body, total, callback);
+            }
+            let total = body_size(request.headers(), request.body());
+            let rejected = match (self.client.expect_continue, total) {
+                (Some((min_size, timeout)), Some(total)) if total >= min_size => {
+                    let rejected = Arc::new(Notify::new());
+                    request.headers_mut().insert(http::header::EXPECT, HeaderValue::from_static("100-continue"));
+                    request.headers_mut().entry(http::header::CONTENT_LENGTH).or_insert(total.into());
+                    let body = std::mem::take(request.body_mut());
+                    *request.body_mut() = expect::hold(body, timeout, rejected.clone());
+                    Some(rejected)
+                }
+                _ => None,
+            };
+            if let Some(RequestTrailers(trailers)) = request_trailers {
+                let body = std::mem::take(request.body_mut());
+                *request.body_mut() = trailers::append(body, trailers);
+            }
+            if self.client.decompress && !request.headers().contains_key(http::header::RANGE) {
+                request.headers_mut().entry(http::header::ACCEPT_ENCODING).or_insert_with(accept_encoding);
+            }
+            let active = url.authority().map(|authority| self.client.pool.start_request(authority.as_str()));
+            let started = Instant::now();
+            let res = self.client.send(request, proxy.as_ref());
+            let res = match self.client.read_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, res).await
+                    .map_err(|_| ProtocolError::Timeout)
+                    .and_then(|res| res.map_err(ProtocolError::from)),
+                None => res.await.map_err(ProtocolError::from),
+            };
+            if let Some(timeline) = timeline {
+                timeline.record(url.clone(), res.as_ref().ok().map(|res| res.status()), started);
+            }
+            let res = res?;
+            if let Some(rejected) = rejected {
+                if res.status().is_client_error() || res.status().is_server_error() {
+                    rejected.notify_one();
+                }
+            }
+            let (mut parts, mut body) = res.into_parts();
+            parts.extensions.insert(url);
+            parts.extensions.insert(extensions);
+            // Only bodies of unknown length, i.e. chunked or HTTP/2, can be followed by trailers.
+            if HttpBody::size_hint(&body).exact().is_none() {
+                let trailers = Trailers::default();
+                body = trailers::capture(body, trailers.clone());
+                parts.extensions.insert(trailers);
+            }
+            if let Some(DownloadProgress(callback)) = download_progress {
+                let total = body_size(&parts.headers, &body);
+                body = track(body, total, callback);
+            }
+            if self.client.decompress {
+                body = decode_response(&mut parts.headers, body, &self.client.decompression_limits);
+            }
+            if let Some(max_size) = self.client.max_body_size {
+                body = limit_size(body, max_size);
+            }
+            if let Some(token) = cancellation {
+                body = cancel_on(body, token);
+            }
+            if let Some(active) = active {
+                body = hold_until_read(body, active);
+            }
             let body: Body = body.into();
             let res = Response::from_parts(parts, body);
             Ok(res)
@@ -50,154 +182,46 @@ pub trait Middleware: Send + Sync + Debug {
     }
 }
 
-#[derive(Debug)]
-/// Retry a request up to 3 times.
-/// TODO: Make this configurable.
-/// TODO: Delays
-/// TODO: Backoff
-pub struct Retry;
-
-fn calc_delay(res: &Response) -> Option<Duration> {
-    let Some(v) = res.headers().get(http::header::RETRY_AFTER) else { return None; };
-    let retry_after = v.to_str().unwrap();
-    if let Some(retry_after) = retry_after.parse().ok() {
-        Some(Duration::from_secs(retry_after))
-    } else if let Some(dt) = time::OffsetDateTime::parse(retry_after, &Rfc2822).ok() {
-        let dur = dt - time::OffsetDateTime::now_utc();
-        Some(dur.try_into().unwrap())
-    } else {
-        None
-    }
-}
-
-#[async_trait]
-impl Middleware for Retry {
-    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
-        let mut i = 0usize;
-        loop {
-            i += 1;
-            if i > 3 {
-                return Err(ProtocolError::TooManyRetries)
-            }
-            match next.run(request.clone().into()).await {
-                Ok(res) => {
-                    let status = res.status();
-                    let status_as_u16 = status.as_u16();
-                    if !([429, 408, 425].contains(&status_as_u16) || status.is_server_error()) {
-                        return Ok(res);
-                    }
-                    if let Some(delay) = calc_delay(&res) {
-                        tokio::time::sleep(delay).await;
-                    }
-                }
-                Err(err) => return Err(err),
-            }
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct Logger;
-
-fn headers_to_string(headers: &http::HeaderMap, dir: char) -> String {
-    headers
-        .iter()
-        .map(|(k, v)| format!("{dir} {}: {}", k, v.to_str().unwrap()))
-        .collect::<Vec<_>>()
-        .join("\n")
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[async_trait]
-impl Middleware for Logger {
-    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
-        let url = request.uri().to_string();
-        let method = request.method().as_str().to_uppercase();
-        let version = request.version();
-        let headers = headers_to_string(request.headers(), '>');
-        let body = request.body();
-        println!(">>> Request:
-> {method} {url} {version:?}
-{headers}");
-        if !body.is_empty() {
-            match body {
-                InMemoryBody::Text(s) => println!("{}", s),
-                InMemoryBody::Json(o) => println!("{}", serde_json::to_string(&o).unwrap()),
-                _ => println!("{:?}", body),
-            }
-        }
-        let res = next.run(request).await;
-        match res {
-            Err(e) => {
-                println!("<<< Response to {url}:\n{e}");
-                Err(e)
-            }
-            Ok(res) => {
-                let version = res.version();
-                let status = res.status();
-                let headers = headers_to_string(res.headers(), '<');
-                println!("<<< Response to {url}:
-< {version:?} {status}
-{headers}");
-                let (parts, body) = res.into_parts();
-                let content_type = parts.headers.get(http::header::CONTENT_TYPE);
-                let body = body.into_content_type(content_type).await?;
-                match &body {
-                    InMemoryBody::Text(text) => println!("{}", text),
-                    InMemoryBody::Json(o) => println!("{}", serde_json::to_string(&o).unwrap()),
-                    _ => println!("{:?}", body),
-                }
-                let res = Response::from_parts(parts, body.into());
-                Ok(res)
-            }
-        }
-    }
-}
+    #[derive(Debug, Clone, PartialEq)]
+    struct CacheStatus(&'static str);
 
-#[derive(Debug, Clone)]
-/// Follow redirects.
-pub struct Follow;
+    #[derive(Debug, Clone, PartialEq)]
+    struct Attempts(u32);
 
-/// Given an original Url, redirect to the new path.
-fn fix_url(original: &Uri, redirect_url: &str) -> Uri {
-    let url = Uri::from_str(redirect_url).unwrap();
-    let mut parts = url.into_parts();
-    if parts.authority.is_none() {
-        parts.authority = original.authority().cloned();
-    }
-    if parts.scheme.is_none() {
-        parts.scheme = original.scheme().cloned();
-    }
-    Uri::from_parts(parts).unwrap()
-}
+    #[derive(Debug)]
+    struct Annotate;
 
-#[async_trait]
-impl Middleware for Follow {
-    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
-        let mut res = next.run(request.clone().into()).await?;
-        let mut allowed_redirects = 10;
-        while res.status().is_redirection() {
-            if allowed_redirects == 0 {
-                return Err(ProtocolError::TooManyRedirects);
-            }
-            let redirect = res.headers().get(http::header::LOCATION).expect("Received a 3xx status code, but no location header was sent.").to_str().unwrap();
-            let url = fix_url(request.url(), redirect);
-            let request = request.clone();
-            let request = request.set_url(url);
-            allowed_redirects -= 1;
-            res = next.run(request.into()).await?;
+    #[async_trait]
+    impl Middleware for Annotate {
+        async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+            request.extensions_mut().insert(CacheStatus("miss"));
+            let mut res = next.run(request).await?;
+            res.extensions_mut().insert(Attempts(1));
+            Ok(res)
         }
-        Ok(res)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_relative_route() {
-        let original = Uri::from_str("https://www.google.com/").unwrap();
-        let url = fix_url(&original, "/test");
-        assert_eq!(url.to_string(), "https://www.google.com/test");
+    #[tokio::test]
+    async fn test_extensions() {
+        use hyper::service::{make_service_fn, service_fn};
+        use crate::ResponseExt;
+
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|_| async { Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::empty())) }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new().with_middleware(Annotate);
+        let res = client.get(&format!("http://{addr}/")).extension("caller").send().await.unwrap();
+        assert_eq!(res.extension::<Attempts>(), Some(&Attempts(1)));
+        assert_eq!(res.extension::<CacheStatus>(), Some(&CacheStatus("miss")));
+        assert_eq!(res.extension::<&str>(), Some(&"caller"));
+        assert!(res.extension::<StreamingBody>().is_none());
     }
 }
\ No newline at end of file
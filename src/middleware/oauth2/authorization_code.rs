@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use http::Uri;
+use indexmap::IndexMap;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::Client;
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::middleware::oauth2::{ClientAuth, Oauth2Error, refresh_token_grant, request_token_with, token_request, Token, TokenResponse, TokenSource};
+
+const UNRESERVED: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
+
+fn random_string(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len)
+        .map(|_| UNRESERVED[rng.gen_range(0..UNRESERVED.len())] as char)
+        .collect()
+}
+
+/// A PKCE code verifier and its S256 challenge (RFC 7636).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    /// Generate a random verifier.
+    pub fn new() -> Self {
+        Self::from_verifier(random_string(64))
+    }
+
+    pub fn from_verifier(verifier: impl Into<String>) -> Self {
+        let verifier = verifier.into();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Pkce { verifier, challenge }
+    }
+
+    pub fn method(&self) -> &'static str {
+        "S256"
+    }
+}
+
+impl Default for Pkce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where to send the user, and the secrets needed to complete the flow when they come back.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+    pub pkce: Pkce,
+}
+
+impl AuthorizationRequest {
+    /// Parse the URL the user was redirected back to, check the `state`, and return the authorization code.
+    pub fn callback(&self, redirect_url: &str) -> Result<String, Oauth2Error> {
+        let uri = Uri::try_from(redirect_url).map_err(|_| invalid("Invalid redirect URL"))?;
+        let mut params: IndexMap<String, String> = uri.query()
+            .map(|q| serde_qs::from_str(q).unwrap_or_default())
+            .unwrap_or_default();
+        if let Some(error) = params.shift_remove("error") {
+            return Err(Oauth2Error {
+                error,
+                error_description: params.shift_remove("error_description"),
+                error_uri: params.shift_remove("error_uri"),
+            });
+        }
+        if params.get("state") != Some(&self.state) {
+            return Err(invalid("State does not match the authorization request"));
+        }
+        params.shift_remove("code").ok_or_else(|| invalid("Redirect URL has no code"))
+    }
+}
+
+fn invalid(description: &str) -> Oauth2Error {
+    Oauth2Error {
+        error: "invalid_request".to_string(),
+        error_description: Some(description.to_string()),
+        error_uri: None,
+    }
+}
+
+/// The authorization code grant (RFC 6749 section 4.1) with PKCE, for acting on behalf of a user.
+///
+/// 1. Send the user to `authorize().url`.
+/// 2. Pass the URL they're redirected back to into `AuthorizationRequest::callback` to get the code.
+/// 3. Call `exchange_code` to get a token.
+/// 4. Use the flow as the token source for the `Oauth2` middleware, starting with that token.
+///    It will refresh the token with the refresh token grant.
+/// ```ignore
+/// let flow = AuthorizationCode::new("https://auth.example.com/authorize", "https://auth.example.com/token", "id", "http://localhost:8080/callback");
+/// let auth = flow.authorize();
+/// // ... redirect the user to auth.url, receive the callback ...
+/// let code = auth.callback(&callback_url)?;
+/// let token = flow.exchange_code(&Client::new(), &code, &auth.pkce).await?;
+/// let client = Client::new().with_middleware(Oauth2::new(flow).token(token));
+/// ```
+#[derive(Clone)]
+pub struct AuthorizationCode {
+    pub auth_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub auth: ClientAuth,
+}
+
+impl std::fmt::Debug for AuthorizationCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthorizationCode")
+            .field("auth_url", &self.auth_url)
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("redirect_uri", &self.redirect_uri)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+impl AuthorizationCode {
+    pub fn new(auth_url: &str, token_url: &str, client_id: &str, redirect_uri: &str) -> Self {
+        AuthorizationCode {
+            auth_url: auth_url.to_string(),
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: None,
+            redirect_uri: redirect_uri.to_string(),
+            scopes: Vec::new(),
+            auth: ClientAuth::default(),
+        }
+    }
+
+    /// Confidential clients also authenticate with a secret. Public clients rely on PKCE alone.
+    pub fn client_secret(mut self, client_secret: &str) -> Self {
+        self.client_secret = Some(client_secret.to_string());
+        self
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    pub fn auth(mut self, auth: ClientAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Build the authorization URL with a fresh `state` and PKCE challenge.
+    pub fn authorize(&self) -> AuthorizationRequest {
+        self.authorize_with(random_string(32), Pkce::new())
+    }
+
+    pub fn authorize_with(&self, state: String, pkce: Pkce) -> AuthorizationRequest {
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("redirect_uri", &self.redirect_uri),
+            ("state", &state),
+            ("code_challenge", &pkce.challenge),
+            ("code_challenge_method", pkce.method()),
+        ];
+        let scope = self.scopes.join(" ");
+        if !scope.is_empty() {
+            params.push(("scope", &scope));
+        }
+        let query = params.iter()
+            .map(|(k, v)| format!("{}={}", k, urlencoding::encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let sep = if self.auth_url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}", self.auth_url, sep, query);
+        AuthorizationRequest { url, state, pkce }
+    }
+
+    /// Exchange an authorization code for a token. `client` sends the request through its middleware,
+    /// so it shouldn't be a client that has this flow's `Oauth2` middleware.
+    pub async fn exchange_code(&self, client: &Client, code: &str, pkce: &Pkce) -> ProtocolResult<Token> {
+        let mut form = IndexMap::new();
+        form.insert("grant_type", "authorization_code".to_string());
+        form.insert("code", code.to_string());
+        form.insert("redirect_uri", self.redirect_uri.clone());
+        form.insert("code_verifier", pkce.verifier.clone());
+        let request = token_request(&self.token_url, form, self.auth, &self.client_id, self.client_secret.as_deref());
+        let res: TokenResponse = request_token_with(client, request).await?;
+        Ok(res.into())
+    }
+}
+
+#[async_trait]
+impl TokenSource for AuthorizationCode {
+    /// Refresh the token. Fails if there's no refresh token, since a new token requires the user.
+    async fn fetch_token(&self, current: Option<&Token>, next: Next<'_>) -> ProtocolResult<Token> {
+        refresh_token_grant(current, next, &self.token_url, self.auth, &self.client_id, self.client_secret.as_deref()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, InMemoryBody, InMemoryRequest, Middleware, Response};
+
+    use super::*;
+
+    #[test]
+    fn test_pkce() {
+        // RFC 7636 Appendix B
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+        assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+        assert_eq!(Pkce::new().verifier.len(), 64);
+    }
+
+    fn flow() -> AuthorizationCode {
+        AuthorizationCode::new("https://auth.example.com/authorize", "https://auth.example.com/token", "app", "http://localhost/cb")
+            .scope("repo")
+            .scope("user")
+    }
+
+    #[test]
+    fn test_authorize_and_callback() {
+        let auth = flow().authorize_with("xyz".to_string(), Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"));
+        assert_eq!(auth.url, "https://auth.example.com/authorize?response_type=code&client_id=app&redirect_uri=http%3A%2F%2Flocalhost%2Fcb&state=xyz&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256&scope=repo%20user");
+        assert_eq!(auth.callback("http://localhost/cb?code=abc&state=xyz").unwrap(), "abc");
+        assert_eq!(auth.callback("http://localhost/cb?code=abc&state=forged").unwrap_err().error, "invalid_request");
+        assert_eq!(auth.callback("http://localhost/cb?error=access_denied&state=xyz").unwrap_err().error, "access_denied");
+    }
+
+    #[derive(Debug)]
+    struct TokenEndpoint;
+
+    #[async_trait]
+    impl Middleware for TokenEndpoint {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> crate::ProtocolResult<Response> {
+            let form = request.body().clone().text().unwrap();
+            assert_eq!(form, "grant_type=authorization_code&code=abc&redirect_uri=http%3A%2F%2Flocalhost%2Fcb&code_verifier=v&client_id=app");
+            let body = InMemoryBody::new_json(serde_json::json!({"access_token": "t", "token_type": "bearer", "refresh_token": "r"}));
+            Ok(http::Response::builder().body(Body::from(body)).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exchange_code() {
+        let client = Client::new().with_middleware(TokenEndpoint);
+        let token = flow().exchange_code(&client, "abc", &Pkce::from_verifier("v")).await.unwrap();
+        assert_eq!(token.access_token, "t");
+        assert_eq!(token.refresh_token.as_deref(), Some("r"));
+    }
+}
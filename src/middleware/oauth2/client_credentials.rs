@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use indexmap::IndexMap;
+
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::middleware::oauth2::{ClientAuth, request_token, token_request, Token, TokenResponse, TokenSource};
+
+/// The client credentials grant (RFC 6749 section 4.4), for machine-to-machine APIs.
+#[derive(Clone)]
+pub struct ClientCredentials {
+    pub token_url: String,
+    pub client_id: String,
+    client_secret: String,
+    pub scopes: Vec<String>,
+    pub auth: ClientAuth,
+    /// Extra form parameters, e.g. `audience`.
+    pub params: Vec<(String, String)>,
+}
+
+impl std::fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scopes", &self.scopes)
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl ClientCredentials {
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        ClientCredentials {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scopes: Vec::new(),
+            auth: ClientAuth::default(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    pub fn auth(mut self, auth: ClientAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn param(mut self, key: &str, value: &str) -> Self {
+        self.params.push((key.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[async_trait]
+impl TokenSource for ClientCredentials {
+    async fn fetch_token(&self, _current: Option<&Token>, next: Next<'_>) -> ProtocolResult<Token> {
+        let mut form = IndexMap::new();
+        form.insert("grant_type", "client_credentials".to_string());
+        if !self.scopes.is_empty() {
+            form.insert("scope", self.scopes.join(" "));
+        }
+        form.extend(self.params.iter().map(|(k, v)| (k.as_str(), v.clone())));
+        let request = token_request(&self.token_url, form, self.auth, &self.client_id, Some(&self.client_secret));
+        let res: TokenResponse = request_token(request, next).await?;
+        Ok(res.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{Body, Client, InMemoryRequest, Middleware, ProtocolError, Response};
+    use crate::middleware::oauth2::{FileTokenStore, Oauth2, TokenStore};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct AuthServer {
+        expires_in: u64,
+        tokens_issued: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Middleware for AuthServer {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let res = if request.path() == "/token" {
+                assert_eq!(request.header("authorization"), Some("Basic aWQ6c2VjcmV0"));
+                assert_eq!(request.body().clone().text().unwrap(), "grant_type=client_credentials&scope=read+write");
+                let n = self.tokens_issued.fetch_add(1, Ordering::SeqCst) + 1;
+                let body = format!(r#"{{"access_token":"t{n}","token_type":"bearer","expires_in":{}}}"#, self.expires_in);
+                http::Response::builder().header("content-type", "application/json").body(Body::from(crate::InMemoryBody::Text(body)))
+            } else {
+                let auth = request.header("authorization").unwrap().to_string();
+                http::Response::builder().header("x-auth", auth).body(Body::new_empty())
+            };
+            Ok(res.unwrap())
+        }
+    }
+
+    async fn run(expires_in: u64) -> (Vec<String>, usize) {
+        let server = Arc::new(AuthServer { expires_in, tokens_issued: AtomicUsize::new(0) });
+        let credentials = ClientCredentials::new("https://auth.example.com/token", "id", "secret")
+            .scope("read")
+            .scope("write");
+        let mut client = Client::new().with_middleware(Oauth2::new(credentials));
+        client.middlewares.push(server.clone());
+        let mut auths = Vec::new();
+        for _ in 0..2 {
+            let res = client.get("https://api.example.com/me").send().await.unwrap();
+            auths.push(res.headers()["x-auth"].to_str().unwrap().to_string());
+        }
+        (auths, server.tokens_issued.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_caches_token() {
+        let (auths, issued) = run(3600).await;
+        assert_eq!(auths, vec!["Bearer t1", "Bearer t1"]);
+        assert_eq!(issued, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_expiring_token() {
+        let (auths, issued) = run(30).await;
+        assert_eq!(auths, vec!["Bearer t1", "Bearer t2"]);
+        assert_eq!(issued, 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_token() {
+        let credentials = ClientCredentials::new("https://auth.example.com/token", "id", "secret");
+        let oauth2 = Oauth2::new(credentials).token(Token {
+            access_token: "t1\r\nX-Injected: 1".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_at: None,
+            refresh_token: None,
+            scope: None,
+        });
+        let client = Client::new().with_middleware(oauth2.clone());
+        let e = client.get("https://api.example.com/me").send().await.unwrap_err();
+        assert!(matches!(e, ProtocolError::Oauth2Error(e) if e.error == "invalid_token"));
+
+        oauth2.set_token(Token { access_token: "t2".to_string(), ..oauth2.current_token().await.unwrap() }).await;
+        assert_eq!(oauth2.current_token().await.unwrap().header_value().unwrap(), "Bearer t2");
+    }
+
+    #[tokio::test]
+    async fn test_token_store() {
+        let path = std::env::temp_dir()
+            .join(format!("httpclient-token-{}", std::process::id()))
+            .join("token.json");
+        let store = FileTokenStore::new(&path);
+        store.save(&Token {
+            access_token: "stored".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_at: Some(std::time::SystemTime::now()),
+            refresh_token: None,
+            scope: None,
+        }).await.unwrap();
+
+        let server = Arc::new(AuthServer { expires_in: 3600, tokens_issued: AtomicUsize::new(0) });
+        let credentials = ClientCredentials::new("https://auth.example.com/token", "id", "secret")
+            .scope("read")
+            .scope("write");
+        let refreshed = Arc::new(AtomicUsize::new(0));
+        let hook = refreshed.clone();
+        let oauth2 = Oauth2::new(credentials)
+            .token_store(store.clone())
+            .on_refresh(move |_| { hook.fetch_add(1, Ordering::SeqCst); });
+        let mut client = Client::new().with_middleware(oauth2);
+        client.middlewares.push(server.clone());
+        client.get("https://api.example.com/me").send().await.unwrap();
+
+        // The stored token was expired, so it was replaced and the new one saved.
+        assert_eq!(store.load().await.unwrap().unwrap().access_token, "t1");
+        assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::Client;
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+use crate::middleware::oauth2::{ClientAuth, Oauth2Error, refresh_token_grant, request_token_with, token_request, Token, TokenResponse, TokenSource};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// The device authorization response (RFC 8628 section 3.2). Show `user_code` and `verification_uri`
+/// to the user, then `poll` for the token.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    /// Seconds until the device code expires.
+    pub expires_in: u64,
+    /// Seconds to wait between polls.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+/// The device authorization grant (RFC 8628), for CLIs and devices without a browser.
+/// ```ignore
+/// let flow = DeviceCode::new("https://auth.example.com/device", "https://auth.example.com/token", "id").scope("read");
+/// let client = Client::new();
+/// let auth = flow.start(&client).await?;
+/// println!("Go to {} and enter {}", auth.verification_uri, auth.user_code);
+/// let token = flow.poll(&client, &auth).await?;
+/// let client = Client::new().with_middleware(Oauth2::new(flow).token(token));
+/// ```
+#[derive(Clone)]
+pub struct DeviceCode {
+    pub device_auth_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    client_secret: Option<String>,
+    pub scopes: Vec<String>,
+    pub auth: ClientAuth,
+}
+
+impl std::fmt::Debug for DeviceCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceCode")
+            .field("device_auth_url", &self.device_auth_url)
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+impl DeviceCode {
+    pub fn new(device_auth_url: &str, token_url: &str, client_id: &str) -> Self {
+        DeviceCode {
+            device_auth_url: device_auth_url.to_string(),
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: None,
+            scopes: Vec::new(),
+            auth: ClientAuth::default(),
+        }
+    }
+
+    pub fn client_secret(mut self, client_secret: &str) -> Self {
+        self.client_secret = Some(client_secret.to_string());
+        self
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    pub fn auth(mut self, auth: ClientAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Request a device code and user code.
+    pub async fn start(&self, client: &Client) -> ProtocolResult<DeviceAuthorization> {
+        let mut form = IndexMap::new();
+        if !self.scopes.is_empty() {
+            form.insert("scope", self.scopes.join(" "));
+        }
+        let request = token_request(&self.device_auth_url, form, self.auth, &self.client_id, self.client_secret.as_deref());
+        request_token_with(client, request).await
+    }
+
+    /// Poll the token endpoint until the user approves or denies the request, or the code expires.
+    /// Waits `interval` between polls, and backs off by 5 seconds each time the server says `slow_down`.
+    pub async fn poll(&self, client: &Client, authorization: &DeviceAuthorization) -> ProtocolResult<Token> {
+        let expires = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = Duration::from_secs(authorization.interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            if Instant::now() >= expires {
+                return Err(ProtocolError::Oauth2Error(Oauth2Error {
+                    error: "expired_token".to_string(),
+                    error_description: Some("The device code expired before the user authorized it.".to_string()),
+                    error_uri: None,
+                }));
+            }
+            let mut form = IndexMap::new();
+            form.insert("grant_type", DEVICE_CODE_GRANT.to_string());
+            form.insert("device_code", authorization.device_code.clone());
+            let request = token_request(&self.token_url, form, self.auth, &self.client_id, self.client_secret.as_deref());
+            match request_token_with::<TokenResponse>(client, request).await {
+                Ok(res) => return Ok(res.into()),
+                Err(ProtocolError::Oauth2Error(e)) if e.error == "authorization_pending" => {}
+                Err(ProtocolError::Oauth2Error(e)) if e.error == "slow_down" => {
+                    interval += Duration::from_secs(5);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TokenSource for DeviceCode {
+    /// Refresh the token. Fails if there's no refresh token, since a new token requires the user.
+    async fn fetch_token(&self, current: Option<&Token>, next: Next<'_>) -> ProtocolResult<Token> {
+        refresh_token_grant(current, next, &self.token_url, self.auth, &self.client_id, self.client_secret.as_deref()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::json;
+
+    use crate::{Body, InMemoryBody, InMemoryRequest, Middleware, Response};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct DeviceServer {
+        polls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Middleware for DeviceServer {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let (status, body) = match request.path() {
+                "/device" => (200, json!({
+                    "device_code": "dc", "user_code": "WDJB-MJHT", "verification_url": "https://example.com/device",
+                    "expires_in": 60, "interval": 0,
+                })),
+                _ => match self.polls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => (400, json!({"error": "authorization_pending"})),
+                    _ => (200, json!({"access_token": "t", "token_type": "bearer"})),
+                },
+            };
+            Ok(http::Response::builder().status(status).body(Body::from(InMemoryBody::new_json(body))).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_flow() {
+        let client = Client::new().with_middleware(DeviceServer::default());
+        let flow = DeviceCode::new("https://auth.example.com/device", "https://auth.example.com/token", "cli");
+        let auth = flow.start(&client).await.unwrap();
+        assert_eq!(auth.user_code, "WDJB-MJHT");
+        assert_eq!(auth.verification_uri, "https://example.com/device");
+        let token = flow.poll(&client, &auth).await.unwrap();
+        assert_eq!(token.access_token, "t");
+    }
+}
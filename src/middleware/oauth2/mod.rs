@@ -0,0 +1,331 @@
+//! OAuth2 support. The [`Oauth2`] middleware attaches an access token to every request, fetching and
+//! refreshing it from a [`TokenSource`] as needed.
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use http::{header, HeaderValue, StatusCode};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{Client, InMemoryRequest, Middleware, Request, Response};
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+
+pub use authorization_code::*;
+pub use client_credentials::*;
+pub use device::*;
+pub use store::*;
+
+mod authorization_code;
+mod client_credentials;
+mod device;
+mod store;
+
+/// An access token, with everything needed to decide when to refresh it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub access_token: String,
+    pub token_type: String,
+    /// `None` if the server didn't say when the token expires.
+    pub expires_at: Option<SystemTime>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl Token {
+    /// Whether the token expires within `leeway` from now.
+    pub fn expires_within(&self, leeway: Duration) -> bool {
+        self.expires_at
+            .map(|e| e <= SystemTime::now() + leeway)
+            .unwrap_or(false)
+    }
+
+    /// The `Authorization` header value. Fails if the token type or access token can't be put in a header, e.g.
+    /// because the token endpoint returned one with a line break.
+    pub fn header_value(&self) -> ProtocolResult<HeaderValue> {
+        let token_type = if self.token_type.eq_ignore_ascii_case("bearer") { "Bearer" } else { &self.token_type };
+        let mut value = HeaderValue::from_str(&format!("{} {}", token_type, self.access_token))
+            .map_err(|_| ProtocolError::Oauth2Error(Oauth2Error {
+                error: "invalid_token".to_string(),
+                error_description: Some("The access token contains characters not allowed in a header".to_string()),
+                error_uri: None,
+            }))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// The successful response from a token endpoint (RFC 6749 section 5.1).
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default = "default_token_type")]
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+fn default_token_type() -> String {
+    "Bearer".to_string()
+}
+
+impl From<TokenResponse> for Token {
+    fn from(res: TokenResponse) -> Self {
+        Token {
+            access_token: res.access_token,
+            token_type: res.token_type,
+            expires_at: res.expires_in.map(|s| SystemTime::now() + Duration::from_secs(s)),
+            refresh_token: res.refresh_token,
+            scope: res.scope,
+        }
+    }
+}
+
+/// The error response from a token endpoint (RFC 6749 section 5.2).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Oauth2Error {
+    pub error: String,
+    pub error_description: Option<String>,
+    pub error_uri: Option<String>,
+}
+
+impl Display for Oauth2Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.error_description {
+            Some(d) => write!(f, "{}: {}", self.error, d),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Send a request to a token endpoint, and parse the JSON response.
+pub async fn request_token<T: serde::de::DeserializeOwned>(request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<T> {
+    let res = next.run(request).await?;
+    let (parts, body) = res.into_parts();
+    let body = body.into_memory().await?;
+    if parts.status.is_success() {
+        Ok(body.json()?)
+    } else {
+        let err = body.clone().json::<Oauth2Error>().unwrap_or_else(|_| Oauth2Error {
+            error: format!("http_{}", parts.status.as_u16()),
+            error_description: body.text().ok(),
+            error_uri: None,
+        });
+        Err(ProtocolError::Oauth2Error(err))
+    }
+}
+
+/// How the client authenticates to the token endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientAuth {
+    /// HTTP Basic auth with the client id and secret. Recommended by RFC 6749.
+    #[default]
+    Basic,
+    /// `client_id` and `client_secret` in the form body.
+    RequestBody,
+}
+
+/// Build a form-encoded POST to a token endpoint. Public clients (no secret) identify themselves
+/// with `client_id` in the form.
+pub(crate) fn token_request(
+    token_url: &str,
+    mut form: IndexMap<&str, String>,
+    auth: ClientAuth,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> InMemoryRequest {
+    let mut request = Request::build_post(token_url)
+        .header(header::ACCEPT.as_str(), "application/json");
+    match (client_secret, auth) {
+        (Some(secret), ClientAuth::Basic) => {
+            request = request.basic_auth(client_id, secret);
+        }
+        (Some(secret), ClientAuth::RequestBody) => {
+            form.insert("client_id", client_id.to_string());
+            form.insert("client_secret", secret.to_string());
+        }
+        (None, _) => {
+            form.insert("client_id", client_id.to_string());
+        }
+    }
+    request.form(form).build()
+}
+
+/// Use the refresh token grant (RFC 6749 section 6) to replace `current`. Fails if there's no
+/// refresh token, since getting a new token then requires the user.
+pub(crate) async fn refresh_token_grant(
+    current: Option<&Token>,
+    next: Next<'_>,
+    token_url: &str,
+    auth: ClientAuth,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> ProtocolResult<Token> {
+    let Some(refresh_token) = current.and_then(|t| t.refresh_token.as_ref()) else {
+        return Err(ProtocolError::Oauth2Error(Oauth2Error {
+            error: "invalid_grant".to_string(),
+            error_description: Some("No refresh token. The user must authorize again.".to_string()),
+            error_uri: None,
+        }));
+    };
+    let mut form = IndexMap::new();
+    form.insert("grant_type", "refresh_token".to_string());
+    form.insert("refresh_token", refresh_token.clone());
+    let request = token_request(token_url, form, auth, client_id, client_secret);
+    let res: TokenResponse = request_token(request, next).await?;
+    Ok(res.into())
+}
+
+/// Send a token request with `client`, outside of any `Oauth2` middleware.
+pub(crate) async fn request_token_with<T: serde::de::DeserializeOwned>(client: &Client, request: InMemoryRequest) -> ProtocolResult<T> {
+    let next = Next {
+        client,
+        middlewares: &client.middlewares,
+    };
+    request_token(request, next).await
+}
+
+/// Somewhere to get access tokens from, i.e. an OAuth2 grant.
+#[async_trait]
+pub trait TokenSource: Send + Sync + Debug {
+    /// Get a new token. `current` is the token being replaced, if any, which has the refresh token.
+    /// Requests made through `next` go through the rest of the middleware chain.
+    async fn fetch_token(&self, current: Option<&Token>, next: Next<'_>) -> ProtocolResult<Token>;
+}
+
+type RefreshHook = Arc<dyn Fn(&Token) + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct TokenState {
+    token: Option<Token>,
+    /// Whether we've checked the token store yet.
+    loaded: bool,
+}
+
+/// Attach an OAuth2 access token to every request.
+///
+/// The token is fetched on first use, and refreshed shortly before it expires. If a request is
+/// rejected with a 401 anyway, the token is refreshed and the request is retried once.
+///
+/// With a [`TokenStore`], the token is loaded from the store on first use, and every new token is
+/// saved to it.
+/// ```
+/// use httpclient::middleware::oauth2::{ClientCredentials, Oauth2};
+/// let oauth2 = Oauth2::new(ClientCredentials::new("https://auth.example.com/token", "id", "secret")
+///     .scope("read"));
+/// ```
+#[derive(Clone)]
+pub struct Oauth2 {
+    source: Arc<dyn TokenSource>,
+    state: Arc<Mutex<TokenState>>,
+    store: Option<Arc<dyn TokenStore>>,
+    on_refresh: Option<RefreshHook>,
+    /// Refresh tokens this long before they expire.
+    pub refresh_before: Duration,
+}
+
+impl Debug for Oauth2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Oauth2")
+            .field("source", &self.source)
+            .field("store", &self.store)
+            .field("refresh_before", &self.refresh_before)
+            .finish()
+    }
+}
+
+impl Oauth2 {
+    pub fn new(source: impl TokenSource + 'static) -> Self {
+        Oauth2 {
+            source: Arc::new(source),
+            state: Arc::new(Mutex::new(TokenState::default())),
+            store: None,
+            on_refresh: None,
+            refresh_before: Duration::from_secs(60),
+        }
+    }
+
+    /// Start with a token obtained elsewhere. Takes precedence over the token store. Clones made before this keep
+    /// their own token; to replace the token of a middleware already in use, use `set_token`.
+    pub fn token(mut self, token: Token) -> Self {
+        self.state = Arc::new(Mutex::new(TokenState { token: Some(token), loaded: true }));
+        self
+    }
+
+    /// Replace the current token, e.g. with one refreshed elsewhere, once requests using it have got theirs. Takes
+    /// precedence over the token store.
+    pub async fn set_token(&self, token: Token) {
+        let mut state = self.state.lock().await;
+        state.token = Some(token);
+        state.loaded = true;
+    }
+
+    pub fn token_store(mut self, store: impl TokenStore + 'static) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Called with every newly fetched token, after it's been saved to the store.
+    pub fn on_refresh(mut self, f: impl Fn(&Token) + Send + Sync + 'static) -> Self {
+        self.on_refresh = Some(Arc::new(f));
+        self
+    }
+
+    pub fn refresh_before(mut self, refresh_before: Duration) -> Self {
+        self.refresh_before = refresh_before;
+        self
+    }
+
+    /// The current token, if one has been fetched.
+    pub async fn current_token(&self) -> Option<Token> {
+        self.state.lock().await.token.clone()
+    }
+
+    /// Get a valid token, fetching a new one if there's none, it's about to expire, or `force` is set.
+    async fn valid_token(&self, force: bool, next: Next<'_>) -> ProtocolResult<Token> {
+        let mut state = self.state.lock().await;
+        if !state.loaded {
+            if let Some(store) = &self.store {
+                state.token = store.load().await?;
+            }
+            state.loaded = true;
+        }
+        match state.token.as_ref() {
+            Some(token) if !force && !token.expires_within(self.refresh_before) => Ok(token.clone()),
+            current => {
+                let mut token = self.source.fetch_token(current, next).await?;
+                // Servers often omit the refresh token when it hasn't changed.
+                if token.refresh_token.is_none() {
+                    token.refresh_token = current.and_then(|t| t.refresh_token.clone());
+                }
+                if let Some(store) = &self.store {
+                    store.save(&token).await?;
+                }
+                if let Some(on_refresh) = &self.on_refresh {
+                    on_refresh(&token);
+                }
+                state.token = Some(token.clone());
+                Ok(token)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for Oauth2 {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let token = self.valid_token(false, next).await?;
+        request.headers_mut().insert(header::AUTHORIZATION, token.header_value()?);
+        let res = next.run(request.clone()).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let token = self.valid_token(true, next).await?;
+        request.headers_mut().insert(header::AUTHORIZATION, token.header_value()?);
+        next.run(request).await
+    }
+}
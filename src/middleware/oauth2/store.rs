@@ -0,0 +1,47 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use crate::middleware::oauth2::Token;
+
+/// Persistence for the tokens of an [`Oauth2`](super::Oauth2) middleware, so a refreshed token
+/// survives a process restart.
+#[async_trait]
+pub trait TokenStore: Send + Sync + Debug {
+    /// The last saved token, if any.
+    async fn load(&self) -> std::io::Result<Option<Token>>;
+    async fn save(&self, token: &Token) -> std::io::Result<()>;
+}
+
+/// Stores the token as JSON in a file. Keep the file private; it contains the refresh token.
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    pub path: PathBuf,
+}
+
+impl FileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenStore { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl TokenStore for FileTokenStore {
+    async fn load(&self) -> std::io::Result<Option<Token>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn save(&self, token: &Token) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(token)?).await?;
+        tokio::fs::rename(tmp, &self.path).await
+    }
+}
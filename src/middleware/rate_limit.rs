@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::{Deadline, InMemoryRequest, Middleware, Response};
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+
+/// A rate, expressed as a number of requests per period, with a burst allowance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    /// Tokens added per second.
+    pub rate: f64,
+    /// Maximum number of tokens the bucket can hold, i.e. how many requests can be sent at once.
+    pub burst: u32,
+}
+
+impl Quota {
+    pub fn per_second(requests: u32) -> Self {
+        Self::per_period(requests, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self::per_period(requests, Duration::from_secs(60))
+    }
+
+    /// # Panics
+    /// If `requests` is zero, since the bucket would never refill.
+    pub fn per_period(requests: u32, period: Duration) -> Self {
+        assert!(requests > 0, "A quota must allow at least one request per period");
+        Quota {
+            rate: requests as f64 / period.as_secs_f64(),
+            burst: requests.max(1),
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket. Starts full, and refills continuously at `quota.rate` tokens per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    quota: Quota,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(quota: Quota) -> Self {
+        TokenBucket {
+            quota,
+            state: Mutex::new(BucketState {
+                tokens: quota.burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token if one is available. Otherwise, return how long until one will be, or `Duration::MAX` if the
+    /// bucket never refills.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.updated).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.quota.rate).min(self.quota.burst as f64);
+        state.updated = now;
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::try_from_secs_f64((1.0 - state.tokens) / self.quota.rate).unwrap_or(Duration::MAX))
+        }
+    }
+
+    /// Wait until a token is available, then take it.
+    pub async fn acquire(&self) {
+        while let Err(wait) = self.try_acquire() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like `acquire`, but fail with `ProtocolError::Timeout` rather than wait past `deadline`.
+    async fn acquire_before(&self, deadline: Option<Deadline>) -> ProtocolResult<()> {
+        while let Err(wait) = self.try_acquire() {
+            if deadline.is_some_and(|deadline| wait >= deadline.remaining()) {
+                return Err(ProtocolError::Timeout);
+            }
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// Limit the rate of outgoing requests. When the limit is reached, requests wait for capacity rather than failing.
+///
+/// Buckets are shared by every clone of the middleware, and every client it's attached to.
+/// ```
+/// use httpclient::middleware::{Quota, RateLimit};
+/// let limit = RateLimit::new()
+///     .global(Quota::per_second(50))
+///     .per_host(Quota::per_second(10))
+///     .host("api.github.com", Quota::per_minute(60));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    global: Option<Arc<TokenBucket>>,
+    per_host: Option<Quota>,
+    host_quotas: HashMap<String, Quota>,
+    hosts: Arc<Mutex<HashMap<String, Arc<TokenBucket>>>>,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the total rate of requests, across all hosts.
+    pub fn global(mut self, quota: Quota) -> Self {
+        self.global = Some(Arc::new(TokenBucket::new(quota)));
+        self
+    }
+
+    /// Limit the rate of requests to each host.
+    pub fn per_host(mut self, quota: Quota) -> Self {
+        self.per_host = Some(quota);
+        self
+    }
+
+    /// Override the per-host limit for a specific host.
+    pub fn host(mut self, host: &str, quota: Quota) -> Self {
+        self.host_quotas.insert(host.to_string(), quota);
+        self
+    }
+
+    fn host_bucket(&self, host: &str) -> Option<Arc<TokenBucket>> {
+        let quota = self.host_quotas.get(host).copied().or(self.per_host)?;
+        let mut hosts = self.hosts.lock().unwrap();
+        let bucket = hosts.entry(host.to_string())
+            .or_insert_with(|| Arc::new(TokenBucket::new(quota)));
+        Some(bucket.clone())
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let deadline = request.extensions().get::<Deadline>().copied();
+        if let Some(bucket) = self.host_bucket(request.host()) {
+            bucket.acquire_before(deadline).await?;
+        }
+        if let Some(bucket) = &self.global {
+            bucket.acquire_before(deadline).await?;
+        }
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Ok200;
+
+    #[async_trait]
+    impl Middleware for Ok200 {
+        async fn handle(&self, _request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            Ok(http::Response::builder().status(200).body(Body::new_empty()).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_bucket() {
+        let bucket = TokenBucket::new(Quota::per_second(10).burst(2));
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one request")]
+    fn test_zero_quota() {
+        Quota::per_second(0);
+    }
+
+    #[tokio::test]
+    async fn test_never_refills() {
+        let bucket = TokenBucket::new(Quota { rate: 0.0, burst: 1 });
+        assert!(bucket.try_acquire().is_ok());
+        assert_eq!(bucket.try_acquire().unwrap_err(), Duration::MAX);
+        let deadline = Deadline::after(Duration::from_secs(1));
+        assert!(matches!(bucket.acquire_before(Some(deadline)).await, Err(ProtocolError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_capacity() {
+        let client = Client::new()
+            .with_middleware(RateLimit::new().per_host(Quota::per_second(20).burst(1)))
+            .with_middleware(Ok200);
+        let start = Instant::now();
+        for _ in 0..3 {
+            client.get("http://a.example.com/").send().await.unwrap();
+        }
+        // Separate hosts get separate buckets.
+        client.get("http://b.example.com/").send().await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(500), "{elapsed:?}");
+    }
+}
@@ -1,4 +1,6 @@
+use std::str::FromStr;
 use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use tracing::info;
@@ -7,34 +9,68 @@ use crate::{InMemoryRequest, Middleware, Response};
 use crate::error::ProtocolResult;
 use crate::middleware::ProtocolError;
 use crate::middleware::Next;
-use crate::recorder::RequestRecorder;
+use crate::recorder::{CassetteFormat, MatchRules, Recording, RequestRecorder, Sanitizer, VerifyError};
 use crate::response::{clone_inmemory_response, mem_response_into_hyper, response_into_content};
 
+/// Set this environment variable to override the mode of every `Recorder`, e.g. `HTTPCLIENT_RECORDER_MODE=replay_only`
+/// in CI. Accepts the mode names in snake_case, kebab-case, or CamelCase.
+pub const RECORDER_MODE_ENV: &str = "HTTPCLIENT_RECORDER_MODE";
+
 #[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
 pub enum RecorderMode {
-    /// Default. Will check for recordings, but will make the request if no recording is found.
+    /// Always make the request, and record the response, replacing any existing recording.
+    RecordAll,
+    /// Default. Will check for recordings, but will make and record the request if no recording is found.
     #[default]
-    RecordOrRequest,
-    /// Always make the request.
-    IgnoreRecordings,
+    RecordMissing,
     /// Always use recordings. Fail if no recording is found.
-    ForceNoRequests,
+    ReplayOnly,
+    /// Ignore recordings entirely. Requests are made, and nothing is recorded.
+    Passthrough,
+}
+
+#[allow(non_upper_case_globals)]
+impl RecorderMode {
+    #[deprecated(note = "Use RecorderMode::RecordMissing")]
+    pub const RecordOrRequest: RecorderMode = RecorderMode::RecordMissing;
+    #[deprecated(note = "Use RecorderMode::RecordAll")]
+    pub const IgnoreRecordings: RecorderMode = RecorderMode::RecordAll;
+    #[deprecated(note = "Use RecorderMode::ReplayOnly")]
+    pub const ForceNoRequests: RecorderMode = RecorderMode::ReplayOnly;
 }
 
 impl RecorderMode {
+    /// The mode set by the `HTTPCLIENT_RECORDER_MODE` environment variable, if any.
+    /// Panics if the variable is set to an unknown mode, rather than silently hitting the network.
+    pub fn from_env() -> Option<RecorderMode> {
+        let value = std::env::var(RECORDER_MODE_ENV).ok()?;
+        Some(value.parse().unwrap_or_else(|e| panic!("{RECORDER_MODE_ENV}: {e}")))
+    }
+
     pub fn should_lookup(self) -> bool {
-        match self {
-            RecorderMode::RecordOrRequest => true,
-            RecorderMode::IgnoreRecordings => false,
-            RecorderMode::ForceNoRequests => true,
-        }
+        matches!(self, RecorderMode::RecordMissing | RecorderMode::ReplayOnly)
     }
 
     pub fn should_request(self) -> bool {
-        match self {
-            RecorderMode::RecordOrRequest => true,
-            RecorderMode::IgnoreRecordings => true,
-            RecorderMode::ForceNoRequests => false,
+        !matches!(self, RecorderMode::ReplayOnly)
+    }
+
+    pub fn should_record(self) -> bool {
+        matches!(self, RecorderMode::RecordAll | RecorderMode::RecordMissing)
+    }
+}
+
+impl FromStr for RecorderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.trim().replace(['_', '-'], "").to_lowercase();
+        match normalized.as_str() {
+            "recordall" => Ok(RecorderMode::RecordAll),
+            "recordmissing" => Ok(RecorderMode::RecordMissing),
+            "replayonly" => Ok(RecorderMode::ReplayOnly),
+            "passthrough" => Ok(RecorderMode::Passthrough),
+            _ => Err(format!("Unknown recorder mode `{s}`. Expected one of record_all, record_missing, replay_only, passthrough.")),
         }
     }
 }
@@ -43,27 +79,71 @@ impl RecorderMode {
 static SHARED_RECORDER: OnceLock<RequestRecorder> = OnceLock::new();
 
 pub fn shared_recorder() -> &'static RequestRecorder {
-    SHARED_RECORDER.get_or_init(|| RequestRecorder::new())
+    SHARED_RECORDER.get_or_init(RequestRecorder::new)
+}
+
+/// How long to wait before returning a replayed response.
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub enum Latency {
+    /// Return replayed responses immediately.
+    #[default]
+    None,
+    /// Wait as long as the response took when it was recorded.
+    Recorded,
+    /// Wait for the recorded duration multiplied by a factor, e.g. `0.1` to keep tests fast but still ordered.
+    Scaled(f64),
+}
+
+impl Latency {
+    pub fn delay(self, recorded: Option<Duration>) -> Option<Duration> {
+        let recorded = recorded?;
+        match self {
+            Latency::None => None,
+            Latency::Recorded => Some(recorded),
+            Latency::Scaled(factor) => Some(recorded.mul_f64(factor.max(0.0))),
+        }
+    }
 }
 
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Clone, Debug)]
 /// This middleware caches requests to the local filesystem. Subsequent requests will return results
 /// from the filesystem, and not touch the remote server.
 ///
-/// The recordings are sanitized to hide secrets.
+/// The recordings are sanitized to hide secrets. Use `.sanitizer()` to customize the redaction rules.
 ///
 /// Use `.mode()` to configure the behavior:
-/// - `RecorderMode::RecordOrRequest` (default): Will check for recordings, but will make the request if no recording is found.
-/// - `RecorderMode::IgnoreRecordings`: Always make the request. (Use to force refresh recordings.)
-/// - `RecorderMode::ForceNoRequests`: Fail if no recording is found. (Use to run tests without hitting the remote server.)
+/// - `RecorderMode::RecordMissing` (default): Will check for recordings, but will make and record the request if no recording is found.
+/// - `RecorderMode::RecordAll`: Always make the request. (Use to force refresh recordings.)
+/// - `RecorderMode::ReplayOnly`: Fail if no recording is found. (Use to run tests without hitting the remote server.)
+/// - `RecorderMode::Passthrough`: Don't replay or record.
+///
+/// The `HTTPCLIENT_RECORDER_MODE` environment variable overrides the mode of every recorder.
+///
+/// Recordings are loaded from `data/vcr` in the current directory, unless another `RequestRecorder` is given with
+/// `.recorder()`. Keep a clone of it to call `verify()` at the end of a test.
+///
+/// Use `.match_rules()` to control which parts of the request must match a recording, and `.format()` to
+/// choose the file format of new recordings.
+///
+/// Response times are recorded. Use `.latency()` to wait that long on replay, to exercise timeouts and retries.
 pub struct Recorder {
     pub mode: RecorderMode,
+    pub rules: MatchRules,
+    pub format: CassetteFormat,
+    pub sanitizer: Sanitizer,
+    pub recorder: Option<RequestRecorder>,
+    pub latency: Latency,
 }
 
 impl Recorder {
     pub fn new() -> Self {
         Self {
             mode: Default::default(),
+            rules: Default::default(),
+            format: Default::default(),
+            sanitizer: Default::default(),
+            recorder: None,
+            latency: Latency::None,
         }
     }
 
@@ -72,32 +152,269 @@ impl Recorder {
         self
     }
 
-    fn should_lookup(&self) -> bool {
-        self.mode.should_lookup()
+    pub fn match_rules(mut self, rules: MatchRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn format(mut self, format: CassetteFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    pub fn recorder(mut self, recorder: RequestRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn latency(mut self, latency: Latency) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    fn store(&self) -> &RequestRecorder {
+        self.recorder.as_ref().unwrap_or_else(|| shared_recorder())
+    }
+
+    /// Check that every recording was played, and that every request had a recording.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        self.store().verify()
     }
 
-    fn should_request(&self) -> bool {
-        self.mode.should_request()
+    /// The mode in effect, taking the environment variable into account.
+    pub fn effective_mode(&self) -> RecorderMode {
+        RecorderMode::from_env().unwrap_or(self.mode)
     }
 }
 
 #[async_trait]
 impl Middleware for Recorder {
     async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
-        let recorder = shared_recorder();
-        if self.should_lookup() {
-            let recorded = recorder.get_response(&request);
+        let mode = self.effective_mode();
+        if mode == RecorderMode::Passthrough {
+            return next.run(request).await;
+        }
+        let recorder = self.store();
+        if mode.should_lookup() {
+            let recorded = recorder.get_matching(&request, &self.rules);
             if let Some(recorded) = recorded {
                 info!(url = request.url().to_string(), "Using recorded response");
-                return Ok(mem_response_into_hyper(recorded));
+                if let Some(delay) = self.latency.delay(recorded.duration) {
+                    tokio::time::sleep(delay).await;
+                }
+                return Ok(mem_response_into_hyper(recorded.response));
             }
+            recorder.record_unexpected(&request);
         }
-        if !self.should_request() {
-            return Err(ProtocolError::IoError(std::io::Error::new(std::io::ErrorKind::NotFound, "No recording found")));
+        if !mode.should_request() {
+            let msg = format!("No recording found for {} {}", request.method(), request.url());
+            return Err(ProtocolError::IoError(std::io::Error::new(std::io::ErrorKind::NotFound, msg)));
         }
-        let response = next.run(request.clone().into()).await?;
+        let start = Instant::now();
+        let response = next.run(request.clone()).await?;
         let response = response_into_content(response).await?;
-        recorder.record_response(request, clone_inmemory_response(&response))?;
+        let recording = Recording {
+            duration: Some(start.elapsed()),
+            ..Recording::new(request, clone_inmemory_response(&response))
+        };
+        recorder.record(recording, self.format, &self.sanitizer)?;
         Ok(mem_response_into_hyper(response))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client, InMemoryBody, Request};
+    use crate::recorder::MemoryCassetteStore;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!("replay_only".parse::<RecorderMode>().unwrap(), RecorderMode::ReplayOnly);
+        assert_eq!("record-all".parse::<RecorderMode>().unwrap(), RecorderMode::RecordAll);
+        assert_eq!("RecordMissing".parse::<RecorderMode>().unwrap(), RecorderMode::RecordMissing);
+        assert_eq!("PASSTHROUGH".parse::<RecorderMode>().unwrap(), RecorderMode::Passthrough);
+        assert!("offline".parse::<RecorderMode>().is_err());
+    }
+
+    #[derive(Debug)]
+    struct Live;
+
+    #[async_trait]
+    impl Middleware for Live {
+        async fn handle(&self, _request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            Ok(http::Response::builder().status(299).body(Body::new_empty()).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_ignores_recordings() {
+        let client = Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().mode(RecorderMode::Passthrough))
+            .with_middleware(Live);
+        // There's a recording for this request in data/vcr.
+        let res = client.get("https://www.jsonip.com/").send().await.unwrap();
+        assert_eq!(res.status(), 299);
+    }
+
+    #[tokio::test]
+    async fn test_replay_only_miss() {
+        let client = Client::new()
+            .with_middleware(Recorder::new().mode(RecorderMode::ReplayOnly))
+            .with_middleware(Live);
+        let res = client.get("https://www.jsonip.com/not-recorded").send().await;
+        assert!(matches!(res, Err(ProtocolError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let path = std::env::temp_dir().join(format!("httpclient-verify-{}", std::process::id()));
+        let recorder = RequestRecorder::load_from_path(&path);
+        let client = Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().recorder(recorder.clone()))
+            .with_middleware(Live);
+        client.get("http://example.com/a").send().await.unwrap();
+        client.get("http://example.com/b?page=1").send().await.unwrap();
+        // Recording counts as an unexpected request.
+        assert_eq!(recorder.verify().unwrap_err().unexpected.len(), 2);
+
+        let recorder = RequestRecorder::load_from_path(&path);
+        let client = Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().mode(RecorderMode::ReplayOnly).recorder(recorder.clone()))
+            .with_middleware(Live);
+        assert_eq!(client.get("http://example.com/a").send().await.unwrap().status(), 299);
+        assert!(client.get("http://example.com/b?page=2").send().await.is_err());
+        let err = recorder.verify().unwrap_err();
+        assert_eq!(err.unused.len(), 1);
+        assert_eq!(err.unused[0].url(), "http://example.com/b?page=1");
+        assert_eq!(err.unexpected[0].closest.as_ref().unwrap().url(), "http://example.com/b?page=1");
+        let msg = err.to_string();
+        assert!(msg.contains("- url: http://example.com/b?page=1"));
+        assert!(msg.contains("+ url: http://example.com/b?page=2"));
+
+        client.get("http://example.com/b?page=1").send().await.unwrap();
+        assert!(recorder.verify().unwrap_err().unused.is_empty());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[derive(Debug, Default)]
+    struct Polling(std::sync::atomic::AtomicU16);
+
+    #[async_trait]
+    impl Middleware for Polling {
+        async fn handle(&self, _request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let n = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(http::Response::builder().status(200 + n).body(Body::new_empty()).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequential_replay() {
+        let path = std::env::temp_dir().join(format!("httpclient-sequential-{}", std::process::id()));
+        let client = Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().mode(RecorderMode::RecordAll).recorder(RequestRecorder::load_from_path(&path)))
+            .with_middleware(Polling::default());
+        for _ in 0..3 {
+            client.get("http://example.com/job").send().await.unwrap();
+        }
+
+        let client = Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().mode(RecorderMode::ReplayOnly).recorder(RequestRecorder::load_from_path(&path)))
+            .with_middleware(Live);
+        let mut statuses = vec![];
+        for _ in 0..4 {
+            statuses.push(client.get("http://example.com/job").send().await.unwrap().status().as_u16());
+        }
+        assert_eq!(statuses, vec![200, 201, 202, 202]);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let path = std::env::temp_dir().join(format!("httpclient-latency-{}", std::process::id()));
+        let recorder = RequestRecorder::load_from_path(&path);
+        let request = Request::build_get("http://example.com/slow").build();
+        let response = http::Response::builder().status(200).body(InMemoryBody::Empty).unwrap();
+        let recording = Recording { duration: Some(Duration::from_millis(200)), ..Recording::new(request, response) };
+        recorder.record(recording, CassetteFormat::Json, &Sanitizer::default()).unwrap();
+
+        let recorder = RequestRecorder::load_from_path(&path);
+        assert_eq!(recorder.recordings.read().unwrap()[0].duration, Some(Duration::from_millis(200)));
+        let client = Client::new()
+            .with_middleware(Recorder::new().mode(RecorderMode::ReplayOnly).recorder(recorder).latency(Latency::Scaled(0.25)))
+            .with_middleware(Live);
+        let start = Instant::now();
+        client.get("http://example.com/slow").send().await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        let res = client.get("http://example.com/slow").timeout(Duration::from_millis(10)).send().await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct Echo;
+
+    #[async_trait]
+    impl Middleware for Echo {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            Ok(http::Response::builder().status(200).body(Body::InMemory(InMemoryBody::Text(request.path().to_string()))).unwrap())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_recording() {
+        let path = std::env::temp_dir().join(format!("httpclient-concurrent-{}", std::process::id()));
+        let client = std::sync::Arc::new(Client::new()
+            .no_default_headers()
+            .with_middleware(Recorder::new().recorder(RequestRecorder::load_from_path(&path)))
+            .with_middleware(Echo));
+        let tasks: Vec<_> = (0..20).map(|i| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                client.get(&format!("http://example.com/item/{i}")).send().await.unwrap();
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let recorder = RequestRecorder::load_from_path(&path);
+        let recordings = recorder.recordings.read().unwrap();
+        assert_eq!(recordings.len(), 20);
+        for recording in recordings.iter() {
+            assert_eq!(recording.response.body().clone().text().unwrap(), recording.request.path());
+            let key = recording.key.as_ref().unwrap();
+            let fingerprint = crate::recorder::fingerprint(&recording.request);
+            assert_eq!(key, &format!("example.com/{}/get.{fingerprint}.0000.json", &recording.request.path()[1..]));
+        }
+        drop(recordings);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryCassetteStore::new();
+        let client = Client::new()
+            .with_middleware(Recorder::new().recorder(RequestRecorder::with_store(store.clone())))
+            .with_middleware(Polling::default());
+        assert_eq!(client.get("http://example.com/a").send().await.unwrap().status(), 200);
+        assert_eq!(store.len(), 1);
+
+        let client = Client::new()
+            .with_middleware(Recorder::new().mode(RecorderMode::ReplayOnly).recorder(RequestRecorder::with_store(store)))
+            .with_middleware(Live);
+        assert_eq!(client.get("http://example.com/a").send().await.unwrap().status(), 200);
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::{Method, StatusCode};
+use rand::Rng;
+
+use crate::{Deadline, InMemoryRequest, Middleware, Response, RetryEvent, TypedHeaders};
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::Next;
+
+/// How long to wait between attempts. The delay before retry `n` (starting at 1) is
+/// `base * multiplier^(n-1)`, capped at `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub base: Duration,
+    pub multiplier: f64,
+    pub max: Duration,
+}
+
+impl Backoff {
+    pub fn exponential(base: Duration) -> Self {
+        Backoff {
+            base,
+            multiplier: 2.0,
+            max: Duration::from_secs(30),
+        }
+    }
+
+    pub fn constant(delay: Duration) -> Self {
+        Backoff {
+            base: delay,
+            multiplier: 1.0,
+            max: delay,
+        }
+    }
+
+    /// Retry immediately.
+    pub fn none() -> Self {
+        Self::constant(Duration::ZERO)
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.saturating_sub(1) as i32);
+        let delay = self.base.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max.as_secs_f64() {
+            self.max
+        } else {
+            Duration::from_secs_f64(delay)
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::exponential(Duration::from_millis(100))
+    }
+}
+
+/// Randomization applied to the backoff delay, so that many clients don't retry in lockstep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// Use the backoff delay as-is.
+    None,
+    /// Pick uniformly between zero and the backoff delay.
+    #[default]
+    Full,
+    /// Pick uniformly between half the backoff delay and the full delay.
+    Equal,
+}
+
+impl Jitter {
+    pub fn apply(self, delay: Duration) -> Duration {
+        if delay.is_zero() {
+            return delay;
+        }
+        let mut rng = rand::thread_rng();
+        match self {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(rng.gen_range(0.0..=1.0)),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(rng.gen_range(0.0..=1.0)),
+        }
+    }
+}
+
+type StatusPredicate = Arc<dyn Fn(StatusCode) -> bool + Send + Sync>;
+type ErrorPredicate = Arc<dyn Fn(&ProtocolError) -> bool + Send + Sync>;
+
+fn default_retry_status(status: StatusCode) -> bool {
+    [429, 408, 425].contains(&status.as_u16()) || status.is_server_error()
+}
+
+fn default_retry_error(err: &ProtocolError) -> bool {
+    matches!(err, ProtocolError::ConnectionError(_))
+}
+
+/// Decides which requests are retried, how many times, and how long to wait in between.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+    pub jitter: Jitter,
+    /// Only retry requests with idempotent methods (GET, HEAD, PUT, DELETE, OPTIONS, TRACE).
+    pub idempotent_only: bool,
+    /// Longest we'll wait when a 429 or 503 response asks us to come back later with `Retry-After`.
+    pub max_retry_after: Duration,
+    retry_status: StatusPredicate,
+    retry_error: ErrorPredicate,
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("jitter", &self.jitter)
+            .field("idempotent_only", &self.idempotent_only)
+            .field("max_retry_after", &self.max_retry_after)
+            .finish()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::default(),
+            jitter: Jitter::default(),
+            idempotent_only: false,
+            max_retry_after: Duration::from_secs(60),
+            retry_status: Arc::new(default_retry_status),
+            retry_error: Arc::new(default_retry_error),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn idempotent_only(mut self, idempotent_only: bool) -> Self {
+        self.idempotent_only = idempotent_only;
+        self
+    }
+
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    /// Decide which response statuses are retried.
+    /// Defaults to 408, 425, 429, and all 5xx.
+    pub fn retry_on_status(mut self, f: impl Fn(StatusCode) -> bool + Send + Sync + 'static) -> Self {
+        self.retry_status = Arc::new(f);
+        self
+    }
+
+    /// Decide which transport errors are retried.
+    /// Defaults to connection errors.
+    pub fn retry_on_error(mut self, f: impl Fn(&ProtocolError) -> bool + Send + Sync + 'static) -> Self {
+        self.retry_error = Arc::new(f);
+        self
+    }
+
+    pub fn should_retry_status(&self, status: StatusCode) -> bool {
+        (self.retry_status)(status)
+    }
+
+    pub fn should_retry_error(&self, err: &ProtocolError) -> bool {
+        (self.retry_error)(err)
+    }
+
+    pub fn allows_method(&self, method: &Method) -> bool {
+        !self.idempotent_only || is_idempotent(method)
+    }
+
+    /// The delay before retry number `retry` (starting at 1), with jitter applied.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.jitter.apply(self.backoff.delay(retry))
+    }
+
+    /// The delay before retrying after `res`. Honors `Retry-After` on 429 and 503 responses,
+    /// otherwise falls back to the backoff.
+    pub fn delay_for_response(&self, res: &Response, retry: u32) -> Duration {
+        match res.status() {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => retry_after(res)
+                .map(|d| d.min(self.max_retry_after))
+                .unwrap_or_else(|| self.delay(retry)),
+            _ => self.delay(retry),
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE)
+}
+
+/// Parse the `Retry-After` header, which is either a number of seconds or an HTTP-date.
+/// Dates in the past give a zero delay.
+pub fn retry_after(res: &Response) -> Option<Duration> {
+    res.retry_after()
+}
+
+/// The number of attempts `Retry` made, including the first, stored in the extensions of the response it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempts(pub u32);
+
+#[derive(Debug, Clone, Default)]
+/// Retry failed requests according to a [`RetryPolicy`].
+///
+/// By default, makes up to 3 attempts with exponential backoff and full jitter, retrying
+/// 408, 425, 429, 5xx responses and connection errors.
+/// ```
+/// use std::time::Duration;
+/// use httpclient::middleware::{Backoff, Retry};
+/// let retry = Retry::new()
+///     .max_attempts(5)
+///     .backoff(Backoff::exponential(Duration::from_millis(250)));
+/// ```
+pub struct Retry {
+    pub policy: RetryPolicy,
+}
+
+impl Retry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.policy = self.policy.max_attempts(max_attempts);
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.policy = self.policy.backoff(backoff);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.policy = self.policy.jitter(jitter);
+        self
+    }
+
+    pub fn idempotent_only(mut self, idempotent_only: bool) -> Self {
+        self.policy = self.policy.idempotent_only(idempotent_only);
+        self
+    }
+
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.policy = self.policy.max_retry_after(max_retry_after);
+        self
+    }
+
+    pub fn retry_on_status(mut self, f: impl Fn(StatusCode) -> bool + Send + Sync + 'static) -> Self {
+        self.policy = self.policy.retry_on_status(f);
+        self
+    }
+
+    pub fn retry_on_error(mut self, f: impl Fn(&ProtocolError) -> bool + Send + Sync + 'static) -> Self {
+        self.policy = self.policy.retry_on_error(f);
+        self
+    }
+}
+
+#[async_trait]
+impl Middleware for Retry {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let policy = &self.policy;
+        if !policy.allows_method(request.method()) {
+            return next.run(request).await;
+        }
+        let deadline = request.extensions().get::<Deadline>().copied();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let exhausted = attempt >= policy.max_attempts;
+            let (delay, status, error) = match next.run(request.clone()).await {
+                Ok(mut res) => {
+                    if !policy.should_retry_status(res.status()) {
+                        res.extensions_mut().insert(Attempts(attempt));
+                        return Ok(res);
+                    }
+                    if exhausted {
+                        return Err(ProtocolError::TooManyRetries);
+                    }
+                    (policy.delay_for_response(&res, attempt), Some(res.status()), None)
+                }
+                Err(err) => {
+                    if exhausted || !policy.should_retry_error(&err) {
+                        return Err(err);
+                    }
+                    (policy.delay(attempt), None, Some(err.to_string()))
+                }
+            };
+            if let Some(deadline) = deadline {
+                // Sleeping past the deadline would only end in a timeout.
+                if delay >= deadline.remaining() {
+                    return Err(ProtocolError::Timeout);
+                }
+            }
+            next.client.hooks.retry(&RetryEvent {
+                method: request.method().clone(),
+                url: request.url().clone(),
+                attempt,
+                delay,
+                status,
+                error,
+            }).await;
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
+
+    use crate::{Body, Client, ResponseExt};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Statuses {
+        statuses: Vec<u16>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Middleware for Statuses {
+        async fn handle(&self, _request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let i = self.calls.fetch_add(1, Ordering::SeqCst);
+            let status = self.statuses[i.min(self.statuses.len() - 1)];
+            Ok(http::Response::builder().status(status).body(Body::new_empty()).unwrap())
+        }
+    }
+
+    fn client(retry: Retry, statuses: Vec<u16>) -> (Client, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = Client::new()
+            .with_middleware(retry)
+            .with_middleware(Statuses { statuses, calls: calls.clone() });
+        (client, calls)
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let retry = Retry::new().max_attempts(5).backoff(Backoff::none());
+        let (client, calls) = client(retry, vec![503, 429, 200]);
+        let res = client.get("http://localhost/").send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(res.extension::<Attempts>(), Some(&Attempts(3)));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_retries() {
+        let retry = Retry::new().max_attempts(2).backoff(Backoff::none());
+        let (client, calls) = client(retry, vec![500]);
+        let res = client.get("http://localhost/").send().await;
+        assert!(matches!(res, Err(ProtocolError::TooManyRetries)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_only() {
+        let retry = Retry::new().backoff(Backoff::none()).idempotent_only(true);
+        let (client, calls) = client(retry, vec![500, 200]);
+        let res = client.post("http://localhost/").send().await.unwrap();
+        assert_eq!(res.status(), 500);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_status_predicate() {
+        let retry = Retry::new().backoff(Backoff::none()).retry_on_status(|s| s == StatusCode::NOT_FOUND);
+        let (client, calls) = client(retry, vec![404, 503]);
+        let res = client.get("http://localhost/").send().await.unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Started(u32);
+
+    #[derive(Debug)]
+    struct Stamp;
+
+    #[async_trait]
+    impl Middleware for Stamp {
+        async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+            request.extensions_mut().insert(Started(1));
+            let res = next.run(request).await?;
+            assert_eq!(res.extension::<Started>(), Some(&Started(1)));
+            Ok(res)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extensions_survive_retries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let client = Client::new()
+            .with_middleware(Stamp)
+            .with_middleware(Retry::new().backoff(Backoff::none()))
+            .with_middleware(Statuses { statuses: vec![503, 200], calls: calls.clone() });
+        let res = client.get("http://localhost/").extension("caller").send().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(res.extension::<Started>(), Some(&Started(1)));
+        assert_eq!(res.extension::<&str>(), Some(&"caller"));
+    }
+
+    #[tokio::test]
+    async fn test_caller_deadline() {
+        let retry = Retry::new().backoff(Backoff::constant(Duration::from_secs(10)));
+        let (client, calls) = client(retry, vec![503]);
+        let start = std::time::Instant::now();
+        let res = client.get("http://localhost/")
+            .deadline(Deadline::after(Duration::from_millis(100)))
+            .send()
+            .await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let res = client.get("http://localhost/").deadline(Deadline::after(Duration::ZERO)).send().await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::exponential(Duration::from_millis(100)).max(Duration::from_millis(500));
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(400));
+        assert_eq!(backoff.delay(4), Duration::from_millis(500));
+        let delay = Jitter::Equal.apply(Duration::from_millis(100));
+        assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+    }
+
+    fn with_retry_after(status: u16, value: &str) -> Response {
+        http::Response::builder()
+            .status(status)
+            .header(http::header::RETRY_AFTER, value)
+            .body(Body::new_empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(&with_retry_after(429, "120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&with_retry_after(429, "Wed, 21 Oct 2015 07:28:00 GMT")), Some(Duration::ZERO));
+        let future = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(3600));
+        let delay = retry_after(&with_retry_after(503, &future)).unwrap();
+        assert!(delay > Duration::from_secs(3500) && delay <= Duration::from_secs(3600));
+        assert_eq!(retry_after(&with_retry_after(503, "soon")), None);
+    }
+
+    #[test]
+    fn test_retry_after_capped() {
+        let policy = RetryPolicy::new()
+            .backoff(Backoff::none())
+            .max_retry_after(Duration::from_secs(5));
+        assert_eq!(policy.delay_for_response(&with_retry_after(429, "120"), 1), Duration::from_secs(5));
+        assert_eq!(policy.delay_for_response(&with_retry_after(503, "2"), 1), Duration::from_secs(2));
+        // Only 429 and 503 are allowed to set the delay.
+        assert_eq!(policy.delay_for_response(&with_retry_after(500, "2"), 1), Duration::ZERO);
+    }
+}
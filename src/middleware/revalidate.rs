@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use http::{header, HeaderMap, Method, StatusCode};
+
+use crate::{InMemoryRequest, InMemoryResponse, Middleware, Response};
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::response::{clone_inmemory_response, mem_response_into_hyper, response_into_content};
+
+#[derive(Debug)]
+struct Validated {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    response: InMemoryResponse,
+}
+
+/// Remembers the `ETag` and `Last-Modified` validators of GET responses, and sends them back as
+/// `If-None-Match` and `If-Modified-Since` on subsequent requests for the same URL.
+/// When the server replies `304 Not Modified`, the caller gets the remembered response, as a 200.
+///
+/// Requests that already carry conditional headers are passed through untouched.
+#[derive(Debug, Clone, Default)]
+pub struct Revalidate {
+    entries: Arc<RwLock<HashMap<String, Validated>>>,
+}
+
+impl Revalidate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all remembered responses.
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers.get(name)?.to_str().ok().map(|s| s.to_string())
+}
+
+#[async_trait]
+impl Middleware for Revalidate {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        if request.method() != Method::GET
+            || request.headers().contains_key(header::IF_NONE_MATCH)
+            || request.headers().contains_key(header::IF_MODIFIED_SINCE) {
+            return next.run(request).await;
+        }
+        let key = request.uri().to_string();
+        let mut conditional = false;
+        if let Some(entry) = self.entries.read().unwrap().get(&key) {
+            let headers = request.headers_mut();
+            if let Some(etag) = entry.etag.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(header::IF_NONE_MATCH, etag);
+                conditional = true;
+            }
+            if let Some(date) = entry.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+                headers.insert(header::IF_MODIFIED_SINCE, date);
+                conditional = true;
+            }
+        }
+        let res = next.run(request).await?;
+        if res.status() == StatusCode::NOT_MODIFIED && conditional {
+            let mut entries = self.entries.write().unwrap();
+            if let Some(entry) = entries.get_mut(&key) {
+                // A 304 can carry updated metadata for the stored response.
+                for (name, value) in res.headers() {
+                    if name != header::CONTENT_LENGTH {
+                        entry.response.headers_mut().insert(name.clone(), value.clone());
+                    }
+                }
+                return Ok(mem_response_into_hyper(clone_inmemory_response(&entry.response)));
+            }
+            return Ok(res);
+        }
+        if !res.status().is_success() {
+            return Ok(res);
+        }
+        let etag = header_string(res.headers(), header::ETAG);
+        let last_modified = header_string(res.headers(), header::LAST_MODIFIED);
+        if etag.is_none() && last_modified.is_none() {
+            return Ok(res);
+        }
+        let res = response_into_content(res).await?;
+        self.entries.write().unwrap().insert(key, Validated {
+            etag,
+            last_modified,
+            response: clone_inmemory_response(&res),
+        });
+        Ok(mem_response_into_hyper(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{Client, InMemoryBody, ResponseExt};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct EtagServer {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Middleware for EtagServer {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let res = if request.header("if-none-match") == Some("\"v1\"") {
+                http::Response::builder().status(304).body(InMemoryBody::Empty)
+            } else {
+                http::Response::builder().status(200).header("etag", "\"v1\"").body(InMemoryBody::new_text("hello"))
+            };
+            Ok(mem_response_into_hyper(res.unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revalidate() {
+        let server = Arc::new(EtagServer::default());
+        let revalidate = Revalidate::new();
+        let mut client = Client::new().with_middleware(revalidate.clone());
+        client.middlewares.push(server.clone());
+        for _ in 0..2 {
+            let res = client.get("http://example.com/a").send().await.unwrap();
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.text().await.unwrap(), "hello");
+        }
+        assert_eq!(server.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(revalidate.len(), 1);
+    }
+}
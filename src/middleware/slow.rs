@@ -0,0 +1,207 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::{Method, StatusCode, Uri};
+
+use crate::{InMemoryRequest, Middleware, Response};
+use crate::error::ProtocolResult;
+use crate::middleware::Next;
+use crate::sanitize::Sanitizer;
+
+/// One trip to the network made while handling a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    pub url: Uri,
+    /// `None` if it failed without a response.
+    pub status: Option<StatusCode>,
+    /// When it started, relative to the start of the request.
+    pub offset: Duration,
+    /// Until the response head arrived, or it failed.
+    pub duration: Duration,
+}
+
+/// Collects the exchanges made for a request. It's shared by every clone of the request's extensions, so the
+/// network trips made by `Retry` and `Follow` all land in the same timeline.
+#[derive(Debug, Clone)]
+pub(crate) struct Timeline {
+    start: Instant,
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Timeline { start: Instant::now(), exchanges: Arc::default() }
+    }
+
+    pub fn record(&self, url: Uri, status: Option<StatusCode>, started: Instant) {
+        self.exchanges.lock().unwrap().push(Exchange {
+            url,
+            status,
+            offset: started.duration_since(self.start),
+            duration: started.elapsed(),
+        });
+    }
+
+    fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+}
+
+/// A request that took longer than the `SlowRequests` threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowRequest {
+    pub method: Method,
+    pub url: Uri,
+    /// `None` if the request failed.
+    pub status: Option<StatusCode>,
+    /// Until the response head arrived, or the request failed.
+    pub elapsed: Duration,
+    pub threshold: Duration,
+    /// The trips to the network, in order. Empty if the response didn't come from the network, e.g. a replay.
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Display for SlowRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} took {:?}, over the {:?} threshold", self.method, self.url, self.elapsed, self.threshold)?;
+        for exchange in &self.exchanges {
+            let status = exchange.status.map_or_else(|| "error".to_string(), |s| s.as_u16().to_string());
+            write!(f, "\n  +{:?} {} -> {} in {:?}", exchange.offset, exchange.url, status, exchange.duration)?;
+        }
+        Ok(())
+    }
+}
+
+type SlowFn = Arc<dyn Fn(&SlowRequest) + Send + Sync>;
+
+/// Warn about requests that take longer than `threshold` to get a response head, with the time spent on each trip
+/// to the network, so retries, redirects, and backoff can be told apart from a slow server.
+///
+/// By default it logs through `tracing` at `WARN`, with the target `httpclient`, redacting the URL with a
+/// [`Sanitizer`]. Use `.on_slow()` to handle slow requests yourself instead, e.g. to count them. Put it first to
+/// time whole requests, including retries.
+/// ```
+/// use std::time::Duration;
+/// use httpclient::Client;
+/// use httpclient::middleware::SlowRequests;
+/// let client = Client::new().with_middleware(SlowRequests::new(Duration::from_secs(2)));
+/// ```
+#[derive(Clone)]
+pub struct SlowRequests {
+    pub threshold: Duration,
+    pub sanitizer: Sanitizer,
+    on_slow: Option<SlowFn>,
+}
+
+impl SlowRequests {
+    pub fn new(threshold: Duration) -> Self {
+        SlowRequests { threshold, sanitizer: Sanitizer::default(), on_slow: None }
+    }
+
+    pub fn sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    /// Call `f` with each slow request instead of logging it. The URL isn't redacted.
+    pub fn on_slow(mut self, f: impl Fn(&SlowRequest) + Send + Sync + 'static) -> Self {
+        self.on_slow = Some(Arc::new(f));
+        self
+    }
+}
+
+impl Debug for SlowRequests {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlowRequests")
+            .field("threshold", &self.threshold)
+            .field("sanitizer", &self.sanitizer)
+            .field("on_slow", &self.on_slow.is_some())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Middleware for SlowRequests {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let timeline = Timeline::new();
+        request.extensions_mut().insert(timeline.clone());
+        let res = next.run(request).await;
+        let elapsed = timeline.start.elapsed();
+        if elapsed > self.threshold {
+            let slow = SlowRequest {
+                method,
+                url,
+                status: res.as_ref().ok().map(|res| res.status()),
+                elapsed,
+                threshold: self.threshold,
+                exchanges: timeline.exchanges(),
+            };
+            match &self.on_slow {
+                Some(on_slow) => on_slow(&slow),
+                None => {
+                    let mut message = slow.to_string();
+                    self.sanitizer.redact_text(&mut message);
+                    tracing::warn!(target: "httpclient", "{}", message);
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::{make_service_fn, service_fn};
+
+    use crate::Client;
+    use crate::middleware::{Backoff, Retry};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slow_requests() {
+        // Fails the first request, and answers the rest slowly.
+        let calls = Arc::new(Mutex::new(0));
+        let make_svc = make_service_fn(move |_| {
+            let calls = calls.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |_req: hyper::Request<hyper::Body>| {
+                    let first = std::mem::replace(&mut *calls.lock().unwrap(), 1) == 0;
+                    async move {
+                        let status = if first { 503 } else { 200 };
+                        if !first {
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                        }
+                        let res = hyper::Response::builder().status(status).body(hyper::Body::empty());
+                        Ok::<_, hyper::Error>(res.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let slow = Arc::new(Mutex::new(Vec::new()));
+        let recorded = slow.clone();
+        let slow_requests = SlowRequests::new(Duration::from_millis(20))
+            .on_slow(move |s| recorded.lock().unwrap().push(s.clone()));
+        let client = Client::new()
+            .with_middleware(slow_requests)
+            .with_middleware(Retry::new().backoff(Backoff::none()));
+        let url = format!("http://{addr}/");
+        client.get(&url).send().await.unwrap();
+        let slow = slow.lock().unwrap();
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].status, Some(StatusCode::OK));
+        let exchanges = &slow[0].exchanges;
+        assert_eq!(exchanges.iter().map(|e| e.status.unwrap().as_u16()).collect::<Vec<_>>(), [503, 200]);
+        assert!(exchanges[1].offset >= exchanges[0].duration);
+        assert!(exchanges[1].duration >= Duration::from_millis(50));
+        assert!(slow[0].to_string().starts_with(&format!("GET {url} took")));
+    }
+}
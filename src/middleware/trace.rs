@@ -0,0 +1,199 @@
+use std::fmt::{Display, Formatter};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use http::HeaderValue;
+use rand::Rng;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::{InMemoryRequest, Middleware, Response, ResponseExt};
+use crate::error::ProtocolResult;
+use crate::middleware::{Attempts, Next};
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// A W3C Trace Context: which trace a request belongs to, and the span it was sent from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub sampled: bool,
+    /// The vendor-specific `tracestate` header, passed along unchanged.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Start a new, sampled trace.
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        TraceContext {
+            trace_id: rng.gen_range(1..=u128::MAX),
+            span_id: rng.gen_range(1..=u64::MAX),
+            sampled: true,
+            state: None,
+        }
+    }
+
+    /// Parse `traceparent` and `tracestate` headers, e.g. those of an incoming request. Invalid headers give `None`.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next().filter(|v| v.len() == 2 && *v != "ff")?;
+        u8::from_str_radix(version, 16).ok()?;
+        let trace_id = parts.next().filter(|id| id.len() == 32)?;
+        let span_id = parts.next().filter(|id| id.len() == 16)?;
+        let flags = parts.next().filter(|f| f.len() == 2)?;
+        // Version 00 has exactly four fields; later versions may add more.
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok().filter(|id| *id != 0)?;
+        let span_id = u64::from_str_radix(span_id, 16).ok().filter(|id| *id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+            state: tracestate.map(str::to_string).filter(|s| !s.is_empty()),
+        })
+    }
+
+    /// A new span in the same trace.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            span_id: rand::thread_rng().gen_range(1..=u64::MAX),
+            ..self.clone()
+        }
+    }
+
+    /// The `traceparent` header value.
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, u8::from(self.sampled))
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.traceparent())
+    }
+}
+
+/// Wrap each request in a `tracing` span named `http.request`, and propagate its trace with the W3C `traceparent`
+/// and `tracestate` headers.
+///
+/// The span records the method, host, and trace id up front, and the status, duration, retry count (when `Retry`
+/// runs after this middleware), or error once the request finishes.
+///
+/// `tracing` spans don't carry W3C ids, so the trace comes from a [`TraceContext`] in the request extensions, e.g.
+/// parsed from an incoming request and attached with `RequestBuilder::extension`, or from a `traceparent` header
+/// already on the request. Otherwise each request starts a new trace. Later middleware can read the request's own
+/// `TraceContext` from its extensions.
+#[derive(Debug, Clone, Default)]
+pub struct Tracing;
+
+impl Tracing {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl Middleware for Tracing {
+    async fn handle(&self, mut request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let parent = request.extensions().get::<TraceContext>().cloned().or_else(|| {
+            TraceContext::parse(request.header(TRACEPARENT)?, request.header(TRACESTATE))
+        });
+        let context = parent.map_or_else(TraceContext::new, |parent| parent.child());
+        let headers = request.headers_mut();
+        headers.insert(TRACEPARENT, HeaderValue::from_str(&context.traceparent()).unwrap());
+        match context.state.as_deref().and_then(|state| HeaderValue::from_str(state).ok()) {
+            Some(state) => headers.insert(TRACESTATE, state),
+            None => headers.remove(TRACESTATE),
+        };
+        let span = tracing::info_span!(
+            "http.request",
+            http.method = %request.method(),
+            http.host = request.host(),
+            http.status_code = Empty,
+            duration_ms = Empty,
+            retries = Empty,
+            error = Empty,
+            trace_id = %format_args!("{:032x}", context.trace_id),
+        );
+        request.extensions_mut().insert(context);
+        let start = Instant::now();
+        let res = next.run(request).instrument(span.clone()).await;
+        span.record("duration_ms", start.elapsed().as_secs_f64() * 1000.0);
+        match &res {
+            Ok(res) => {
+                span.record("http.status_code", res.status().as_u16());
+                if let Some(Attempts(attempts)) = res.extension::<Attempts>() {
+                    span.record("retries", attempts - 1);
+                }
+            }
+            Err(e) => {
+                span.record("error", tracing::field::display(e));
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Body, Client};
+
+    use super::*;
+
+    /// Echoes the trace headers it receives.
+    #[derive(Debug)]
+    struct Echo;
+
+    #[async_trait]
+    impl Middleware for Echo {
+        async fn handle(&self, request: InMemoryRequest, _next: Next<'_>) -> ProtocolResult<Response> {
+            let res = http::Response::builder()
+                .header(TRACEPARENT, request.header(TRACEPARENT).unwrap_or_default())
+                .header(TRACESTATE, request.header(TRACESTATE).unwrap_or_default());
+            Ok(res.body(Body::new_empty()).unwrap())
+        }
+    }
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse() {
+        let context = TraceContext::parse(PARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.span_id, 0x00f067aa0ba902b7);
+        assert!(context.sampled);
+        assert_eq!(context.traceparent(), PARENT);
+        assert_eq!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01", None), None);
+        assert_eq!(TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", None), None);
+        assert_eq!(TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7", None), None);
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra", None).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_propagation() {
+        let client = Client::new().with_middleware(Tracing::new()).with_middleware(Echo);
+        let parent = TraceContext::parse(PARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        let res = client.get("https://example.com/").extension(parent.clone()).send().await.unwrap();
+        let sent = TraceContext::parse(res.headers()[TRACEPARENT].to_str().unwrap(), None).unwrap();
+        assert_eq!(sent.trace_id, parent.trace_id);
+        assert_ne!(sent.span_id, parent.span_id);
+        assert_eq!(res.headers()[TRACESTATE], "congo=t61rcWkgMzE");
+
+        let res = client.get("https://example.com/").send().await.unwrap();
+        let sent = TraceContext::parse(res.headers()[TRACEPARENT].to_str().unwrap(), None).unwrap();
+        assert_ne!(sent.trace_id, parent.trace_id);
+        assert_eq!(res.extension::<TraceContext>().unwrap().span_id, sent.span_id);
+    }
+}
@@ -1,7 +1,18 @@
-use http::HeaderMap;
+use std::path::Path;
+
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use http::{HeaderMap, HeaderValue};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use hyper::body::Bytes;
 use rand::Rng;
+use tokio::io::AsyncRead;
+
+use crate::{Body, InMemoryBody};
 
-use crate::InMemoryBody;
+pub mod batch;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 fn gen_boundary() -> String {
     let mut rng = rand::thread_rng();
@@ -49,46 +60,182 @@ impl Form {
         self.parts.push(part);
         self
     }
+
+    /// Add a text field.
+    pub fn text(self, name: &str, value: impl Into<String>) -> Self {
+        self.part(Part::text(value).name(name))
+    }
+
+    /// Add a file field, read from `path`. The filename and MIME type are taken from the path.
+    pub fn file(self, name: &str, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(self.part(Part::file(path)?.name(name)))
+    }
+
+    /// Add a file field which is streamed from `path` as the request is sent, rather than read into memory.
+    pub async fn file_stream(self, name: &str, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(self.part(Part::file_stream(path).await?.name(name)))
+    }
+
+    /// Whether any part is streamed, in which case the form can only be encoded with `into_body`.
+    pub fn is_streaming(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p.body, Body::Hyper(_)))
+    }
+
+    /// Encode the form. If any part is streamed, so is the encoded body, boundaries and all.
+    pub fn into_body(self) -> Body {
+        if !self.is_streaming() {
+            let bytes: Vec<u8> = self.into();
+            return Body::InMemory(InMemoryBody::Bytes(bytes.into()));
+        }
+        let mut streams: Vec<BoxStream<'static, Result<Bytes, BoxError>>> = Vec::new();
+        for part in self.parts {
+            let head = Bytes::from(part_head(&self.boundary, &part.headers));
+            streams.push(futures::stream::once(async { Ok(head) }).boxed());
+            streams.push(match part.body {
+                Body::InMemory(body) => {
+                    let bytes = body.bytes().map_err(|e| -> BoxError { e.to_string().into() });
+                    futures::stream::once(async { bytes }).boxed()
+                }
+                Body::Hyper(body) => TryStreamExt::map_err(body, |e| -> BoxError { Box::new(e) }).boxed(),
+            });
+            streams.push(futures::stream::once(async { Ok(Bytes::from_static(b"\r\n")) }).boxed());
+        }
+        let tail = Bytes::from(format!("--{}--\r\n", self.boundary));
+        streams.push(futures::stream::once(async { Ok(tail) }).boxed());
+        Body::from_stream(futures::stream::iter(streams).flatten())
+    }
 }
 
-impl Into<Vec<u8>> for Form {
-    fn into(self) -> Vec<u8> {
+/// The boundary delimiter and headers which precede a part's body.
+fn part_head(boundary: &str, headers: &HeaderMap) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice("--".as_bytes());
+    bytes.extend_from_slice(boundary.as_bytes());
+    bytes.extend_from_slice("\r\n".as_bytes());
+    for (key, value) in headers {
+        let key = key.as_str();
+        bytes.extend_from_slice(key.as_bytes());
+        bytes.extend_from_slice(": ".as_bytes());
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.extend_from_slice("\r\n".as_bytes());
+    }
+    bytes.extend_from_slice("\r\n".as_bytes());
+    bytes
+}
+
+/// Panics if any part is streamed. Use `Form::into_body` instead.
+impl From<Form> for Vec<u8> {
+    fn from(form: Form) -> Vec<u8> {
         let mut bytes = Vec::new();
-        for part in self.parts {
-            bytes.extend_from_slice(&"--".as_bytes());
-            bytes.extend_from_slice(self.boundary.as_bytes());
-            bytes.extend_from_slice("\r\n".as_bytes());
-            for (key, value) in &part.headers {
-                let key = key.as_str();
-                bytes.extend_from_slice(key.as_bytes());
-                bytes.extend_from_slice(": ".as_bytes());
-                bytes.extend_from_slice(value.as_bytes());
-                bytes.extend_from_slice("\r\n".as_bytes());
-            }
-            bytes.extend_from_slice("\r\n".as_bytes());
-            let body = part.body.bytes().expect("Failed to convert body to bytes");
+        for part in form.parts {
+            bytes.extend(part_head(&form.boundary, &part.headers));
+            let Body::InMemory(body) = part.body else {
+                panic!("Cannot encode a streamed part into bytes. Use Form::into_body instead.");
+            };
+            let body = body.bytes().expect("Failed to convert body to bytes");
             bytes.extend_from_slice(body.as_ref());
             bytes.extend_from_slice("\r\n".as_bytes());
         }
         bytes.extend_from_slice("--".as_bytes());
-        bytes.extend_from_slice(self.boundary.as_bytes());
+        bytes.extend_from_slice(form.boundary.as_bytes());
         bytes.extend_from_slice("--\r\n".as_bytes());
         bytes
     }
 }
 
+impl Default for Form {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Part {
     pub headers: HeaderMap,
-    pub body: InMemoryBody,
+    pub body: Body,
 }
 
 impl Part {
-    pub fn new(body: InMemoryBody) -> Self {
+    pub fn new(body: impl Into<Body>) -> Self {
         Part {
             headers: HeaderMap::new(),
-            body,
+            body: body.into(),
+        }
+    }
+
+    /// A part which is streamed from `reader` as the request is sent.
+    pub fn reader(reader: impl AsyncRead + Send + 'static) -> Self {
+        Part::new(Body::from_reader(reader))
+            .mime("application/octet-stream")
+    }
+
+    /// Like `Part::file`, but the file is streamed as the request is sent, rather than read into memory.
+    pub async fn file_stream(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        Ok(Part::reader(file).file_info(path))
+    }
+
+    pub fn text(value: impl Into<String>) -> Self {
+        Part::new(InMemoryBody::Text(value.into()))
+    }
+
+    pub fn bytes(value: impl Into<Bytes>) -> Self {
+        Part::new(InMemoryBody::Bytes(value.into()))
+            .mime("application/octet-stream")
+    }
+
+    /// A part with the contents of the file at `path`, with its filename and a MIME type guessed from the extension.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Part::new(InMemoryBody::Bytes(std::fs::read(path)?.into())).file_info(path))
+    }
+
+    /// Set the filename and MIME type from `path`.
+    fn file_info(mut self, path: &Path) -> Self {
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        self = self.mime(mime.essence_str());
+        match path.file_name().and_then(|s| s.to_str()) {
+            Some(file_name) => self.file_name(file_name),
+            None => self,
         }
     }
+
+    /// Set the field name in the Content-Disposition header.
+    pub fn name(self, name: &str) -> Self {
+        self.disposition_param("name", name)
+    }
+
+    /// Set the filename in the Content-Disposition header.
+    pub fn file_name(self, file_name: &str) -> Self {
+        self.disposition_param("filename", file_name)
+    }
+
+    pub fn mime(mut self, mime: &str) -> Self {
+        self.headers.insert(CONTENT_TYPE, HeaderValue::from_str(mime).expect("Invalid MIME type"));
+        self
+    }
+
+    /// Add or replace a parameter of the `form-data` Content-Disposition.
+    fn disposition_param(mut self, key: &str, value: &str) -> Self {
+        let existing = self.headers.get(CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("form-data");
+        let prefix = format!("{}=", key);
+        let mut params: Vec<String> = existing.split("; ")
+            .filter(|p| !p.starts_with(&prefix))
+            .map(str::to_string)
+            .collect();
+        params.push(format!("{}=\"{}\"", key, escape_quoted(value)));
+        // `name` comes before `filename`, as in browsers.
+        params[1..].sort_by_key(|p| !p.starts_with("name="));
+        self.headers.insert(CONTENT_DISPOSITION, HeaderValue::from_str(&params.join("; ")).expect("Invalid Content-Disposition"));
+        self
+    }
+}
+
+/// Escape a Content-Disposition parameter the way browsers do for multipart/form-data.
+fn escape_quoted(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
 }
 
 #[cfg(test)]
@@ -107,4 +254,46 @@ mod tests {
         let right = format!("--{0}\r\ncontent-type: application/http\r\n\r\nGET /farm/v1/animals/pony\r\n--{0}--\r\n", &boundary);
         assert_eq!(s, right);
     }
+
+    #[test]
+    fn test_helpers() {
+        let path = std::env::temp_dir().join(format!("httpclient-multipart-{}.json", std::process::id()));
+        std::fs::write(&path, "{}").unwrap();
+        let form = Form::new()
+            .text("title", "hello")
+            .file("upload", &path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(form.parts[0].headers[CONTENT_DISPOSITION], r#"form-data; name="title""#);
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(form.parts[1].headers[CONTENT_DISPOSITION], format!(r#"form-data; name="upload"; filename="{}""#, file_name).as_str());
+        assert_eq!(form.parts[1].headers[CONTENT_TYPE], "application/json");
+
+        let part = Part::text("x").file_name("a\"b.txt").name("field");
+        assert_eq!(part.headers[CONTENT_DISPOSITION], r#"form-data; name="field"; filename="a%22b.txt""#);
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let build = |streaming: bool| {
+            let file = if streaming {
+                Part::reader(std::io::Cursor::new(b"file contents".to_vec()))
+            } else {
+                Part::bytes(b"file contents".to_vec())
+            };
+            Form::new()
+                .boundary("boundary".to_string())
+                .text("title", "hello")
+                .part(file.name("upload").file_name("a.txt"))
+        };
+        let streamed = build(true);
+        assert!(streamed.is_streaming());
+        let body = streamed.into_body();
+        assert!(matches!(body, Body::Hyper(_)));
+        let InMemoryBody::Bytes(streamed) = body.into_memory().await.unwrap() else {
+            panic!("Expected bytes");
+        };
+        let buffered: Vec<u8> = build(false).into();
+        assert_eq!(String::from_utf8(streamed.to_vec()).unwrap(), String::from_utf8(buffered).unwrap());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+//! Batch requests, as used by Google APIs: several requests sent as `application/http` parts of a single
+//! `multipart/mixed` request, with the responses returned the same way.
+//!
+//! ```ignore
+//! let form = batch::encode(&requests);
+//! let res = client.post("https://www.googleapis.com/batch/drive/v3").multipart(form).await?;
+//! let responses = batch::decode(res)?;
+//! ```
+
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use hyper::body::Bytes;
+
+use crate::{InMemoryBody, InMemoryRequest, InMemoryResponse, InMemoryResponseExt, ProtocolError, ProtocolResult};
+
+use super::{Form, Part};
+
+static CONTENT_ID: HeaderName = HeaderName::from_static("content-id");
+
+/// Encode `requests` as a `multipart/mixed` form. Each part is given a `Content-ID` of `<item{n}>`, so responses
+/// can be matched back to requests.
+pub fn encode(requests: &[InMemoryRequest]) -> Form {
+    let mut form = Form::new().content_type("multipart/mixed".to_string());
+    for (i, request) in requests.iter().enumerate() {
+        let mut part = Part::bytes(encode_request(request)).mime("application/http");
+        part.headers.insert(CONTENT_ID.clone(), HeaderValue::from_str(&format!("<item{}>", i + 1)).unwrap());
+        form = form.part(part);
+    }
+    form
+}
+
+/// The request as it would be sent on the wire: the request line, headers, and body.
+fn encode_request(request: &InMemoryRequest) -> Vec<u8> {
+    let path = request.url().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    let mut out = format!("{} {} HTTP/1.1\r\n", request.method(), path).into_bytes();
+    let body = request.body().clone().bytes().unwrap_or_default();
+    let mut headers = request.headers().clone();
+    if !body.is_empty() {
+        if let InMemoryBody::Json(_) = request.body() {
+            headers.entry(CONTENT_TYPE).or_insert(HeaderValue::from_static("application/json"));
+        }
+        headers.entry(CONTENT_LENGTH).or_insert(HeaderValue::from(body.len()));
+    }
+    for (key, value) in &headers {
+        out.extend_from_slice(key.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Split a batch response into the individual responses. When the parts carry Content-IDs of the form
+/// `<response-item{n}>`, as Google APIs return them, the responses are put in the order of the requests.
+pub fn decode(response: InMemoryResponse) -> ProtocolResult<Vec<InMemoryResponse>> {
+    let content_type = response.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let boundary = boundary(&content_type).ok_or_else(|| invalid("Batch response has no multipart boundary"))?;
+    let body = response.into_body().bytes().map_err(|_| invalid("Batch response body is not bytes"))?;
+    let mut responses = split_parts(&body, &boundary)?
+        .into_iter()
+        .map(|part| {
+            let (headers, content) = split_head(part).ok_or_else(|| invalid("Batch part has no header section"))?;
+            let headers = parse_headers(headers);
+            let order = headers.get(&CONTENT_ID)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|id| id.trim_matches(|c| c == '<' || c == '>').strip_prefix("response-item")?.split(['@', ':']).next()?.parse::<usize>().ok());
+            Ok((order, decode_response(content)?))
+        })
+        .collect::<ProtocolResult<Vec<_>>>()?;
+    if responses.iter().all(|(order, _)| order.is_some()) {
+        responses.sort_by_key(|(order, _)| *order);
+    }
+    Ok(responses.into_iter().map(|(_, res)| res).collect())
+}
+
+fn decode_response(content: &[u8]) -> ProtocolResult<InMemoryResponse> {
+    let (head, body) = split_head(content).unwrap_or((content, &[]));
+    let head = std::str::from_utf8(head).map_err(|_| invalid("Batch response headers are not UTF-8"))?;
+    let (status_line, headers) = head.split_once('\n').unwrap_or((head, ""));
+    let status = status_line.split_whitespace().nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .and_then(|s| StatusCode::from_u16(s).ok())
+        .ok_or_else(|| invalid("Batch response part has no status line"))?;
+    let headers = parse_headers(headers.as_bytes());
+    let body = InMemoryBody::from_content_type(Bytes::copy_from_slice(body), headers.get(CONTENT_TYPE))?;
+    Ok(<InMemoryResponse as InMemoryResponseExt>::new(status, headers, body))
+}
+
+fn parse_headers(head: &[u8]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for line in head.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            continue;
+        };
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(line[..colon].trim_ascii()),
+            HeaderValue::from_bytes(line[colon + 1..].trim_ascii()),
+        ) else {
+            continue;
+        };
+        headers.append(name, value);
+    }
+    headers
+}
+
+fn boundary(content_type: &str) -> Option<String> {
+    content_type.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim_matches('"').to_string())
+}
+
+/// The content of each part between the delimiters, ignoring the preamble and epilogue.
+fn split_parts<'a>(body: &'a [u8], boundary: &str) -> ProtocolResult<Vec<&'a [u8]>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let start = find(body, &delimiter).ok_or_else(|| invalid("Batch response has no parts"))?;
+    let mut rest = &body[start + delimiter.len()..];
+    let mut parts = Vec::new();
+    while !rest.starts_with(b"--") {
+        // Skip the rest of the delimiter line.
+        let newline = find(rest, b"\n").ok_or_else(|| invalid("Batch response ended unexpectedly"))?;
+        rest = &rest[newline + 1..];
+        let end = find(rest, &delimiter).ok_or_else(|| invalid("Batch response ended unexpectedly"))?;
+        let part = &rest[..end];
+        let part = part.strip_suffix(b"\n").unwrap_or(part);
+        parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+        rest = &rest[end + delimiter.len()..];
+    }
+    Ok(parts)
+}
+
+/// Split at the first blank line, into the headers and what follows.
+fn split_head(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let crlf = find(bytes, b"\r\n\r\n").map(|i| (i, 4));
+    let lf = find(bytes, b"\n\n").map(|i| (i, 2));
+    let (i, len) = match (crlf, lf) {
+        (Some(a), Some(b)) => if a.0 <= b.0 { a } else { b },
+        (a, b) => a.or(b)?,
+    };
+    Some((&bytes[..i], &bytes[i + len..]))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid(msg: &str) -> ProtocolError {
+    ProtocolError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let requests = vec![
+            Request::build_get("https://example.com/farm/v1/animals/pony").build(),
+            Request::build_post("https://example.com/farm/v1/animals/sheep").method(http::Method::PUT).json(json!({"name": "sheep"})).build(),
+        ];
+        let form = encode(&requests).boundary("batch".to_string());
+        assert_eq!(form.full_content_type(), "multipart/mixed; boundary=batch");
+        let body = String::from_utf8(form.into()).unwrap();
+        assert!(body.starts_with("--batch\r\ncontent-type: application/http\r\ncontent-id: <item1>\r\n\r\nGET /farm/v1/animals/pony HTTP/1.1\r\n"));
+        assert!(body.contains("PUT /farm/v1/animals/sheep HTTP/1.1\r\ncontent-type: application/json; charset=utf-8\r\n"));
+        assert!(body.contains("content-length: 16\r\n\r\n{\"name\":\"sheep\"}\r\n--batch--\r\n"));
+    }
+
+    #[test]
+    fn test_decode() {
+        let body = "preamble\r\n\
+            --batch_abc\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-item2>\r\n\
+            \r\n\
+            HTTP/1.1 404 Not Found\r\n\
+            \r\n\
+            \r\n\
+            --batch_abc\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-item1>\r\n\
+            \r\n\
+            HTTP/1.1 200 OK\r\n\
+            Content-Type: application/json; charset=UTF-8\r\n\
+            ETag: \"etag/pony\"\r\n\
+            \r\n\
+            {\"name\": \"pony\"}\r\n\
+            --batch_abc--\r\n";
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("multipart/mixed; boundary=batch_abc"));
+        let response = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, headers, InMemoryBody::Text(body.to_string()));
+        let responses = decode(response).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].status(), StatusCode::OK);
+        assert_eq!(responses[0].headers()["etag"], "\"etag/pony\"");
+        assert_eq!(responses[0].body().clone().json::<serde_json::Value>().unwrap(), json!({"name": "pony"}));
+        assert_eq!(responses[1].status(), StatusCode::NOT_FOUND);
+        assert!(responses[1].body().is_empty());
+    }
+}
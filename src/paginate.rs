@@ -0,0 +1,343 @@
+//! Paginated APIs, following a `Link: <...>; rel="next"` header, a cursor, or an offset from page to page.
+//!
+//! ```ignore
+//! let mut repos = client.get("https://api.github.com/orgs/rust-lang/repos").paginate(LinkHeader).items::<Repo>("");
+//! while let Some(repo) = repos.try_next().await? {
+//!     println!("{}", repo.name);
+//! }
+//! ```
+
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt, TryStreamExt};
+use http::{HeaderMap, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::request::send_request;
+use crate::response::response_into_content;
+use crate::uri::resolve;
+use crate::{Client, Error, InMemoryBody, InMemoryRequest, InMemoryResponse, InMemoryResponseExt, InMemoryResult, Middleware, TypedHeaders};
+
+/// How long to wait after a `429 Too Many Requests` without a `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// How many times to wait out a `429 Too Many Requests` for the same page before giving up.
+const MAX_RATE_LIMITED: u32 = 5;
+
+/// Decides which page comes next.
+pub trait Pagination: Send + 'static {
+    /// The request for the page after `response`, or `None` if it was the last.
+    fn next(&mut self, request: &InMemoryRequest, response: &InMemoryResponse) -> Option<InMemoryRequest>;
+}
+
+/// Follow the `rel="next"` link of the `Link` header (RFC 8288), as GitHub and many other APIs send.
+#[derive(Debug, Clone, Default)]
+pub struct LinkHeader;
+
+impl Pagination for LinkHeader {
+    fn next(&mut self, request: &InMemoryRequest, response: &InMemoryResponse) -> Option<InMemoryRequest> {
+        let link = next_link(response.headers())?;
+        Some(request.clone().set_url(resolve(request.url(), &link).ok()?))
+    }
+}
+
+/// Send the cursor found at a JSON pointer in the body, e.g. `/meta/next_cursor`, as a query parameter. Ends when
+/// the cursor is missing, null, or empty.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    param: String,
+    pointer: String,
+}
+
+impl Cursor {
+    pub fn new(param: impl Into<String>, pointer: impl Into<String>) -> Self {
+        Cursor { param: param.into(), pointer: pointer.into() }
+    }
+}
+
+impl Pagination for Cursor {
+    fn next(&mut self, request: &InMemoryRequest, response: &InMemoryResponse) -> Option<InMemoryRequest> {
+        let body = body_json(response.body())?;
+        let cursor = match body.pointer(&self.pointer)? {
+            Value::String(s) if !s.is_empty() => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => return None,
+        };
+        Some(request.clone().set_url(with_query_param(request.url(), &self.param, &cursor)?))
+    }
+}
+
+/// Advance a query parameter by the number of items in each page, found at a JSON pointer in the body (`""` for a
+/// body which is itself an array). Ends at an empty page.
+#[derive(Debug, Clone)]
+pub struct Offset {
+    param: String,
+    pointer: String,
+}
+
+impl Offset {
+    pub fn new(param: impl Into<String>, pointer: impl Into<String>) -> Self {
+        Offset { param: param.into(), pointer: pointer.into() }
+    }
+}
+
+impl Pagination for Offset {
+    fn next(&mut self, request: &InMemoryRequest, response: &InMemoryResponse) -> Option<InMemoryRequest> {
+        let body = body_json(response.body())?;
+        let count = body.pointer(&self.pointer)?.as_array()?.len() as u64;
+        if count == 0 {
+            return None;
+        }
+        let offset = query_param(request.url(), &self.param).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        Some(request.clone().set_url(with_query_param(request.url(), &self.param, &(offset + count).to_string())?))
+    }
+}
+
+/// A stream of pages, created with `RequestBuilder::paginate`.
+///
+/// A `429 Too Many Requests` is waited out, for as long as `Retry-After` asks, and the page is requested again. Any
+/// other 4xx or 5xx response is yielded as `Error::HttpError` and ends the stream.
+pub struct Pages<'a> {
+    state: Option<State<'a>>,
+    inner: Option<BoxStream<'a, InMemoryResult<InMemoryResponse>>>,
+}
+
+impl<'a> Pages<'a> {
+    pub(crate) fn new(client: &'a Client, request: InMemoryRequest, middlewares: Vec<Arc<dyn Middleware>>, timeout: Option<Duration>, pagination: Box<dyn Pagination>) -> Self {
+        let state = State {
+            client,
+            request: Some(request),
+            middlewares,
+            timeout,
+            pagination,
+            delay: None,
+            first: true,
+        };
+        Pages { state: Some(state), inner: None }
+    }
+
+    /// Wait between pages, to stay under an API's rate limit.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        if let Some(state) = self.state.as_mut() {
+            state.delay = Some(delay);
+        }
+        self
+    }
+
+    /// Deserialize each page from JSON.
+    pub fn json<T: DeserializeOwned + Send + 'a>(self) -> BoxStream<'a, InMemoryResult<T>> {
+        Box::pin(self.and_then(|res| futures::future::ready(res.json())))
+    }
+
+    /// The items of each page, from the array at a JSON pointer in the body, e.g. `/data`, or `""` for a body which is
+    /// itself an array.
+    #[allow(clippy::result_large_err)]
+    pub fn items<T: DeserializeOwned + Send + 'a>(self, pointer: &str) -> BoxStream<'a, InMemoryResult<T>> {
+        let pointer = pointer.to_string();
+        Box::pin(self.map_ok(move |res| {
+            let items = res.json::<Value>().and_then(|mut body| match body.pointer_mut(&pointer).map(Value::take) {
+                Some(Value::Array(items)) => Ok(items),
+                _ => Err(<serde_json::Error as serde::de::Error>::custom(format!("No array at `{}` in the page", pointer)).into()),
+            });
+            let items: Vec<InMemoryResult<T>> = match items {
+                Ok(items) => items.into_iter().map(|item| serde_json::from_value(item).map_err(Into::into)).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(items)
+        }).try_flatten())
+    }
+}
+
+impl<'a> Stream for Pages<'a> {
+    type Item = InMemoryResult<InMemoryResponse>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(state) = self.state.take() {
+            self.inner = Some(futures::stream::unfold(state, State::next).boxed());
+        }
+        match self.inner.as_mut() {
+            Some(inner) => inner.as_mut().poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+struct State<'a> {
+    client: &'a Client,
+    /// The next page to request, or `None` once the last has been.
+    request: Option<InMemoryRequest>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Duration>,
+    pagination: Box<dyn Pagination>,
+    delay: Option<Duration>,
+    first: bool,
+}
+
+impl State<'_> {
+    async fn next(mut self) -> Option<(InMemoryResult<InMemoryResponse>, Self)> {
+        let request = self.request.take()?;
+        if let Some(delay) = self.delay.filter(|_| !self.first) {
+            tokio::time::sleep(delay).await;
+        }
+        self.first = false;
+        let mut rate_limited = 0;
+        let res = loop {
+            let res = match send_request(self.client, request.clone(), &self.middlewares, self.timeout).await {
+                Ok(res) => res,
+                Err(e) => return Some((Err(e.into()), self)),
+            };
+            if res.status() == StatusCode::TOO_MANY_REQUESTS && rate_limited < MAX_RATE_LIMITED {
+                rate_limited += 1;
+                tokio::time::sleep(res.retry_after().unwrap_or(DEFAULT_RETRY_AFTER)).await;
+                continue;
+            }
+            break res;
+        };
+        if res.status().is_client_error() || res.status().is_server_error() {
+            // Error bodies are kept as they are, so a malformed one doesn't hide the status.
+            let (parts, body) = res.into_parts();
+            return match body.into_memory().await {
+                Ok(body) => Some((Err(Error::HttpError(InMemoryResponse::from_parts(parts, body))), self)),
+                Err(e) => Some((Err(e.into()), self)),
+            };
+        }
+        let res = match response_into_content(res).await {
+            Ok(res) => res,
+            Err(e) => return Some((Err(e.into()), self)),
+        };
+        self.request = self.pagination.next(&request, &res);
+        Some((Ok(res), self))
+    }
+}
+
+fn body_json(body: &InMemoryBody) -> Option<Cow<'_, Value>> {
+    match body {
+        InMemoryBody::Json(value) => Some(Cow::Borrowed(value)),
+        body => body.clone().json().ok().map(Cow::Owned),
+    }
+}
+
+/// The target of the `rel="next"` link, from any `Link` header.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    for value in headers.get_all(http::header::LINK) {
+        let mut rest = value.to_str().ok()?;
+        while let Some(start) = rest.find('<') {
+            let end = start + rest[start..].find('>')?;
+            let target = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            let params = &rest[..rest.find('<').unwrap_or(rest.len())];
+            let is_next = params.split([';', ','])
+                .filter_map(|param| param.split_once('='))
+                .filter(|(key, _)| key.trim().eq_ignore_ascii_case("rel"))
+                .any(|(_, rels)| rels.trim().trim_matches('"').split_whitespace().any(|rel| rel.eq_ignore_ascii_case("next")));
+            if is_next {
+                return Some(target.to_string());
+            }
+        }
+    }
+    None
+}
+
+fn query_param(uri: &Uri, key: &str) -> Option<String> {
+    uri.query()?.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| urlencoding::decode(k).ok().as_deref() == Some(key))
+        .and_then(|(_, v)| urlencoding::decode(v).ok().map(Cow::into_owned))
+}
+
+/// Set a query parameter, replacing any existing values.
+fn with_query_param(uri: &Uri, key: &str, value: &str) -> Option<Uri> {
+    let mut pairs: Vec<&str> = uri.query().unwrap_or_default().split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| urlencoding::decode(pair.split('=').next().unwrap()).ok().as_deref() != Some(key))
+        .collect();
+    let param = format!("{}={}", urlencoding::encode(key), urlencoding::encode(value));
+    pairs.push(&param);
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(format!("{}?{}", uri.path(), pairs.join("&")).parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_next_link() {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::LINK, r#"<https://api.example.com/repos?page=1>; rel="prev first", <https://api.example.com/repos?page=3&a=1,2>; rel="next""#.parse().unwrap());
+        assert_eq!(next_link(&headers).unwrap(), "https://api.example.com/repos?page=3&a=1,2");
+        headers.insert(http::header::LINK, "</repos?page=1>; rel=first".parse().unwrap());
+        assert!(next_link(&headers).is_none());
+
+        let base: Uri = "https://example.com/api/repos?page=1".parse().unwrap();
+        assert_eq!(with_query_param(&base, "page", "a b").unwrap().to_string(), "https://example.com/api/repos?page=a%20b");
+    }
+
+    #[tokio::test]
+    async fn test_paginate() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let make_svc = make_service_fn({
+            let requests = requests.clone();
+            move |_| {
+                let requests = requests.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<hyper::Body>| {
+                        let n = requests.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            let query = req.uri().query().unwrap_or_default().to_string();
+                            let res = hyper::Response::builder().header("content-type", "application/json");
+                            let res = match (req.uri().path(), query.as_str()) {
+                                // Link headers, with a rate limit on the second page.
+                                ("/link", "") => res.header("link", "</link?page=2>; rel=\"next\"").body(json!([1, 2]).to_string()),
+                                ("/link", "page=2") if n == 1 => res.status(429).header("retry-after", "0").body(String::new()),
+                                ("/link", "page=2") => res.body(json!([3]).to_string()),
+                                ("/cursor", "") => res.body(json!({"data": [1], "next": "b"}).to_string()),
+                                ("/cursor", "after=b") => res.body(json!({"data": [2], "next": null}).to_string()),
+                                ("/offset", "") => res.body(json!({"data": [1, 2]}).to_string()),
+                                ("/offset", "offset=2") => res.body(json!({"data": [3]}).to_string()),
+                                ("/offset", "offset=3") => res.body(json!({"data": []}).to_string()),
+                                _ => res.status(404).body(String::new()),
+                            };
+                            Ok::<_, hyper::Error>(res.unwrap().map(hyper::Body::from))
+                        }
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new();
+        let items: Vec<u32> = client.get(&format!("http://{addr}/link")).paginate(LinkHeader).items::<u32>("").try_collect().await.unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let pages: Vec<Value> = client.get(&format!("http://{addr}/cursor")).paginate(Cursor::new("after", "/next")).json().try_collect().await.unwrap();
+        assert_eq!(pages, vec![json!({"data": [1], "next": "b"}), json!({"data": [2], "next": null})]);
+
+        let items: Vec<u32> = client.get(&format!("http://{addr}/offset"))
+            .paginate(Offset::new("offset", "/data"))
+            .delay(Duration::from_millis(1))
+            .items::<u32>("/data")
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+
+        let err = client.get(&format!("http://{addr}/missing")).paginate(LinkHeader).try_collect::<Vec<_>>().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use hyper::body::HttpBody;
+
+/// How much of a request or response body has been transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub transferred: u64,
+    /// The size of the body, if it's known up front.
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// The fraction transferred, between 0 and 1, if the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some(self.transferred as f64 / total as f64),
+            None => None,
+        }
+    }
+}
+
+type ProgressFn = Arc<dyn Fn(Progress) + Send + Sync>;
+
+macro_rules! progress_callback {
+    ($name:ident) => {
+        #[derive(Clone)]
+        pub(crate) struct $name(pub ProgressFn);
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str(stringify!($name))
+            }
+        }
+    };
+}
+
+// Stored in the request extensions by `RequestBuilder`, and applied when the request is sent.
+progress_callback!(UploadProgress);
+progress_callback!(DownloadProgress);
+
+/// The size of `body`, from the Content-Length header if there is one.
+pub(crate) fn body_size(headers: &http::HeaderMap, body: &hyper::Body) -> Option<u64> {
+    headers.get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse().ok())
+        .or_else(|| HttpBody::size_hint(body).exact())
+}
+
+/// Wrap `body` so `callback` is called as each chunk passes through.
+pub(crate) fn track(body: hyper::Body, total: Option<u64>, callback: ProgressFn) -> hyper::Body {
+    let mut transferred = 0;
+    hyper::Body::wrap_stream(body.inspect_ok(move |chunk| {
+        transferred += chunk.len() as u64;
+        callback(Progress { transferred, total });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use hyper::service::{make_service_fn, service_fn};
+
+    use crate::{Body, Client, InMemoryResponseExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress() {
+        // Echoes the request body.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(body)))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let uploads = Arc::new(Mutex::new(Vec::new()));
+        let downloads = Arc::new(Mutex::new(Vec::new()));
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(vec![b'a'; 1024]));
+        let res = Client::new().post(&format!("http://{addr}/"))
+            .stream(Body::from_stream(futures::stream::iter(chunks)))
+            .upload_progress({
+                let uploads = uploads.clone();
+                move |p| uploads.lock().unwrap().push(p)
+            })
+            .download_progress({
+                let downloads = downloads.clone();
+                move |p| downloads.lock().unwrap().push(p)
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.bytes().unwrap().len(), 4096);
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(uploads.len(), 4);
+        assert_eq!(uploads.last(), Some(&Progress { transferred: 4096, total: None }));
+        let downloads = downloads.lock().unwrap();
+        assert_eq!(downloads.last(), Some(&Progress { transferred: 4096, total: Some(4096) }));
+        assert_eq!(downloads.last().unwrap().fraction(), Some(1.0));
+    }
+}
@@ -1,51 +1,184 @@
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::path::Path;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::{debug, info};
-use walkdir::WalkDir;
 
 use crate::{InMemoryRequest, InMemoryResponse};
 use crate::error::ProtocolResult;
-use crate::response::{clone_inmemory_response, InMemoryResponseExt};
+use crate::response::clone_inmemory_response;
+
+pub use matching::MatchRules;
+pub use crate::sanitize::Sanitizer;
+pub use verify::{UnmatchedRequest, VerifyError};
+pub use store::{CassetteStore, FileCassetteStore, MemoryCassetteStore};
+use har::{Har, HarEntry};
+use vcr::{Cassette, Interaction};
+
+mod matching;
+mod store;
+mod verify;
+pub mod har;
+pub mod vcr;
+
+/// The file format used to write new recordings. Recordings in any format are loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CassetteFormat {
+    /// One JSON file per interaction.
+    #[default]
+    Json,
+    /// One HAR 1.2 file per interaction.
+    Har,
+    /// One VCR-style YAML cassette per interaction.
+    Yaml,
+}
+
+impl CassetteFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            CassetteFormat::Json => "json",
+            CassetteFormat::Har => "har",
+            CassetteFormat::Yaml => "yml",
+        }
+    }
+}
+
+fn yaml_error(e: serde_yaml::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RequestResponsePair {
     pub request: InMemoryRequest,
     #[serde(with = "crate::response::serde_response")]
     pub response: InMemoryResponse,
+    /// How long the response took, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct RRPair {
+    pub recording: Recording,
+    pub fname: String,
+}
+
+/// A recorded interaction.
+#[derive(Debug)]
+pub struct Recording {
     pub request: InMemoryRequest,
     pub response: InMemoryResponse,
-    pub fname: String,
+    /// How long the response took, if known.
+    pub duration: Option<Duration>,
+    /// The key of the cassette holding only this recording, if any.
+    pub key: Option<String>,
+}
+
+impl Recording {
+    pub fn new(request: InMemoryRequest, response: InMemoryResponse) -> Self {
+        Recording {
+            request,
+            response,
+            duration: None,
+            key: None,
+        }
+    }
+}
+
+impl Clone for Recording {
+    fn clone(&self) -> Self {
+        Recording {
+            request: self.request.clone(),
+            response: clone_inmemory_response(&self.response),
+            duration: self.duration,
+            key: self.key.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum StoreOp {
+    Save(String, String),
+    Remove(String),
+}
+
+impl StoreOp {
+    fn apply(self, store: &dyn CassetteStore) -> std::io::Result<()> {
+        match self {
+            StoreOp::Save(key, contents) => store.save(&key, &contents),
+            StoreOp::Remove(key) => store.remove(&key),
+        }
+    }
 }
 
+/// A short, stable fingerprint of the parts of a request used for matching, for naming its recording.
+pub(crate) fn fingerprint(request: &InMemoryRequest) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(request.method().as_str());
+    hasher.update(b" ");
+    hasher.update(request.url().to_string());
+    hasher.update(b"\n");
+    hasher.update(request.body().clone().bytes().unwrap_or_default());
+    hasher.finalize()[..4].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Recordings are replayed in the order they were recorded. When the same request was recorded multiple times (e.g.
+/// polling until a job finishes), each replay returns the next recorded response, and the last one repeats once
+/// they're exhausted. Use `RecorderMode::RecordAll` to record such sequences.
 #[derive(Debug, Clone)]
 pub struct RequestRecorder {
-    pub base_path: PathBuf,
-    pub requests: Arc<RwLock<IndexMap<InMemoryRequest, InMemoryResponse>>>,
-}
-
-fn load_requests(path: &PathBuf) -> impl Iterator<Item=RRPair> {
-    WalkDir::new(path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file() && e.file_name().to_str().unwrap().ends_with(".json"))
-        .map(|filepath| {
-            debug!(file=filepath.path().display().to_string(), "Loading recording");
-            let f = fs::read_to_string(filepath.path()).unwrap();
-            let rr: RequestResponsePair = serde_json::from_str(&f).unwrap();
-            RRPair {
-                request: rr.request,
-                response: rr.response,
-                fname: filepath.path().file_name().unwrap().to_str().unwrap().to_string(),
-            }
+    store: Arc<dyn CassetteStore>,
+    pub recordings: Arc<RwLock<Vec<Recording>>>,
+    tracking: Arc<Mutex<Tracking>>,
+    /// Writes waiting for the writer. Queued while holding the `recordings` lock, so they're in the same order.
+    pending: Arc<Mutex<Vec<StoreOp>>>,
+    /// Held while writing to the store, so concurrent recordings never interleave writes.
+    writer: Arc<Mutex<()>>,
+}
+
+/// What happened during replay, for `RequestRecorder::verify`.
+#[derive(Debug, Default)]
+struct Tracking {
+    /// Indices into `recordings` which have been played or recorded.
+    played: HashSet<usize>,
+    unexpected: Vec<InMemoryRequest>,
+}
+
+fn load_requests(store: &dyn CassetteStore) -> impl Iterator<Item=RRPair> + '_ {
+    let keys = store.keys().expect("Failed to list recordings");
+    keys.into_iter()
+        .flat_map(move |key| {
+            let load = || store.load(&key).expect("Failed to load recording").unwrap_or_default();
+            let recordings = if key.ends_with(".json") {
+                debug!(key, "Loading recording");
+                let rr: RequestResponsePair = serde_json::from_str(&load()).unwrap();
+                vec![Recording {
+                    duration: rr.duration_ms.map(Duration::from_millis),
+                    ..Recording::new(rr.request, rr.response)
+                }]
+            } else if key.ends_with(".har") {
+                debug!(key, "Loading HAR recording");
+                let har: Har = serde_json::from_str(&load()).unwrap();
+                har.into_recordings()
+            } else if key.ends_with(".yml") || key.ends_with(".yaml") {
+                debug!(key, "Loading VCR cassette");
+                let cassette: Cassette = serde_yaml::from_str(&load()).unwrap();
+                cassette.into_recordings()
+            } else {
+                vec![]
+            };
+            let many = recordings.len() > 1;
+            let fname = key.rsplit('/').next().unwrap().to_string();
+            let single = (!many).then_some(key);
+            recordings.into_iter().enumerate().map(move |(i, recording)| RRPair {
+                recording: Recording { key: single.clone(), ..recording },
+                fname: if many { format!("{fname}.{i:04}") } else { fname.clone() },
+            })
         })
 }
 
@@ -56,68 +189,232 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
 }
 
 impl RequestRecorder {
+    /// Load the recordings in `data/vcr` of the current directory.
     pub fn new() -> Self {
-        let path = std::env::current_dir().unwrap().join("data").join("vcr");
-        debug!(dir=path.display().to_string(), "Request recorder created");
-        let mut requests = load_requests(&path).collect::<Vec<_>>();
+        Self::load_from_path(&std::env::current_dir().unwrap().join("data").join("vcr"))
+    }
+
+    /// Load the recordings in a directory. New recordings are written there too.
+    pub fn load_from_path(path: &Path) -> Self {
+        Self::with_store(FileCassetteStore::new(path))
+    }
+
+    /// Load the recordings in a store. New recordings are saved there too.
+    pub fn with_store(store: impl CassetteStore + 'static) -> Self {
+        debug!(store=?store, "Request recorder created");
+        let mut requests = load_requests(&store).collect::<Vec<_>>();
         requests.sort_by_key(|rr| rr.fname.clone());
-        let requests: IndexMap<InMemoryRequest, InMemoryResponse> = requests.into_iter()
-            .map(|r| (r.request, r.response))
-            .collect::<_>();
-        info!(num_recordings=requests.len(), dir=path.display().to_string(), "Request recorder loaded");
-        let requests = Arc::new(RwLock::new(requests));
+        let recordings: Vec<Recording> = requests.into_iter()
+            .map(|r| r.recording)
+            .collect();
+        info!(num_recordings=recordings.len(), store=?store, "Request recorder loaded");
         RequestRecorder {
-            base_path: path,
-            requests,
+            store: Arc::new(store),
+            recordings: Arc::new(RwLock::new(recordings)),
+            tracking: Default::default(),
+            pending: Default::default(),
+            writer: Default::default(),
         }
     }
 
     pub fn get_response(&self, request: &InMemoryRequest) -> Option<InMemoryResponse> {
         debug!(url=request.url().to_string(), hash=calculate_hash(request), "Checking for recorded response");
-        self.requests.read().unwrap().get(request).map(clone_inmemory_response)
+        self.get_matching_response(request, &MatchRules::default())
+    }
+
+    /// Find a recorded response for a request, using `rules` to decide which parts of the request must match.
+    pub fn get_matching_response(&self, request: &InMemoryRequest, rules: &MatchRules) -> Option<InMemoryResponse> {
+        self.get_matching(request, rules).map(|r| r.response)
+    }
+
+    /// Find a recording for a request, using `rules` to decide which parts of the request must match.
+    pub fn get_matching(&self, request: &InMemoryRequest, rules: &MatchRules) -> Option<Recording> {
+        debug!(url=request.url().to_string(), "Checking for recorded response");
+        let recordings = self.recordings.read().unwrap();
+        let mut tracking = self.tracking.lock().unwrap();
+        let matching: Vec<usize> = recordings.iter().enumerate()
+            .filter(|(_, recorded)| rules.matches(&recorded.request, request))
+            .map(|(i, _)| i)
+            .collect();
+        let idx = matching.iter().copied()
+            .find(|i| !tracking.played.contains(i))
+            .or(matching.last().copied())?;
+        tracking.played.insert(idx);
+        Some(recordings[idx].clone())
+    }
+
+    /// Note that a request had no recording, so `verify` reports it.
+    pub fn record_unexpected(&self, request: &InMemoryRequest) {
+        self.tracking.lock().unwrap().unexpected.push(request.clone());
+    }
+
+    /// Check that every recording was played, and that every request had a recording. Call this at the end of a test
+    /// to catch stale recordings and requests that unexpectedly went to the network.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let recordings = self.recordings.read().unwrap();
+        let tracking = self.tracking.lock().unwrap();
+        let unused: Vec<_> = recordings.iter().enumerate()
+            .filter(|(i, _)| !tracking.played.contains(i))
+            .map(|(_, recorded)| recorded.request.clone())
+            .collect();
+        let unexpected: Vec<_> = tracking.unexpected.iter()
+            .map(|request| UnmatchedRequest {
+                request: request.clone(),
+                closest: verify::closest(request, recordings.iter().map(|r| &r.request)),
+            })
+            .collect();
+        if unused.is_empty() && unexpected.is_empty() {
+            Ok(())
+        } else {
+            Err(VerifyError { unused, unexpected })
+        }
+    }
+
+    /// Like `verify`, but panics with the details on failure.
+    pub fn assert_verified(&self) {
+        if let Err(e) = self.verify() {
+            panic!("{e}");
+        }
     }
 
-    fn partial_filepath(&self, request: &InMemoryRequest) -> PathBuf {
-        let mut path = self.base_path.clone();
-        path.push(request.host());
-        path.push(&request.path()[1..]);
-        path.push(request.method().as_str().to_lowercase());
-        path
+    pub fn store(&self) -> &dyn CassetteStore {
+        self.store.as_ref()
+    }
+
+    /// The key of a recording without the extension, e.g. `example.com/users/get`.
+    fn partial_key(&self, request: &InMemoryRequest) -> String {
+        let method = request.method().as_str().to_lowercase();
+        std::iter::once(request.host())
+            .chain(request.path().split('/').filter(|s| !s.is_empty()))
+            .chain(std::iter::once(method.as_str()))
+            .collect::<Vec<_>>()
+            .join("/")
     }
 
     pub fn clear(&mut self) {
-        self.requests.write().unwrap().clear();
+        self.recordings.write().unwrap().clear();
+        *self.tracking.lock().unwrap() = Tracking::default();
     }
 
-    pub fn record_response(&self, mut request: InMemoryRequest, mut response: InMemoryResponse) -> ProtocolResult<()> {
-        let partial_path = self.partial_filepath(&request);
-        request.sanitize();
-        response.sanitize();
+    pub fn record_response(&self, request: InMemoryRequest, response: InMemoryResponse) -> ProtocolResult<()> {
+        self.record(Recording::new(request, response), CassetteFormat::Json, &Sanitizer::default())
+    }
 
-        let rr = RequestResponsePair {
-            request,
-            response,
+    /// Record an interaction, redacting it with `sanitizer` and saving it to the store in the given format.
+    /// The first recording of the same request which hasn't been played or recorded yet is replaced; otherwise the
+    /// interaction is added after the existing recordings.
+    ///
+    /// Recordings are named after the request and their position among recordings of the same request, e.g.
+    /// `example.com/users/get.1a2b3c4d.0000.json`, so the name doesn't depend on the order of concurrent requests.
+    /// Safe to call from concurrent tasks: writes are queued, and saved in batches by one caller at a time.
+    pub fn record(&self, recording: Recording, format: CassetteFormat, sanitizer: &Sanitizer) -> ProtocolResult<()> {
+        let Recording { mut request, mut response, duration, .. } = recording;
+        let partial_key = self.partial_key(&request);
+        sanitizer.sanitize_request(&mut request);
+        sanitizer.sanitize_response(&mut response);
+
+        let stringified = match format {
+            CassetteFormat::Json => {
+                let rr = RequestResponsePair {
+                    request,
+                    response,
+                    duration_ms: duration.map(|d| d.as_millis() as u64),
+                };
+                let stringified = serde_json::to_string_pretty(&rr).unwrap();
+                let RequestResponsePair { request: req, response: res, .. } = rr;
+                request = req;
+                response = res;
+                stringified
+            }
+            CassetteFormat::Har => {
+                let entry = HarEntry::new(&request, &response).with_duration(duration);
+                serde_json::to_string_pretty(&Har::new(vec![entry])).unwrap()
+            }
+            CassetteFormat::Yaml => {
+                let interaction = Interaction::new(&request, &response).with_duration(duration);
+                serde_yaml::to_string(&Cassette::new(vec![interaction])).unwrap()
+            }
         };
-        let stringified = serde_json::to_string_pretty(&rr).unwrap();
-        let RequestResponsePair { request, response } = rr;
-        let idx;
+        let fingerprint = fingerprint(&request);
         {
-            let mut write = self.requests.write().unwrap();
-            let (i, _old) = write.insert_full(request, response);
-            idx = i;
+            let mut recordings = self.recordings.write().unwrap();
+            let mut tracking = self.tracking.lock().unwrap();
+            let existing = recordings.iter().enumerate()
+                .position(|(i, recorded)| !tracking.played.contains(&i) && recorded.request == request);
+            let idx = existing.unwrap_or(recordings.len());
+            let seq = recordings[..idx].iter().filter(|recorded| recorded.request == request).count();
+            let key = format!("{partial_key}.{fingerprint}.{seq:04}.{}", format.extension());
+            let mut pending = self.pending.lock().unwrap();
+            let recording = Recording { request, response, duration, key: Some(key.clone()) };
+            match existing {
+                Some(i) => {
+                    let old = std::mem::replace(&mut recordings[i], recording);
+                    if let Some(old_key) = old.key.filter(|old_key| old_key != &key) {
+                        pending.push(StoreOp::Remove(old_key));
+                    }
+                }
+                None => recordings.push(recording),
+            }
+            pending.push(StoreOp::Save(key, stringified));
+            tracking.played.insert(idx);
+        }
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Save any queued recordings to the store. `record` calls this, so it's only needed to retry after an error.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let _writing = self.writer.lock().unwrap();
+        let ops = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut result = Ok(());
+        for op in ops {
+            if let Err(e) = op.apply(self.store.as_ref()) {
+                result = result.and(Err(e));
+            }
         }
-        let path = partial_path.with_extension(format!("{:04}.json", idx));
-        fs::create_dir_all(path.parent().unwrap()).unwrap();
-        fs::write(&path, stringified)?;
+        result
+    }
+
+    /// Load the entries of a HAR file, e.g. one exported from a browser's developer tools, as recordings.
+    /// They are held in memory only. Returns the number of entries loaded.
+    pub fn import_har(&self, path: &Path) -> ProtocolResult<usize> {
+        let har: Har = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(self.import_recordings(har.into_recordings()))
+    }
+
+    /// Load the interactions of a VCR YAML cassette as recordings. They are held in memory only.
+    /// Returns the number of interactions loaded.
+    pub fn import_vcr(&self, path: &Path) -> ProtocolResult<usize> {
+        let cassette: Cassette = serde_yaml::from_str(&fs::read_to_string(path)?).map_err(yaml_error)?;
+        Ok(self.import_recordings(cassette.into_recordings()))
+    }
+
+    fn import_recordings(&self, recordings: Vec<Recording>) -> usize {
+        let count = recordings.len();
+        self.recordings.write().unwrap().extend(recordings);
+        count
+    }
+
+    /// Write every recording to a single HAR file.
+    pub fn export_har(&self, path: &Path) -> ProtocolResult<()> {
+        let entries = self.recordings.read().unwrap().iter()
+            .map(|r| HarEntry::new(&r.request, &r.response).with_duration(r.duration))
+            .collect();
+        fs::write(path, serde_json::to_string_pretty(&Har::new(entries))?)?;
         Ok(())
     }
 
-    pub fn load_from_path(_path: &Path) {
-        unimplemented!()
+    /// Write every recording to a single VCR YAML cassette.
+    pub fn export_vcr(&self, path: &Path) -> ProtocolResult<()> {
+        let interactions = self.recordings.read().unwrap().iter()
+            .map(|r| Interaction::new(&r.request, &r.response).with_duration(r.duration))
+            .collect();
+        fs::write(path, serde_yaml::to_string(&Cassette::new(interactions)).map_err(yaml_error)?)?;
+        Ok(())
     }
 
-    pub fn load_default() {
-        unimplemented!()
+    pub fn load_default() -> Self {
+        Self::new()
     }
 }
 
@@ -0,0 +1,376 @@
+//! HTTP Archive (HAR 1.2) support, so recordings can be exchanged with browsers and other tools.
+//! Spec: <http://www.softwareishard.com/blog/har-12-spec/>
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, Version};
+use serde::{Deserialize, Serialize};
+
+use crate::{InMemoryBody, InMemoryRequest, InMemoryResponse, InMemoryResponseExt};
+use crate::body::FORM_CONTENT_TYPE;
+
+use super::Recording;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    #[serde(default)]
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub started_date_time: String,
+    /// Total time of the request, in milliseconds.
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: serde_json::Value,
+    #[serde(default)]
+    pub timings: HarTimings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub headers: Vec<HarPair>,
+    #[serde(default)]
+    pub query_string: Vec<HarPair>,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    pub http_version: String,
+    pub headers: Vec<HarPair>,
+    #[serde(default)]
+    pub cookies: Vec<serde_json::Value>,
+    pub content: HarContent,
+    #[serde(default, rename = "redirectURL")]
+    pub redirect_url: String,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HarPair {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    /// The decoded fields of a url-encoded form.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<HarPair>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+fn unknown_size() -> i64 {
+    -1
+}
+
+impl Har {
+    pub fn new(entries: Vec<HarEntry>) -> Self {
+        Har {
+            log: HarLog {
+                version: "1.2".to_string(),
+                creator: HarCreator {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries,
+            },
+        }
+    }
+
+    /// Convert the entries into recordings. Entries which can't be represented (e.g. invalid urls) are skipped.
+    pub fn into_recordings(self) -> Vec<Recording> {
+        self.log.entries.into_iter()
+            .filter_map(|entry| entry.into_recording())
+            .collect()
+    }
+}
+
+impl HarEntry {
+    pub fn new(request: &InMemoryRequest, response: &InMemoryResponse) -> Self {
+        let query_string = request.url().query().unwrap_or_default()
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                HarPair { name: name.to_string(), value: value.to_string() }
+            })
+            .collect();
+        let post_data = (!request.body().is_empty()).then(|| HarPostData {
+            mime_type: content_type(request.headers(), request.body()),
+            text: request.body().clone().text().unwrap_or_default(),
+            params: match request.body() {
+                InMemoryBody::Form(pairs) => pairs.iter()
+                    .map(|(name, value)| HarPair { name: name.clone(), value: value.clone() })
+                    .collect(),
+                _ => vec![],
+            },
+        });
+        let request = HarRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            http_version: version_str(request.version()),
+            headers: har_headers(request.headers()),
+            query_string,
+            cookies: vec![],
+            post_data,
+            headers_size: -1,
+            body_size: -1,
+        };
+        let (text, encoding) = match response.body() {
+            InMemoryBody::Empty => (None, None),
+            InMemoryBody::Bytes(b) => match std::str::from_utf8(b) {
+                Ok(s) => (Some(s.to_string()), None),
+                Err(_) => (Some(BASE64_STANDARD.encode(b)), Some("base64".to_string())),
+            },
+            body => (body.clone().text().ok(), None),
+        };
+        let response = HarResponse {
+            status: response.status().as_u16(),
+            status_text: response.status().canonical_reason().unwrap_or_default().to_string(),
+            http_version: version_str(response.version()),
+            headers: har_headers(response.headers()),
+            cookies: vec![],
+            content: HarContent {
+                size: response.body().clone().bytes().map(|b| b.len() as i64).unwrap_or(-1),
+                mime_type: content_type(response.headers(), response.body()),
+                text,
+                encoding,
+            },
+            redirect_url: response.headers().get(http::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+            headers_size: -1,
+            body_size: -1,
+        };
+        HarEntry {
+            started_date_time: iso8601(SystemTime::now()),
+            time: 0.0,
+            request,
+            response,
+            cache: serde_json::Value::Object(Default::default()),
+            timings: HarTimings::default(),
+        }
+    }
+
+    /// Set the total time of the request, which is replayed as the time spent waiting for the response.
+    pub fn with_duration(mut self, duration: Option<Duration>) -> Self {
+        if let Some(duration) = duration {
+            let ms = duration.as_secs_f64() * 1000.0;
+            self.time = ms;
+            self.timings = HarTimings { send: 0.0, wait: ms, receive: 0.0 };
+        }
+        self
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        (self.time > 0.0).then(|| Duration::from_secs_f64(self.time / 1000.0))
+    }
+
+    pub fn into_recording(self) -> Option<Recording> {
+        let duration = self.duration();
+        let (request, response) = self.into_pair()?;
+        Some(Recording { duration, ..Recording::new(request, response) })
+    }
+
+    pub fn into_pair(self) -> Option<(InMemoryRequest, InMemoryResponse)> {
+        let HarEntry { request: req, response: res, .. } = self;
+        let uri: Uri = req.url.parse().ok()?;
+        let method: Method = req.method.parse().ok()?;
+        let body = match req.post_data {
+            Some(data) if !data.text.is_empty() => body_from_text(data.text, &data.mime_type),
+            _ => InMemoryBody::Empty,
+        };
+        let mut request = InMemoryRequest::new(method, uri, from_har_headers(req.headers), body);
+        *request.version_mut() = parse_version(&req.http_version);
+
+        let mut headers = from_har_headers(res.headers);
+        // Browsers export the decoded content, so the original encoding no longer applies.
+        headers.remove(http::header::CONTENT_ENCODING);
+        let body = match (res.content.text, res.content.encoding.as_deref()) {
+            (None, _) => InMemoryBody::Empty,
+            (Some(text), Some("base64")) => InMemoryBody::new_bytes(BASE64_STANDARD.decode(text.as_bytes()).ok()?),
+            (Some(text), _) => body_from_text(text, &res.content.mime_type),
+        };
+        let mut response = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::from_u16(res.status).ok()?, headers, body);
+        *response.version_mut() = parse_version(&res.http_version);
+        Some((request, response))
+    }
+}
+
+fn content_type(headers: &HeaderMap, body: &InMemoryBody) -> String {
+    match headers.get(http::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(ct) => ct.to_string(),
+        None if matches!(body, InMemoryBody::Json(_)) => "application/json".to_string(),
+        None if matches!(body, InMemoryBody::Form(_)) => FORM_CONTENT_TYPE.to_string(),
+        None => String::new(),
+    }
+}
+
+fn body_from_text(text: String, mime_type: &str) -> InMemoryBody {
+    match mime_type.split(';').next().unwrap().trim() {
+        "application/json" => if let Ok(value) = serde_json::from_str(&text) {
+            return InMemoryBody::Json(value);
+        },
+        FORM_CONTENT_TYPE => if let Ok(pairs) = serde_urlencoded::from_str(&text) {
+            return InMemoryBody::Form(pairs);
+        },
+        _ => {}
+    }
+    InMemoryBody::Text(text)
+}
+
+fn har_headers(headers: &HeaderMap) -> Vec<HarPair> {
+    headers.iter()
+        .map(|(k, v)| HarPair { name: k.to_string(), value: String::from_utf8_lossy(v.as_bytes()).into_owned() })
+        .collect()
+}
+
+/// Invalid names are skipped, including the HTTP/2 pseudo-headers (`:authority`, ...) that browsers export.
+fn from_har_headers(pairs: Vec<HarPair>) -> HeaderMap {
+    pairs.into_iter()
+        .filter_map(|p| Some((HeaderName::from_bytes(p.name.as_bytes()).ok()?, HeaderValue::from_str(&p.value).ok()?)))
+        .collect()
+}
+
+fn version_str(version: Version) -> String {
+    format!("{version:?}")
+}
+
+fn parse_version(s: &str) -> Version {
+    match s.to_ascii_uppercase().as_str() {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/2" | "HTTP/2.0" | "H2" => Version::HTTP_2,
+        "HTTP/3" | "HTTP/3.0" | "H3" => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    }
+}
+
+/// Format a time as ISO 8601 in UTC, e.g. `2024-01-02T03:04:05.678Z`.
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z", rem / 3600, rem % 3600 / 60, rem % 60, since_epoch.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(UNIX_EPOCH + Duration::from_millis(1_709_251_199_250)), "2024-02-29T23:59:59.250Z");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let request = Request::build_post("https://example.com/a?b=c").json(json!({"a": 1})).build();
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "image/png".parse().unwrap());
+        let response = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, headers, InMemoryBody::new_bytes(vec![0x89, 0x50, 0xff]));
+        let har = Har::new(vec![HarEntry::new(&request, &response)]);
+        let har: Har = serde_json::from_str(&serde_json::to_string(&har).unwrap()).unwrap();
+        assert_eq!(har.log.entries[0].response.content.encoding.as_deref(), Some("base64"));
+        let Recording { request: req, response: res, .. } = har.into_recordings().pop().unwrap();
+        assert_eq!(req, request);
+        assert_eq!(res.status(), 200);
+        assert!(matches!(res.body(), InMemoryBody::Bytes(b) if b[..] == [0x89, 0x50, 0xff]));
+    }
+
+    #[test]
+    fn test_browser_export() {
+        let har = json!({"log": {"version": "1.2", "creator": {"name": "Firefox", "version": "120"}, "entries": [{
+            "startedDateTime": "2024-01-01T00:00:00.000Z",
+            "time": 12.5,
+            "request": {"method": "GET", "url": "https://example.com/api", "httpVersion": "HTTP/2",
+                "headers": [{"name": ":authority", "value": "example.com"}, {"name": "accept", "value": "*/*"}]},
+            "response": {"status": 200, "statusText": "OK", "httpVersion": "HTTP/2",
+                "headers": [{"name": "content-type", "value": "application/json"}, {"name": "content-encoding", "value": "br"}],
+                "content": {"size": 8, "mimeType": "application/json", "text": "{\"a\":1}"}}
+        }]}});
+        let har: Har = serde_json::from_value(har).unwrap();
+        let Recording { request: req, response: res, .. } = har.into_recordings().pop().unwrap();
+        assert_eq!(req.headers().len(), 1);
+        assert!(res.headers().get("content-encoding").is_none());
+        assert_eq!(res.body().clone().json::<serde_json::Value>().unwrap(), json!({"a": 1}));
+    }
+}
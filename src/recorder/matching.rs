@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use http::header::HeaderName;
+use http::Uri;
+use serde_json::Value;
+
+use crate::{InMemoryBody, InMemoryRequest};
+use crate::sanitize::SANITIZED_VALUE;
+
+/// Controls which parts of a request participate in matching it against a recording.
+///
+/// By default, the method, url, and body must match exactly, and headers are ignored. Use the builder methods to
+/// ignore volatile values (timestamps, nonces, request ids) so recordings don't break when they change.
+///
+/// ```ignore
+/// let rules = MatchRules::new()
+///     .ignore_query_param("timestamp")
+///     .ignore_body_field("/meta/request_id")
+///     .match_headers()
+///     .ignore_header("x-request-id");
+/// let recorder = Recorder::new().match_rules(rules);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MatchRules {
+    pub match_headers: bool,
+    pub ignore_headers: HashSet<HeaderName>,
+    pub ignore_query_params: HashSet<String>,
+    pub ignore_body_fields: Vec<String>,
+    pub ignore_body: bool,
+}
+
+impl MatchRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require headers to match as well. Headers which were sanitized in the recording match any value.
+    pub fn match_headers(mut self) -> Self {
+        self.match_headers = true;
+        self
+    }
+
+    /// Exclude a header from matching. Only relevant with `match_headers`.
+    pub fn ignore_header(mut self, name: impl TryInto<HeaderName>) -> Self {
+        if let Ok(name) = name.try_into() {
+            self.ignore_headers.insert(name);
+        }
+        self
+    }
+
+    /// Exclude a query param from matching, e.g. a timestamp or nonce.
+    pub fn ignore_query_param(mut self, name: impl Into<String>) -> Self {
+        self.ignore_query_params.insert(name.into());
+        self
+    }
+
+    /// Drop a field from JSON or form bodies before matching. A field starting with `/` is a JSON pointer (RFC 6901);
+    /// otherwise, the key is removed wherever it appears in the body.
+    pub fn ignore_body_field(mut self, field: impl Into<String>) -> Self {
+        self.ignore_body_fields.push(field.into());
+        self
+    }
+
+    /// Don't consider the body at all.
+    pub fn ignore_body(mut self) -> Self {
+        self.ignore_body = true;
+        self
+    }
+
+    pub fn matches(&self, recorded: &InMemoryRequest, request: &InMemoryRequest) -> bool {
+        recorded.method() == request.method()
+            && self.uri_matches(recorded.url(), request.url())
+            && (!self.match_headers || self.headers_match(recorded, request))
+            && (self.ignore_body || self.body_matches(recorded.body(), request.body()))
+    }
+
+    fn uri_matches(&self, a: &Uri, b: &Uri) -> bool {
+        if self.ignore_query_params.is_empty() {
+            return a == b;
+        }
+        a.scheme() == b.scheme()
+            && a.authority() == b.authority()
+            && a.path() == b.path()
+            && self.query_pairs(a) == self.query_pairs(b)
+    }
+
+    fn query_pairs(&self, uri: &Uri) -> Vec<(String, String)> {
+        let mut pairs: Vec<_> = uri.query().unwrap_or_default()
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|pair| {
+                let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                let decode = |s: &str| urlencoding::decode(s).map(|s| s.into_owned()).unwrap_or_else(|_| s.to_string());
+                (decode(k), decode(v))
+            })
+            .filter(|(k, _)| !self.ignore_query_params.contains(k))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn headers_match(&self, recorded: &InMemoryRequest, request: &InMemoryRequest) -> bool {
+        let names: HashSet<&HeaderName> = recorded.headers().keys().chain(request.headers().keys())
+            .filter(|name| !self.ignore_headers.contains(*name))
+            .collect();
+        names.into_iter().all(|name| {
+            let a: Vec<_> = recorded.headers().get_all(name).iter().collect();
+            let b: Vec<_> = request.headers().get_all(name).iter().collect();
+            a == b || (!a.is_empty() && !b.is_empty() && a.iter().all(|v| v.as_bytes() == SANITIZED_VALUE.as_bytes()))
+        })
+    }
+
+    fn body_matches(&self, a: &InMemoryBody, b: &InMemoryBody) -> bool {
+        match (a, b) {
+            (InMemoryBody::Json(a), InMemoryBody::Json(b)) if !self.ignore_body_fields.is_empty() => {
+                let mut a = a.clone();
+                let mut b = b.clone();
+                self.strip_fields(&mut a);
+                self.strip_fields(&mut b);
+                a == b
+            }
+            (InMemoryBody::Form(a), InMemoryBody::Form(b)) if !self.ignore_body_fields.is_empty() => {
+                let keep = |(k, _): &&(String, String)| !self.ignore_body_fields.iter()
+                    .any(|f| f.strip_prefix('/').unwrap_or(f) == k);
+                a.iter().filter(keep).eq(b.iter().filter(keep))
+            }
+            (InMemoryBody::Empty, InMemoryBody::Empty) => true,
+            (InMemoryBody::Text(a), InMemoryBody::Text(b)) => a == b,
+            (InMemoryBody::Bytes(a), InMemoryBody::Bytes(b)) => a == b,
+            (InMemoryBody::Json(a), InMemoryBody::Json(b)) => a == b,
+            (InMemoryBody::Form(a), InMemoryBody::Form(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn strip_fields(&self, value: &mut Value) {
+        for field in &self.ignore_body_fields {
+            if field.starts_with('/') {
+                remove_pointer(value, field);
+            } else {
+                remove_key(value, field);
+            }
+        }
+    }
+}
+
+fn remove_pointer(value: &mut Value, pointer: &str) {
+    let Some((parent, key)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    let key = key.replace("~1", "/").replace("~0", "~");
+    match value.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.remove(&key);
+        }
+        Some(Value::Array(vec)) => {
+            if let Ok(idx) = key.parse::<usize>() {
+                if idx < vec.len() {
+                    vec.remove(idx);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn remove_key(value: &mut Value, key: &str) {
+    match value {
+        Value::Object(map) => {
+            map.remove(key);
+            map.values_mut().for_each(|v| remove_key(v, key));
+        }
+        Value::Array(vec) => vec.iter_mut().for_each(|v| remove_key(v, key)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_ignore_query_param() {
+        let a = Request::build_get("https://example.com/a?q=1&ts=100").build();
+        let b = Request::build_get("https://example.com/a?ts=200&q=1").build();
+        let c = Request::build_get("https://example.com/a?q=2&ts=100").build();
+        assert!(!MatchRules::new().matches(&a, &b));
+        let rules = MatchRules::new().ignore_query_param("ts");
+        assert!(rules.matches(&a, &b));
+        assert!(!rules.matches(&a, &c));
+    }
+
+    #[test]
+    fn test_ignore_body_fields() {
+        let a = Request::build_post("https://example.com/").json(json!({"a": 1, "nonce": "x", "meta": {"id": 1}})).build();
+        let b = Request::build_post("https://example.com/").json(json!({"a": 1, "nonce": "y", "meta": {"id": 2}})).build();
+        assert!(!MatchRules::new().ignore_body_field("nonce").matches(&a, &b));
+        let rules = MatchRules::new().ignore_body_field("nonce").ignore_body_field("/meta/id");
+        assert!(rules.matches(&a, &b));
+        assert!(MatchRules::new().ignore_body().matches(&a, &b));
+    }
+
+    #[test]
+    fn test_match_headers() {
+        let a = Request::build_get("https://example.com/")
+            .header("accept", "application/json")
+            .header("x-request-id", "1")
+            .header("authorization", SANITIZED_VALUE)
+            .build();
+        let b = Request::build_get("https://example.com/")
+            .header("accept", "application/json")
+            .header("x-request-id", "2")
+            .header("authorization", "Bearer abc")
+            .build();
+        let rules = MatchRules::new().match_headers();
+        assert!(!rules.matches(&a, &b));
+        let rules = rules.ignore_header("x-request-id");
+        assert!(rules.matches(&a, &b));
+        let c = Request::build_get("https://example.com/").header("accept", "text/html").build();
+        assert!(!rules.matches(&a, &c));
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use walkdir::WalkDir;
+
+/// Where recordings are kept. Keys are `/`-separated relative paths, e.g. `example.com/users/get.1a2b3c4d.0000.json`,
+/// whose extension determines the format.
+///
+/// Implement this to keep recordings somewhere other than the local filesystem, e.g. object storage.
+pub trait CassetteStore: Send + Sync + Debug {
+    /// Every key in the store.
+    fn keys(&self) -> io::Result<Vec<String>>;
+
+    fn load(&self, key: &str) -> io::Result<Option<String>>;
+
+    fn save(&self, key: &str, contents: &str) -> io::Result<()>;
+
+    /// Removing a key which doesn't exist is not an error.
+    fn remove(&self, key: &str) -> io::Result<()>;
+}
+
+/// Keeps recordings as files under a directory.
+#[derive(Debug, Clone)]
+pub struct FileCassetteStore {
+    root: PathBuf,
+}
+
+impl FileCassetteStore {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self { root: root.as_ref().to_path_buf() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        key.split('/').fold(self.root.clone(), |path, part| path.join(part))
+    }
+}
+
+impl CassetteStore for FileCassetteStore {
+    fn keys(&self) -> io::Result<Vec<String>> {
+        Ok(WalkDir::new(&self.root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let relative = e.path().strip_prefix(&self.root).ok()?;
+                let parts: Option<Vec<_>> = relative.components().map(|c| c.as_os_str().to_str()).collect();
+                Some(parts?.join("/"))
+            })
+            .collect())
+    }
+
+    fn load(&self, key: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.path(key)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, key: &str, contents: &str) -> io::Result<()> {
+        let path = self.path(key);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // Write to a temporary file and rename, so a reader never sees a partial recording.
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &path)
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps recordings in memory, so tests can record and replay without touching disk.
+/// Clones share the same recordings.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCassetteStore {
+    cassettes: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl MemoryCassetteStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cassettes.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CassetteStore for MemoryCassetteStore {
+    fn keys(&self) -> io::Result<Vec<String>> {
+        Ok(self.cassettes.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn load(&self, key: &str) -> io::Result<Option<String>> {
+        Ok(self.cassettes.lock().unwrap().get(key).cloned())
+    }
+
+    fn save(&self, key: &str, contents: &str) -> io::Result<()> {
+        self.cassettes.lock().unwrap().insert(key.to_string(), contents.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        self.cassettes.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_store() {
+        let root = std::env::temp_dir().join(format!("httpclient-cassettes-{}", std::process::id()));
+        let store = FileCassetteStore::new(&root);
+        store.save("example.com/users/get.0000.json", "{}").unwrap();
+        assert_eq!(store.keys().unwrap(), vec!["example.com/users/get.0000.json"]);
+        assert_eq!(store.load("example.com/users/get.0000.json").unwrap().unwrap(), "{}");
+        assert!(store.load("missing.json").unwrap().is_none());
+        store.remove("example.com/users/get.0000.json").unwrap();
+        store.remove("example.com/users/get.0000.json").unwrap();
+        assert!(store.keys().unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! VCR-style YAML cassettes, as written by Ruby's VCR (and, with `interactions` as the top-level key, Python's vcrpy).
+
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{InMemoryBody, InMemoryRequest, InMemoryResponse, InMemoryResponseExt};
+use crate::body::FORM_CONTENT_TYPE;
+
+use super::Recording;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cassette {
+    #[serde(alias = "interactions")]
+    pub http_interactions: Vec<Interaction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_with: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interaction {
+    pub request: VcrRequest,
+    pub response: VcrResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<String>,
+    /// Not part of the VCR format, which ignores unknown keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VcrRequest {
+    pub method: String,
+    pub uri: String,
+    #[serde(default)]
+    pub body: VcrBody,
+    #[serde(default)]
+    pub headers: IndexMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VcrResponse {
+    pub status: VcrStatus,
+    #[serde(default)]
+    pub headers: IndexMap<String, Vec<String>>,
+    #[serde(default)]
+    pub body: VcrBody,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VcrStatus {
+    pub code: u16,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum VcrBody {
+    Encoded {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        string: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        base64_string: Option<String>,
+    },
+    /// vcrpy writes request bodies as plain strings (or null).
+    Plain(Option<String>),
+}
+
+impl Default for VcrBody {
+    fn default() -> Self {
+        VcrBody::Plain(None)
+    }
+}
+
+impl VcrBody {
+    fn new(body: &InMemoryBody) -> Self {
+        let (string, base64_string) = match body {
+            InMemoryBody::Bytes(b) => match std::str::from_utf8(b) {
+                Ok(s) => (Some(s.to_string()), None),
+                Err(_) => (None, Some(BASE64_STANDARD.encode(b))),
+            },
+            body => (Some(body.clone().text().unwrap_or_default()), None),
+        };
+        VcrBody::Encoded {
+            encoding: Some(if base64_string.is_some() { "ASCII-8BIT" } else { "UTF-8" }.to_string()),
+            string,
+            base64_string,
+        }
+    }
+
+    fn into_body(self, headers: &HeaderMap) -> Option<InMemoryBody> {
+        let text = match self {
+            VcrBody::Encoded { base64_string: Some(b64), .. } => {
+                return BASE64_STANDARD.decode(b64.trim().as_bytes()).ok().map(InMemoryBody::new_bytes);
+            }
+            VcrBody::Encoded { string, .. } => string,
+            VcrBody::Plain(string) => string,
+        };
+        let Some(text) = text.filter(|s| !s.is_empty()) else {
+            return Some(InMemoryBody::Empty);
+        };
+        let mime = headers.get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|ct| ct.split(';').next().unwrap().trim());
+        match mime {
+            Some("application/json") => if let Ok(value) = serde_json::from_str(&text) {
+                return Some(InMemoryBody::Json(value));
+            },
+            Some(FORM_CONTENT_TYPE) => if let Ok(pairs) = serde_urlencoded::from_str(&text) {
+                return Some(InMemoryBody::Form(pairs));
+            },
+            _ => {}
+        }
+        Some(InMemoryBody::Text(text))
+    }
+}
+
+impl Cassette {
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        Cassette {
+            http_interactions: interactions,
+            recorded_with: Some(format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))),
+        }
+    }
+
+    /// Convert the interactions into recordings. Interactions which can't be represented are skipped.
+    pub fn into_recordings(self) -> Vec<Recording> {
+        self.http_interactions.into_iter()
+            .filter_map(|interaction| interaction.into_recording())
+            .collect()
+    }
+}
+
+impl Interaction {
+    pub fn new(request: &InMemoryRequest, response: &InMemoryResponse) -> Self {
+        Interaction {
+            request: VcrRequest {
+                method: request.method().as_str().to_lowercase(),
+                uri: request.url().to_string(),
+                body: VcrBody::new(request.body()),
+                headers: vcr_headers(request.headers()),
+            },
+            response: VcrResponse {
+                status: VcrStatus {
+                    code: response.status().as_u16(),
+                    message: response.status().canonical_reason().unwrap_or_default().to_string(),
+                },
+                headers: vcr_headers(response.headers()),
+                body: VcrBody::new(response.body()),
+            },
+            recorded_at: Some(httpdate::fmt_http_date(SystemTime::now())),
+            duration_ms: None,
+        }
+    }
+
+    pub fn with_duration(mut self, duration: Option<Duration>) -> Self {
+        self.duration_ms = duration.map(|d| d.as_millis() as u64);
+        self
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms.map(Duration::from_millis)
+    }
+
+    pub fn into_recording(self) -> Option<Recording> {
+        let duration = self.duration();
+        let (request, response) = self.into_pair()?;
+        Some(Recording { duration, ..Recording::new(request, response) })
+    }
+
+    pub fn into_pair(self) -> Option<(InMemoryRequest, InMemoryResponse)> {
+        let Interaction { request: req, response: res, .. } = self;
+        let method = Method::from_bytes(req.method.to_uppercase().as_bytes()).ok()?;
+        let uri: Uri = req.uri.parse().ok()?;
+        let headers = from_vcr_headers(req.headers);
+        let body = req.body.into_body(&headers)?;
+        let request = InMemoryRequest::new(method, uri, headers, body);
+
+        let headers = from_vcr_headers(res.headers);
+        let body = res.body.into_body(&headers)?;
+        let response = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::from_u16(res.status.code).ok()?, headers, body);
+        Some((request, response))
+    }
+}
+
+fn vcr_headers(headers: &HeaderMap) -> IndexMap<String, Vec<String>> {
+    let mut map = IndexMap::<String, Vec<String>>::new();
+    for (k, v) in headers {
+        map.entry(k.to_string()).or_default().push(String::from_utf8_lossy(v.as_bytes()).into_owned());
+    }
+    map
+}
+
+fn from_vcr_headers(headers: IndexMap<String, Vec<String>>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (k, values) in headers {
+        let Ok(name) = HeaderName::from_bytes(k.as_bytes()) else {
+            continue;
+        };
+        for value in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                map.append(name.clone(), value);
+            }
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_ruby_cassette() {
+        let yaml = r#"
+---
+http_interactions:
+- request:
+    method: post
+    uri: https://example.com/api
+    body:
+      encoding: UTF-8
+      string: '{"a":1}'
+    headers:
+      Content-Type:
+      - application/json
+  response:
+    status:
+      code: 201
+      message: Created
+    headers:
+      Content-Type:
+      - application/json; charset=utf-8
+      Set-Cookie:
+      - a=1
+      - b=2
+    body:
+      encoding: UTF-8
+      string: '{"id":5}'
+  recorded_at: Mon, 01 Jan 2024 00:00:00 GMT
+recorded_with: VCR 6.2.0
+"#;
+        let cassette: Cassette = serde_yaml::from_str(yaml).unwrap();
+        let Recording { request: req, response: res, .. } = cassette.into_recordings().pop().unwrap();
+        let expected = Request::build_post("https://example.com/api").json(json!({"a": 1})).build();
+        assert_eq!(req, expected);
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get_all("set-cookie").iter().count(), 2);
+        assert_eq!(res.body().clone().json::<serde_json::Value>().unwrap(), json!({"id": 5}));
+    }
+
+    #[test]
+    fn test_vcrpy_cassette() {
+        let yaml = r#"
+interactions:
+- request:
+    body: null
+    headers:
+      Accept: ['*/*']
+    method: GET
+    uri: https://example.com/
+  response:
+    body: {string: hello}
+    headers: {}
+    status: {code: 200, message: OK}
+version: 1
+"#;
+        let cassette: Cassette = serde_yaml::from_str(yaml).unwrap();
+        let Recording { request: req, response: res, .. } = cassette.into_recordings().pop().unwrap();
+        assert_eq!(req.method(), Method::GET);
+        assert!(matches!(res.body(), InMemoryBody::Text(s) if s == "hello"));
+    }
+
+    #[test]
+    fn test_roundtrip_binary() {
+        let request = Request::build_get("https://example.com/image.png").build();
+        let response = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, HeaderMap::new(), InMemoryBody::new_bytes(vec![0xff, 0x00, 0xfe]));
+        let yaml = serde_yaml::to_string(&Cassette::new(vec![Interaction::new(&request, &response)])).unwrap();
+        assert!(yaml.contains("base64_string"));
+        let res = serde_yaml::from_str::<Cassette>(&yaml).unwrap().into_recordings().pop().unwrap().response;
+        assert!(matches!(res.body(), InMemoryBody::Bytes(b) if b[..] == [0xff, 0x00, 0xfe]));
+    }
+}
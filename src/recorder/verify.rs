@@ -0,0 +1,68 @@
+use std::fmt::{Display, Formatter, Write};
+
+use crate::InMemoryRequest;
+
+/// A request the recorder couldn't find a recording for, along with the most similar recording, if any.
+#[derive(Debug, Clone)]
+pub struct UnmatchedRequest {
+    pub request: InMemoryRequest,
+    pub closest: Option<InMemoryRequest>,
+}
+
+/// Returned by `RequestRecorder::verify` when recordings weren't played, or requests weren't recorded.
+#[derive(Debug, Clone)]
+pub struct VerifyError {
+    /// Recorded requests that were never played.
+    pub unused: Vec<InMemoryRequest>,
+    /// Requests that had no matching recording.
+    pub unexpected: Vec<UnmatchedRequest>,
+}
+
+impl std::error::Error for VerifyError {}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Recorder verification failed.")?;
+        if !self.unused.is_empty() {
+            writeln!(f, "{} recorded interaction(s) were never played:", self.unused.len())?;
+            for request in &self.unused {
+                writeln!(f, "  {} {}", request.method(), request.url())?;
+            }
+        }
+        if !self.unexpected.is_empty() {
+            writeln!(f, "{} request(s) had no matching recording:", self.unexpected.len())?;
+            for unmatched in &self.unexpected {
+                writeln!(f, "  {} {}", unmatched.request.method(), unmatched.request.url())?;
+                if let Some(closest) = &unmatched.closest {
+                    writeln!(f, "    closest recording: {} {}", closest.method(), closest.url())?;
+                    f.write_str(&diff(closest, &unmatched.request))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line-oriented diff of the parts of two requests that participate in matching.
+fn diff(recorded: &InMemoryRequest, request: &InMemoryRequest) -> String {
+    let mut out = String::new();
+    if recorded.url() != request.url() {
+        let _ = writeln!(out, "      - url: {}", recorded.url());
+        let _ = writeln!(out, "      + url: {}", request.url());
+    }
+    let body = |r: &InMemoryRequest| r.body().clone().text().unwrap_or_else(|_| "<binary>".to_string());
+    let (a, b) = (body(recorded), body(request));
+    if a != b {
+        let _ = writeln!(out, "      - body: {a}");
+        let _ = writeln!(out, "      + body: {b}");
+    }
+    out
+}
+
+/// The recording most similar to `request`: same method and path, preferring the same query.
+pub(crate) fn closest<'a>(request: &InMemoryRequest, recorded: impl Iterator<Item=&'a InMemoryRequest>) -> Option<InMemoryRequest> {
+    recorded
+        .filter(|r| r.method() == request.method() && r.host() == request.host() && r.path() == request.path())
+        .max_by_key(|r| (r.url().query() == request.url().query(), r.body().clone().text().ok() == request.body().clone().text().ok()))
+        .cloned()
+}
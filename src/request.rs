@@ -3,10 +3,12 @@ use std::str::FromStr;
 use http::{HeaderMap, HeaderValue, Version};
 use hyper::{Method, Uri};
 
-pub use builder::RequestBuilder;
+pub use builder::{ArrayFormat, RequestBuilder};
+pub(crate) use builder::send_request;
 pub use memory::InMemoryRequest;
 
-use crate::{Body, InMemoryBody, Result};
+use crate::{Body, Extensions, InMemoryBody, Result};
+use crate::body::encode_form;
 
 mod memory;
 mod builder;
@@ -18,9 +20,21 @@ pub struct Request<T = Body> {
     version: Version,
     headers: HeaderMap,
     body: T,
+    extensions: Extensions,
 }
 
 impl<T> Request<T> {
+    pub fn new(method: Method, uri: Uri, headers: HeaderMap, body: T) -> Self {
+        Request {
+            method,
+            uri,
+            version: Version::default(),
+            headers,
+            body,
+            extensions: Extensions::default(),
+        }
+    }
+
     pub fn host(&self) -> &str {
         self.uri.host().unwrap_or("")
     }
@@ -28,10 +42,18 @@ impl<T> Request<T> {
         self.version
     }
 
+    pub fn version_mut(&mut self) -> &mut Version {
+        &mut self.version
+    }
+
     pub fn method(&self) -> &Method {
         &self.method
     }
 
+    pub fn method_mut(&mut self) -> &mut Method {
+        &mut self.method
+    }
+
     pub fn uri(&self) -> &Uri {
         &self.uri
     }
@@ -40,6 +62,10 @@ impl<T> Request<T> {
         &self.uri
     }
 
+    pub fn uri_mut(&mut self) -> &mut Uri {
+        &mut self.uri
+    }
+
     pub fn path(&self) -> &str {
         self.uri.path()
     }
@@ -69,6 +95,14 @@ impl<T> Request<T> {
         let value = self.headers.get(key)?;
         value.to_str().ok()
     }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
 }
 
 impl Request {
@@ -80,22 +114,36 @@ impl Request {
             version: self.version,
             headers: self.headers,
             body,
+            extensions: self.extensions,
         })
     }
 
-    pub fn build_post(url: &str) -> RequestBuilder<(), InMemoryBody> {
+    /// Like `into_memory`, but fails with `ProtocolError::BodyTooLarge` rather than buffer more than `max_size` bytes.
+    pub async fn into_memory_limited(self, max_size: u64) -> Result<InMemoryRequest> {
+        let body = self.body.into_memory_limited(max_size).await?;
+        Ok(Request {
+            method: self.method,
+            uri: self.uri,
+            version: self.version,
+            headers: self.headers,
+            body,
+            extensions: self.extensions,
+        })
+    }
+
+    pub fn build_post(url: &str) -> RequestBuilder<'static, (), InMemoryBody> {
         RequestBuilder::new(&(), Method::POST, Uri::from_str(url).expect("Invalid URL"))
     }
 
-    pub fn build_get(url: &str) -> RequestBuilder<(), InMemoryBody> {
+    pub fn build_get(url: &str) -> RequestBuilder<'static, (), InMemoryBody> {
         RequestBuilder::new(&(), Method::GET, Uri::from_str(url).expect("Invalid URL"))
     }
 
-    pub fn build_patch(url: &str) -> RequestBuilder<(), InMemoryBody> {
+    pub fn build_patch(url: &str) -> RequestBuilder<'static, (), InMemoryBody> {
         RequestBuilder::new(&(), Method::PATCH, Uri::from_str(url).expect("Invalid URL"))
     }
 
-    pub fn build_delete(url: &str) -> RequestBuilder<(), InMemoryBody> {
+    pub fn build_delete(url: &str) -> RequestBuilder<'static, (), InMemoryBody> {
         RequestBuilder::new(&(), Method::DELETE, Uri::from_str(url).expect("Invalid URL"))
     }
 }
@@ -124,6 +172,11 @@ impl InMemoryRequest {
                 length = Some(s.len());
                 hyper::Body::from(s)
             }
+            InMemoryBody::Form(pairs) => {
+                let s = encode_form(&pairs);
+                length = Some(s.len());
+                hyper::Body::from(s)
+            }
         };
         if let Some(length) = length {
             let name = http::header::CONTENT_LENGTH;
@@ -144,6 +197,7 @@ impl From<InMemoryRequest> for Request {
             version: val.version,
             headers: val.headers,
             body: val.body.into(),
+            extensions: val.extensions,
         }
     }
 }
@@ -200,7 +254,7 @@ mod tests {
         let r1 = Request::build_get("http://example.com/foo/bar")
             .set_query(HashMap::from([("a", Some("b")), ("c", Some("d")), ("e", None)]));
         let r1 = r1.build();
-        let value: HashMap<String, String> = serde_qs::from_str(&r1.url().query().unwrap()).unwrap();
+        let value: HashMap<String, String> = serde_qs::from_str(r1.url().query().unwrap()).unwrap();
         assert_eq!(value.get("a"), Some(&"b".to_string()));
         assert_eq!(value.get("c"), Some(&"d".to_string()));
         assert_eq!(value.len(), 2);
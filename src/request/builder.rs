@@ -1,19 +1,28 @@
 use std::future::IntoFuture;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::future::BoxFuture;
 use http::{HeaderMap, HeaderValue, Method, Uri, Version};
 use http::header::{Entry, HeaderName};
 use http::uri::PathAndQuery;
+use headers::HeaderMapExt;
+use hyper::body::Bytes;
 use hyper::header;
 use serde::Serialize;
 use serde_json::Value;
-
-use crate::{Client, Error, InMemoryBody, InMemoryResponse, Middleware, Request, Response};
-use crate::error::ProtocolResult;
-use crate::middleware::Next;
+use tokio_util::sync::CancellationToken;
+
+use crate::{Body, Client, Deadline, Proxy, Error, Extensions, InMemoryBody, InMemoryRequest, InMemoryResponse, Middleware, Progress, Request, Response};
+use crate::body::{form_pairs, StreamingBody, FORM_CONTENT_TYPE};
+use crate::progress::{DownloadProgress, UploadProgress};
+use crate::trailers::RequestTrailers;
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::middleware::{Credentials, Next, RedirectPolicy};
 use crate::multipart::Form;
+use crate::paginate::{Pages, Pagination};
+use crate::sse::EventSource;
 
 #[derive(Debug)]
 pub struct RequestBuilder<'a, C = Client, B = InMemoryBody> {
@@ -24,7 +33,11 @@ pub struct RequestBuilder<'a, C = Client, B = InMemoryBody> {
     pub uri: Uri,
     pub headers: HeaderMap,
     pub body: Option<B>,
+    pub extensions: Extensions,
+    pub timeout: Option<Duration>,
     pub middlewares: Vec<Arc<dyn Middleware>>,
+    /// The client's default query parameters, added when the request is built unless it sets them itself.
+    default_query: Vec<(String, String)>,
 }
 
 impl<'a, C> RequestBuilder<'a, C> {
@@ -36,22 +49,28 @@ impl<'a, C> RequestBuilder<'a, C> {
             uri,
             headers: Default::default(),
             body: Default::default(),
+            extensions: Default::default(),
+            timeout: Default::default(),
             middlewares: Default::default(),
+            default_query: Default::default(),
         }
     }
 
+    /// Add the fields of `obj` to a url-encoded form body. `obj` can be a struct, a map, or a slice of tuples; nested
+    /// structs and sequences are written with brackets, as `query` does, e.g. `user[name]=x&ids[0]=1`.
+    /// # Panics
+    /// If `obj` is a bare string, number, or other value without field names.
     pub fn form<S: Serialize>(mut self, obj: S) -> Self {
+        let pairs = form_pairs(obj);
         match self.body {
             None => {
-                self.body = Some(InMemoryBody::Text(serde_qs::to_string(&obj).unwrap()));
-                self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/x-www-form-urlencoded"));
+                self.body = Some(InMemoryBody::Form(pairs));
+                self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static(FORM_CONTENT_TYPE));
                 self.headers.entry(header::ACCEPT).or_insert(HeaderValue::from_static("html/text"));
                 self
             }
-            Some(InMemoryBody::Text(ref mut body)) => {
-                let new_body = serde_qs::to_string(&obj).unwrap();
-                body.push('&');
-                body.push_str(&new_body);
+            Some(InMemoryBody::Form(ref mut body)) => {
+                body.extend(pairs);
                 self
             }
             _ => {
@@ -75,7 +94,7 @@ impl<'a, C> RequestBuilder<'a, C> {
             }
             Some(InMemoryBody::Json(Value::Object(ref mut body))) => {
                 if let Value::Object(obj) = serde_json::to_value(obj).unwrap() {
-                    body.extend(obj.into_iter());
+                    body.extend(obj);
                 } else {
                     panic!("Tried to push a non-object to a json body.");
                 }
@@ -85,9 +104,48 @@ impl<'a, C> RequestBuilder<'a, C> {
         }
     }
 
+    /// Sets content-type and accept to `application/xml` and the body to the serialized object.
+    #[cfg(feature = "xml")]
+    pub fn xml<S: Serialize>(mut self, obj: S) -> Self {
+        self.body = Some(InMemoryBody::Text(quick_xml::se::to_string(&obj).expect("Failed to serialize XML")));
+        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/xml"));
+        self.headers.entry(header::ACCEPT).or_insert(HeaderValue::from_static("application/xml"));
+        self
+    }
+
+    /// Sets content-type and accept to `application/msgpack` and the body to the serialized object. Structs are
+    /// serialized as maps, so field names are kept.
+    #[cfg(feature = "msgpack")]
+    pub fn msgpack<S: Serialize>(mut self, obj: S) -> Self {
+        self.body = Some(InMemoryBody::Bytes(rmp_serde::to_vec_named(&obj).expect("Failed to serialize MessagePack").into()));
+        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/msgpack"));
+        self.headers.entry(header::ACCEPT).or_insert(HeaderValue::from_static("application/msgpack"));
+        self
+    }
+
+    /// Sets content-type and accept to `application/cbor` and the body to the serialized object.
+    #[cfg(feature = "cbor")]
+    pub fn cbor<S: Serialize>(mut self, obj: S) -> Self {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&obj, &mut bytes).expect("Failed to serialize CBOR");
+        self.body = Some(InMemoryBody::Bytes(bytes.into()));
+        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/cbor"));
+        self.headers.entry(header::ACCEPT).or_insert(HeaderValue::from_static("application/cbor"));
+        self
+    }
+
+    /// Sets content-type and accept to `application/x-protobuf` and the body to the encoded message.
+    #[cfg(feature = "protobuf")]
+    pub fn protobuf<M: prost::Message>(mut self, message: &M) -> Self {
+        self.body = Some(InMemoryBody::Bytes(message.encode_to_vec().into()));
+        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/x-protobuf"));
+        self.headers.entry(header::ACCEPT).or_insert(HeaderValue::from_static("application/x-protobuf"));
+        self
+    }
+
     /// Sets content-type to `application/octet-stream` and the body to the supplied bytes.
-    pub fn bytes(mut self, bytes: Vec<u8>) -> Self {
-        self.body = Some(InMemoryBody::Bytes(bytes));
+    pub fn bytes(mut self, bytes: impl Into<Bytes>) -> Self {
+        self.body = Some(InMemoryBody::Bytes(bytes.into()));
         self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/octet-stream"));
         self
     }
@@ -99,11 +157,33 @@ impl<'a, C> RequestBuilder<'a, C> {
         self
     }
 
+    /// Send the body as it's produced, rather than buffering it in memory. Use `Body::from_reader` or
+    /// `Body::from_stream` to upload large files. Sets content-type to `application/octet-stream` if it isn't set.
+    ///
+    /// Unless you set a Content-Length header, the body is sent with chunked transfer encoding. Middleware sees an
+    /// empty body, and since the stream can only be read once, the request can't be retried or replayed.
+    pub fn stream(mut self, body: impl Into<Body>) -> Self {
+        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_static("application/octet-stream"));
+        match body.into() {
+            Body::InMemory(body) => self.body = Some(body),
+            Body::Hyper(body) => {
+                self.body = None;
+                self.extensions.insert(StreamingBody::new(body));
+            }
+        }
+        self
+    }
+
+    /// Sets the body to the encoded form, and content-type to the form's content type, including its boundary.
+    /// If any part is streamed, the form is streamed too. See `stream`.
     pub fn multipart(mut self, form: Form) -> Self {
-        self.headers.entry(header::CONTENT_TYPE).or_insert(HeaderValue::from_str(form.content_type.as_str()).unwrap());
+        self.headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(&form.full_content_type()).unwrap());
+        if form.is_streaming() {
+            return self.stream(form.into_body());
+        }
         let body: Vec<u8> = form.into();
         let len = body.len();
-        self.body = Some(InMemoryBody::Bytes(body));
+        self.body = Some(InMemoryBody::Bytes(body.into()));
         self.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
         self
     }
@@ -114,34 +194,94 @@ impl<'a> RequestBuilder<'a> {
     /// which also awaits the body. If you want to await them separately, use this method `.send()`
     pub async fn send(self) -> ProtocolResult<Response> {
         let client = self.client;
+        let timeout = self.timeout.or(client.timeout);
         let (request, middlewares) = self.into_req_and_middleware();
-        let next = Next {
-            client,
-            middlewares: &middlewares,
-        };
-        next.run(request.into()).await
+        send_request(client, request, &middlewares, timeout).await
+    }
+
+    /// Open a Server-Sent Events stream. See [`EventSource`].
+    pub fn eventsource(mut self) -> EventSource<'a> {
+        self.headers.insert(header::ACCEPT, HeaderValue::from_static("text/event-stream"));
+        self.headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        let client = self.client;
+        let timeout = self.timeout.or(client.timeout);
+        let (request, middlewares) = self.into_req_and_middleware();
+        EventSource::new(client, request, middlewares, timeout)
+    }
+
+    /// Request each page in turn, as chosen by `pagination`. See [`Pages`].
+    pub fn paginate(self, pagination: impl Pagination) -> Pages<'a> {
+        let client = self.client;
+        let timeout = self.timeout.or(client.timeout);
+        let (request, middlewares) = self.into_req_and_middleware();
+        Pages::new(client, request, middlewares, timeout, Box::new(pagination))
     }
 }
 
+/// An error from building a request, reported when it's sent.
+#[derive(Debug, Clone)]
+pub(crate) struct InvalidRequest(String);
+
+/// Run `request` through the middleware and send it. The timeout covers receiving the response head, not the body.
+pub(crate) async fn send_request(client: &Client, mut request: InMemoryRequest, middlewares: &[Arc<dyn Middleware>], timeout: Option<Duration>) -> ProtocolResult<Response> {
+    if let Some(InvalidRequest(message)) = request.extensions().get() {
+        return Err(ProtocolError::InvalidRequest(message.clone()));
+    }
+    let next = Next {
+        client,
+        middlewares,
+    };
+    let _in_flight = client.pool.begin().ok_or(ProtocolError::ClientClosed)?;
+    client.hooks.request_start(&request).await;
+    let cancellation = request.extensions().get::<CancellationToken>().cloned();
+    let deadline = request.extensions().get::<Deadline>().copied();
+    let deadline = match (deadline, timeout.map(Deadline::after)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let res = async move {
+        match deadline {
+            Some(deadline) => {
+                request.extensions_mut().insert(deadline);
+                tokio::time::timeout_at(deadline.0.into(), next.run(request)).await
+                    .map_err(|_| ProtocolError::Timeout)?
+            }
+            None => next.run(request).await,
+        }
+    };
+    let res = match cancellation {
+        Some(token) => tokio::select! {
+            biased;
+            _ = token.cancelled() => Err(ProtocolError::Cancelled),
+            res = res => res,
+        },
+        None => res.await,
+    }?;
+    client.hooks.response(&res).await;
+    Ok(res)
+}
+
 
 impl<'a, C, B: Default> RequestBuilder<'a, C, B> {
     pub fn build(self) -> Request<B> {
         Request {
             method: self.method,
-            uri: self.uri,
+            uri: with_default_query(self.uri, &self.default_query),
             version: self.version,
             headers: self.headers,
             body: self.body.unwrap_or_default(),
+            extensions: self.extensions,
         }
     }
 
     pub fn into_req_and_middleware(self) -> (Request<B>, Vec<Arc<dyn Middleware>>) {
         (Request {
             method: self.method,
-            uri: self.uri,
+            uri: with_default_query(self.uri, &self.default_query),
             version: self.version,
             headers: self.headers,
             body: self.body.unwrap_or_default(),
+            extensions: self.extensions,
         }, self.middlewares)
     }
 }
@@ -155,7 +295,10 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
             uri: Default::default(),
             headers: Default::default(),
             body: Default::default(),
+            extensions: Default::default(),
+            timeout: Default::default(),
             middlewares: Default::default(),
+            default_query: Default::default(),
         }
     }
 
@@ -164,6 +307,13 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
+    /// Require `version` for this request. By default it's HTTP/1.1, and sent over HTTP/2 if the connection
+    /// negotiated it; requiring HTTP/2 fails on HTTP/1.1 connections.
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn url(mut self, uri: &str) -> Self {
         self.uri = Uri::from_str(uri).expect("Invalid URI");
         self
@@ -190,6 +340,12 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
+    /// Set a header from any header type in the `headers` crate, e.g. `headers::ContentType::json()`.
+    pub fn typed_header(mut self, header: impl headers::Header) -> Self {
+        self.headers.typed_insert(header);
+        self
+    }
+
     pub fn cookie(mut self, key: &str, value: &str) -> Self {
         match self.headers.entry(hyper::header::COOKIE) {
             Entry::Occupied(mut e) => {
@@ -206,18 +362,40 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
-    pub fn bearer_auth(mut self, token: &str) -> Self {
-        self.headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
-        self
+    /// Set a bearer token. If it isn't a valid header value, sending the request fails with
+    /// `ProtocolError::InvalidRequest`.
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.credentials(Credentials::bearer(token))
     }
 
-    pub fn token_auth(mut self, token: &str) -> Self {
-        self.headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Token {}", token)).unwrap());
-        self
+    pub fn token_auth(self, token: &str) -> Self {
+        self.credentials(Credentials::token(token))
     }
 
-    pub fn basic_auth(mut self, token: &str) -> Self {
-        self.headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Basic {}", token)).unwrap());
+    /// Set HTTP Basic authentication. The username and password are base64 encoded for you.
+    pub fn basic_auth(self, username: &str, password: &str) -> Self {
+        self.credentials(Credentials::basic(username, password))
+    }
+
+    fn credentials(mut self, credentials: Credentials) -> Self {
+        match credentials.header_value() {
+            Ok(value) => {
+                self.headers.insert(header::AUTHORIZATION, value);
+                self
+            }
+            Err(e) => self.invalid(e),
+        }
+    }
+
+    /// Fail the request with `error` when it's sent, keeping the first such error.
+    pub(crate) fn invalid(mut self, error: ProtocolError) -> Self {
+        if self.extensions.get::<InvalidRequest>().is_none() {
+            let message = match error {
+                ProtocolError::InvalidRequest(message) => message,
+                e => e.to_string(),
+            };
+            self.extensions.insert(InvalidRequest(message));
+        }
         self
     }
 
@@ -232,6 +410,29 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
+    /// Add the fields of `obj` to the query, keeping existing parameters. Sequences are written as repeated keys,
+    /// e.g. `ids=1&ids=2`; use `query_params_with` for other formats.
+    pub fn query_params<S: Serialize>(self, obj: &S) -> Self {
+        self.query_params_with(obj, ArrayFormat::default())
+    }
+
+    /// Add the fields of `obj` to the query, keeping existing parameters, and writing sequences in `format`.
+    pub fn query_params_with<S: Serialize>(mut self, obj: &S, format: ArrayFormat) -> Self {
+        let qs = encode_query(obj, format);
+        if qs.is_empty() {
+            return self;
+        }
+        let mut parts = std::mem::take(&mut self.uri).into_parts();
+        let pq = parts.path_and_query.unwrap();
+        let pq = PathAndQuery::from_str(&match pq.query() {
+            Some(q) if !q.is_empty() => format!("{}?{}&{}", pq.path(), q, qs),
+            _ => format!("{}?{}", pq.path(), qs),
+        }).unwrap();
+        parts.path_and_query = Some(pq);
+        self.uri = Uri::from_parts(parts).unwrap();
+        self
+    }
+
     /// Add a url query parameter, but keep existing parameters.
     /// # Examples
     /// ```
@@ -253,6 +454,27 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
+    /// Fill in a `{name}` placeholder in the path, e.g. `client.get("/users/{id}/repos").path_param("id", id)`. The
+    /// value is percent-encoded, so it stays a single path segment. If the path has no such placeholder, sending the
+    /// request fails with `ProtocolError::InvalidRequest`.
+    pub fn path_param(mut self, name: &str, value: impl std::fmt::Display) -> Self {
+        let placeholder = format!("{{{}}}", name);
+        if !self.uri.path().contains(&placeholder) {
+            let message = format!("No {} placeholder in the path {}", placeholder, self.uri.path());
+            return self.invalid(ProtocolError::InvalidRequest(message));
+        }
+        let mut parts = std::mem::take(&mut self.uri).into_parts();
+        let pq = parts.path_and_query.unwrap();
+        let path = pq.path().replace(&placeholder, &urlencoding::encode(&value.to_string()));
+        let pq = match pq.query() {
+            Some(q) => format!("{}?{}", path, q),
+            None => path,
+        };
+        parts.path_and_query = Some(PathAndQuery::from_str(&pq).unwrap());
+        self.uri = Uri::from_parts(parts).unwrap();
+        self
+    }
+
     pub fn content_type(mut self, content_type: &str) -> Self {
         self.headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
         self
@@ -264,17 +486,142 @@ impl<'a, C, B> RequestBuilder<'a, C, B> {
         self
     }
 
+    /// Attach a value to the request, which middleware can read from `request.extensions()`.
+    pub fn extension<T: Clone + Send + Sync + 'static>(mut self, val: T) -> Self {
+        self.extensions.insert(val);
+        self
+    }
+
+    /// Send `trailers` after the body, which is then sent chunked. hyper only sends trailers over HTTP/2; HTTP/1.1
+    /// connections drop them.
+    pub fn trailers(mut self, trailers: HeaderMap) -> Self {
+        self.extensions.insert(RequestTrailers(trailers));
+        self
+    }
+
+    /// Call `f` as the request body is sent, e.g. to render a progress bar for a large upload.
+    pub fn upload_progress(mut self, f: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.extensions.insert(UploadProgress(Arc::new(f)));
+        self
+    }
+
+    /// Call `f` as the response body is received, e.g. to render a progress bar for a large download.
+    pub fn download_progress(mut self, f: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.extensions.insert(DownloadProgress(Arc::new(f)));
+        self
+    }
+
+    /// Abandon the request once `token` is cancelled, failing with `ProtocolError::Cancelled`. That covers waiting
+    /// for the response, including any retries, and reading the body. The connection is closed rather than
+    /// returned to the pool, since it may be mid-message.
+    pub fn cancel_on(self, token: CancellationToken) -> Self {
+        self.extension(token)
+    }
+
+    /// Override the client's total timeout for this request.
+    /// While the request is in flight, middleware can read the [`Deadline`] from the request extensions.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send this request through `proxy`, rather than the client's proxy or directly.
+    pub fn proxy(self, proxy: Proxy) -> Self {
+        self.extension(proxy)
+    }
+
+    /// Override the `Follow` middleware's policy for this request.
+    pub fn redirect_policy(self, policy: RedirectPolicy) -> Self {
+        self.extension(policy)
+    }
+
+    /// Finish by `deadline`, e.g. one passed down from an incoming request, however many retries or redirects it
+    /// takes. With a timeout as well, whichever ends first applies.
+    pub fn deadline(self, deadline: Deadline) -> Self {
+        self.extension(deadline)
+    }
+
     pub fn set_middlewares(mut self, middlewares: Vec<Arc<dyn Middleware>>) -> Self {
         self.middlewares = middlewares;
         self
     }
 
+    pub(crate) fn set_default_query(mut self, default_query: Vec<(String, String)>) -> Self {
+        self.default_query = default_query;
+        self
+    }
+
     pub fn middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
         self.middlewares.push(middleware);
         self
     }
 }
 
+/// How `RequestBuilder::query_params_with` writes sequences, e.g. `ids: vec![1, 2]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayFormat {
+    /// `ids=1&ids=2`
+    #[default]
+    Repeat,
+    /// `ids=1,2`
+    Comma,
+    /// `ids[]=1&ids[]=2`
+    Brackets,
+}
+
+/// Add the `defaults` whose keys aren't already in the query.
+fn with_default_query(uri: Uri, defaults: &[(String, String)]) -> Uri {
+    let query = uri.query().unwrap_or_default();
+    let keys: Vec<_> = query.split('&')
+        .filter_map(|pair| urlencoding::decode(pair.split('=').next().unwrap()).ok())
+        .collect();
+    let missing: Vec<_> = defaults.iter()
+        .filter(|(k, _)| !keys.iter().any(|key| key == k))
+        .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+        .collect();
+    if missing.is_empty() {
+        return uri;
+    }
+    let pq = match query {
+        "" => format!("{}?{}", uri.path(), missing.join("&")),
+        query => format!("{}?{}&{}", uri.path(), query, missing.join("&")),
+    };
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(PathAndQuery::from_str(&pq).unwrap());
+    Uri::from_parts(parts).unwrap()
+}
+
+/// Serialize with serde_qs, which writes sequences as `ids[0]=1&ids[1]=2`, then rewrite them in `format`.
+fn encode_query<S: Serialize>(obj: &S, format: ArrayFormat) -> String {
+    let qs = serde_qs::to_string(obj).expect("Failed to serialize query");
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut last_array_key = None;
+    for pair in qs.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let array_key = key.strip_suffix(']')
+            .and_then(|k| k.rsplit_once('['))
+            .filter(|(_, index)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+            .map(|(k, _)| k);
+        let Some(array_key) = array_key else {
+            last_array_key = None;
+            pairs.push((key.to_string(), value.to_string()));
+            continue;
+        };
+        match format {
+            ArrayFormat::Repeat => pairs.push((array_key.to_string(), value.to_string())),
+            ArrayFormat::Brackets => pairs.push((format!("{array_key}[]"), value.to_string())),
+            ArrayFormat::Comma if last_array_key == Some(array_key) => {
+                let (_, values) = pairs.last_mut().unwrap();
+                values.push(',');
+                values.push_str(value);
+            }
+            ArrayFormat::Comma => pairs.push((array_key.to_string(), value.to_string())),
+        }
+        last_array_key = Some(array_key);
+    }
+    pairs.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>().join("&")
+}
+
 impl<'a> IntoFuture for RequestBuilder<'a, Client> {
     type Output = crate::InMemoryResult<InMemoryResponse>;
     type IntoFuture = BoxFuture<'a, Self::Output>;
@@ -295,9 +642,9 @@ impl<'a> IntoFuture for RequestBuilder<'a, Client> {
             if status.is_client_error() || status.is_server_error() {
                 // Prevents us from showing bytes to end users in error situations.
                 if let InMemoryBody::Bytes(bytes) = body {
-                    body = match String::from_utf8(bytes) {
-                        Ok(text) => InMemoryBody::Text(text),
-                        Err(e) => InMemoryBody::Bytes(e.into_bytes()),
+                    body = match std::str::from_utf8(&bytes) {
+                        Ok(text) => InMemoryBody::Text(text.to_string()),
+                        Err(_) => InMemoryBody::Bytes(bytes),
                     };
                 }
                 Err(Error::HttpError(InMemoryResponse::from_parts(parts, body)))
@@ -310,8 +657,11 @@ impl<'a> IntoFuture for RequestBuilder<'a, Client> {
 
 #[cfg(test)]
 mod tests {
+    use async_trait::async_trait;
     use serde::{Deserialize, Serialize};
 
+    use crate::InMemoryResponseExt;
+
     use super::*;
 
     #[derive(Serialize, Deserialize)]
@@ -337,4 +687,157 @@ mod tests {
             .build();
         assert_eq!(r.uri().to_string(), "/api?inside[a]=1");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_query_params() {
+        #[derive(Serialize)]
+        struct Search {
+            q: &'static str,
+            ids: Vec<u32>,
+            page: Option<u32>,
+        }
+        let search = Search { q: "a b", ids: vec![1, 2], page: None };
+        let c = Client::new();
+        let r = c.get("/api?v=1").query_params(&search).build();
+        assert_eq!(r.uri().to_string(), "/api?v=1&q=a+b&ids=1&ids=2");
+        let r = c.get("/api").query_params_with(&search, ArrayFormat::Comma).build();
+        assert_eq!(r.uri().to_string(), "/api?q=a+b&ids=1,2");
+        let r = c.get("/api").query_params_with(&search, ArrayFormat::Brackets).build();
+        assert_eq!(r.uri().to_string(), "/api?q=a+b&ids[]=1&ids[]=2");
+    }
+
+    #[test]
+    fn test_client_defaults() {
+        let c = Client::builder()
+            .default_header("X-Api-Version", "1")
+            .default_header("user-agent", "sdk/1.0")
+            .default_query("api_key", "secret")
+            .default_query("lang", "en")
+            .build();
+        let r = c.get("https://example.com/items?lang=fr").header("X-Api-Version", "2").build();
+        assert_eq!(r.uri().to_string(), "https://example.com/items?lang=fr&api_key=secret");
+        assert_eq!(r.headers()["x-api-version"], "2");
+        assert_eq!(r.headers().get_all("user-agent").iter().collect::<Vec<_>>(), vec!["sdk/1.0"]);
+        let r = c.get("https://example.com/items").query("api_key", "mine").build();
+        assert_eq!(r.uri().to_string(), "https://example.com/items?api_key=mine&lang=en");
+
+        let c = Client::new().default_query("api_key", "old").default_query("api_key", "new");
+        let r = c.get("https://example.com/items").build();
+        assert_eq!(r.uri().to_string(), "https://example.com/items?api_key=new");
+    }
+
+    #[test]
+    fn test_path_param() {
+        let c = Client::new();
+        let r = c.get("https://example.com/users/{user}/repos/{repo}?page=1")
+            .path_param("user", "a b/c")
+            .path_param("repo", 42)
+            .build();
+        assert_eq!(r.uri().to_string(), "https://example.com/users/a%20b%2Fc/repos/42?page=1");
+    }
+
+    #[tokio::test]
+    async fn test_path_param_missing() {
+        let e = Client::new().get("/users").path_param("id", 1).send().await.unwrap_err();
+        assert!(matches!(e, ProtocolError::InvalidRequest(ref message) if message.contains("No {id} placeholder")), "{e:?}");
+    }
+
+    #[derive(Debug)]
+    struct SleepPastDeadline;
+
+    #[async_trait]
+    impl Middleware for SleepPastDeadline {
+        async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+            let deadline = request.extensions().get::<Deadline>().expect("send() sets a deadline");
+            assert!(deadline.remaining() <= Duration::from_millis(10));
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            next.run(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_override() {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .with_middleware(SleepPastDeadline);
+        let res = client.get("http://localhost/")
+            .timeout(Duration::from_millis(10))
+            .send()
+            .await;
+        assert!(matches!(res, Err(ProtocolError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunked() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        // Responds with the request's transfer-encoding and the number of body bytes received.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                let encoding = req.headers().get(header::TRANSFER_ENCODING).cloned();
+                let len = hyper::body::to_bytes(req.into_body()).await?.len();
+                let encoding = encoding.as_ref().and_then(|v| v.to_str().ok()).unwrap_or("none");
+                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(format!("{encoding} {len}"))))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(vec![b'a'; 1024]));
+        let client = Client::new();
+        let res = client.post(&format!("http://{addr}/upload"))
+            .stream(Body::from_stream(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+        assert_eq!(res.text().unwrap(), "chunked 4096");
+
+        let reader = std::io::Cursor::new(vec![b'b'; 100]);
+        let res = client.post(&format!("http://{addr}/upload"))
+            .header("content-length", "100")
+            .stream(Body::from_reader(reader))
+            .await
+            .unwrap();
+        assert_eq!(res.text().unwrap(), "none 100");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_on() {
+        use hyper::service::{make_service_fn, service_fn};
+
+        // `/slow` never responds; anything else sends one chunk of the body, then stalls.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|req: hyper::Request<hyper::Body>| async move {
+                if req.uri().path() == "/slow" {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                let (mut sender, body) = hyper::Body::channel();
+                tokio::spawn(async move {
+                    sender.send_data(Bytes::from_static(b"partial")).await.unwrap();
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                });
+                Ok::<_, hyper::Error>(hyper::Response::new(body))
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new();
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            cancel.cancel();
+        });
+        let res = client.get(&format!("http://{addr}/slow")).cancel_on(token).send().await;
+        assert!(matches!(res, Err(ProtocolError::Cancelled)));
+
+        let token = CancellationToken::new();
+        let res = client.get(&format!("http://{addr}/stall")).cancel_on(token.clone()).send().await.unwrap();
+        token.cancel();
+        let res = res.into_body().into_memory().await;
+        assert!(matches!(res, Err(ProtocolError::Cancelled)), "{res:?}");
+    }
+}
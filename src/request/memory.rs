@@ -7,7 +7,8 @@ use serde::de::Error;
 use serde::ser::SerializeMap;
 
 use crate::{InMemoryBody, Request, Result};
-use crate::sanitize::sanitize_headers;
+use crate::body::RecordedBody;
+use crate::sanitize::Sanitizer;
 
 pub type InMemoryRequest = Request<InMemoryBody>;
 
@@ -15,9 +16,55 @@ pub type InMemoryRequest = Request<InMemoryBody>;
 impl InMemoryRequest {
     /// Attempt to clear sensitive information from the request.
     pub fn sanitize(&mut self) {
-        sanitize_headers(&mut self.headers);
-        self.body.sanitize();
+        Sanitizer::default().sanitize_request(self);
     }
+
+    /// A `curl` command which sends the same request, to paste into a shell or a bug report. Binary bodies use
+    /// `$'...'` quoting, which bash and zsh understand. Secrets aren't redacted, so `sanitize` a copy first if needed.
+    pub fn to_curl(&self) -> String {
+        let mut parts = vec![match self.method {
+            Method::GET => format!("curl {}", shell_quote(&self.uri.to_string())),
+            _ => format!("curl -X {} {}", self.method, shell_quote(&self.uri.to_string())),
+        }];
+        for (name, value) in &self.headers {
+            if name == http::header::CONTENT_LENGTH {
+                continue;
+            }
+            parts.push(format!("-H {}", shell_quote(&format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes())))));
+        }
+        let body = match &self.body {
+            InMemoryBody::Empty => None,
+            InMemoryBody::Bytes(bytes) => match std::str::from_utf8(bytes) {
+                Ok(text) => Some(format!("--data-raw {}", shell_quote(text))),
+                Err(_) => Some(format!("--data-binary {}", ansi_c_quote(bytes))),
+            },
+            body => Some(format!("--data-raw {}", shell_quote(&body.clone().text().unwrap_or_default()))),
+        };
+        parts.extend(body);
+        parts.join(" \\\n  ")
+    }
+}
+
+/// Quote `s` for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quote arbitrary bytes with bash's `$'...'`, escaping everything but printable ASCII.
+fn ansi_c_quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("$'");
+    for &b in bytes {
+        match b {
+            b'\'' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(b as char);
+            }
+            0x20..=0x7e => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('\'');
+    quoted
 }
 
 impl Clone for InMemoryRequest {
@@ -28,6 +75,7 @@ impl Clone for InMemoryRequest {
             version: self.version,
             headers: self.headers.clone(),
             body: self.body.clone(),
+            extensions: self.extensions.clone(),
         }
     }
 }
@@ -66,6 +114,7 @@ impl PartialEq<Self> for Request<InMemoryBody> {
             (InMemoryBody::Text(ref a), InMemoryBody::Text(ref b)) => a == b,
             (InMemoryBody::Bytes(ref a), InMemoryBody::Bytes(ref b)) => a == b,
             (InMemoryBody::Json(ref a), InMemoryBody::Json(ref b)) => a == b,
+            (InMemoryBody::Form(ref a), InMemoryBody::Form(ref b)) => a == b,
             _ => false,
         }
     }
@@ -84,7 +133,7 @@ impl Serialize for InMemoryRequest {
             .collect();
         map.serialize_entry("headers", &ordered)?;
         if !self.body.is_empty() {
-            map.serialize_entry("body", &self.body)?;
+            map.serialize_entry("body", &RecordedBody(&self.body))?;
         }
         map.end()
     }
@@ -133,7 +182,7 @@ impl<'de> Deserialize<'de> for InMemoryRequest {
                             if body.is_some() {
                                 return Err(<A::Error as Error>::duplicate_field("data"));
                             }
-                            body = Some(map.next_value::<InMemoryBody>()?);
+                            body = Some(map.next_value::<serde_json::Value>()?);
                         }
                         "headers" => {
                             if headers.is_some() {
@@ -151,13 +200,15 @@ impl<'de> Deserialize<'de> for InMemoryRequest {
                 let headers = HeaderMap::from_iter(headers.ok_or_else(|| Error::missing_field("headers"))?.iter()
                     .map(|(k, v)| (HeaderName::from_bytes(k.as_bytes()).unwrap(), HeaderValue::from_str(v).unwrap()))
                 );
-                let body = body.unwrap_or(InMemoryBody::Empty);
+                let body = body.map(|body| InMemoryBody::from_recorded(body, headers.get(http::header::CONTENT_TYPE)))
+                    .unwrap_or(InMemoryBody::Empty);
                 Ok(InMemoryRequest {
                     method,
                     uri: url,
                     version: Default::default(),
                     headers,
                     body,
+                    extensions: Default::default(),
                 })
             }
         }
@@ -189,6 +240,60 @@ mod tests {
         assert_eq!(r1, r2);
     }
 
+    #[test]
+    fn test_form_roundtrip() {
+        let r1 = Request::build_post("http://example.com/")
+            .form([("name", "Jane Doe"), ("tag", "a&b")])
+            .form([("tag", "c")])
+            .build();
+        let InMemoryBody::Form(pairs) = r1.body() else {
+            panic!("Expected a form body");
+        };
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(r1.body().clone().text().unwrap(), "name=Jane+Doe&tag=a%26b&tag=c");
+        let s = serde_json::to_string(&r1).unwrap();
+        assert!(s.contains(r#""body":"name=Jane+Doe&tag=a%26b&tag=c""#));
+        let r2: InMemoryRequest = serde_json::from_str(&s).unwrap();
+        assert_eq!(r1, r2);
+        assert!(matches!(r2.body(), InMemoryBody::Form(_)));
+    }
+
+    #[test]
+    fn test_nested_form() {
+        #[derive(Serialize)]
+        struct User {
+            name: &'static str,
+            tags: Vec<&'static str>,
+        }
+        let r = Request::build_post("http://example.com/")
+            .form(serde_json::json!({"user": User { name: "Jane", tags: vec!["a", "b"] }}))
+            .build();
+        let InMemoryBody::Form(pairs) = r.body() else {
+            panic!("Expected a form body");
+        };
+        let pairs: Vec<_> = pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(pairs, [("user[name]", "Jane"), ("user[tags][0]", "a"), ("user[tags][1]", "b")]);
+    }
+
+    #[test]
+    fn test_to_curl() {
+        let request = Request::build_post("https://example.com/items?a=1")
+            .header("x-note", "it's")
+            .json(serde_json::json!({"name": "o'brien"}))
+            .build();
+        assert_eq!(request.to_curl(), [
+            "curl -X POST 'https://example.com/items?a=1'",
+            "  -H 'x-note: it'\\''s'",
+            "  -H 'content-type: application/json; charset=utf-8'",
+            "  -H 'accept: application/json'",
+            r#"  --data-raw '{"name":"o'\''brien"}'"#,
+        ].join(" \\\n"));
+        assert_eq!(Request::build_get("https://example.com/").build().to_curl(), "curl 'https://example.com/'");
+        let request = Request::build_post("https://example.com/").body(InMemoryBody::Bytes(vec![0xff, b'a', b'\''].into())).build();
+        assert_eq!(request.to_curl(), r"curl -X POST 'https://example.com/' \
+  --data-binary $'\xffa\''");
+    }
+
     #[test]
     fn test_equal() {
         #[derive(Serialize, Deserialize, Debug)]
@@ -1,13 +1,22 @@
+use std::path::Path;
+
 use async_trait::async_trait;
-use http::Response;
+use futures::stream::BoxStream;
+use encoding_rs::UTF_8;
+use futures::TryStreamExt;
+use http::header::CONTENT_TYPE;
+use http::{Response, StatusCode, Uri};
 use hyper::body::Bytes;
+use hyper::upgrade::Upgraded;
 use serde::de::DeserializeOwned;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 pub use memory::*;
 
-use crate::body::Body;
-use crate::error::ProtocolResult;
-use crate::{InMemoryResult, Result};
+use crate::body::{charset, Body};
+use crate::error::{ProtocolError, ProtocolResult};
+use crate::extensions::response_extension;
+use crate::{ApiError, InMemoryResult, Result, Trailers};
 
 mod memory;
 
@@ -24,14 +33,66 @@ pub(crate) fn mem_response_into_hyper(res: InMemoryResponse) -> Response<Body> {
     Response::from_parts(parts, body)
 }
 
+/// Write the body to `writer` as it arrives, returning the number of bytes written.
+pub(crate) async fn write_body<W: AsyncWrite + Unpin>(body: Body, mut writer: W) -> ProtocolResult<u64> {
+    let mut stream = body.into_stream();
+    let mut written = 0;
+    while let Some(chunk) = stream.try_next().await? {
+        writer.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    writer.flush().await?;
+    Ok(written)
+}
+
 #[async_trait]
 pub trait ResponseExt where Self: Sized {
+    #[allow(clippy::result_large_err)]
     fn error_for_status(self) -> Result<Self>;
+    /// Like `error_for_status`, but a 4xx or 5xx body is deserialized into the API's error type `E`.
+    async fn error_for_status_json<E: DeserializeOwned>(self) -> Result<Self, crate::Error<ApiError<E>>>;
+    /// Decode the body using the charset of the Content-Type, or UTF-8 if there isn't one.
     async fn text(self) -> InMemoryResult<String>;
+    /// Like `text`, but malformed text is replaced with U+FFFD rather than failing.
+    async fn text_lossy(self) -> ProtocolResult<String>;
+    /// Deserialize a JSON body. Failures are reported as `ProtocolError::JsonDecodeError`, with the path that
+    /// failed, the start of the body, and the URL.
     async fn json<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    /// Deserialize a successful JSON body into `T`, or a 4xx or 5xx body into the API's error type `E`, returned as
+    /// `Error::HttpError`. If either fails to deserialize, it's reported as `ProtocolError::JsonDecodeError`.
+    async fn json_or_error<T: DeserializeOwned, E: DeserializeOwned>(self) -> Result<T, crate::Error<ApiError<E>>>;
     /// Get body as bytes.
     async fn bytes(self) -> InMemoryResult<Bytes>;
+    #[cfg(feature = "xml")]
+    async fn xml<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "msgpack")]
+    async fn msgpack<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "cbor")]
+    async fn cbor<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "protobuf")]
+    async fn protobuf<U: prost::Message + Default>(self) -> InMemoryResult<U>;
+    /// Buffer the body, failing with `ProtocolError::BodyTooLarge` rather than buffer more than `max_size` bytes.
+    async fn into_memory_limited(self, max_size: u64) -> ProtocolResult<InMemoryResponse>;
+    /// Download the body, spooling it to a temporary file if it's larger than `threshold` bytes. See
+    /// `Body::into_spooled`.
+    async fn into_spooled(self, threshold: u64) -> ProtocolResult<Self>;
+    /// Get body as a stream of chunks, so large downloads can be processed without holding them in memory.
+    fn bytes_stream(self) -> BoxStream<'static, ProtocolResult<Bytes>>;
+    /// Deserialize a newline-delimited JSON (JSON Lines) body one line at a time, as it arrives. Blank lines are skipped.
+    fn ndjson<U: DeserializeOwned + Send + 'static>(self) -> BoxStream<'static, ProtocolResult<U>>;
+    /// Stream the body to a file, replacing it if it exists. Returns the number of bytes written.
+    async fn save_to_path<P: AsRef<Path> + Send>(self, path: P) -> ProtocolResult<u64>;
     fn get_cookie(&self, name: &str) -> Option<&str>;
+    /// After a `101 Switching Protocols` response, take the connection to speak the new protocol over it. Any other
+    /// response is returned as `Error::HttpError`.
+    async fn into_upgraded(self) -> Result<Upgraded>;
+    /// The trailers sent after the body, which are filled in once the body has been read. Keep a clone to check
+    /// them after consuming the response, e.g. with `bytes_stream`.
+    fn trailers(&self) -> Trailers;
+    /// The URL the response came from, after any redirects. Responses replayed from a recording don't have one.
+    fn url(&self) -> Option<&Uri>;
+    /// A value middleware attached to the response or, before sending it, to the request.
+    fn extension<T: Send + Sync + 'static>(&self) -> Option<&T>;
 }
 
 #[async_trait]
@@ -45,16 +106,38 @@ impl ResponseExt for Response<Body> {
         }
     }
 
+    async fn error_for_status_json<E: DeserializeOwned>(self) -> Result<Self, crate::Error<ApiError<E>>> {
+        let status = self.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(self);
+        }
+        let (parts, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        Err(api_error(parts, &body))
+    }
+
     async fn text(self) -> InMemoryResult<String> {
-        let (_, body) = self.into_parts();
+        let (parts, body) = self.into_parts();
         let body = body.into_memory().await?;
-        body.text()
+        body.decode(charset(parts.headers.get(CONTENT_TYPE)).unwrap_or(UTF_8))
+    }
+
+    async fn text_lossy(self) -> ProtocolResult<String> {
+        let (parts, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        Ok(body.decode_lossy(charset(parts.headers.get(CONTENT_TYPE)).unwrap_or(UTF_8)))
     }
 
     async fn json<U: DeserializeOwned>(self) -> InMemoryResult<U> {
-        let (_, body) = self.into_parts();
+        let (parts, body) = self.into_parts();
         let body = body.into_memory().await?;
-        body.json().map_err(Into::into)
+        Ok(decode_json(&body, parts.extensions.get::<Uri>())?)
+    }
+
+    async fn json_or_error<T: DeserializeOwned, E: DeserializeOwned>(self) -> Result<T, crate::Error<ApiError<E>>> {
+        let (parts, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        InMemoryResponse::from_parts(parts, body).json_or_error()
     }
 
     /// Get body as bytes.
@@ -64,6 +147,80 @@ impl ResponseExt for Response<Body> {
         body.bytes()
     }
 
+    #[cfg(feature = "xml")]
+    async fn xml<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        body.xml()
+    }
+
+    #[cfg(feature = "msgpack")]
+    async fn msgpack<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        body.msgpack()
+    }
+
+    #[cfg(feature = "cbor")]
+    async fn cbor<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        body.cbor()
+    }
+
+    #[cfg(feature = "protobuf")]
+    async fn protobuf<U: prost::Message + Default>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        let body = body.into_memory().await?;
+        body.protobuf()
+    }
+
+    async fn into_memory_limited(self, max_size: u64) -> ProtocolResult<InMemoryResponse> {
+        let (parts, body) = self.into_parts();
+        let body = body.into_memory_limited(max_size).await?;
+        Ok(InMemoryResponse::from_parts(parts, body))
+    }
+
+    async fn into_spooled(self, threshold: u64) -> ProtocolResult<Self> {
+        let (parts, body) = self.into_parts();
+        let body = body.into_spooled(threshold).await?;
+        Ok(Response::from_parts(parts, body))
+    }
+
+    fn bytes_stream(self) -> BoxStream<'static, ProtocolResult<Bytes>> {
+        self.into_body().into_stream()
+    }
+
+    fn ndjson<U: DeserializeOwned + Send + 'static>(self) -> BoxStream<'static, ProtocolResult<U>> {
+        Box::pin(self.into_body().into_lines()
+            .try_filter(|line| futures::future::ready(!line.iter().all(u8::is_ascii_whitespace)))
+            .and_then(|line| futures::future::ready(serde_json::from_slice(&line).map_err(ProtocolError::from))))
+    }
+
+    async fn save_to_path<P: AsRef<Path> + Send>(self, path: P) -> ProtocolResult<u64> {
+        let file = tokio::fs::File::create(path).await?;
+        write_body(self.into_body(), file).await
+    }
+
+    async fn into_upgraded(self) -> Result<Upgraded> {
+        if self.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Err(crate::Error::HttpError(self));
+        }
+        Ok(hyper::upgrade::on(self).await.map_err(ProtocolError::from)?)
+    }
+
+    fn trailers(&self) -> Trailers {
+        self.extensions().get::<Trailers>().cloned().unwrap_or_default()
+    }
+
+    fn url(&self) -> Option<&Uri> {
+        self.extensions().get::<Uri>()
+    }
+
+    fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        response_extension(self.extensions())
+    }
+
     fn get_cookie(&self, name: &str) -> Option<&str> {
         let value = self.headers().get("set-cookie")?;
         let value = value.to_str().ok()?;
@@ -73,4 +230,129 @@ impl ResponseExt for Response<Body> {
             .find(|c| c.name() == name)?;
         cookie.value_raw()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bytes_stream() {
+        let chunks = vec![Ok::<_, std::io::Error>("hello "), Ok("world")];
+        let res = Response::new(Body::from_stream(futures::stream::iter(chunks)));
+        let chunks: Vec<Bytes> = res.bytes_stream().try_collect().await.unwrap();
+        assert_eq!(chunks, vec![Bytes::from("hello "), Bytes::from("world")]);
+
+        let res = Response::new(Body::InMemory(crate::InMemoryBody::Text("hello".into())));
+        let chunks: Vec<Bytes> = res.bytes_stream().try_collect().await.unwrap();
+        assert_eq!(chunks, vec![Bytes::from("hello")]);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson() {
+        // Lines split across chunks, with a blank line and no trailing newline.
+        let chunks = vec![Ok::<_, std::io::Error>("{\"id\": 1}\n{\"id\""), Ok(": 2}\r\n\n"), Ok("{\"id\": 3}")];
+        let res = Response::new(Body::from_stream(futures::stream::iter(chunks)));
+        let values: Vec<serde_json::Value> = res.ndjson().try_collect().await.unwrap();
+        assert_eq!(values, vec![serde_json::json!({"id": 1}), serde_json::json!({"id": 2}), serde_json::json!({"id": 3})]);
+
+        let res = Response::new(Body::InMemory(crate::InMemoryBody::Text("{\"id\": 1}\nnot json\n".into())));
+        let mut stream = res.ndjson::<serde_json::Value>();
+        assert!(stream.try_next().await.unwrap().is_some());
+        assert!(matches!(stream.try_next().await, Err(ProtocolError::JsonError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_text_charset() {
+        let latin1 = |body: &'static [u8]| Response::builder()
+            .header(CONTENT_TYPE, "text/plain; charset=ISO-8859-1")
+            .body(Body::from(hyper::Body::from(body)))
+            .unwrap();
+        assert_eq!(latin1(b"caf\xe9").text().await.unwrap(), "café");
+        // Valid UTF-8 is still decoded as Latin-1 when the charset says so.
+        assert_eq!(latin1("café".as_bytes()).text().await.unwrap(), "cafÃ©");
+
+        let res = Response::builder()
+            .header(CONTENT_TYPE, "text/plain; charset=Shift_JIS")
+            .body(Body::from(hyper::Body::from(&b"\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd"[..])))
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "こんにちは");
+
+        let invalid = || Response::new(Body::from(hyper::Body::from(&b"caf\xe9"[..])));
+        assert!(invalid().text().await.is_err());
+        assert_eq!(invalid().text_lossy().await.unwrap(), "caf\u{fffd}");
+
+        let res = latin1(b"caf\xe9");
+        let res = response_into_content(res).await.unwrap();
+        assert_eq!(crate::InMemoryResponseExt::text(res).unwrap(), "café");
+    }
+
+    #[tokio::test]
+    async fn test_json_or_error() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Problem {
+            code: String,
+        }
+        let res = |status: u16, body: &'static str| Response::builder()
+            .status(status)
+            .body(Body::from(hyper::Body::from(body)))
+            .unwrap();
+
+        let ok: serde_json::Value = res(200, r#"{"id": 1}"#).json_or_error::<_, Problem>().await.unwrap();
+        assert_eq!(ok["id"], 1);
+        let Err(crate::Error::HttpError(e)) = res(404, r#"{"code": "not_found"}"#).json_or_error::<serde_json::Value, Problem>().await else {
+            panic!("expected an ApiError");
+        };
+        assert_eq!(e.status, StatusCode::NOT_FOUND);
+        assert_eq!(e.body.code, "not_found");
+        let err = res(502, "<html>Bad Gateway</html>").json_or_error::<serde_json::Value, Problem>().await.unwrap_err();
+        assert!(matches!(err, crate::Error::Protocol(ProtocolError::JsonDecodeError(_))));
+
+        assert!(res(200, "").error_for_status_json::<Problem>().await.is_ok());
+        let err = res(500, r#"{"code": "internal"}"#).error_for_status_json::<Problem>().await.unwrap_err();
+        assert!(matches!(err, crate::Error::HttpError(e) if e.body.code == "internal"));
+    }
+
+    #[tokio::test]
+    async fn test_into_upgraded() {
+        use hyper::service::{make_service_fn, service_fn};
+        use tokio::io::AsyncReadExt;
+
+        // Switches to a protocol which echoes what it's sent.
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, hyper::Error>(service_fn(|mut req: hyper::Request<hyper::Body>| async move {
+                tokio::spawn(async move {
+                    let mut io = hyper::upgrade::on(&mut req).await.unwrap();
+                    let mut buf = [0; 4];
+                    io.read_exact(&mut buf).await.unwrap();
+                    io.write_all(&buf).await.unwrap();
+                });
+                Ok::<_, hyper::Error>(hyper::Response::builder()
+                    .status(StatusCode::SWITCHING_PROTOCOLS)
+                    .header("connection", "upgrade")
+                    .header("upgrade", "echo")
+                    .body(hyper::Body::empty())
+                    .unwrap())
+            }))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = crate::Client::new();
+        let res = client.get(&format!("http://{addr}/"))
+            .header("connection", "upgrade")
+            .header("upgrade", "echo")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.url().unwrap().to_string(), format!("http://{addr}/"));
+        let mut io = res.into_upgraded().await.unwrap();
+        io.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        io.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let res = Response::new(Body::default());
+        assert!(matches!(res.into_upgraded().await, Err(crate::Error::HttpError(_))));
+    }
+}
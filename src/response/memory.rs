@@ -1,17 +1,51 @@
-use http::{HeaderMap, Response, StatusCode};
+use encoding_rs::UTF_8;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Response, StatusCode, Uri};
+use http::response::Parts;
 use hyper::body::Bytes;
 use serde::de::{DeserializeOwned, Error};
 
-use crate::{InMemoryBody, InMemoryResult, Result};
-use crate::sanitize::sanitize_headers;
+use crate::{ApiError, InMemoryBody, InMemoryResult, JsonDecodeError, ProtocolError, ProtocolResult, Result, Trailers};
+use crate::body::charset;
+use crate::extensions::response_extension;
+use crate::sanitize::Sanitizer;
 
 pub type InMemoryResponse = Response<InMemoryBody>;
 
 pub trait InMemoryResponseExt {
     fn new(status: StatusCode, headers: HeaderMap, body: InMemoryBody) -> Self;
+    /// Decode the body using the charset of the Content-Type, or UTF-8 if there isn't one.
+    #[allow(clippy::result_large_err)]
     fn text(self) -> InMemoryResult<String>;
-    fn json<U: DeserializeOwned>(self) -> serde_json::Result<U>;
+    /// Like `text`, but malformed text is replaced with U+FFFD rather than failing.
+    fn text_lossy(self) -> String;
+    /// Deserialize a JSON body. Failures are reported as `ProtocolError::JsonDecodeError`, with the path that
+    /// failed, the start of the body, and the URL.
+    #[allow(clippy::result_large_err)]
+    fn json<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    /// Deserialize a successful JSON body into `T`, or a 4xx or 5xx body into the API's error type `E`, returned as
+    /// `Error::HttpError`. If either fails to deserialize, it's reported as `ProtocolError::JsonDecodeError`.
+    fn json_or_error<T: DeserializeOwned, E: DeserializeOwned>(self) -> Result<T, crate::Error<ApiError<E>>>;
+    #[allow(clippy::result_large_err)]
     fn bytes(self) -> InMemoryResult<Bytes>;
+    #[cfg(feature = "xml")]
+    #[allow(clippy::result_large_err)]
+    fn xml<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "msgpack")]
+    #[allow(clippy::result_large_err)]
+    fn msgpack<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "cbor")]
+    #[allow(clippy::result_large_err)]
+    fn cbor<U: DeserializeOwned>(self) -> InMemoryResult<U>;
+    #[cfg(feature = "protobuf")]
+    #[allow(clippy::result_large_err)]
+    fn protobuf<U: prost::Message + Default>(self) -> InMemoryResult<U>;
+    /// The trailers sent after the body, if any.
+    fn trailers(&self) -> Option<HeaderMap>;
+    /// The URL the response came from, after any redirects. Responses replayed from a recording don't have one.
+    fn url(&self) -> Option<&Uri>;
+    /// A value middleware attached to the response or, before sending it, to the request.
+    fn extension<T: Send + Sync + 'static>(&self) -> Option<&T>;
     /// Attempt to clear sensitive information from the response.
     fn sanitize(&mut self);
 
@@ -28,13 +62,26 @@ impl InMemoryResponseExt for InMemoryResponse {
     }
 
     fn text(self) -> InMemoryResult<String> {
-        let (_, body) = self.into_parts();
-        body.text()
+        let (parts, body) = self.into_parts();
+        body.decode(charset(parts.headers.get(CONTENT_TYPE)).unwrap_or(UTF_8))
     }
 
-    fn json<U: DeserializeOwned>(self) -> serde_json::Result<U> {
-        let (_, body) = self.into_parts();
-        body.json()
+    fn text_lossy(self) -> String {
+        let (parts, body) = self.into_parts();
+        body.decode_lossy(charset(parts.headers.get(CONTENT_TYPE)).unwrap_or(UTF_8))
+    }
+
+    fn json<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (parts, body) = self.into_parts();
+        Ok(decode_json(&body, parts.extensions.get::<Uri>())?)
+    }
+
+    fn json_or_error<T: DeserializeOwned, E: DeserializeOwned>(self) -> Result<T, crate::Error<ApiError<E>>> {
+        let (parts, body) = self.into_parts();
+        if parts.status.is_client_error() || parts.status.is_server_error() {
+            return Err(api_error(parts, &body));
+        }
+        Ok(decode_json(&body, parts.extensions.get::<Uri>())?)
     }
 
     fn bytes(self) -> InMemoryResult<Bytes> {
@@ -42,11 +89,45 @@ impl InMemoryResponseExt for InMemoryResponse {
         body.bytes()
     }
 
+    #[cfg(feature = "xml")]
+    fn xml<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        body.xml()
+    }
+
+    #[cfg(feature = "msgpack")]
+    fn msgpack<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        body.msgpack()
+    }
+
+    #[cfg(feature = "cbor")]
+    fn cbor<U: DeserializeOwned>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        body.cbor()
+    }
+
+    #[cfg(feature = "protobuf")]
+    fn protobuf<U: prost::Message + Default>(self) -> InMemoryResult<U> {
+        let (_, body) = self.into_parts();
+        body.protobuf()
+    }
+
+    fn trailers(&self) -> Option<HeaderMap> {
+        self.extensions().get::<Trailers>()?.get()
+    }
+
+    fn url(&self) -> Option<&Uri> {
+        self.extensions().get::<Uri>()
+    }
+
+    fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        response_extension(self.extensions())
+    }
+
     /// Attempt to clear sensitive information from the response.
     fn sanitize(&mut self) {
-        let h = self.headers_mut();
-        sanitize_headers(h);
-        self.body_mut().sanitize();
+        Sanitizer::default().sanitize_response(self);
     }
 
     fn get_cookie(&self, name: &str) -> Option<&str> {
@@ -60,6 +141,41 @@ impl InMemoryResponseExt for InMemoryResponse {
     }
 }
 
+/// How much of the body to include in a `JsonDecodeError`, in characters.
+const SNIPPET_LEN: usize = 200;
+
+pub(crate) fn api_error<E: DeserializeOwned>(parts: Parts, body: &InMemoryBody) -> crate::Error<ApiError<E>> {
+    match decode_json(body, parts.extensions.get::<Uri>()) {
+        Ok(body) => crate::Error::HttpError(ApiError { status: parts.status, headers: parts.headers, body }),
+        Err(e) => crate::Error::Protocol(e),
+    }
+}
+
+pub(crate) fn decode_json<U: DeserializeOwned>(body: &InMemoryBody, url: Option<&Uri>) -> ProtocolResult<U> {
+    let result = match body {
+        InMemoryBody::Bytes(b) => from_json_read(serde_json::de::SliceRead::new(b)),
+        InMemoryBody::Text(t) => from_json_read(serde_json::de::StrRead::new(t)),
+        InMemoryBody::Json(v) => serde_path_to_error::deserialize(v).map_err(|e| (e.path().to_string(), e.into_inner())),
+        body => return Ok(body.clone().json()?),
+    };
+    result.map_err(|(path, source)| {
+        let text = body.clone().text_lossy();
+        let mut snippet: String = text.chars().take(SNIPPET_LEN).collect();
+        if snippet.len() < text.len() {
+            snippet.push('…');
+        }
+        let url = url.cloned();
+        ProtocolError::JsonDecodeError(Box::new(JsonDecodeError { path, snippet, url, source }))
+    })
+}
+
+/// Deserialize with the path to any failure, which `serde_json::from_slice` doesn't report.
+fn from_json_read<'de, R: serde_json::de::Read<'de>, U: DeserializeOwned>(read: R) -> Result<U, (String, serde_json::Error)> {
+    let mut de = serde_json::Deserializer::new(read);
+    let value = serde_path_to_error::deserialize(&mut de).map_err(|e| (e.path().to_string(), e.into_inner()))?;
+    de.end().map_err(|e| (".".to_string(), e))?;
+    Ok(value)
+}
 
 pub(crate) fn clone_inmemory_response(res: &InMemoryResponse) -> InMemoryResponse {
     let (mut parts, _) = Response::new(()).into_parts();
@@ -76,6 +192,8 @@ pub mod serde_response {
     use serde::Deserializer;
     use serde::ser::SerializeStruct;
 
+    use crate::body::RecordedBody;
+
     use super::*;
 
     pub fn serialize<S>(v: &InMemoryResponse, serializer: S) -> Result<S::Ok, S::Error>
@@ -89,7 +207,7 @@ pub mod serde_response {
             .map(|(k, v)| (k.as_str(), v.to_str().unwrap()))
             .collect();
         map.serialize_field("headers", &ordered)?;
-        map.serialize_field("body", &v.body())?;
+        map.serialize_field("body", &RecordedBody(v.body()))?;
         map.end()
     }
 
@@ -130,7 +248,7 @@ pub mod serde_response {
                         if body.is_some() {
                             return Err(<A::Error as Error>::duplicate_field("body"));
                         }
-                        body = Some(map.next_value::<InMemoryBody>()?);
+                        body = Some(map.next_value::<serde_json::Value>()?);
                     }
                     _ => {
                         map.next_value::<serde::de::IgnoredAny>()?;
@@ -142,6 +260,7 @@ pub mod serde_response {
                 .map(|(k, v)| (HeaderName::from_bytes(k.as_bytes()).unwrap(), HeaderValue::from_str(v).unwrap()))
             );
             let body = body.ok_or_else(|| Error::missing_field("data"))?;
+            let body = InMemoryBody::from_recorded(body, headers.get(hyper::header::CONTENT_TYPE));
             let mut b = http::response::Builder::new()
                 .status(status);
             let h = b.headers_mut().unwrap();
@@ -181,4 +300,62 @@ mod tests {
         let serialized = String::from_utf8(serializer.into_inner().into_inner().unwrap()).unwrap();
         assert_eq!(serialized, r#"{"status":200,"headers":{},"body":{"Password":"**********","email":"amazing"}}"#);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let bytes = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe];
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/gzip".parse().unwrap());
+        let res = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, headers, InMemoryBody::new_bytes(bytes.clone()));
+        let serialized = serde_response::serialize(&res, serde_json::value::Serializer).unwrap();
+        assert_eq!(serialized["body"], json!({"$base64": "H4sIAP/+"}));
+        let res = serde_response::deserialize(serialized).unwrap();
+        assert!(matches!(res.body(), InMemoryBody::Bytes(b) if b == &bytes));
+    }
+
+    #[test]
+    fn test_json_error_context() {
+        #[derive(Debug, serde::Deserialize)]
+        #[allow(dead_code)]
+        struct Item {
+            price: u32,
+        }
+        #[derive(Debug, serde::Deserialize)]
+        #[allow(dead_code)]
+        struct Order {
+            items: Vec<Item>,
+        }
+
+        let body = format!(r#"{{"items": [{{"price": 1}}, {{"price": "2"}}], "note": "{}"}}"#, "x".repeat(300));
+        let mut res = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, HeaderMap::new(), InMemoryBody::Text(body));
+        res.extensions_mut().insert("https://example.com/orders/1".parse::<Uri>().unwrap());
+        let Err(crate::Error::Protocol(ProtocolError::JsonDecodeError(e))) = res.json::<Order>() else {
+            panic!("expected a JsonDecodeError");
+        };
+        assert_eq!(e.path, "items[1].price");
+        assert_eq!(e.url.as_ref().unwrap().path(), "/orders/1");
+        assert!(e.snippet.starts_with(r#"{"items": [{"price": 1}"#));
+        assert!(e.snippet.ends_with('…'));
+        assert!(e.to_string().contains("at `items[1].price` in response from https://example.com/orders/1"));
+
+        let res = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::OK, HeaderMap::new(), InMemoryBody::new_json(json!({"items": {}})));
+        let Err(crate::Error::Protocol(ProtocolError::JsonDecodeError(e))) = res.json::<Order>() else {
+            panic!("expected a JsonDecodeError");
+        };
+        assert_eq!(e.path, "items");
+        assert!(e.url.is_none());
+    }
+
+    #[test]
+    fn test_deserialize_ambiguous_bodies() {
+        let legacy = json!({"status": 200, "headers": {}, "body": [1, 2, 3]});
+        let res = serde_response::deserialize(legacy).unwrap();
+        assert!(matches!(res.body(), InMemoryBody::Bytes(b) if b[..] == [1, 2, 3]));
+        let json_array = json!({"status": 200, "headers": {"content-type": "application/json"}, "body": [1, 2, 3]});
+        let res = serde_response::deserialize(json_array).unwrap();
+        assert!(matches!(res.body(), InMemoryBody::Json(_)));
+        let json_string = json!({"status": 200, "headers": {"content-type": "application/json"}, "body": "hi"});
+        let res = serde_response::deserialize(json_string).unwrap();
+        assert_eq!(res.body().clone().text().unwrap(), r#""hi""#);
+    }
+}
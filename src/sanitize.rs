@@ -1,16 +1,19 @@
 use http::{HeaderMap, HeaderValue};
+use std::collections::HashSet;
 use std::sync::OnceLock;
 use regex::Regex;
 use serde_json::Value;
 
+use crate::{InMemoryBody, InMemoryRequest, InMemoryResponse};
+
 static REGEX: OnceLock<Regex> = OnceLock::new();
 
 trait AsLowercase   {
-    fn as_lowercase(&self) -> std::borrow::Cow<str>;
+    fn as_lowercase(&self) -> std::borrow::Cow<'_, str>;
 }
 
 impl AsLowercase for str {
-    fn as_lowercase(&self) -> std::borrow::Cow<str> {
+    fn as_lowercase(&self) -> std::borrow::Cow<'_, str> {
         use std::borrow::Cow;
         if let Some(first_uppercase) = self.bytes().position(|b| b.is_ascii_alphabetic() && !b.is_ascii_lowercase()) {
             let mut string = String::with_capacity(self.len());
@@ -45,6 +48,7 @@ pub fn should_sanitize(key: &str) -> bool {
     let key = key.as_lowercase();
     match key.as_ref() {
         "authorization" => true,
+        "proxy-authorization" => true,
         "cookie" => true,
         "set-cookie" => true,
         "password" => true,
@@ -73,11 +77,287 @@ pub fn sanitize_value(value: &mut Value) {
     }
 }
 
-pub fn sanitize_headers(headers: &mut HeaderMap) {
-    let sanitized: HeaderValue = SANITIZED_VALUE.parse().unwrap();
-    for (key, value) in headers.iter_mut() {
-        if should_sanitize(key.as_str()) {
-            *value = sanitized.clone();
+/// Configurable redaction rules, applied to requests and responses before they are recorded.
+///
+/// The default rules redact the `authorization`, `proxy-authorization`, `cookie`, and `set-cookie` headers, and any
+/// header or JSON field whose name looks like a secret (`password`, `api_key`, `session_token`, ...). Add your own
+/// rules on top:
+///
+/// ```ignore
+/// let sanitizer = Sanitizer::new()
+///     .header("x-api-signature")
+///     .field("ssn")
+///     .field_pattern(r"(?i)^card_")
+///     .value_pattern(r"sk_live_[0-9a-zA-Z]+")
+///     .allow("token_type");
+/// ```
+///
+/// Use `Sanitizer::allowlist()` to instead redact every header and JSON field except those passed to `allow`.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    defaults: bool,
+    allowlist: bool,
+    headers: HashSet<String>,
+    fields: HashSet<String>,
+    field_patterns: Vec<Regex>,
+    value_patterns: Vec<Regex>,
+    allowed: HashSet<String>,
+    replacement: String,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sanitizer {
+    /// The default rules.
+    pub fn new() -> Self {
+        Sanitizer {
+            defaults: true,
+            allowlist: false,
+            headers: HashSet::new(),
+            fields: HashSet::new(),
+            field_patterns: Vec::new(),
+            value_patterns: Vec::new(),
+            allowed: HashSet::new(),
+            replacement: SANITIZED_VALUE.to_string(),
         }
     }
-}
\ No newline at end of file
+
+    /// No rules at all. Nothing is redacted unless rules are added.
+    pub fn empty() -> Self {
+        Sanitizer {
+            defaults: false,
+            ..Self::new()
+        }
+    }
+
+    /// Redact every header and JSON field, except those passed to `allow`. Value patterns still apply to allowed
+    /// headers and fields.
+    pub fn allowlist() -> Self {
+        Sanitizer {
+            defaults: false,
+            allowlist: true,
+            ..Self::new()
+        }
+    }
+
+    /// Redact a header, matched case-insensitively.
+    pub fn header(mut self, name: &str) -> Self {
+        self.headers.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Redact a JSON field (at any depth), matched case-insensitively.
+    pub fn field(mut self, name: &str) -> Self {
+        self.fields.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Redact JSON fields whose name matches the regex. Panics if the regex is invalid.
+    pub fn field_pattern(mut self, pattern: &str) -> Self {
+        self.field_patterns.push(Regex::new(pattern).expect("Invalid field pattern"));
+        self
+    }
+
+    /// Redact any text matching the regex wherever it appears: header values, the url, JSON strings, and text bodies.
+    /// Panics if the regex is invalid.
+    pub fn value_pattern(mut self, pattern: &str) -> Self {
+        self.value_patterns.push(Regex::new(pattern).expect("Invalid value pattern"));
+        self
+    }
+
+    /// Never redact this header or JSON field by name, even if another rule matches it.
+    pub fn allow(mut self, name: &str) -> Self {
+        self.allowed.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// The text that replaces redacted values. Defaults to `**********`.
+    pub fn replacement(mut self, replacement: impl Into<String>) -> Self {
+        self.replacement = replacement.into();
+        self
+    }
+
+    pub fn should_redact_header(&self, name: &str) -> bool {
+        let name = name.as_lowercase();
+        if self.allowed.contains(name.as_ref()) {
+            return false;
+        }
+        self.allowlist
+            || self.headers.contains(name.as_ref())
+            || (self.defaults && should_sanitize(&name))
+    }
+
+    pub fn should_redact_field(&self, name: &str) -> bool {
+        let lower = name.as_lowercase();
+        if self.allowed.contains(lower.as_ref()) {
+            return false;
+        }
+        self.allowlist
+            || self.fields.contains(lower.as_ref())
+            || self.field_patterns.iter().any(|p| p.is_match(name))
+            || (self.defaults && should_sanitize(&lower))
+    }
+
+    /// Replace every match of the value patterns. Returns whether anything was replaced.
+    pub fn redact_text(&self, text: &mut String) -> bool {
+        let mut changed = false;
+        for pattern in &self.value_patterns {
+            if let std::borrow::Cow::Owned(replaced) = pattern.replace_all(text, self.replacement.as_str()) {
+                *text = replaced;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn sanitize_headers(&self, headers: &mut HeaderMap) {
+        let Ok(replacement) = HeaderValue::from_str(&self.replacement) else {
+            return;
+        };
+        for (key, value) in headers.iter_mut() {
+            if self.should_redact_header(key.as_str()) {
+                *value = replacement.clone();
+                continue;
+            }
+            let Ok(text) = value.to_str() else {
+                continue;
+            };
+            let mut text = text.to_string();
+            if self.redact_text(&mut text) {
+                if let Ok(redacted) = HeaderValue::from_str(&text) {
+                    *value = redacted;
+                }
+            }
+        }
+    }
+
+    pub fn sanitize_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    let nested = matches!(value, Value::Object(_) | Value::Array(_));
+                    // In allowlist mode, recurse into nested values so allowed fields within them survive.
+                    if self.should_redact_field(key) && !(self.allowlist && nested) {
+                        *value = Value::String(self.replacement.clone());
+                    } else {
+                        self.sanitize_value(value);
+                    }
+                }
+            }
+            Value::Array(vec) => {
+                for value in vec.iter_mut() {
+                    self.sanitize_value(value);
+                }
+            }
+            Value::String(s) => {
+                self.redact_text(s);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn sanitize_body(&self, body: &mut InMemoryBody) {
+        match body {
+            InMemoryBody::Json(value) => self.sanitize_value(value),
+            InMemoryBody::Text(text) => {
+                self.redact_text(text);
+            }
+            InMemoryBody::Form(pairs) => {
+                for (key, value) in pairs {
+                    if self.should_redact_field(key) {
+                        *value = self.replacement.clone();
+                    } else {
+                        self.redact_text(value);
+                    }
+                }
+            }
+            InMemoryBody::Bytes(bytes) if !self.value_patterns.is_empty() => {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    let mut text = text.to_string();
+                    if self.redact_text(&mut text) {
+                        *bytes = text.into();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn sanitize_request(&self, request: &mut InMemoryRequest) {
+        self.sanitize_headers(request.headers_mut());
+        self.sanitize_body(request.body_mut());
+        let mut url = request.url().to_string();
+        if self.redact_text(&mut url) {
+            // The replacement may not be valid in a url, so percent-encode it.
+            let url = url.replace(&self.replacement, &urlencoding::encode(&self.replacement));
+            if let Ok(uri) = url.parse() {
+                *request.uri_mut() = uri;
+            }
+        }
+    }
+
+    pub fn sanitize_response(&self, response: &mut InMemoryResponse) {
+        self.sanitize_headers(response.headers_mut());
+        self.sanitize_body(response.body_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_default_rules() {
+        let sanitizer = Sanitizer::new();
+        assert!(sanitizer.should_redact_header("Authorization"));
+        assert!(sanitizer.should_redact_header("x-api-key"));
+        assert!(!sanitizer.should_redact_header("accept"));
+        let mut value = json!({"password": "hunter2", "user": {"api_key": "abc"}, "name": "bob"});
+        sanitizer.sanitize_value(&mut value);
+        assert_eq!(value, json!({"password": SANITIZED_VALUE, "user": {"api_key": SANITIZED_VALUE}, "name": "bob"}));
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let sanitizer = Sanitizer::new()
+            .header("x-signature")
+            .field("ssn")
+            .field_pattern("(?i)^card_")
+            .value_pattern(r"sk_live_[0-9a-zA-Z]+")
+            .allow("token_type");
+        let mut request = Request::build_post("https://example.com/charge?key=sk_live_abc123")
+            .header("x-signature", "deadbeef")
+            .header("x-note", "uses sk_live_abc123")
+            .json(json!({"ssn": "123", "Card_Number": "4242", "token_type": "bearer", "memo": "sk_live_zzz"}))
+            .build();
+        sanitizer.sanitize_request(&mut request);
+        assert_eq!(request.header("x-signature").unwrap(), SANITIZED_VALUE);
+        assert_eq!(request.header("x-note").unwrap(), format!("uses {SANITIZED_VALUE}"));
+        assert!(!request.url().to_string().contains("sk_live"));
+        let body = request.body().clone().json::<Value>().unwrap();
+        assert_eq!(body, json!({"ssn": SANITIZED_VALUE, "Card_Number": SANITIZED_VALUE, "token_type": "bearer", "memo": SANITIZED_VALUE}));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let sanitizer = Sanitizer::allowlist().allow("content-type").allow("id").allow("name");
+        let mut request = Request::build_post("https://example.com/")
+            .header("content-type", "application/json")
+            .header("x-custom", "value")
+            .json(json!({"id": 1, "email": "a@b.c", "items": [{"name": "x", "price": 5}]}))
+            .build();
+        sanitizer.sanitize_request(&mut request);
+        assert_eq!(request.header("content-type").unwrap(), "application/json");
+        assert_eq!(request.header("x-custom").unwrap(), SANITIZED_VALUE);
+        let body = request.body().clone().json::<Value>().unwrap();
+        assert_eq!(body, json!({"id": 1, "email": SANITIZED_VALUE, "items": [{"name": "x", "price": SANITIZED_VALUE}]}));
+    }
+}
@@ -0,0 +1,258 @@
+//! Server-Sent Events, following the `EventSource` interface of the HTML standard.
+//!
+//! ```ignore
+//! let mut events = client.get("https://example.com/updates").eventsource();
+//! while let Some(event) = events.try_next().await? {
+//!     let update: Update = event.json()?;
+//! }
+//! ```
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use futures::{Stream, TryStreamExt};
+use http::header::CONTENT_TYPE;
+use http::{HeaderName, HeaderValue, StatusCode};
+use hyper::body::Bytes;
+use serde::de::DeserializeOwned;
+
+use crate::request::send_request;
+use crate::{Client, Error, InMemoryRequest, Middleware, ProtocolResult, Result};
+
+static LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+/// How long to wait before reconnecting, unless the server sends a `retry` field.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The event type: `message` unless the server set one.
+    pub event: String,
+    pub data: String,
+    /// The most recent event ID the server sent. It's sent back as `Last-Event-ID` when reconnecting.
+    pub id: Option<String>,
+}
+
+impl Event {
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.data)
+    }
+}
+
+/// A stream of events from a Server-Sent Events endpoint, created with `RequestBuilder::eventsource`.
+///
+/// When the connection closes, the stream reconnects after the retry delay (3 seconds, unless the server sets one),
+/// sending `Last-Event-ID` so the server can resume. Connection errors are yielded before reconnecting; stop
+/// polling to give up. A 204 ends the stream, and any other response which isn't a 200 `text/event-stream` yields
+/// `Error::HttpError` and ends it.
+pub struct EventSource<'a> {
+    inner: BoxStream<'a, Result<Event>>,
+}
+
+impl<'a> EventSource<'a> {
+    pub(crate) fn new(client: &'a Client, request: InMemoryRequest, middlewares: Vec<Arc<dyn Middleware>>, timeout: Option<Duration>) -> Self {
+        let state = State {
+            client,
+            request,
+            middlewares,
+            timeout,
+            lines: None,
+            parser: Parser::default(),
+            reconnect: false,
+            done: false,
+        };
+        EventSource { inner: Box::pin(futures::stream::unfold(state, State::next)) }
+    }
+}
+
+impl Stream for EventSource<'_> {
+    type Item = Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+struct State<'a> {
+    client: &'a Client,
+    request: InMemoryRequest,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    timeout: Option<Duration>,
+    lines: Option<BoxStream<'static, ProtocolResult<Bytes>>>,
+    parser: Parser,
+    reconnect: bool,
+    done: bool,
+}
+
+impl State<'_> {
+    async fn next(mut self) -> Option<(Result<Event>, Self)> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let Some(lines) = self.lines.as_mut() else {
+                if self.reconnect {
+                    tokio::time::sleep(self.parser.retry.unwrap_or(DEFAULT_RETRY)).await;
+                }
+                self.reconnect = true;
+                match self.connect().await {
+                    Ok(Some(lines)) => self.lines = Some(lines),
+                    Ok(None) => return None,
+                    Err(e) => {
+                        self.done = matches!(e, Error::HttpError(_));
+                        return Some((Err(e), self));
+                    }
+                }
+                continue;
+            };
+            match lines.try_next().await {
+                Ok(Some(line)) => if let Some(event) = self.parser.line(&line) {
+                    return Some((Ok(event), self));
+                },
+                Ok(None) => {
+                    self.lines = None;
+                    self.parser.reset();
+                }
+                Err(e) => {
+                    self.lines = None;
+                    self.parser.reset();
+                    return Some((Err(e.into()), self));
+                }
+            }
+        }
+    }
+
+    /// Send the request, returning the body's lines, or `None` if the server said not to reconnect.
+    async fn connect(&mut self) -> Result<Option<BoxStream<'static, ProtocolResult<Bytes>>>> {
+        let mut request = self.request.clone();
+        if !self.parser.last_event_id.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.parser.last_event_id) {
+                request.headers_mut().insert(LAST_EVENT_ID.clone(), value);
+            }
+        }
+        let res = send_request(self.client, request, &self.middlewares, self.timeout).await?;
+        if res.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        let is_event_stream = res.headers().get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.split(';').next().unwrap().trim().eq_ignore_ascii_case("text/event-stream"));
+        if res.status() != StatusCode::OK || !is_event_stream {
+            return Err(Error::HttpError(res));
+        }
+        Ok(Some(res.into_body().into_lines()))
+    }
+}
+
+/// Collects fields line by line until a blank line completes the event.
+#[derive(Default)]
+struct Parser {
+    event: String,
+    data: String,
+    last_event_id: String,
+    retry: Option<Duration>,
+}
+
+impl Parser {
+    fn line(&mut self, line: &[u8]) -> Option<Event> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_prefix('\u{feff}').unwrap_or(&line);
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // Lines starting with a colon are comments, often sent as keep-alives.
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_string(),
+            "retry" if value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        Some(Event {
+            event: if event.is_empty() { "message".to_string() } else { event },
+            data,
+            id: Some(self.last_event_id.clone()).filter(|id| !id.is_empty()),
+        })
+    }
+
+    /// Discard a partly received event, as when the connection drops.
+    fn reset(&mut self) {
+        self.event.clear();
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hyper::service::{make_service_fn, service_fn};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let make_svc = make_service_fn({
+            let connections = connections.clone();
+            move |_| {
+                let connections = connections.clone();
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req: hyper::Request<hyper::Body>| {
+                        let n = connections.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            assert_eq!(req.headers()["accept"], "text/event-stream");
+                            let body = match n {
+                                0 => "retry: 10\n\nid: 1\nevent: update\ndata: {\"n\": 1}\n\n: keep-alive\r\ndata: a\ndata: b\n\ndata: partial\n".to_string(),
+                                1 => format!("data: resumed from {}\n\n", req.headers()["last-event-id"].to_str().unwrap()),
+                                _ => return Ok(hyper::Response::builder().status(204).body(hyper::Body::empty()).unwrap()),
+                            };
+                            Ok::<_, hyper::Error>(hyper::Response::builder()
+                                .header("content-type", "text/event-stream")
+                                .body(hyper::Body::from(body))
+                                .unwrap())
+                        }
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let client = Client::new();
+        let events: Vec<Event> = client.get(&format!("http://{addr}/events")).eventsource().try_collect().await.unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], Event { event: "update".to_string(), data: "{\"n\": 1}".to_string(), id: Some("1".to_string()) });
+        assert_eq!(events[0].json::<serde_json::Value>().unwrap()["n"], 1);
+        assert_eq!(events[1].data, "a\nb");
+        assert_eq!(events[1].event, "message");
+        assert_eq!(events[2].data, "resumed from 1");
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+}
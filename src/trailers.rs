@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+
+use http::HeaderMap;
+use hyper::body::HttpBody;
+
+/// Trailing headers sent after a response body, as used by gRPC and some CDNs. They're only known once the body has
+/// been read to the end. Clones share the same trailers.
+///
+/// Trailers are received over HTTP/2. hyper discards the trailers of chunked HTTP/1.1 responses.
+#[derive(Debug, Clone, Default)]
+pub struct Trailers(Arc<Mutex<Option<HeaderMap>>>);
+
+impl Trailers {
+    /// The trailers, if the body has been read and the server sent any.
+    pub fn get(&self) -> Option<HeaderMap> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Stored in the request extensions by `RequestBuilder::trailers`, and applied when the request is sent.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestTrailers(pub HeaderMap);
+
+/// Wrap `body` so its trailers are stored in `trailers` when it ends.
+pub(crate) fn capture(body: hyper::Body, trailers: Trailers) -> hyper::Body {
+    hyper::Body::wrap_stream(futures::stream::try_unfold(body, move |mut body| {
+        let trailers = trailers.clone();
+        async move {
+            match HttpBody::data(&mut body).await {
+                Some(chunk) => Ok(Some((chunk?, body))),
+                None => {
+                    *trailers.0.lock().unwrap() = HttpBody::trailers(&mut body).await?;
+                    Ok::<_, hyper::Error>(None)
+                }
+            }
+        }
+    }))
+}
+
+/// Send `trailers` after `body`. A `hyper::Body` can't gain trailers, so a spawned task copies the body into a
+/// channel and sends the trailers at the end.
+pub(crate) fn append(mut body: hyper::Body, trailers: HeaderMap) -> hyper::Body {
+    let (mut sender, channel) = hyper::Body::channel();
+    tokio::spawn(async move {
+        while let Some(chunk) = HttpBody::data(&mut body).await {
+            let Ok(chunk) = chunk else {
+                sender.abort();
+                return;
+            };
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(trailers).await;
+    });
+    channel
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn grpc_status() -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        trailers
+    }
+
+    #[tokio::test]
+    async fn test_trailers() {
+        let mut body = append(hyper::Body::from("hello"), grpc_status());
+        assert_eq!(HttpBody::data(&mut body).await.unwrap().unwrap(), "hello");
+        assert!(HttpBody::data(&mut body).await.is_none());
+        assert_eq!(HttpBody::trailers(&mut body).await.unwrap(), Some(grpc_status()));
+
+        let trailers = Trailers::default();
+        let body = capture(append(hyper::Body::from("hello"), grpc_status()), trailers.clone());
+        assert!(trailers.get().is_none());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello");
+        assert_eq!(trailers.get(), Some(grpc_status()));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, SystemTime};
+
+use headers::{Header, HeaderMapExt};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, LAST_MODIFIED, RETRY_AFTER};
+use http::HeaderMap;
+use mime::Mime;
+
+use crate::Request;
+
+/// Parsed access to common headers, so callers don't have to pick apart `HeaderValue` strings. Implemented for
+/// requests, responses, and header maps. Headers which are missing or malformed give `None`.
+pub trait TypedHeaders {
+    fn header_map(&self) -> &HeaderMap;
+
+    /// Decode any header type from the `headers` crate, e.g. `headers::CacheControl`.
+    fn typed_header<H: Header>(&self) -> Option<H> {
+        self.header_map().typed_get()
+    }
+
+    fn content_type(&self) -> Option<Mime> {
+        self.header_map().get(CONTENT_TYPE)?.to_str().ok()?.parse().ok()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.header_map().get(CONTENT_LENGTH)?.to_str().ok()?.trim().parse().ok()
+    }
+
+    fn last_modified(&self) -> Option<SystemTime> {
+        httpdate::parse_http_date(self.header_map().get(LAST_MODIFIED)?.to_str().ok()?).ok()
+    }
+
+    /// How long the server asked to wait, from a number of seconds or an HTTP-date. Dates in the past give a
+    /// zero delay.
+    fn retry_after(&self) -> Option<Duration> {
+        let retry_after = self.header_map().get(RETRY_AFTER)?.to_str().ok()?.trim();
+        if let Ok(secs) = retry_after.parse() {
+            Some(Duration::from_secs(secs))
+        } else if let Ok(date) = httpdate::parse_http_date(retry_after) {
+            Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+        } else {
+            None
+        }
+    }
+}
+
+impl TypedHeaders for HeaderMap {
+    fn header_map(&self) -> &HeaderMap {
+        self
+    }
+}
+
+impl<T> TypedHeaders for http::Response<T> {
+    fn header_map(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+impl<T> TypedHeaders for Request<T> {
+    fn header_map(&self) -> &HeaderMap {
+        self.headers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use headers::{CacheControl, ContentType};
+
+    use crate::{InMemoryBody, InMemoryResponse, InMemoryResponseExt};
+
+    use super::*;
+
+    #[test]
+    fn test_typed_headers() {
+        let request = Request::build_post("https://example.com/")
+            .typed_header(ContentType::json())
+            .typed_header(CacheControl::new().with_no_cache())
+            .build();
+        assert_eq!(request.content_type(), Some(mime::APPLICATION_JSON));
+        assert!(request.typed_header::<CacheControl>().unwrap().no_cache());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/html; charset=ISO-8859-1".parse().unwrap());
+        headers.insert(CONTENT_LENGTH, "42".parse().unwrap());
+        headers.insert(LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        let res = <InMemoryResponse as InMemoryResponseExt>::new(http::StatusCode::OK, headers, InMemoryBody::Empty);
+        let content_type = res.content_type().unwrap();
+        assert_eq!(content_type.essence_str(), "text/html");
+        assert_eq!(content_type.get_param(mime::CHARSET).unwrap(), "ISO-8859-1");
+        assert_eq!(res.content_length(), Some(42));
+        assert_eq!(res.last_modified(), Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1445412480)));
+        assert_eq!(res.retry_after(), Some(Duration::from_secs(120)));
+        assert_eq!(HeaderMap::new().content_length(), None);
+    }
+}
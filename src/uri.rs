@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use http::Uri;
+use http::uri::InvalidUri;
+
+/// Resolve `reference` against `base`, following RFC 3986 section 5.2. As with links in a browser, a relative path
+/// replaces the last segment of the base path, so `users` against `https://example.com/v2/` gives
+/// `https://example.com/v2/users`, but against `https://example.com/v2` gives `https://example.com/users`. Fragments
+/// are dropped, since they aren't sent.
+pub(crate) fn resolve(base: &Uri, reference: &str) -> Result<Uri, InvalidUri> {
+    let reference = reference.split('#').next().unwrap();
+    let (rest, query) = match reference.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (reference, None),
+    };
+    if has_scheme(rest) {
+        let (scheme, rest) = rest.split_once(':').unwrap();
+        let (authority, path) = split_authority(rest);
+        return build(scheme, authority.unwrap_or_default(), &remove_dot_segments(path), query);
+    }
+    let scheme = base.scheme_str().unwrap_or("https");
+    if let (Some(authority), path) = split_authority(rest) {
+        return build(scheme, authority, &remove_dot_segments(path), query);
+    }
+    let authority = base.authority().map(|a| a.as_str()).unwrap_or_default();
+    let base_path = base.path();
+    if rest.is_empty() {
+        return build(scheme, authority, base_path, query.or(base.query()));
+    }
+    let path = if rest.starts_with('/') {
+        remove_dot_segments(rest)
+    } else {
+        let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+        let dir = if dir.is_empty() { "/" } else { dir };
+        remove_dot_segments(&format!("{}{}", dir, rest))
+    };
+    build(scheme, authority, &path, query)
+}
+
+/// Only URLs with an authority, e.g. `https://`, are treated as absolute, so paths with a colon like
+/// `documents:batchGet` are still relative. `Uri` can't represent URIs without an authority anyway.
+fn has_scheme(s: &str) -> bool {
+    let Some((scheme, rest)) = s.split_once(':') else {
+        return false;
+    };
+    if !rest.starts_with("//") {
+        return false;
+    }
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Split `//authority/path` into the authority and path.
+fn split_authority(s: &str) -> (Option<&str>, &str) {
+    match s.strip_prefix("//") {
+        Some(rest) => {
+            let end = rest.find('/').unwrap_or(rest.len());
+            (Some(&rest[..end]), &rest[end..])
+        }
+        None => (None, s),
+    }
+}
+
+fn build(scheme: &str, authority: &str, path: &str, query: Option<&str>) -> Result<Uri, InvalidUri> {
+    let path = if path.is_empty() { "/" } else { path };
+    match query {
+        Some(query) => Uri::from_str(&format!("{}://{}{}?{}", scheme, authority, path, query)),
+        None => Uri::from_str(&format!("{}://{}{}", scheme, authority, path)),
+    }
+}
+
+/// Resolve `.` and `..` segments, keeping a trailing slash where one was implied.
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let segments: Vec<&str> = path.split('/').skip(usize::from(absolute)).collect();
+    let mut out: Vec<&str> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." => {}
+            ".." => {
+                out.pop();
+            }
+            segment => {
+                out.push(segment);
+                continue;
+            }
+        }
+        if last {
+            out.push("");
+        }
+    }
+    let path = out.join("/");
+    if absolute { format!("/{}", path) } else { path }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        // The examples of RFC 3986 section 5.4, without params or fragments.
+        let base: Uri = "http://a/b/c/d?q".parse().unwrap();
+        let cases = [
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d?q"),
+            ("", "http://a/b/c/d?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("https://example.com/x/../y", "https://example.com/y"),
+            ("documents:batchGet", "http://a/b/c/documents:batchGet"),
+        ];
+        for (reference, expected) in cases {
+            assert_eq!(resolve(&base, reference).unwrap().to_string(), expected, "{}", reference);
+        }
+
+        let base: Uri = "https://api.example.com/v2/".parse().unwrap();
+        assert_eq!(resolve(&base, "users/1").unwrap().to_string(), "https://api.example.com/v2/users/1");
+        let base: Uri = "https://api.example.com".parse().unwrap();
+        assert_eq!(resolve(&base, "users").unwrap().to_string(), "https://api.example.com/users");
+    }
+}